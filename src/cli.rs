@@ -0,0 +1,729 @@
+use crate::config::{DeviceConfig, ExperimentConfig, LogLevel};
+use crate::experiment::{CurrentSweepConfig, OutputFormat, PowerSweepConfig, PowerUnit, StabilityConfig};
+use crate::experiment::validation::parse_limit;
+use clap::{Args, Parser, Subcommand};
+use serde::de::DeserializeOwned;
+use std::path::PathBuf;
+
+/// Value of a flag that turns a limit off with `off`. Not spelled `Option<Option<f64>>`,
+/// which clap would take for a flag with an optional value.
+type Limit = Option<f64>;
+
+/// Command line of the application. Flags override the matching settings of the config file,
+/// which override the device file and the built-in defaults. The global flags can also be set
+/// with the environment variable named in their help, e.g. to point CI at the simulator;
+/// a flag on the command line wins over the variable.
+#[derive(Debug, Parser)]
+#[command(name = "cld1015-mpm210h", version,
+          about = "Optical power measurements with a Thorlabs CLD1015 and a Santec MPM-210H")]
+pub struct Cli {
+    /// Experiment configuration file (.toml, .yaml or .yml)
+    #[arg(long, global = true, value_name = "FILE", env = "CLD_MPM_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Named profile: the experiment file of that name in the profile directory
+    #[arg(long, global = true, value_name = "NAME", env = "CLD_MPM_PROFILE", conflicts_with = "config")]
+    pub profile: Option<String>,
+
+    /// Directory of the profiles [default: profiles]
+    #[arg(long, global = true, value_name = "DIR", env = "CLD_MPM_PROFILE_DIR")]
+    pub profile_dir: Option<PathBuf>,
+
+    /// Directory the data files are written to
+    #[arg(long, global = true, value_name = "DIR", env = "CLD_MPM_OUTPUT_DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Format of the application log
+    #[arg(long, global = true, value_name = "FORMAT", value_parser = ["text", "json"], env = "CLD_MPM_LOG_FORMAT")]
+    pub log_format: Option<String>,
+
+    /// Most detailed level written to the log files
+    #[arg(long, global = true, value_name = "LEVEL", value_parser = serde_value::<LogLevel>, env = "CLD_MPM_LOG_LEVEL")]
+    pub log_level: Option<LogLevel>,
+
+    /// Print more log lines on the console: -v for info, -vv for debug (every SCPI command)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Print fewer log lines on the console: -q for errors only, -qq for none
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub quiet: u8,
+
+    #[command(flatten)]
+    pub devices: DeviceArgs,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Current sweep (L-I curve), or the fast sweep when [fast_sweep] is configured
+    Sweep(SweepCommand),
+    /// Current sweep recording the forward voltage at every point (L-I-V curve)
+    Liv(SweepCommand),
+    /// Hold the laser at one current and record the power over time
+    Stability(StabilityCommand),
+    /// Step the CLD1015 optical power setpoint in constant-power mode and record the drive current
+    PowerSweep(PowerSweepCommand),
+    /// Continue an interrupted current sweep from its checkpoint
+    Resume(ResumeArgs),
+    /// Run a sweep or stability measurement on a cron schedule, e.g. a stability snapshot every 6 hours
+    Schedule(ScheduleArgs),
+    /// Read one MPM210H port continuously until interrupted, e.g. for fiber alignment
+    Monitor(MonitorArgs),
+    /// Zero the MPM210H; its inputs must be dark
+    Zero(ZeroArgs),
+    /// Print the identification and state of both instruments
+    Status,
+    /// Drive the laser at one current and leave it on, for a quick operating-point check
+    SetCurrent(SetCurrentArgs),
+    /// Turn the laser off unconditionally, e.g. after a crash left it on
+    #[command(visible_alias = "off")]
+    Stop(StopArgs),
+    /// List the VISA instruments with their identification and the MPM210H modules and ports
+    Scan,
+    /// Check the configured instruments, e.g. for the start-of-day checklist
+    #[command(subcommand)]
+    Devices(DevicesCommand),
+    /// Check both instruments and report pass/fail per check
+    Selftest(SelftestArgs),
+    /// Interactive prompt sending raw commands to the instruments
+    Repl(ReplArgs),
+    /// Re-export saved runs to other formats
+    Convert(ConvertArgs),
+    /// Compare the L-I parameters of saved runs
+    Compare(CompareArgs),
+    /// Write a commented template experiment file, or check an existing one
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// List the profiles of the profile directory
+    Profiles,
+    /// Stay connected to the instruments and run the experiment jobs sent to a local port
+    Service(ServiceArgs),
+    /// Print the shell completion script for every subcommand and flag
+    Completions(CompletionsArgs),
+}
+
+/// Instrument addresses, for every subcommand
+#[derive(Debug, Args)]
+pub struct DeviceArgs {
+    /// Device file with the instrument addresses of this bench [default: devices.toml, if present]
+    #[arg(long = "devices", global = true, value_name = "FILE", env = "CLD_MPM_DEVICES")]
+    pub file: Option<PathBuf>,
+
+    /// VISA resource string of the CLD1015
+    #[arg(long, global = true, value_name = "RESOURCE", env = "CLD_MPM_CLD1015_RESOURCE")]
+    pub cld1015_resource: Option<String>,
+
+    /// IP address of the MPM210H
+    #[arg(long, global = true, value_name = "IP", env = "CLD_MPM_MPM210H_ADDRESS")]
+    pub mpm210h_address: Option<String>,
+
+    /// TCP port of the MPM210H
+    #[arg(long, global = true, value_name = "PORT", env = "CLD_MPM_MPM210H_PORT")]
+    pub mpm210h_port: Option<u16>,
+
+    /// Use a simulated rig instead of the hardware
+    #[arg(long, global = true, env = "CLD_MPM_SIMULATE")]
+    pub simulate: bool,
+}
+
+impl DeviceArgs {
+    pub fn apply(&self, devices: &mut DeviceConfig) {
+        set(&mut devices.cld1015_resource, &self.cld1015_resource);
+        set(&mut devices.mpm210h_address, &self.mpm210h_address);
+        set(&mut devices.mpm210h_port, &self.mpm210h_port);
+    }
+}
+
+/// Sample and lab conditions recorded with a run
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Chip / device under test
+    #[arg(long, value_name = "ID")]
+    pub sample_id: Option<String>,
+
+    /// Free-form notes stored with the data
+    #[arg(long)]
+    pub notes: Option<String>,
+
+    /// Person running the measurement
+    #[arg(long)]
+    pub operator: Option<String>,
+
+    /// Setup / bench identifier
+    #[arg(long, value_name = "ID")]
+    pub bench_id: Option<String>,
+
+    /// Ambient temperature in °C
+    #[arg(long, value_name = "CELSIUS")]
+    pub ambient_temp: Option<f64>,
+
+    /// Relative humidity in %
+    #[arg(long, value_name = "PERCENT")]
+    pub humidity: Option<f64>,
+}
+
+impl RunArgs {
+    pub fn apply(&self, config: &mut ExperimentConfig) {
+        set(&mut config.sample_id, &self.sample_id);
+        set_some(&mut config.notes, &self.notes);
+        set_some(&mut config.environment.operator, &self.operator);
+        set_some(&mut config.environment.bench_id, &self.bench_id);
+        set_some(&mut config.environment.ambient_temperature_c, &self.ambient_temp);
+        set_some(&mut config.environment.humidity_percent, &self.humidity);
+    }
+}
+
+/// Options of the measuring subcommands
+#[derive(Debug, Args)]
+pub struct SessionArgs {
+    /// Print the instruments and channels used and every planned point, then exit without
+    /// connecting to the hardware
+    #[arg(long, visible_alias = "dry-run", alias = "preview", env = "CLD_MPM_DRY_RUN")]
+    pub plan: bool,
+
+    /// Show the curve in a window as it is measured; closing it aborts the run
+    #[arg(long)]
+    pub live_plot: bool,
+
+    /// Print only a JSON result (status, files, L-I figures) on stdout, without progress
+    /// bar or prompts, for test scripts
+    #[arg(long, conflicts_with_all = ["plan", "live_plot"])]
+    pub machine_output: bool,
+
+    /// Print one plain line per point instead of the live table, as when the output is piped
+    #[arg(long, conflicts_with = "machine_output")]
+    pub plain: bool,
+
+    /// Run the measurement this many times, each into its own files
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub repeat: u32,
+
+    /// With --repeat, start a run every this many minutes instead of back to back
+    #[arg(long = "interval", value_name = "MIN")]
+    pub repeat_interval_min: Option<f64>,
+
+    /// Fail on a missing sample ID, port or wavelength instead of asking for it
+    #[arg(long)]
+    pub no_input: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SweepCommand {
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub sweep: SweepArgs,
+
+    #[command(flatten)]
+    pub session: SessionArgs,
+}
+
+/// Fields of `CurrentSweepConfig`. The `settling`, `warm_up` and `tec_check` sections are
+/// only set in the config file.
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Sweep")]
+pub struct SweepArgs {
+    /// MPM210H module number
+    #[arg(long)]
+    pub module: Option<u8>,
+
+    /// MPM210H port number (1-4)
+    #[arg(long)]
+    pub port: Option<u8>,
+
+    /// Start current in mA
+    #[arg(long, value_name = "MA")]
+    pub start_ma: Option<f64>,
+
+    /// End current in mA
+    #[arg(long, value_name = "MA")]
+    pub stop_ma: Option<f64>,
+
+    /// Step size in mA
+    #[arg(long, value_name = "MA")]
+    pub step_ma: Option<f64>,
+
+    /// Delay after setting the current before measuring, in ms
+    #[arg(long, value_name = "MS")]
+    pub stabilization_delay_ms: Option<u64>,
+
+    /// Calibration wavelengths in nm, one sweep pass each
+    #[arg(long, value_name = "NM", value_delimiter = ',')]
+    pub wavelengths_nm: Vec<u32>,
+
+    /// Power meter averaging time in ms
+    #[arg(long, value_name = "MS")]
+    pub averaging_time_ms: Option<f64>,
+
+    /// Power measurement unit: dBm or mW
+    #[arg(long, value_name = "UNIT", value_parser = serde_value::<PowerUnit>)]
+    pub power_unit: Option<PowerUnit>,
+
+    /// Retries for a failed point before aborting the sweep
+    #[arg(long, value_name = "N")]
+    pub point_retries: Option<u32>,
+
+    /// Initial delay before retrying a point in ms, doubled on each retry
+    #[arg(long, value_name = "MS")]
+    pub retry_backoff_ms: Option<u64>,
+
+    /// Abort the sweep if any reading exceeds this power in dBm, `off` for no limit
+    #[arg(long, value_name = "DBM", value_parser = parse_limit)]
+    pub max_power_dbm: Option<Limit>,
+
+    /// Relative change in dP/dI flagged as a kink
+    #[arg(long, value_name = "FRACTION")]
+    pub kink_tolerance: Option<f64>,
+
+    /// Emergency stop if the power drops by this fraction while the current increases,
+    /// `off` to disable
+    #[arg(long, value_name = "FRACTION", value_parser = parse_limit)]
+    pub cod_drop_fraction: Option<Limit>,
+
+    /// Turn the TEC off as part of the shutdown sequence
+    #[arg(long, value_name = "BOOL")]
+    pub disable_tec_after_run: Option<bool>,
+
+    /// Visit the current points in random order
+    #[arg(long, value_name = "BOOL")]
+    pub randomize_order: Option<bool>,
+
+    /// Seed for the random order
+    #[arg(long, value_name = "SEED")]
+    pub random_seed: Option<u64>,
+
+    /// Ramp through intermediate setpoints when a current change would be faster, in mA/s
+    #[arg(long, value_name = "MA_PER_S")]
+    pub max_slew_rate_ma_per_s: Option<f64>,
+
+    /// Flag points where the delivered current is off by more than this, in mA
+    #[arg(long, value_name = "MA")]
+    pub current_tolerance_ma: Option<f64>,
+
+    /// Take a dark reading this often during the sweep, in s
+    #[arg(long, value_name = "S")]
+    pub dark_check_interval_s: Option<u64>,
+
+    /// Re-zero the MPM210H this often during the sweep, in minutes
+    #[arg(long, value_name = "MIN")]
+    pub rezero_interval_min: Option<u64>,
+
+    /// End a pass once the power falls for this many consecutive points
+    #[arg(long, value_name = "N")]
+    pub rollover_points: Option<u32>,
+
+    /// Record the LD forward voltage at each point
+    #[arg(long, value_name = "BOOL")]
+    pub measure_voltage: Option<bool>,
+
+    /// Record the laser temperature at each point
+    #[arg(long, value_name = "BOOL")]
+    pub measure_temperature: Option<bool>,
+}
+
+impl SweepArgs {
+    pub fn apply(&self, config: &mut CurrentSweepConfig) {
+        set(&mut config.module, &self.module);
+        set(&mut config.port, &self.port);
+        set(&mut config.start_ma, &self.start_ma);
+        set(&mut config.stop_ma, &self.stop_ma);
+        set(&mut config.step_ma, &self.step_ma);
+        set(&mut config.stabilization_delay_ms, &self.stabilization_delay_ms);
+        if !self.wavelengths_nm.is_empty() {
+            config.wavelengths_nm = self.wavelengths_nm.clone();
+        }
+        set(&mut config.averaging_time_ms, &self.averaging_time_ms);
+        set(&mut config.power_unit, &self.power_unit);
+        set(&mut config.point_retries, &self.point_retries);
+        set(&mut config.retry_backoff_ms, &self.retry_backoff_ms);
+        set(&mut config.max_power_dbm, &self.max_power_dbm);
+        set(&mut config.kink_tolerance, &self.kink_tolerance);
+        set(&mut config.cod_drop_fraction, &self.cod_drop_fraction);
+        set(&mut config.disable_tec_after_run, &self.disable_tec_after_run);
+        set(&mut config.randomize_order, &self.randomize_order);
+        set_some(&mut config.random_seed, &self.random_seed);
+        set_some(&mut config.max_slew_rate_ma_per_s, &self.max_slew_rate_ma_per_s);
+        set_some(&mut config.current_tolerance_ma, &self.current_tolerance_ma);
+        set_some(&mut config.dark_check_interval_s, &self.dark_check_interval_s);
+        set_some(&mut config.rezero_interval_min, &self.rezero_interval_min);
+        set_some(&mut config.rollover_points, &self.rollover_points);
+        set(&mut config.measure_voltage, &self.measure_voltage);
+        set(&mut config.measure_temperature, &self.measure_temperature);
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct StabilityCommand {
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub stability: StabilityArgs,
+
+    #[command(flatten)]
+    pub session: SessionArgs,
+}
+
+/// Fields of `StabilityConfig`. The `tec_check` section is only set in the config file.
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Stability")]
+pub struct StabilityArgs {
+    /// MPM210H module number
+    #[arg(long)]
+    pub module: Option<u8>,
+
+    /// MPM210H port number (1-4)
+    #[arg(long)]
+    pub port: Option<u8>,
+
+    /// Drive current held during the run, in mA
+    #[arg(long, value_name = "MA")]
+    pub current_ma: Option<f64>,
+
+    /// Calibration wavelength in nm
+    #[arg(long, value_name = "NM")]
+    pub wavelength_nm: Option<u32>,
+
+    /// Length of the run in s
+    #[arg(long, value_name = "S")]
+    pub duration_s: Option<f64>,
+
+    /// Time between readings in ms
+    #[arg(long, value_name = "MS")]
+    pub interval_ms: Option<u64>,
+
+    /// Power meter averaging time in ms
+    #[arg(long, value_name = "MS")]
+    pub averaging_time_ms: Option<f64>,
+
+    /// Power measurement unit: dBm or mW
+    #[arg(long, value_name = "UNIT", value_parser = serde_value::<PowerUnit>)]
+    pub power_unit: Option<PowerUnit>,
+
+    /// Abort the run if any reading exceeds this power in dBm, `off` for no limit
+    #[arg(long, value_name = "DBM", value_parser = parse_limit)]
+    pub max_power_dbm: Option<Limit>,
+
+    /// Record the laser temperature with each reading
+    #[arg(long, value_name = "BOOL")]
+    pub measure_temperature: Option<bool>,
+
+    /// Turn the TEC off as part of the shutdown sequence
+    #[arg(long, value_name = "BOOL")]
+    pub disable_tec_after_run: Option<bool>,
+}
+
+impl StabilityArgs {
+    pub fn apply(&self, config: &mut StabilityConfig) {
+        set(&mut config.module, &self.module);
+        set(&mut config.port, &self.port);
+        set(&mut config.current_ma, &self.current_ma);
+        set(&mut config.wavelength_nm, &self.wavelength_nm);
+        set(&mut config.duration_s, &self.duration_s);
+        set(&mut config.interval_ms, &self.interval_ms);
+        set(&mut config.averaging_time_ms, &self.averaging_time_ms);
+        set(&mut config.power_unit, &self.power_unit);
+        set(&mut config.max_power_dbm, &self.max_power_dbm);
+        set(&mut config.measure_temperature, &self.measure_temperature);
+        set(&mut config.disable_tec_after_run, &self.disable_tec_after_run);
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct PowerSweepCommand {
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub power_sweep: PowerSweepArgs,
+
+    #[command(flatten)]
+    pub session: SessionArgs,
+}
+
+/// Fields of `PowerSweepConfig`. The `tec_check` section is only set in the config file.
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Power sweep")]
+pub struct PowerSweepArgs {
+    /// MPM210H module number
+    #[arg(long)]
+    pub module: Option<u8>,
+
+    /// MPM210H port number (1-4)
+    #[arg(long)]
+    pub port: Option<u8>,
+
+    /// First optical power setpoint in mW
+    #[arg(long, value_name = "MW")]
+    pub start_mw: Option<f64>,
+
+    /// Last optical power setpoint in mW
+    #[arg(long, value_name = "MW")]
+    pub stop_mw: Option<f64>,
+
+    /// Setpoint step size in mW
+    #[arg(long, value_name = "MW")]
+    pub step_mw: Option<f64>,
+
+    /// Delay after changing the setpoint before measuring, in ms
+    #[arg(long, value_name = "MS")]
+    pub stabilization_delay_ms: Option<u64>,
+
+    /// Calibration wavelength in nm
+    #[arg(long, value_name = "NM")]
+    pub wavelength_nm: Option<u32>,
+
+    /// Power meter averaging time in ms
+    #[arg(long, value_name = "MS")]
+    pub averaging_time_ms: Option<f64>,
+
+    /// Power measurement unit: dBm or mW
+    #[arg(long, value_name = "UNIT", value_parser = serde_value::<PowerUnit>)]
+    pub power_unit: Option<PowerUnit>,
+
+    /// Abort the sweep if any reading exceeds this power in dBm, `off` for no limit
+    #[arg(long, value_name = "DBM", value_parser = parse_limit)]
+    pub max_power_dbm: Option<Limit>,
+
+    /// Emergency stop if the power drops by this fraction while the current increases,
+    /// `off` to disable
+    #[arg(long, value_name = "FRACTION", value_parser = parse_limit)]
+    pub cod_drop_fraction: Option<Limit>,
+}
+
+impl PowerSweepArgs {
+    pub fn apply(&self, config: &mut PowerSweepConfig) {
+        set(&mut config.module, &self.module);
+        set(&mut config.port, &self.port);
+        set(&mut config.start_mw, &self.start_mw);
+        set(&mut config.stop_mw, &self.stop_mw);
+        set(&mut config.step_mw, &self.step_mw);
+        set(&mut config.stabilization_delay_ms, &self.stabilization_delay_ms);
+        set(&mut config.wavelength_nm, &self.wavelength_nm);
+        set(&mut config.averaging_time_ms, &self.averaging_time_ms);
+        set(&mut config.power_unit, &self.power_unit);
+        set(&mut config.max_power_dbm, &self.max_power_dbm);
+        set(&mut config.cod_drop_fraction, &self.cod_drop_fraction);
+    }
+}
+
+/// Channel and meter settings of `monitor`; those not given are taken from `[sweep]`
+#[derive(Debug, Args)]
+pub struct MonitorArgs {
+    /// MPM210H module number
+    #[arg(long)]
+    pub module: Option<u8>,
+
+    /// MPM210H port number (1-4)
+    #[arg(long)]
+    pub port: Option<u8>,
+
+    /// Calibration wavelength in nm [default: the first of [sweep]]
+    #[arg(long, value_name = "NM")]
+    pub wavelength_nm: Option<u32>,
+
+    /// Power meter averaging time in ms
+    #[arg(long, value_name = "MS")]
+    pub averaging_time_ms: Option<f64>,
+
+    /// Power measurement unit
+    #[arg(long, value_name = "UNIT", value_parser = serde_value::<PowerUnit>)]
+    pub power_unit: Option<PowerUnit>,
+
+    /// Time between readings in ms
+    #[arg(long, value_name = "MS", default_value_t = 200, value_parser = clap::value_parser!(u64).range(1..))]
+    pub interval_ms: u64,
+
+    /// Stop after this many seconds instead of at Ctrl-C
+    #[arg(long, value_name = "S")]
+    pub duration_s: Option<f64>,
+
+    /// Also write every reading to this CSV file
+    #[arg(long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct SetCurrentArgs {
+    /// Current in mA; 0 turns the laser off
+    #[arg(value_name = "MA")]
+    pub current_ma: f64,
+
+    /// Ask for confirmation above this current
+    #[arg(long, value_name = "MA", default_value_t = 50.0)]
+    pub confirm_above_ma: f64,
+
+    /// Do not ask for confirmation
+    #[arg(long, short)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct StopArgs {
+    /// Also turn the TEC off
+    #[arg(long)]
+    pub tec: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ZeroArgs {
+    /// Inputs reading above this power before zeroing are reported as not dark
+    #[arg(long, value_name = "DBM", default_value_t = -50.0, allow_negative_numbers = true)]
+    pub dark_threshold_dbm: f64,
+
+    /// Zero even when an input is not dark
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SelftestArgs {
+    /// Also drive the laser at this current for a moment and check the measured current;
+    /// block the beam first
+    #[arg(long, value_name = "MA")]
+    pub pulse_ma: Option<f64>,
+}
+
+#[derive(Debug, Args)]
+pub struct ScheduleArgs {
+    /// Cron expression in local time, with seconds: "sec min hour day month weekday",
+    /// e.g. "0 0 */6 * * *" for every 6 hours
+    #[arg(long, value_name = "EXPR")]
+    pub cron: String,
+
+    /// Stop after this many runs [default: run until interrupted]
+    #[arg(long, value_name = "N")]
+    pub count: Option<u32>,
+
+    #[command(subcommand)]
+    pub experiment: ScheduledCommand,
+}
+
+/// Measuring subcommands that can be scheduled, with their usual flags
+#[derive(Debug, Subcommand)]
+pub enum ScheduledCommand {
+    /// Current sweep, or the fast sweep when [fast_sweep] is configured
+    Sweep(SweepCommand),
+    /// Current sweep recording the forward voltage at every point
+    Liv(SweepCommand),
+    /// Hold the laser at one current and record the power over time
+    Stability(StabilityCommand),
+}
+
+#[derive(Debug, Args)]
+pub struct ResumeArgs {
+    /// Run ID of the interrupted run: its data file name without extension
+    #[arg(value_name = "RUN_ID")]
+    pub run_id: String,
+
+    /// Print one plain line per point instead of the live table
+    #[arg(long)]
+    pub plain: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplArgs {
+    /// Record every command and response of the session in this file
+    #[arg(long, value_name = "FILE")]
+    pub transcript: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    /// CSV, JSON Lines or JSON data files of saved runs
+    #[arg(required = true, value_name = "FILE")]
+    pub files: Vec<PathBuf>,
+
+    /// Formats to write, e.g. parquet,xlsx
+    #[arg(long, required = true, value_name = "FORMAT", value_delimiter = ',', value_parser = serde_value::<OutputFormat>)]
+    pub to: Vec<OutputFormat>,
+
+    /// Keep the first of every N points
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "average")]
+    pub every: Option<u64>,
+
+    /// Write the mean of every N consecutive points
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub average: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct CompareArgs {
+    /// CSV, JSON Lines or JSON data files of saved runs; the first is the reference
+    #[arg(required = true, value_name = "FILE")]
+    pub files: Vec<PathBuf>,
+
+    /// Current in mA at which the power of the runs is compared; the highest current of each run without it
+    #[arg(long, value_name = "MA")]
+    pub reference_current: Option<f64>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DevicesCommand {
+    /// Connect to each configured instrument and report its identification, round-trip
+    /// latency and error queue; fails if one is unreachable
+    Test,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Write an experiment file with every setting at its default and commented
+    Init(ConfigInitArgs),
+    /// Report every problem in an experiment file without touching the hardware
+    Check(ConfigCheckArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigInitArgs {
+    /// File to write
+    #[arg(value_name = "FILE", default_value = crate::config::DEFAULT_CONFIG_FILE)]
+    pub path: PathBuf,
+
+    /// Replace the file if it exists
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigCheckArgs {
+    /// TOML or YAML experiment file
+    #[arg(value_name = "FILE", default_value = crate::config::DEFAULT_CONFIG_FILE)]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ServiceArgs {
+    /// Address and port to accept jobs on
+    #[arg(long, value_name = "ADDR", env = "CLD_MPM_SERVICE_LISTEN", default_value = crate::service::DEFAULT_LISTEN_ADDRESS)]
+    pub listen: std::net::SocketAddr,
+}
+
+#[derive(Debug, Args)]
+pub struct CompletionsArgs {
+    /// Shell to generate the script for
+    #[arg(value_enum)]
+    pub shell: clap_complete::Shell,
+}
+
+/// Parse a flag value the way the config file spells it, e.g. "dBm" or "parquet"
+fn serde_value<T: DeserializeOwned>(value: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string())).map_err(|e| e.to_string())
+}
+
+/// Override a setting with a flag that was given
+fn set<T: Clone>(field: &mut T, value: &Option<T>) {
+    if let Some(value) = value {
+        *field = value.clone();
+    }
+}
+
+/// Override an optional setting with a flag that was given
+fn set_some<T: Clone>(field: &mut Option<T>, value: &Option<T>) {
+    if value.is_some() {
+        *field = value.clone();
+    }
+}
@@ -0,0 +1,374 @@
+use crate::devices::EnvSensor;
+use crate::devices::env_sensor::EnvSensorConfig;
+use crate::experiment::{CurrentSweepConfig, FastSweepConfig, OutputConfig, PowerSweepConfig, StabilityConfig};
+use crate::experiment::data::{EnvironmentMetadata, RunInfo};
+use crate::experiment::validation::{format_violations, ConfigViolation};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Connection settings for the instruments
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceConfig {
+    pub cld1015_resource: String, // VISA resource string of the CLD1015
+    pub mpm210h_address: String,  // IP address of the MPM210H
+    pub mpm210h_port: u16,        // TCP port of the MPM210H
+    pub cld1015_timeout_ms: u64,  // VISA timeout of the CLD1015
+    pub mpm210h_timeout_ms: u64,  // Connection, read and write timeout of the MPM210H
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        DeviceConfig {
+            cld1015_resource: "USB0::4883::32847::M01053290::0::INSTR".to_string(),
+            mpm210h_address: "192.168.1.161".to_string(),
+            mpm210h_port: 5000,
+            cld1015_timeout_ms: 2000, // USB answers within milliseconds
+            mpm210h_timeout_ms: 5000, // Long logging reads over the network
+        }
+    }
+}
+
+/// Device file read at startup when no other is given, in the working directory
+pub const DEFAULT_DEVICE_FILE: &str = "devices.toml";
+
+impl DeviceConfig {
+    /// Check every field and report all problems found
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        if self.cld1015_resource.trim().is_empty() {
+            violations.push(ConfigViolation::new("cld1015_resource", "\"\"", "VISA resource string is empty")
+                .allowed("e.g. USB0::4883::32847::<serial>::0::INSTR"));
+        }
+        if self.mpm210h_address.parse::<std::net::IpAddr>().is_err() {
+            violations.push(ConfigViolation::new("mpm210h_address", &self.mpm210h_address, "not a valid IP address")
+                .allowed("IPv4 or IPv6 address, e.g. 192.168.1.161"));
+        }
+        if self.mpm210h_port == 0 {
+            violations.push(ConfigViolation::new("mpm210h_port", self.mpm210h_port, "TCP port must not be 0")
+                .allowed("1-65535"));
+        }
+        for (field, timeout_ms) in [("cld1015_timeout_ms", self.cld1015_timeout_ms), ("mpm210h_timeout_ms", self.mpm210h_timeout_ms)] {
+            if timeout_ms == 0 {
+                violations.push(ConfigViolation::new(field, timeout_ms, "timeout must be positive").allowed("> 0 ms"));
+            }
+        }
+        violations
+    }
+
+    /// Take the settings an experiment file does not give, listed by name in `given`, from `base`
+    fn fill_from(&mut self, base: &DeviceConfig, given: &[String]) {
+        let given = |field: &str| given.iter().any(|g| g == field);
+        if !given("cld1015_resource") {
+            self.cld1015_resource = base.cld1015_resource.clone();
+        }
+        if !given("mpm210h_address") {
+            self.mpm210h_address = base.mpm210h_address.clone();
+        }
+        if !given("mpm210h_port") {
+            self.mpm210h_port = base.mpm210h_port;
+        }
+        if !given("cld1015_timeout_ms") {
+            self.cld1015_timeout_ms = base.cld1015_timeout_ms;
+        }
+        if !given("mpm210h_timeout_ms") {
+            self.mpm210h_timeout_ms = base.mpm210h_timeout_ms;
+        }
+    }
+}
+
+/// Experiment file written by `config init` when no other path is given
+pub const DEFAULT_CONFIG_FILE: &str = "experiment.toml";
+
+/// Commented experiment file with every section, see `config_template`
+const CONFIG_TEMPLATE: &str = include_str!("config_template.toml");
+
+/// Experiment file with every section and field at its default and commented, with the
+/// instrument addresses of `devices`, e.g. those of the device file
+pub fn config_template(devices: &DeviceConfig) -> String {
+    CONFIG_TEMPLATE
+        .replace("{cld1015_resource}", &devices.cld1015_resource)
+        .replace("{mpm210h_address}", &devices.mpm210h_address)
+        .replace("{mpm210h_port}", &devices.mpm210h_port.to_string())
+        .replace("{cld1015_timeout_ms}", &devices.cld1015_timeout_ms.to_string())
+        .replace("{mpm210h_timeout_ms}", &devices.mpm210h_timeout_ms.to_string())
+}
+
+/// Directory of the named profiles when no other is given, in the working directory
+pub const DEFAULT_PROFILE_DIR: &str = "profiles";
+
+/// Extensions of experiment files, in the order profiles are looked up
+const CONFIG_EXTENSIONS: [&str; 3] = ["toml", "yaml", "yml"];
+
+/// Names of the profiles in `directory`: its experiment files without extension, sorted
+pub fn list_profiles(directory: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()).is_some_and(|e| CONFIG_EXTENSIONS.contains(&e)))
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Experiment file of the profile `name` in `directory`, e.g. `profiles/pump-burn-in.toml`.
+/// A profile is an experiment file like any other, bundling the sweep, channel, wavelength
+/// and output settings of one kind of measurement.
+pub fn profile_path(directory: &Path, name: &str) -> Result<PathBuf, String> {
+    CONFIG_EXTENSIONS
+        .iter()
+        .map(|extension| directory.join(format!("{}.{}", name, extension)))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            let available = list_profiles(directory);
+            if available.is_empty() {
+                format!("No profile {:?}: {} holds no profiles", name, directory.display())
+            } else {
+                format!("No profile {:?} in {}; available: {}", name, directory.display(), available.join(", "))
+            }
+        })
+}
+
+/// Load the instrument addresses and settings of this bench from a TOML device file,
+/// with the same fields as the `[devices]` section
+pub fn load_device_file(path: &Path) -> Result<DeviceConfig, String> {
+    info!("Loading device settings from {}", path.display());
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read device file {}: {}", path.display(), e))?;
+    toml::from_str(&contents).map_err(|e| format!("Failed to parse device file {}: {}", path.display(), e))
+}
+
+/// Lab conditions recorded with each run, entered by hand or read from a sensor
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnvironmentConfig {
+    pub ambient_temperature_c: Option<f64>, // Ambient temperature in °C, used when no sensor is configured
+    pub humidity_percent: Option<f64>,      // Relative humidity in %, used when no sensor is configured
+    pub operator: Option<String>,           // Person running the measurement
+    pub bench_id: Option<String>,           // Setup / bench identifier
+    pub sensor: Option<EnvSensorConfig>,    // USB temperature/humidity sensor to read at the start of the run
+}
+
+impl EnvironmentConfig {
+    /// Check every field and report all problems found
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        if let Some(humidity) = self.humidity_percent {
+            if !(0.0..=100.0).contains(&humidity) {
+                violations.push(ConfigViolation::new("humidity_percent", humidity, "relative humidity out of range")
+                    .allowed("0-100 %"));
+            }
+        }
+        if let Some(sensor) = &self.sensor {
+            if sensor.port.trim().is_empty() {
+                violations.push(ConfigViolation::new("sensor.port", "\"\"", "serial port name is empty")
+                    .allowed("e.g. COM3 or /dev/ttyACM0"));
+            }
+            if sensor.baud_rate == 0 {
+                violations.push(ConfigViolation::new("sensor.baud_rate", sensor.baud_rate, "baud rate must not be 0")
+                    .allowed("e.g. 9600 or 115200"));
+            }
+        }
+        violations
+    }
+
+    /// Metadata for the current run. Sensor readings take precedence over the values
+    /// entered by hand; if the sensor cannot be read the entered values are kept.
+    pub fn capture(&self) -> EnvironmentMetadata {
+        let mut metadata = EnvironmentMetadata {
+            ambient_temperature_c: self.ambient_temperature_c,
+            humidity_percent: self.humidity_percent,
+            operator: self.operator.clone(),
+            bench_id: self.bench_id.clone(),
+        };
+
+        if let Some(sensor_config) = &self.sensor {
+            match EnvSensor::new(sensor_config.clone()).read() {
+                Ok(reading) => {
+                    info!("Ambient conditions: {:.2} °C, {:.1} % RH", reading.temperature_c, reading.humidity_percent);
+                    metadata.ambient_temperature_c = Some(reading.temperature_c);
+                    metadata.humidity_percent = Some(reading.humidity_percent);
+                },
+                Err(e) => warn!("Failed to read environment sensor, keeping configured values: {}", e),
+            }
+        }
+        metadata
+    }
+}
+
+/// Complete experiment configuration as stored in a config file
+/// How often a new application log file is started
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+/// Format of the application log lines
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json, // One JSON object per line, with the run ID and sample ID of the current run
+}
+
+/// Most detailed level written to the application log
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug, // Adds every command and response exchanged with the instruments
+    Trace,
+}
+
+impl LogLevel {
+    const ALL: [LogLevel; 6] = [LogLevel::Off, LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace];
+
+    /// Level `steps` more detailed, or less detailed for negative steps, e.g. for -v and -q
+    pub fn shifted(self, steps: i32) -> LogLevel {
+        let index = Self::ALL.iter().position(|&l| l == self).unwrap_or(0) as i32 + steps;
+        Self::ALL[index.clamp(0, Self::ALL.len() as i32 - 1) as usize]
+    }
+}
+
+/// Application log files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub directory: PathBuf,          // Directory of the app.log files
+    pub rotation: LogRotation,
+    pub retention_days: Option<u64>, // Delete log files older than this at startup, keep all if None
+    pub format: LogFormat,
+    pub level: LogLevel,             // Level of the log files
+    pub console_level: LogLevel,     // Level of the log lines printed on stderr
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            directory: PathBuf::from("logs"),
+            rotation: LogRotation::Daily,
+            retention_days: Some(30), // A month of logs
+            format: LogFormat::Text,
+            level: LogLevel::Info,
+            console_level: LogLevel::Warn, // Keeps the progress bar readable
+        }
+    }
+}
+
+impl LoggingConfig {
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        if self.retention_days == Some(0) {
+            violations.push(ConfigViolation::new("retention_days", 0, "the current log file would be deleted")
+                .allowed("at least 1 day, or omit to keep all logs"));
+        }
+        violations
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExperimentConfig {
+    pub sample_id: String,     // Chip / device under test, required
+    pub notes: Option<String>, // Free-form notes stored with the data
+    pub devices: DeviceConfig,
+    pub sweep: CurrentSweepConfig,
+    pub fast_sweep: Option<FastSweepConfig>, // Run a hardware-logged fast sweep instead of `sweep`
+    pub stability: StabilityConfig,          // Settings of the `stability` subcommand
+    pub power_sweep: PowerSweepConfig,       // Settings of the `power-sweep` subcommand
+    pub environment: EnvironmentConfig,
+    pub output: OutputConfig,
+    pub logging: LoggingConfig,
+}
+
+impl ExperimentConfig {
+    /// Every problem in the configuration, with field paths relative to the file
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        if self.sample_id.trim().is_empty() {
+            violations.push(ConfigViolation::new("sample_id", "\"\"", "a sample ID is required")
+                .allowed("set sample_id in the config file or pass --sample-id"));
+        }
+        let devices = self.devices.violations().into_iter().map(|v| v.in_section("devices"));
+        let sweep = self.sweep.violations().into_iter().map(|v| v.in_section("sweep"));
+        let environment = self.environment.violations().into_iter().map(|v| v.in_section("environment"));
+        let output = self.output.violations().into_iter().map(|v| v.in_section("output"));
+        let stability = self.stability.violations().into_iter().map(|v| v.in_section("stability"));
+        let power_sweep = self.power_sweep.violations().into_iter().map(|v| v.in_section("power_sweep"));
+        let logging = self.logging.violations().into_iter().map(|v| v.in_section("logging"));
+        violations.extend(devices.chain(sweep).chain(stability).chain(power_sweep).chain(environment).chain(output).chain(logging));
+        if let Some(fast_sweep) = &self.fast_sweep {
+            violations.extend(fast_sweep.violations().into_iter().map(|v| v.in_section("fast_sweep")));
+        }
+        violations
+    }
+
+    /// Check the configuration, reporting every problem found at once
+    pub fn validate(&self) -> Result<(), String> {
+        let violations = self.violations();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(format_violations(&violations))
+        }
+    }
+
+    /// Sample and environment information for a run, reading the environment sensor if configured
+    pub fn run_info(&self) -> RunInfo {
+        RunInfo {
+            sample_id: self.sample_id.trim().to_string(),
+            notes: self.notes.clone(),
+            environment: self.environment.capture(),
+            repeat_index: 0,
+            resumed_from: None,
+        }
+    }
+}
+
+/// Load an experiment configuration from a TOML or YAML file, chosen by extension.
+/// Device settings missing from its `[devices]` section are taken from `devices`, the
+/// settings of the device file.
+///
+/// The result is not validated yet, so command line overrides can still be applied;
+/// call `ExperimentConfig::validate` before using it.
+pub fn load_config(path: &Path, devices: &DeviceConfig) -> Result<ExperimentConfig, String> {
+    info!("Loading experiment configuration from {}", path.display());
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let mut config: ExperimentConfig = match extension.as_str() {
+        "toml" => toml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse TOML config {}: {}", path.display(), e)),
+        "yaml" | "yml" => serde_yaml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse YAML config {}: {}", path.display(), e)),
+        _ => Err(format!(
+            "Unsupported config file extension for {}; use .toml, .yaml or .yml",
+            path.display()
+        )),
+    }?;
+
+    // Names of the device settings the file gives; it has been parsed above, so the
+    // generic parse cannot fail
+    let given: Vec<String> = match extension.as_str() {
+        "toml" => toml::from_str::<toml::Table>(&contents).ok()
+            .and_then(|t| t.get("devices")?.as_table().map(|d| d.keys().cloned().collect())),
+        _ => serde_yaml::from_str::<serde_yaml::Value>(&contents).ok()
+            .and_then(|v| v.get("devices")?.as_mapping().map(|d| d.keys().filter_map(|k| k.as_str().map(str::to_string)).collect())),
+    }
+    .unwrap_or_default();
+    config.devices.fill_from(devices, &given);
+    Ok(config)
+}
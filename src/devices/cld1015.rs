@@ -1,40 +1,102 @@
-#![allow(unused)]
-
-use std::ffi::CString;
-use std::io::{BufRead, BufReader, Write};
 use std::time::Duration;
-use visa_rs::prelude::*;
+use thiserror::Error;
 use tracing::{info, warn, error};
+use super::scpi::{RigTransport, ScpiError, ScpiSession, VisaTransport};
+use super::simulator::SharedRig;
+use super::transcript::Transcript;
 
-pub struct CLD1015 {
-    device: Option<Instrument>,
-    resource_string: String,
+#[derive(Error, Debug)]
+pub enum CLD1015Error {
+    #[error("VISA error: {0}")]
+    Visa(#[from] visa_rs::Error),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Parse error: {0}")]
+    ParseError(String),
+
+    #[error("Device not connected")]
+    NotConnected,
+
+    #[error("Refused by the driver safety checks: {0}")]
+    SafetyInterlock(String),
+}
+
+pub type Result<T> = std::result::Result<T, CLD1015Error>;
+
+impl From<ScpiError> for CLD1015Error {
+    fn from(error: ScpiError) -> Self {
+        match error {
+            ScpiError::Io(e) => CLD1015Error::IoError(e),
+            ScpiError::NotConnected => CLD1015Error::NotConnected,
+        }
+    }
 }
 
-// Helper function to convert IO errors to VISA errors
-fn io_to_vs_err(err: std::io::Error) -> visa_rs::Error {
-    visa_rs::io_to_vs_err(err)
+/// Maximum current the driver will ever command, regardless of the controller limit
+pub const MAX_SAFE_CURRENT_AMPS: f64 = 1.5;
+
+pub struct CLD1015 {
+    session: ScpiSession,
+    resource_string: String,
+    simulator: Option<SharedRig>,
+    timeout: Duration,
 }
 
 impl CLD1015 {
     pub fn new(resource_string: &str) -> Self {
         info!("Initializing CLD1015 with resource string: {}", resource_string);
         CLD1015 {
-            device: None,
+            session: CLD1015::session(),
             resource_string: resource_string.to_string(),
+            simulator: None,
+            timeout: Duration::from_secs(2),
+        }
+    }
+
+    /// Create a CLD1015 backed by a simulated rig instead of real hardware
+    pub fn simulated(rig: SharedRig) -> Self {
+        info!("Initializing simulated CLD1015");
+        let mut session = CLD1015::session();
+        session.open(Box::new(RigTransport::cld1015(rig.clone())));
+        CLD1015 {
+            session,
+            resource_string: "SIMULATED".to_string(),
+            simulator: Some(rig),
+            timeout: Duration::from_secs(2),
         }
     }
 
-    pub fn connect(&mut self) -> visa_rs::Result<String> {
+    fn session() -> ScpiSession {
+        // A small delay between a query and its response ensures the command is processed
+        ScpiSession::new("CLD1015").with_query_delay(Duration::from_millis(50))
+    }
+
+    /// Record every command and response in `transcript`, or stop recording with `None`
+    pub fn set_transcript(&mut self, transcript: Option<Transcript>) {
+        self.session.set_transcript(transcript);
+    }
+
+    /// VISA timeout used when the connection is opened
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn is_simulated(&self) -> bool {
+        self.simulator.is_some()
+    }
+
+    pub fn connect(&mut self) -> Result<String> {
         info!("Attempting to connect to CLD1015 at {}", self.resource_string);
-        let rm = DefaultRM::new()?;
-        let resource = CString::new(self.resource_string.clone()).unwrap();
-        let device = rm.open(
-            &resource.into(),
-            AccessMode::NO_LOCK,
-            Duration::from_secs(2),
-        )?;
-        self.device = Some(device);
+        if let Some(rig) = &self.simulator {
+            self.session.open(Box::new(RigTransport::cld1015(rig.clone())));
+            let id = self.query("*IDN?")?;
+            info!("Simulated CLD1015 connected. IDN: {}", id);
+            return Ok(id);
+        }
+        let transport = VisaTransport::open(&self.resource_string, self.timeout)?;
+        self.session.open(Box::new(transport));
         
         // Identify the device
         let id = self.query("*IDN?")?;
@@ -43,93 +105,127 @@ impl CLD1015 {
     }
 
     pub fn is_connected(&self) -> bool {
-        self.device.is_some()
+        self.session.is_open()
     }
 
-    pub fn write(&mut self, command: &str) -> visa_rs::Result<()> {
-        if let Some(device) = &mut self.device {
-            let command_with_newline = format!("{}\n", command);
-            info!("Sending command to CLD1015: {}", command);
-            device.write_all(command_with_newline.as_bytes()).map_err(io_to_vs_err)?;
-            Ok(())
-        } else {
-            error!("Attempted to write to CLD1015 but device is not connected");
-            Err(visa_rs::io_to_vs_err(std::io::Error::new(
-                std::io::ErrorKind::NotConnected,
-                "Device not connected",
-            )))
-        }
+    /// Close the VISA session; `connect` opens it again
+    pub fn disconnect(&mut self) {
+        info!("Disconnecting CLD1015");
+        self.session.close();
     }
 
-    pub fn read(&mut self) -> visa_rs::Result<String> {
-        if let Some(device) = &mut self.device {
-            let mut response = String::new();
-            let bytes_read = BufReader::new(device).read_line(&mut response).map_err(io_to_vs_err)?;
-            let trimmed = response.trim().to_string();
-            info!("Received response from CLD1015: {}", trimmed);
-            Ok(trimmed)
-        } else {
-            error!("Attempted to read from CLD1015 but device is not connected");
-            Err(visa_rs::io_to_vs_err(std::io::Error::new(
-                std::io::ErrorKind::NotConnected,
-                "Device not connected",
-            )))
-        }
+    pub fn write(&mut self, command: &str) -> Result<()> {
+        Ok(self.session.write(command)?)
     }
 
-    pub fn query(&mut self, command: &str) -> visa_rs::Result<String> {
-        self.write(command)?;
-        // Add a small delay to ensure command is processed
-        std::thread::sleep(Duration::from_millis(50));
-        self.read()
+    pub fn read(&mut self) -> Result<String> {
+        Ok(self.session.read()?)
     }
 
-    pub fn enable_tec(&mut self) -> visa_rs::Result<()> {
+    pub fn query(&mut self, command: &str) -> Result<String> {
+        Ok(self.session.query(command)?)
+    }
+
+    pub fn enable_tec(&mut self) -> Result<()> {
         info!("Enabling TEC");
         self.write("OUTPut2:STATe ON")
     }
     
-    pub fn get_tec_state(&mut self) -> visa_rs::Result<bool> {
+    pub fn disable_tec(&mut self) -> Result<()> {
+        info!("Disabling TEC");
+        self.write("OUTPut2:STATe OFF")
+    }
+
+    pub fn get_tec_state(&mut self) -> Result<bool> {
         let response = self.query("OUTPut2:STATe?")?;
         Ok(response.eq_ignore_ascii_case("ON") || response == "1")
     }
 
-    pub fn set_current_mode(&mut self) -> visa_rs::Result<()> {
+    /// Set the TEC temperature setpoint in °C
+    pub fn set_tec_setpoint(&mut self, temperature_c: f64) -> Result<()> {
+        info!("Setting TEC setpoint to {} °C", temperature_c);
+        self.write(&format!("SOURce2:TEMPerature:SPOint {}", temperature_c))
+    }
+
+    /// Query the TEC temperature setpoint in °C
+    pub fn get_tec_setpoint(&mut self) -> Result<f64> {
+        let response = self.query("SOURce2:TEMPerature:SPOint?")?;
+        info!("Queried TEC setpoint: {} °C", response);
+        response.parse::<f64>().map_err(|_| CLD1015Error::ParseError(format!("Failed to parse TEC setpoint value: {}", response)))
+    }
+
+    /// Measure the LD forward voltage in V
+    pub fn measure_voltage(&mut self) -> Result<f64> {
+        let response = self.query("MEASure:VOLTage?")?;
+        info!("Measured voltage: {} V", response);
+        response.parse::<f64>().map_err(|_| CLD1015Error::ParseError(format!("Failed to parse measured voltage value: {}", response)))
+    }
+
+    /// Measure the temperature of the laser mount in °C
+    pub fn measure_temperature(&mut self) -> Result<f64> {
+        let response = self.query("MEASure:TEMPerature?")?;
+        info!("Measured temperature: {} °C", response);
+        response.parse::<f64>().map_err(|_| CLD1015Error::ParseError(format!("Failed to parse temperature value: {}", response)))
+    }
+
+    pub fn set_current_mode(&mut self) -> Result<()> {
         self.write("SOURce:FUNCtion:MODE CURRent")
     }
     
-    pub fn set_current(&mut self, current_amps: f64) -> visa_rs::Result<()> {
-        const MAX_SAFE_CURRENT_AMPS: f64 = 1.5;
+    /// Operating mode of the controller, "CURR" (constant current) or "POW" (constant power)
+    pub fn get_operating_mode(&mut self) -> Result<String> {
+        let response = self.query("SOURce:FUNCtion:MODE?")?;
+        info!("Queried operating mode: {}", response);
+        Ok(response)
+    }
+
+    /// Switch the controller to constant-power mode (regulated on the monitor photodiode)
+    pub fn set_power_mode(&mut self) -> Result<()> {
+        self.write("SOURce:FUNCtion:MODE POWer")
+    }
+
+    pub fn set_power(&mut self, power_watts: f64) -> Result<()> {
+        info!("Setting optical power setpoint to {:.6} W", power_watts);
+        self.write(&format!("SOURce:POWer:LEVel:IMMediate:AMPLitude {}", power_watts))
+    }
+
+    /// Measure the LD current actually delivered by the controller
+    pub fn measure_current(&mut self) -> Result<f64> {
+        let response = self.query("MEASure:CURRent?")?;
+        info!("Measured current: {} A", response);
+        response.parse::<f64>().map_err(|_| CLD1015Error::ParseError(format!("Failed to parse measured current value: {}", response)))
+    }
+
+    pub fn set_current(&mut self, current_amps: f64) -> Result<()> {
         if current_amps > MAX_SAFE_CURRENT_AMPS {
             warn!("Attempted to set current above safe limit: {} A", current_amps);
-            return Err(visa_rs::io_to_vs_err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                format!("Requested current {} A exceeds the 1.5 A safety limit", current_amps),
-            )));
+            return Err(CLD1015Error::SafetyInterlock(format!(
+                "Requested current {} A exceeds the 1.5 A safety limit", current_amps)));
         }
         info!("Setting current to {:.3} A", current_amps);
         self.write(&format!("SOURce:CURRent:LEVel:IMMediate:AMPLitude {}", current_amps))
     }
 
-    pub fn get_current(&mut self) -> visa_rs::Result<f64> {
+    pub fn get_current(&mut self) -> Result<f64> {
         let response = self.query("SOURce:CURRent:LEVel:IMMediate:AMPLitude?")?;
         info!("Queried current: {} A", response);
-        response.parse::<f64>().map_err(|_| visa_rs::io_to_vs_err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Failed to parse current value",
-        )))
+        response.parse::<f64>().map_err(|_| CLD1015Error::ParseError(format!("Failed to parse current value: {}", response)))
+    }
+
+    /// Query the LD current limit configured on the controller
+    pub fn get_current_limit(&mut self) -> Result<f64> {
+        let response = self.query("SOURce:CURRent:LIMit:AMPLitude?")?;
+        info!("Queried current limit: {} A", response);
+        response.parse::<f64>().map_err(|_| CLD1015Error::ParseError(format!("Failed to parse current limit value: {}", response)))
     }
 
-    pub fn set_laser_output(&mut self, enabled: bool) -> visa_rs::Result<()> {
+    pub fn set_laser_output(&mut self, enabled: bool) -> Result<()> {
         if enabled {
             // Safety check: ensure TEC is ON before enabling laser
             let tec_on = self.get_tec_state()?;
             if !tec_on {
                 error!("Attempt to enable laser while TEC is OFF");
-                return Err(visa_rs::io_to_vs_err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Cannot enable laser: TEC is OFF",
-                )));
+                return Err(CLD1015Error::SafetyInterlock("Cannot enable laser: TEC is OFF".to_string()));
             }
             info!("Enabling laser output");
         } else {
@@ -140,18 +236,18 @@ impl CLD1015 {
         self.write(&format!("OUTPut:STATe {}", state))
     }
 
-    pub fn get_laser_output(&mut self) -> visa_rs::Result<bool> {
+    pub fn get_laser_output(&mut self) -> Result<bool> {
         let response = self.query("OUTPut:STATe?")?;
         Ok(response.eq_ignore_ascii_case("ON") || response == "1")
     }
 
-    pub fn get_error(&mut self) -> visa_rs::Result<String> {
+    pub fn get_error(&mut self) -> Result<String> {
         let response = self.query("SYST:ERR?")?;
         info!("Queried CLD1015 error queue: {}", response);
         Ok(response)
     }
 
-    pub fn clear_error_queue(&mut self) -> visa_rs::Result<Vec<String>> {
+    pub fn clear_error_queue(&mut self) -> Result<Vec<String>> {
         let mut errors = Vec::new();
         loop {
             let response = self.query("SYST:ERR?")?;
@@ -164,16 +260,13 @@ impl CLD1015 {
         Ok(errors)
     }    
     
-    pub fn reset(&mut self) -> visa_rs::Result<()> {
+    pub fn reset(&mut self) -> Result<()> {
         info!("Resetting CLD1015 to default state");
         
         // First, ensure device is connected
         if !self.is_connected() {
             error!("Cannot reset CLD1015: device not connected");
-            return Err(visa_rs::io_to_vs_err(std::io::Error::new(
-                std::io::ErrorKind::NotConnected,
-                "Device not connected",
-            )));
+            return Err(CLD1015Error::NotConnected);
         }
         
         // Send the IEEE 488.2 *RST command to reset the device to defaults
@@ -185,10 +278,7 @@ impl CLD1015 {
         // Verify reset was successful by checking device status
         let status = self.query("*OPC?")?;
         if status != "1" {
-            return Err(visa_rs::io_to_vs_err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Reset operation failed, unexpected response: {}", status),
-            )));
+            return Err(CLD1015Error::ParseError(format!("Reset operation failed, unexpected response: {}", status)));
         }
         
         // Clear error queue to ensure we're starting with a clean slate
@@ -197,4 +287,43 @@ impl CLD1015 {
         info!("CLD1015 reset completed successfully");
         Ok(())
     }
+    /// Send a command typed by a user, returning the response of queries. Current setpoints
+    /// and turning the output on are subject to the same checks as `set_current` and
+    /// `set_laser_output`: the current safety limit and the TEC interlock. Compound
+    /// commands are refused, as only a single command can be checked.
+    pub fn send_raw(&mut self, command: &str) -> Result<Option<String>> {
+        let command = command.trim();
+        if command.contains([';', '\n', '\r']) {
+            warn!("Refused raw compound command: {:?}", command);
+            return Err(CLD1015Error::SafetyInterlock(
+                "Compound commands are not accepted, send one command at a time".to_string()));
+        }
+        let (header, argument) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let argument = argument.trim();
+        if header.ends_with('?') {
+            return self.query(command).map(Some);
+        }
+
+        // Long and short SCPI forms, in any case, with the optional SOURce root
+        let nodes: Vec<String> = header.trim_start_matches(':').split(':').map(|n| n.to_uppercase()).collect();
+        let nodes: Vec<&str> = nodes.iter().map(String::as_str).skip_while(|n| n.starts_with("SOUR")).collect();
+        let is_current = nodes.first().is_some_and(|n| n.starts_with("CURR"));
+        let is_output = nodes.first().is_some_and(|n| n.starts_with("OUTP"))
+            && nodes.get(1).is_none_or(|n| n.starts_with("STAT"));
+
+        if is_current {
+            // MAXimum and DEFault are resolved by the controller, so only numbers can be checked
+            let amps = argument.parse::<f64>().map_err(|_| CLD1015Error::ParseError(
+                format!("Current argument {:?} is not a number in A", argument)))?;
+            if amps > MAX_SAFE_CURRENT_AMPS {
+                warn!("Refused raw current command above safe limit: {}", command);
+                return Err(CLD1015Error::SafetyInterlock(format!(
+                    "Requested current {} A exceeds the {} A safety limit", amps, MAX_SAFE_CURRENT_AMPS)));
+            }
+        }
+        if is_output && (argument.eq_ignore_ascii_case("ON") || argument == "1") {
+            return self.set_laser_output(true).map(|_| None);
+        }
+        self.write(command).map(|_| None)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,37 @@
+use std::ffi::CString;
+use std::io::{BufRead, BufReader, Write};
+use std::time::Duration;
+use visa_rs::prelude::*;
+use tracing::{info, warn};
+
+/// VISA resource expression matching every instrument
+const INSTRUMENT_EXPRESSION: &str = "?*INSTR";
+
+/// Resource strings of every instrument VISA can see, e.g. USB0::4883::32847::M01053290::0::INSTR
+pub fn visa_resources() -> visa_rs::Result<Vec<String>> {
+    let rm = DefaultRM::new()?;
+    let expression = CString::new(INSTRUMENT_EXPRESSION).unwrap().into();
+    let mut list = rm.find_res_list(&expression)?;
+    let mut resources = Vec::new();
+    while let Some(resource) = list.find_next()? {
+        resources.push(resource.to_string());
+    }
+    info!("VISA found {} instrument resource(s)", resources.len());
+    Ok(resources)
+}
+
+/// Identification of the instrument at `resource`, from *IDN?. Instruments that do not
+/// answer within `timeout` are reported as an error.
+pub fn visa_identify(resource: &str, timeout: Duration) -> visa_rs::Result<String> {
+    let rm = DefaultRM::new()?;
+    let name = CString::new(resource).unwrap();
+    let mut device = rm.open(&name.into(), AccessMode::NO_LOCK, timeout)?;
+    device.write_all(b"*IDN?\n").map_err(visa_rs::io_to_vs_err)?;
+    let mut response = String::new();
+    BufReader::new(&mut device).read_line(&mut response).map_err(visa_rs::io_to_vs_err)?;
+    let idn = response.trim().to_string();
+    if idn.is_empty() {
+        warn!("{} answered *IDN? with an empty line", resource);
+    }
+    Ok(idn)
+}
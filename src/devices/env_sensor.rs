@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::time::Duration;
+use tracing::info;
+
+/// Connection settings for a USB (virtual COM port) temperature/humidity sensor.
+///
+/// The sensor is sent `query` followed by a newline and must answer with one line
+/// of the form `<temperature °C>,<relative humidity %>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnvSensorConfig {
+    pub port: String,   // Serial port name, e.g. "COM3" or "/dev/ttyACM0"
+    pub baud_rate: u32,
+    #[serde(default = "default_query")]
+    pub query: String,
+}
+
+fn default_query() -> String {
+    "READ?".to_string()
+}
+
+/// A single ambient reading
+#[derive(Debug, Clone, Copy)]
+pub struct EnvReading {
+    pub temperature_c: f64,
+    pub humidity_percent: f64,
+}
+
+pub struct EnvSensor {
+    config: EnvSensorConfig,
+}
+
+impl EnvSensor {
+    pub fn new(config: EnvSensorConfig) -> Self {
+        info!("Initializing environment sensor on {}", config.port);
+        EnvSensor { config }
+    }
+
+    pub fn read(&mut self) -> Result<EnvReading, String> {
+        let mut port = serialport::new(&self.config.port, self.config.baud_rate)
+            .timeout(Duration::from_secs(2))
+            .open()
+            .map_err(|e| format!("Failed to open environment sensor on {}: {}", self.config.port, e))?;
+
+        port.write_all(format!("{}\n", self.config.query).as_bytes())
+            .map_err(|e| format!("Failed to query environment sensor: {}", e))?;
+
+        let mut line = String::new();
+        BufReader::new(port).read_line(&mut line)
+            .map_err(|e| format!("Failed to read environment sensor: {}", e))?;
+        let line = line.trim();
+        info!("Received response from environment sensor: {}", line);
+
+        let values: Vec<f64> = line
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("Unexpected environment sensor response: {}", line))?;
+        match values.as_slice() {
+            [temperature_c, humidity_percent] => Ok(EnvReading {
+                temperature_c: *temperature_c,
+                humidity_percent: *humidity_percent,
+            }),
+            _ => Err(format!("Unexpected environment sensor response: {}", line)),
+        }
+    }
+}
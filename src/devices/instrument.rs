@@ -0,0 +1,223 @@
+use super::transcript::Transcript;
+use super::{cld1015, mpm210h, CLD1015, MPM210H};
+
+/// Laser diode current source the experiments drive: the CLD1015, or another controller
+/// or SMU with the same capabilities. Currents are in A, temperatures in °C.
+pub trait CurrentSource {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Open the connection, returning the identification of the instrument
+    fn connect(&mut self) -> Result<String, Self::Error>;
+    fn is_connected(&self) -> bool;
+    /// Close the connection; `connect` opens it again
+    fn disconnect(&mut self);
+    /// Identification of an instrument that is already connected
+    fn identify(&mut self) -> Result<String, Self::Error>;
+    /// Record the traffic with the instrument; instruments without a text protocol ignore it
+    fn set_transcript(&mut self, _transcript: Option<Transcript>) {}
+    /// Bring the instrument back to its default settings
+    fn reset(&mut self) -> Result<(), Self::Error>;
+    /// Drain the error queue, returning the errors reported
+    fn clear_error_queue(&mut self) -> Result<Vec<String>, Self::Error>;
+
+    /// Switch to constant-current operation
+    fn set_current_mode(&mut self) -> Result<(), Self::Error>;
+    fn set_current(&mut self, current_amps: f64) -> Result<(), Self::Error>;
+    fn measure_current(&mut self) -> Result<f64, Self::Error>;
+    /// Highest current the instrument will deliver
+    fn get_current_limit(&mut self) -> Result<f64, Self::Error>;
+    /// Forward voltage of the laser diode in V
+    fn measure_voltage(&mut self) -> Result<f64, Self::Error>;
+    fn set_laser_output(&mut self, enabled: bool) -> Result<(), Self::Error>;
+    fn get_laser_output(&mut self) -> Result<bool, Self::Error>;
+
+    fn enable_tec(&mut self) -> Result<(), Self::Error>;
+    fn disable_tec(&mut self) -> Result<(), Self::Error>;
+    fn get_tec_state(&mut self) -> Result<bool, Self::Error>;
+    fn set_tec_setpoint(&mut self, temperature_c: f64) -> Result<(), Self::Error>;
+    fn get_tec_setpoint(&mut self) -> Result<f64, Self::Error>;
+    /// Temperature of the laser as measured by the TEC sensor
+    fn measure_temperature(&mut self) -> Result<f64, Self::Error>;
+}
+
+/// Optical power meter the experiments read: the MPM-210H, or another meter with the
+/// same capabilities. Channels are addressed by module and port; a single-channel meter
+/// accepts module 0, port 1.
+pub trait OpticalPowerMeter {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Open the connection, returning the identification of the instrument. Also used to
+    /// reconnect after a timed out query.
+    fn connect(&mut self) -> Result<String, Self::Error>;
+    fn is_connected(&self) -> bool;
+    /// Close the connection; `connect` opens it again
+    fn disconnect(&mut self);
+    /// Identification of an instrument that is already connected
+    fn identify(&mut self) -> Result<String, Self::Error>;
+    /// Record the traffic with the instrument; instruments without a text protocol ignore it
+    fn set_transcript(&mut self, _transcript: Option<Transcript>) {}
+    /// Drain the error queue, returning the errors reported
+    fn clear_error_queue(&mut self) -> Result<Vec<String>, Self::Error>;
+    /// Modules the meter recognizes, by slot
+    fn recognized_module_slots(&mut self) -> Result<Vec<u8>, Self::Error>;
+
+    /// Start zeroing the electrical offsets; the caller waits for it to complete
+    fn perform_zeroing(&mut self) -> Result<(), Self::Error>;
+    /// Read at a fixed calibration wavelength with a manual range
+    fn set_fixed_wavelength_mode(&mut self) -> Result<(), Self::Error>;
+    fn set_average_time(&mut self, averaging_time_ms: f64) -> Result<(), Self::Error>;
+    /// Report readings in dBm, or in mW when false
+    fn set_unit_dbm(&mut self, dbm: bool) -> Result<(), Self::Error>;
+    /// Calibration wavelength in nm
+    fn set_wavelength(&mut self, wavelength_nm: u32) -> Result<(), Self::Error>;
+
+    /// Power at one port as reported by the instrument, kept as is in the metadata
+    fn read_power_from_port(&mut self, module: u8, port: u8) -> Result<String, Self::Error>;
+    /// Power at one port in the configured unit
+    fn read_power_value(&mut self, module: u8, port: u8) -> Result<f64, Self::Error>;
+}
+
+impl CurrentSource for CLD1015 {
+    type Error = cld1015::CLD1015Error;
+
+    fn connect(&mut self) -> cld1015::Result<String> {
+        CLD1015::connect(self)
+    }
+
+    fn is_connected(&self) -> bool {
+        CLD1015::is_connected(self)
+    }
+
+    fn disconnect(&mut self) {
+        CLD1015::disconnect(self)
+    }
+
+    fn identify(&mut self) -> cld1015::Result<String> {
+        self.query("*IDN?")
+    }
+
+    fn set_transcript(&mut self, transcript: Option<Transcript>) {
+        CLD1015::set_transcript(self, transcript)
+    }
+
+    fn reset(&mut self) -> cld1015::Result<()> {
+        CLD1015::reset(self)
+    }
+
+    fn clear_error_queue(&mut self) -> cld1015::Result<Vec<String>> {
+        CLD1015::clear_error_queue(self)
+    }
+
+    fn set_current_mode(&mut self) -> cld1015::Result<()> {
+        CLD1015::set_current_mode(self)
+    }
+
+    fn set_current(&mut self, current_amps: f64) -> cld1015::Result<()> {
+        CLD1015::set_current(self, current_amps)
+    }
+
+    fn measure_current(&mut self) -> cld1015::Result<f64> {
+        CLD1015::measure_current(self)
+    }
+
+    fn get_current_limit(&mut self) -> cld1015::Result<f64> {
+        CLD1015::get_current_limit(self)
+    }
+
+    fn measure_voltage(&mut self) -> cld1015::Result<f64> {
+        CLD1015::measure_voltage(self)
+    }
+
+    fn set_laser_output(&mut self, enabled: bool) -> cld1015::Result<()> {
+        CLD1015::set_laser_output(self, enabled)
+    }
+
+    fn get_laser_output(&mut self) -> cld1015::Result<bool> {
+        CLD1015::get_laser_output(self)
+    }
+
+    fn enable_tec(&mut self) -> cld1015::Result<()> {
+        CLD1015::enable_tec(self)
+    }
+
+    fn disable_tec(&mut self) -> cld1015::Result<()> {
+        CLD1015::disable_tec(self)
+    }
+
+    fn get_tec_state(&mut self) -> cld1015::Result<bool> {
+        CLD1015::get_tec_state(self)
+    }
+
+    fn set_tec_setpoint(&mut self, temperature_c: f64) -> cld1015::Result<()> {
+        CLD1015::set_tec_setpoint(self, temperature_c)
+    }
+
+    fn get_tec_setpoint(&mut self) -> cld1015::Result<f64> {
+        CLD1015::get_tec_setpoint(self)
+    }
+
+    fn measure_temperature(&mut self) -> cld1015::Result<f64> {
+        CLD1015::measure_temperature(self)
+    }
+}
+
+impl OpticalPowerMeter for MPM210H {
+    type Error = mpm210h::MPM210HError;
+
+    fn connect(&mut self) -> mpm210h::Result<String> {
+        MPM210H::connect(self)
+    }
+
+    fn is_connected(&self) -> bool {
+        MPM210H::is_connected(self)
+    }
+
+    fn disconnect(&mut self) {
+        MPM210H::disconnect(self)
+    }
+
+    fn identify(&mut self) -> mpm210h::Result<String> {
+        self.query("*IDN?")
+    }
+
+    fn set_transcript(&mut self, transcript: Option<Transcript>) {
+        MPM210H::set_transcript(self, transcript)
+    }
+
+    fn clear_error_queue(&mut self) -> mpm210h::Result<Vec<String>> {
+        MPM210H::clear_error_queue(self)
+    }
+
+    fn recognized_module_slots(&mut self) -> mpm210h::Result<Vec<u8>> {
+        MPM210H::recognized_module_slots(self)
+    }
+
+    fn perform_zeroing(&mut self) -> mpm210h::Result<()> {
+        MPM210H::perform_zeroing(self)
+    }
+
+    fn set_fixed_wavelength_mode(&mut self) -> mpm210h::Result<()> {
+        // CONST1: fixed wavelength, manual range
+        self.set_measurement_mode("CONST1")
+    }
+
+    fn set_average_time(&mut self, averaging_time_ms: f64) -> mpm210h::Result<()> {
+        MPM210H::set_average_time(self, averaging_time_ms)
+    }
+
+    fn set_unit_dbm(&mut self, dbm: bool) -> mpm210h::Result<()> {
+        self.set_unit(if dbm { 0 } else { 1 })
+    }
+
+    fn set_wavelength(&mut self, wavelength_nm: u32) -> mpm210h::Result<()> {
+        MPM210H::set_wavelength(self, wavelength_nm)
+    }
+
+    fn read_power_from_port(&mut self, module: u8, port: u8) -> mpm210h::Result<String> {
+        MPM210H::read_power_from_port(self, module, port)
+    }
+
+    fn read_power_value(&mut self, module: u8, port: u8) -> mpm210h::Result<f64> {
+        MPM210H::read_power_value(self, module, port)
+    }
+}
@@ -1,5 +1,18 @@
 pub mod cld1015;
+pub mod discovery;
+pub mod env_sensor;
+pub mod instrument;
 pub mod mpm210h;
+pub mod registry;
+pub mod scpi;
+pub mod simulator;
+pub mod transcript;
 
 pub use cld1015::CLD1015;
-pub use mpm210h::MPM210H;
\ No newline at end of file
+pub use env_sensor::EnvSensor;
+pub use instrument::{CurrentSource, OpticalPowerMeter};
+pub use mpm210h::MPM210H;
+pub use registry::DeviceRegistry;
+pub use scpi::{AsyncScpiTransport, AsyncTcpTransport, BlockingTransport, ScpiSession, ScpiTransport};
+pub use simulator::{simulated_bench, Faults, LaserModel, SharedRig, SimulatedLink, SimulatedRig};
+pub use transcript::Transcript;
\ No newline at end of file
@@ -1,10 +1,11 @@
-#![allow(unused)]
-
-use std::io::{Read, Write};
-use std::net::{TcpStream, SocketAddr};
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
 use std::time::Duration;
 use thiserror::Error;
-use tracing::{info, warn, error};
+use tracing::info;
+use super::scpi::{runtime, AsyncTcpTransport, BlockingTransport, RigTransport, ScpiError, ScpiSession};
+use super::simulator::SharedRig;
+use super::transcript::Transcript;
 
 #[derive(Error, Debug)]
 pub enum MPM210HError {
@@ -16,14 +17,84 @@ pub enum MPM210HError {
     
     #[error("Device not connected")]
     NotConnected,
+
+    #[error("Invalid power reading: {0:?}")]
+    InvalidReading(String),
 }
 
 pub type Result<T> = std::result::Result<T, MPM210HError>;
 
+impl From<ScpiError> for MPM210HError {
+    fn from(error: ScpiError) -> Self {
+        match error {
+            ScpiError::Io(e) => MPM210HError::IoError(e),
+            ScpiError::NotConnected => MPM210HError::NotConnected,
+        }
+    }
+}
+
+/// Parse a power value as returned by READ?, rejecting anything that is not a finite number
+pub fn parse_power(reading: &str) -> Result<f64> {
+    match reading.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(MPM210HError::InvalidReading(reading.to_string())),
+    }
+}
+
+/// Reject port numbers outside 1-4 before anything is sent
+pub fn check_port(port: u8) -> Result<()> {
+    if port < 1 || port > PORTS_PER_MODULE {
+        return Err(MPM210HError::ParseError(format!("Invalid port number: {}. Port must be between 1 and 4.", port)));
+    }
+    Ok(())
+}
+
+/// Reading of one port in a READ? response, which holds the comma-separated values of
+/// every port of the module
+pub fn port_reading(response: &str, port: u8) -> Result<String> {
+    check_port(port)?;
+    let values: Vec<&str> = response.split(',').collect();
+
+    // Port index is 0-based in the array, but 1-based in the command
+    let port_index = (port - 1) as usize;
+    if port_index >= values.len() {
+        return Err(MPM210HError::ParseError(format!(
+            "Response doesn't contain enough values. Expected at least {} values, got {}",
+            port_index + 1,
+            values.len()
+        )));
+    }
+    Ok(values[port_index].trim().to_string())
+}
+
+/// Module slots flagged as recognized in an IDIS? response
+pub fn module_slots(response: &str) -> Vec<u8> {
+    response
+        .split(',')
+        .enumerate()
+        .filter(|(_, flag)| flag.trim() == "1")
+        .map(|(slot, _)| slot as u8)
+        .collect()
+}
+
+/// Number of optical ports on each MPM-210H module
+pub const PORTS_PER_MODULE: u8 = 4;
+
+/// Calibration wavelength range accepted by the WAV command in nm
+pub const WAVELENGTH_RANGE_NM: RangeInclusive<u32> = 800..=1700;
+
+/// Averaging time range accepted by the AVG command in ms
+pub const AVERAGE_TIME_RANGE_MS: RangeInclusive<f64> = 0.01..=10000.0;
+
+/// Maximum number of points the MPM-210H can hold in its logging memory
+pub const MAX_LOGGING_POINTS: usize = 1_000_000;
+
 pub struct MPM210H {
-    connection: Option<TcpStream>,
+    session: ScpiSession,
     address: String,
     port: u16,
+    simulator: Option<SharedRig>,
+    timeout: Duration,
 }
 
 impl MPM210H {
@@ -31,24 +102,59 @@ impl MPM210H {
         let address = format!("{}:{}", ip_address, port);
         info!("Initializing MPM210H with address: {}", address);
         MPM210H {
-            connection: None,
+            // MPM210H requires a small delay after each command
+            session: ScpiSession::new("MPM210H").with_write_delay(Duration::from_millis(10)),
             address: ip_address.to_string(),
             port,
+            simulator: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Create an MPM210H backed by a simulated rig instead of real hardware
+    pub fn simulated(rig: SharedRig) -> Self {
+        info!("Initializing simulated MPM210H");
+        let mut session = ScpiSession::new("MPM210H");
+        session.open(Box::new(RigTransport::mpm210h(rig.clone())));
+        MPM210H {
+            session,
+            address: "SIMULATED".to_string(),
+            port: 0,
+            simulator: Some(rig),
+            timeout: Duration::from_secs(5),
         }
     }
 
+    /// Record every command and response in `transcript`, or stop recording with `None`
+    pub fn set_transcript(&mut self, transcript: Option<Transcript>) {
+        self.session.set_transcript(transcript);
+    }
+
+    /// Connection, read and write timeout used when the connection is opened
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn is_simulated(&self) -> bool {
+        self.simulator.is_some()
+    }
+
     pub fn connect(&mut self) -> Result<String> {
         let socket_addr = format!("{}:{}", self.address, self.port);
         info!("Attempting to connect to MPM210H at {}", socket_addr);
+        if let Some(rig) = &self.simulator {
+            self.session.open(Box::new(RigTransport::mpm210h(rig.clone())));
+            let id = self.query("*IDN?")?;
+            info!("Simulated MPM210H connected. IDN: {}", id);
+            return Ok(id);
+        }
         
         let socket_addr: SocketAddr = socket_addr.parse()
             .map_err(|e: std::net::AddrParseError| MPM210HError::ParseError(e.to_string()))?;
         
-        let stream = TcpStream::connect_timeout(&socket_addr, Duration::from_secs(5))?;
-        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
-        stream.set_write_timeout(Some(Duration::from_secs(5)))?;
-        
-        self.connection = Some(stream);
+        // The socket is driven by the shared tokio runtime
+        let transport = runtime().block_on(AsyncTcpTransport::connect(socket_addr, self.timeout))?;
+        self.session.open(Box::new(BlockingTransport::new(transport)));
         
         // Return the device identification
         let id = self.query("*IDN?")?;
@@ -57,57 +163,39 @@ impl MPM210H {
     }
     
     pub fn is_connected(&self) -> bool {
-        self.connection.is_some()
+        self.session.is_open()
+    }
+
+    /// Close the socket; `connect` opens it again
+    pub fn disconnect(&mut self) {
+        info!("Disconnecting MPM210H");
+        self.session.close();
     }
 
     pub fn send_command(&mut self, command: &str) -> Result<()> {
-        if let Some(stream) = &mut self.connection {
-            let cmd = format!("{}\n", command);
-            info!("Sending command to MPM210H: {}", command);
-            stream.write_all(cmd.as_bytes())?;
-            stream.flush()?;
-            
-            // MPM210H requires a small delay after each command
-            std::thread::sleep(Duration::from_millis(10));
-            Ok(())
-        } else {
-            error!("Attempted to send command but MPM210H is not connected");
-            Err(MPM210HError::NotConnected)
-        }
+        Ok(self.session.write(command)?)
     }
 
     pub fn read_response(&mut self) -> Result<String> {
-        if let Some(stream) = &mut self.connection {
-            let mut buf = [0_u8; 1024];
-            let mut result = String::new();
-            
-            // MPM210H responses can be large, need to read until terminator or timeout
-            let n = stream.read(&mut buf)?;
-            if n == 0 {
-                return Err(MPM210HError::IoError(std::io::Error::new(
-                    std::io::ErrorKind::ConnectionAborted,
-                    "Connection closed by remote",
-                )));
-            }
-            
-            let response = String::from_utf8_lossy(&buf[..n]).trim().to_string();
-            info!("Received response from MPM210H: {}", response);
-            Ok(response)
-        } else {
-            error!("Attempted to read from MPM210H but device is not connected");
-            Err(MPM210HError::NotConnected)
-        }
+        Ok(self.session.read()?)
     }
 
     pub fn query(&mut self, command: &str) -> Result<String> {
-        self.send_command(command)?;
-        self.read_response()
+        Ok(self.session.query(command)?)
     }
 
     pub fn get_recognized_modules(&mut self) -> Result<String> {
         self.query("IDIS?")
     }
 
+    /// Module slots in which a module is recognized, parsed from the per-slot IDIS? flags
+    pub fn recognized_module_slots(&mut self) -> Result<Vec<u8>> {
+        let response = self.get_recognized_modules()?;
+        let slots = module_slots(&response);
+        info!("MPM210H recognized module slots: {:?}", slots);
+        Ok(slots)
+    }
+
     pub fn perform_zeroing(&mut self) -> Result<()> {
         info!("Performing zeroing operation to remove electrical offsets");
         if !self.is_connected() {
@@ -125,35 +213,23 @@ impl MPM210H {
     
     /// Read the optical power from a specific module and port
     pub fn read_power_from_port(&mut self, module: u8, port: u8) -> Result<String> {
-        if port < 1 || port > 4 {
-            return Err(MPM210HError::ParseError(format!("Invalid port number: {}. Port must be between 1 and 4.", port)));
-        }
-        
+        check_port(port)?;
         info!("Reading power from module {}, port {}", module, port);
         
         // The READ? command returns comma-separated values for all ports in the module
         let response = self.query(&format!("READ? {}", module))?;
-        
-        // Split response by commas and extract the port value
-        let values: Vec<&str> = response.split(',').collect();
-        
-        // Port index is 0-based in the array, but 1-based in the command 
-        let port_index = (port - 1) as usize;
-        
-        if port_index >= values.len() {
-            return Err(MPM210HError::ParseError(format!(
-                "Response doesn't contain enough values. Expected at least {} values, got {}",
-                port_index + 1,
-                values.len()
-            )));
-        }
-        
-        let power = values[port_index].trim().to_string();
+        let power = port_reading(&response, port)?;
         info!("Power at module {}, port {}: {}", module, port, power);
         
         Ok(power)
     }
 
+    /// Read the optical power from a specific module and port as a number, in the configured unit
+    pub fn read_power_value(&mut self, module: u8, port: u8) -> Result<f64> {
+        let reading = self.read_power_from_port(module, port)?;
+        parse_power(&reading)
+    }
+
     pub fn get_wavelength(&mut self) -> Result<String> {
         self.query("WAV?")
     }
@@ -192,6 +268,23 @@ impl MPM210H {
         self.send_command(&format!("AVG {}", avg_ms))
     }
     
+    /// Measurement mode set with WMOD, e.g. "CONST1"
+    pub fn get_measurement_mode(&mut self) -> Result<String> {
+        self.query("WMOD?")
+    }
+
+    /// Averaging time in ms
+    pub fn get_average_time(&mut self) -> Result<f64> {
+        let response = self.query("AVG?")?;
+        response.trim().parse::<f64>().map_err(|_| MPM210HError::ParseError(format!("Invalid averaging time: {}", response)))
+    }
+
+    /// Measurement unit: 0 for dBm, 1 for mW
+    pub fn get_unit(&mut self) -> Result<u8> {
+        let response = self.query("UNIT?")?;
+        response.trim().parse::<u8>().map_err(|_| MPM210HError::ParseError(format!("Invalid unit: {}", response)))
+    }
+
     // Set measurement unit (dBm or mW)
     pub fn set_unit(&mut self, unit: u8) -> Result<()> {
         if unit > 1 {
@@ -199,4 +292,82 @@ impl MPM210H {
         }
         self.send_command(&format!("UNIT {}", unit))
     }
-}
\ No newline at end of file
+
+    /// Number of points to record in logging mode
+    pub fn set_logging_points(&mut self, points: usize) -> Result<()> {
+        if points == 0 || points > MAX_LOGGING_POINTS {
+            return Err(MPM210HError::ParseError(format!(
+                "Logging points must be between 1 and {}, got {}", MAX_LOGGING_POINTS, points)));
+        }
+        self.send_command(&format!("LOGN {}", points))
+    }
+
+    /// Select the trigger source for logging: internal timer (one sample per averaging time) or external input
+    pub fn set_trigger_input(&mut self, external: bool) -> Result<()> {
+        self.send_command(&format!("TRIG {}", if external { 1 } else { 0 }))
+    }
+
+    /// Start logging
+    pub fn start_measurement(&mut self) -> Result<()> {
+        info!("Starting MPM210H logging measurement");
+        self.send_command("MEAS")
+    }
+
+    /// Stop logging before all points have been recorded
+    pub fn stop_measurement(&mut self) -> Result<()> {
+        info!("Stopping MPM210H logging measurement");
+        self.send_command("STOP")
+    }
+
+    /// Logging status: whether the measurement is complete, and the number of points logged so far
+    pub fn get_logging_status(&mut self) -> Result<(bool, usize)> {
+        let response = self.query("STAT?")?;
+        let (status, count) = response.split_once(',').ok_or_else(|| MPM210HError::ParseError(
+            format!("Unexpected STAT? response: {}", response)))?;
+        let count = count.trim().parse::<usize>().map_err(|_| MPM210HError::ParseError(
+            format!("Unexpected STAT? response: {}", response)))?;
+        Ok((status.trim() == "1", count))
+    }
+
+    /// Retrieve the logged data of one port. The instrument sends a binary block of 4-byte
+    /// little-endian floats, one per logged point, in the configured unit.
+    pub fn read_logged_data(&mut self, module: u8, port: u8, points: usize) -> Result<Vec<f64>> {
+        check_port(port)?;
+        info!("Reading {} logged points from module {}, port {}", points, module, port);
+
+        self.send_command(&format!("LOGG? {},{}", module, port))?;
+        let bytes = self.session.read_block()?;
+        if bytes.len() != points * 4 {
+            return Err(MPM210HError::ParseError(format!(
+                "Logged data of {} bytes does not hold {} points of 4 bytes", bytes.len(), points)));
+        }
+        let values = bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as f64)
+            .collect();
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn port_reading_picks_the_port() {
+        assert_eq!(port_reading("-10.5, -20.25,-30,-40", 2).unwrap(), "-20.25");
+        assert_eq!(port_reading("-10.5,-20.25,-30,-40", 4).unwrap(), "-40");
+    }
+
+    #[test]
+    fn port_reading_rejects_invalid_ports() {
+        for port in [0, PORTS_PER_MODULE + 1, u8::MAX] {
+            assert!(matches!(port_reading("-10,-20,-30,-40", port), Err(MPM210HError::ParseError(_))), "port {}", port);
+        }
+    }
+
+    #[test]
+    fn port_reading_of_a_short_response() {
+        assert!(matches!(port_reading("-10,-20", 3), Err(MPM210HError::ParseError(_))));
+    }
+}
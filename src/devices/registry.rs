@@ -0,0 +1,113 @@
+use super::instrument::{CurrentSource, OpticalPowerMeter};
+use super::transcript::Transcript;
+use super::{CLD1015, MPM210H};
+use crate::error::Error;
+use tracing::info;
+
+/// Role of the laser diode controller the experiments drive
+pub const LASER_DRIVER: &str = "laser_driver";
+
+/// Role of the power meter the experiments read
+pub const POWER_METER: &str = "power_meter_1";
+
+/// Instruments of a bench keyed by role, e.g. `"laser_driver"` and `"power_meter_1"`.
+/// The registry owns the instruments, connects and disconnects them together, and is
+/// what the experiments are run on.
+pub struct DeviceRegistry<S: CurrentSource = CLD1015, M: OpticalPowerMeter = MPM210H> {
+    current_sources: Vec<(String, S)>,
+    power_meters: Vec<(String, M)>,
+}
+
+impl<S: CurrentSource, M: OpticalPowerMeter> Default for DeviceRegistry<S, M> {
+    fn default() -> Self {
+        DeviceRegistry { current_sources: Vec::new(), power_meters: Vec::new() }
+    }
+}
+
+impl<S: CurrentSource, M: OpticalPowerMeter> DeviceRegistry<S, M> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry of the usual bench: one laser driver and one power meter
+    pub fn bench(laser_driver: S, power_meter: M) -> Self {
+        Self::new().with_current_source(LASER_DRIVER, laser_driver).with_power_meter(POWER_METER, power_meter)
+    }
+
+    /// Add a current source under `name`, replacing any instrument of that name
+    pub fn with_current_source(mut self, name: &str, device: S) -> Self {
+        self.current_sources.retain(|(existing, _)| existing != name);
+        self.current_sources.push((name.to_string(), device));
+        self
+    }
+
+    /// Add a power meter under `name`, replacing any instrument of that name
+    pub fn with_power_meter(mut self, name: &str, device: M) -> Self {
+        self.power_meters.retain(|(existing, _)| existing != name);
+        self.power_meters.push((name.to_string(), device));
+        self
+    }
+
+    pub fn current_source(&mut self, name: &str) -> Option<&mut S> {
+        self.current_sources.iter_mut().find(|(existing, _)| existing == name).map(|(_, device)| device)
+    }
+
+    pub fn power_meter(&mut self, name: &str) -> Option<&mut M> {
+        self.power_meters.iter_mut().find(|(existing, _)| existing == name).map(|(_, device)| device)
+    }
+
+    /// Roles of every registered instrument, current sources first
+    pub fn names(&self) -> Vec<&str> {
+        self.current_sources.iter().map(|(name, _)| name.as_str())
+            .chain(self.power_meters.iter().map(|(name, _)| name.as_str()))
+            .collect()
+    }
+
+    /// The laser driver and the power meter, the instruments the built-in experiments use
+    pub fn laser_and_meter(&mut self) -> Option<(&mut S, &mut M)> {
+        let cld = self.current_sources.iter_mut().find(|(name, _)| name == LASER_DRIVER)?;
+        let mpm = self.power_meters.iter_mut().find(|(name, _)| name == POWER_METER)?;
+        Some((&mut cld.1, &mut mpm.1))
+    }
+
+    /// Record the traffic of every instrument in `transcript`, or stop recording with `None`
+    pub fn set_transcript(&mut self, transcript: Option<Transcript>) {
+        for (_, device) in &mut self.current_sources {
+            device.set_transcript(transcript.clone());
+        }
+        for (_, device) in &mut self.power_meters {
+            device.set_transcript(transcript.clone());
+        }
+    }
+
+    /// Connect every instrument, returning the identification of each by role. Instruments
+    /// still connected, e.g. from an earlier run, are only identified again.
+    pub fn connect_all(&mut self) -> Result<Vec<(String, String)>, Error> {
+        let mut ids = Vec::new();
+        for (name, device) in &mut self.current_sources {
+            let id = if device.is_connected() { device.identify() } else { device.connect() }
+                .map_err(|e| Error::connection(format!("Failed to connect to {}", name), e))?;
+            info!("{} connected: {}", name, id);
+            ids.push((name.clone(), id));
+        }
+        for (name, device) in &mut self.power_meters {
+            let id = if device.is_connected() { device.identify() } else { device.connect() }
+                .map_err(|e| Error::connection(format!("Failed to connect to {}", name), e))?;
+            info!("{} connected: {}", name, id);
+            ids.push((name.clone(), id));
+        }
+        Ok(ids)
+    }
+
+    /// Close the connection to every instrument. The laser is not touched, turn it off first.
+    pub fn disconnect_all(&mut self) {
+        for (name, device) in &mut self.current_sources {
+            device.disconnect();
+            info!("{} disconnected", name);
+        }
+        for (name, device) in &mut self.power_meters {
+            device.disconnect();
+            info!("{} disconnected", name);
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use super::simulator::{SharedRig, SimulatedLink, SimulatedRig};
+use super::transcript::Transcript;
+use std::ffi::CString;
+use std::future::Future;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Runtime;
+use tracing::{debug, error};
+use visa_rs::prelude::*;
+
+#[derive(Error, Debug)]
+pub enum ScpiError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Device not connected")]
+    NotConnected,
+}
+
+pub type Result<T> = std::result::Result<T, ScpiError>;
+
+/// Byte link to an SCPI instrument. Implementations only move bytes; terminators,
+/// delays, logging and the transcript are handled by `ScpiSession`.
+pub trait ScpiTransport: Send {
+    /// Send a command, terminator included
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()>;
+    /// Receive one response; surrounding whitespace and the terminator are trimmed by the caller
+    fn receive(&mut self) -> io::Result<String>;
+    /// Receive exactly `buf.len()` bytes of a binary response
+    fn receive_exact(&mut self, buf: &mut [u8]) -> io::Result<()>;
+}
+
+/// Instrument session on a VISA resource, e.g. USB-TMC
+pub struct VisaTransport {
+    instrument: Instrument,
+}
+
+impl VisaTransport {
+    pub fn open(resource: &str, timeout: Duration) -> visa_rs::Result<Self> {
+        let rm = DefaultRM::new()?;
+        let resource = CString::new(resource).unwrap();
+        let instrument = rm.open(&resource.into(), AccessMode::NO_LOCK, timeout)?;
+        Ok(VisaTransport { instrument })
+    }
+}
+
+impl ScpiTransport for VisaTransport {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.instrument.write_all(bytes)
+    }
+
+    fn receive(&mut self) -> io::Result<String> {
+        let mut response = String::new();
+        BufReader::new(&mut self.instrument).read_line(&mut response)?;
+        Ok(response)
+    }
+
+    fn receive_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.instrument.read_exact(buf)
+    }
+}
+
+/// Future of one exchange on an `AsyncScpiTransport`
+pub type TransportFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// Byte link to an SCPI instrument driven by the shared tokio `runtime`, so waiting for
+/// the instrument does not tie up a thread. `BlockingTransport` opens it in an `ScpiSession`.
+pub trait AsyncScpiTransport: Send {
+    /// Send a command, terminator included
+    fn send<'a>(&'a mut self, bytes: &'a [u8]) -> TransportFuture<'a, ()>;
+    /// Receive one response; surrounding whitespace and the terminator are trimmed by the caller
+    fn receive(&mut self) -> TransportFuture<'_, String>;
+    /// Receive exactly `buf.len()` bytes of a binary response
+    fn receive_exact<'a>(&'a mut self, buf: &'a mut [u8]) -> TransportFuture<'a, ()>;
+}
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Tokio runtime the async instrument links run on. Watchdogs, live streaming or a
+/// network API can spawn their tasks on it too instead of starting threads of their own.
+pub fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("instrument-io")
+            .enable_all()
+            .build()
+            .expect("Failed to start the instrument IO runtime")
+    })
+}
+
+/// `future`, failed with `TimedOut` if it does not complete within `timeout`
+async fn within<T>(timeout: Duration, future: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    tokio::time::timeout(timeout, future)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("No response within {:?}", timeout)))?
+}
+
+/// Raw socket connection on tokio, e.g. the LAN port of the MPM-210H. Connection, read
+/// and write are bounded by the timeout.
+pub struct AsyncTcpTransport {
+    stream: tokio::net::TcpStream,
+    timeout: Duration,
+}
+
+impl AsyncTcpTransport {
+    pub async fn connect(address: SocketAddr, timeout: Duration) -> io::Result<Self> {
+        let stream = within(timeout, tokio::net::TcpStream::connect(address)).await?;
+        stream.set_nodelay(true)?;
+        Ok(AsyncTcpTransport { stream, timeout })
+    }
+}
+
+impl AsyncScpiTransport for AsyncTcpTransport {
+    fn send<'a>(&'a mut self, bytes: &'a [u8]) -> TransportFuture<'a, ()> {
+        let stream = &mut self.stream;
+        Box::pin(within(self.timeout, async move {
+            stream.write_all(bytes).await?;
+            stream.flush().await
+        }))
+    }
+
+    fn receive(&mut self) -> TransportFuture<'_, String> {
+        let stream = &mut self.stream;
+        Box::pin(within(self.timeout, async move {
+            // Responses fit one read; a closed connection reads as nothing
+            let mut buf = [0_u8; 1024];
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "Connection closed by remote"));
+            }
+            Ok(String::from_utf8_lossy(&buf[..n]).into_owned())
+        }))
+    }
+
+    fn receive_exact<'a>(&'a mut self, buf: &'a mut [u8]) -> TransportFuture<'a, ()> {
+        let stream = &mut self.stream;
+        Box::pin(within(self.timeout, async move { stream.read_exact(buf).await.map(drop) }))
+    }
+}
+
+/// Async link used by the blocking `ScpiSession`: every exchange runs on the shared
+/// `runtime`. It must not be used from a task running on that runtime.
+pub struct BlockingTransport {
+    transport: Box<dyn AsyncScpiTransport>,
+}
+
+impl BlockingTransport {
+    pub fn new(transport: impl AsyncScpiTransport + 'static) -> Self {
+        BlockingTransport { transport: Box::new(transport) }
+    }
+}
+
+impl ScpiTransport for BlockingTransport {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        runtime().block_on(self.transport.send(bytes))
+    }
+
+    fn receive(&mut self) -> io::Result<String> {
+        runtime().block_on(self.transport.receive())
+    }
+
+    fn receive_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        runtime().block_on(self.transport.receive_exact(buf))
+    }
+}
+
+/// One instrument of a simulated rig, answering from the rig's model instead of hardware
+pub struct RigTransport {
+    rig: SharedRig,
+    name: &'static str,
+    handler: fn(&mut SimulatedRig, &str) -> Option<String>,
+    link: fn(&mut SimulatedRig) -> &mut SimulatedLink, // Faults of the instrument on the rig
+    pending: Option<String>,
+    pending_block: Vec<u8>,
+}
+
+impl RigTransport {
+    pub fn cld1015(rig: SharedRig) -> Self {
+        RigTransport {
+            rig,
+            name: "CLD1015",
+            handler: SimulatedRig::cld_command,
+            link: |rig| &mut rig.cld_link,
+            pending: None,
+            pending_block: Vec::new(),
+        }
+    }
+
+    pub fn mpm210h(rig: SharedRig) -> Self {
+        RigTransport {
+            rig,
+            name: "MPM210H",
+            handler: SimulatedRig::mpm_command,
+            link: |rig| &mut rig.mpm_link,
+            pending: None,
+            pending_block: Vec::new(),
+        }
+    }
+}
+
+impl ScpiTransport for RigTransport {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let command = String::from_utf8_lossy(bytes);
+        let command = command.trim();
+        let mut rig = self.rig.lock().unwrap();
+        self.pending = None;
+        (self.link)(&mut rig).command(self.name)?;
+        // Logged data is the only binary response, 4-byte little-endian floats in a block
+        if let Some(argument) = command.to_uppercase().strip_prefix("LOGG?") {
+            let (module, port) = argument.trim().split_once(',').unwrap_or(("0", "1"));
+            let values = rig.logged_data(module.trim().parse().unwrap_or(0), port.trim().parse().unwrap_or(1));
+            let data: Vec<u8> = values.iter().flat_map(|&value| (value as f32).to_le_bytes()).collect();
+            let len = data.len().to_string();
+            self.pending_block = format!("#{}{}", len.len(), len).into_bytes();
+            self.pending_block.extend(data);
+            return Ok(());
+        }
+        self.pending = (self.handler)(&mut rig, command);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<String> {
+        self.pending.take().ok_or_else(|| io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("Simulated {} has no pending response", self.name),
+        ))
+    }
+
+    fn receive_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if self.pending_block.len() < buf.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!(
+                "Simulated {} returned {} bytes, expected {}", self.name, self.pending_block.len(), buf.len())));
+        }
+        let rest = self.pending_block.split_off(buf.len());
+        buf.copy_from_slice(&self.pending_block);
+        self.pending_block = rest;
+        Ok(())
+    }
+}
+
+/// Line-based SCPI exchange with one instrument over any transport: appends the
+/// terminator, waits the delays the instrument needs, and records the traffic in the
+/// debug log and the transcript. Drivers only map their methods to commands.
+pub struct ScpiSession {
+    name: &'static str,
+    transport: Option<Box<dyn ScpiTransport>>,
+    transcript: Option<Transcript>,
+    terminator: &'static str,
+    write_delay: Duration, // After every command
+    query_delay: Duration, // Between a query and reading its response
+}
+
+impl ScpiSession {
+    /// Session for the instrument called `name` in logs and transcripts, not connected yet
+    pub fn new(name: &'static str) -> Self {
+        ScpiSession {
+            name,
+            transport: None,
+            transcript: None,
+            terminator: "\n",
+            write_delay: Duration::ZERO,
+            query_delay: Duration::ZERO,
+        }
+    }
+
+    pub fn with_terminator(mut self, terminator: &'static str) -> Self {
+        self.terminator = terminator;
+        self
+    }
+
+    pub fn with_write_delay(mut self, delay: Duration) -> Self {
+        self.write_delay = delay;
+        self
+    }
+
+    pub fn with_query_delay(mut self, delay: Duration) -> Self {
+        self.query_delay = delay;
+        self
+    }
+
+    /// Exchange commands over `transport` from now on, replacing any previous connection
+    pub fn open(&mut self, transport: Box<dyn ScpiTransport>) {
+        self.transport = Some(transport);
+    }
+
+    pub fn close(&mut self) {
+        self.transport = None;
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.transport.is_some()
+    }
+
+    /// Record every command and response in `transcript`, or stop recording with `None`
+    pub fn set_transcript(&mut self, transcript: Option<Transcript>) {
+        self.transcript = transcript;
+    }
+
+    pub fn write(&mut self, command: &str) -> Result<()> {
+        if let Some(transcript) = &self.transcript {
+            transcript.sent(self.name, command);
+        }
+        let Some(transport) = self.transport.as_mut() else {
+            error!("Attempted to write to {} but device is not connected", self.name);
+            return Err(ScpiError::NotConnected);
+        };
+        debug!("Sending command to {}: {}", self.name, command);
+        transport.send(format!("{}{}", command, self.terminator).as_bytes())?;
+        if !self.write_delay.is_zero() {
+            std::thread::sleep(self.write_delay);
+        }
+        Ok(())
+    }
+
+    pub fn read(&mut self) -> Result<String> {
+        let result = self.receive();
+        if let Some(transcript) = &self.transcript {
+            match &result {
+                Ok(response) => transcript.received(self.name, response),
+                Err(e) => transcript.failed(self.name, &e.to_string()),
+            }
+        }
+        result
+    }
+
+    fn receive(&mut self) -> Result<String> {
+        let Some(transport) = self.transport.as_mut() else {
+            error!("Attempted to read from {} but device is not connected", self.name);
+            return Err(ScpiError::NotConnected);
+        };
+        let response = transport.receive()?.trim().to_string();
+        debug!("Received response from {}: {}", self.name, response);
+        Ok(response)
+    }
+
+    pub fn query(&mut self, command: &str) -> Result<String> {
+        self.write(command)?;
+        if !self.query_delay.is_zero() {
+            std::thread::sleep(self.query_delay);
+        }
+        self.read()
+    }
+
+    /// Read a binary response sent after a query as an IEEE 488.2 definite length block:
+    /// `#`, the number of length digits, the length in bytes, then the data
+    pub fn read_block(&mut self) -> Result<Vec<u8>> {
+        let transport = self.transport.as_mut().ok_or(ScpiError::NotConnected)?;
+        let invalid = |message: String| ScpiError::Io(io::Error::new(io::ErrorKind::InvalidData, message));
+        let mut start = [0_u8; 2];
+        transport.receive_exact(&mut start)?;
+        let digits = match start {
+            [b'#', digit @ b'1'..=b'9'] => (digit - b'0') as usize,
+            _ => return Err(invalid(format!("{} sent no definite length block header: {:?}", self.name, start))),
+        };
+        let mut length = vec![0_u8; digits];
+        transport.receive_exact(&mut length)?;
+        let len: usize = std::str::from_utf8(&length).ok().and_then(|l| l.parse().ok())
+            .ok_or_else(|| invalid(format!("{} sent an invalid block length: {:?}", self.name, length)))?;
+        let mut bytes = vec![0_u8; len];
+        transport.receive_exact(&mut bytes)?;
+        if let Some(transcript) = &self.transcript {
+            transcript.received(self.name, &format!("<{} bytes of binary data>", bytes.len()));
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// Session on a local socket whose peer answers `*IDN?` once and then stays silent
+    fn local_session() -> ScpiSession {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut peer, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(peer.try_clone().unwrap()).read_line(&mut line).unwrap();
+            assert_eq!(line, "*IDN?\n");
+            peer.write_all(b"santec,MPM-210H,0,1\n").unwrap();
+            std::thread::sleep(Duration::from_secs(1));
+        });
+        let transport = runtime().block_on(AsyncTcpTransport::connect(address, Duration::from_millis(200))).unwrap();
+        let mut session = ScpiSession::new("MPM210H");
+        session.open(Box::new(BlockingTransport::new(transport)));
+        session
+    }
+
+    #[test]
+    fn blocking_session_over_async_tcp() {
+        let mut session = local_session();
+        assert_eq!(session.query("*IDN?").unwrap(), "santec,MPM-210H,0,1");
+        match session.read() {
+            Err(ScpiError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+}
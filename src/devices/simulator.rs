@@ -0,0 +1,445 @@
+use super::cld1015::CLD1015;
+use super::mpm210h::MPM210H;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Synthetic L-I characteristic used by the simulated bench
+#[derive(Debug, Clone)]
+pub struct LaserModel {
+    pub threshold_ma: f64,        // Lasing threshold current in mA
+    pub slope_mw_per_ma: f64,     // Slope efficiency above threshold in mW/mA
+    pub spontaneous_mw: f64,      // Spontaneous emission power at threshold in mW
+    pub coupling_efficiency: f64, // Fraction of the emitted power reaching the meter
+    pub noise_fraction: f64,      // Relative noise added to each reading
+    pub turn_on_voltage_v: f64,   // Diode forward voltage at zero current in V
+    pub series_resistance_ohm: f64, // Series resistance of the diode
+}
+
+impl Default for LaserModel {
+    fn default() -> Self {
+        LaserModel {
+            threshold_ma: 25.0,
+            slope_mw_per_ma: 0.8,
+            spontaneous_mw: 0.01,
+            coupling_efficiency: 0.7,
+            noise_fraction: 0.002,
+            turn_on_voltage_v: 1.1,
+            series_resistance_ohm: 4.0,
+        }
+    }
+}
+
+impl LaserModel {
+    /// Optical power in mW emitted at the given drive current
+    pub fn power_mw(&self, current_ma: f64) -> f64 {
+        if current_ma <= 0.0 {
+            return 0.0;
+        }
+        let spontaneous = self.spontaneous_mw * (current_ma / self.threshold_ma).min(1.0);
+        let stimulated = if current_ma > self.threshold_ma {
+            self.slope_mw_per_ma * (current_ma - self.threshold_ma)
+        } else {
+            0.0
+        };
+        spontaneous + stimulated
+    }
+
+    /// Drive current in mA needed to emit the given power (inverse of `power_mw`)
+    pub fn current_for_power_ma(&self, power_mw: f64) -> f64 {
+        if power_mw <= self.spontaneous_mw {
+            return self.threshold_ma * (power_mw / self.spontaneous_mw).max(0.0);
+        }
+        self.threshold_ma + (power_mw - self.spontaneous_mw) / self.slope_mw_per_ma
+    }
+}
+
+/// Faults injected into a simulated instrument. Commands are counted from the creation
+/// of the rig, including the identification queries.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    pub unreachable: bool,                 // Every command fails, as if the instrument were not on the network
+    pub fail_after_commands: Option<usize>, // Every command fails once this many have succeeded, as after a lost connection
+    pub timeout_every: Option<usize>,      // Every nth command fails once, as a transient timeout
+    pub laser_stuck_on: bool,              // CLD1015: the laser output cannot be turned off
+    pub tec_offset_c: f64,                 // CLD1015: the laser temperature stays this far from the TEC setpoint
+    pub current_limit_a: Option<f64>,      // CLD1015: current limit reported instead of the rig limit
+    pub invalid_readings: bool,            // MPM210H: power readings are not numbers
+}
+
+/// Faults and command count of the link to one simulated instrument
+#[derive(Debug, Default)]
+pub struct SimulatedLink {
+    pub faults: Faults,
+    commands: usize,
+}
+
+impl SimulatedLink {
+    /// Number of commands the instrument received since the rig was created
+    pub fn commands(&self) -> usize {
+        self.commands
+    }
+
+    /// Count one command sent to the instrument called `name`, failing it as the faults
+    /// require with the IO error a real link would report
+    pub(crate) fn command(&mut self, name: &str) -> io::Result<()> {
+        if self.faults.unreachable {
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, format!("Simulated {} is unreachable", name)));
+        }
+        if let Some(limit) = self.faults.fail_after_commands {
+            if self.commands >= limit {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                          format!("Simulated {} stopped responding after {} commands", name, limit)));
+            }
+        }
+        self.commands += 1;
+        if let Some(every) = self.faults.timeout_every.filter(|&every| every > 0) {
+            if self.commands % every == 0 {
+                warn!("Simulated {} injecting a timeout on command {}", name, self.commands);
+                return Err(io::Error::new(io::ErrorKind::TimedOut,
+                                          format!("Simulated {} timed out on command {}", name, self.commands)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Shared state of a simulated CLD1015 + MPM210H bench.
+///
+/// Both simulated drivers hold a handle to the same rig so that the power
+/// meter sees the light produced by the simulated laser.
+pub struct SimulatedRig {
+    pub model: LaserModel,
+    pub cld_link: SimulatedLink, // Faults of the CLD1015
+    pub mpm_link: SimulatedLink, // Faults of the MPM210H
+    laser_on: bool,
+    tec_on: bool,
+    tec_setpoint_c: f64,
+    current_a: f64,
+    current_limit_a: f64,
+    constant_power: bool,
+    power_setpoint_w: f64,
+    wavelength_nm: u32,
+    unit: u8,
+    average_time_ms: f64,
+    measurement_mode: String,
+    noise_state: u64,
+    logging_points: usize,
+    logging_started: Option<Instant>,
+    emission_history: Vec<(Instant, f64)>, // Laser power in mW after each change while logging
+}
+
+pub type SharedRig = Arc<Mutex<SimulatedRig>>;
+
+/// CLD1015 and MPM210H drivers on one simulated rig with the given L-I model. Faults are
+/// injected through the `cld_link` and `mpm_link` of the returned rig, below the drivers,
+/// so their safety checks apply as on real hardware.
+pub fn simulated_bench(model: LaserModel) -> (CLD1015, MPM210H, SharedRig) {
+    let rig = SimulatedRig::shared(model);
+    (CLD1015::simulated(rig.clone()), MPM210H::simulated(rig.clone()), rig)
+}
+
+/// Temperature of the simulated laser mount while the TEC is off, in °C
+const AMBIENT_TEMPERATURE_C: f64 = 22.0;
+
+/// Power reported by a dark input in mW (MPM210H noise floor)
+const DARK_POWER_MW: f64 = 1e-7;
+
+impl SimulatedRig {
+    pub fn new(model: LaserModel) -> Self {
+        info!("Initializing simulated rig with model: {:?}", model);
+        SimulatedRig {
+            model,
+            cld_link: SimulatedLink::default(),
+            mpm_link: SimulatedLink::default(),
+            laser_on: false,
+            tec_on: false,
+            tec_setpoint_c: 25.0,
+            current_a: 0.0,
+            current_limit_a: 0.5,
+            constant_power: false,
+            power_setpoint_w: 0.0,
+            wavelength_nm: 1550,
+            unit: 0,
+            average_time_ms: 1.0,
+            measurement_mode: "CONST1".to_string(),
+            noise_state: 0x2545_F491_4F6C_DD1D,
+            logging_points: 0,
+            logging_started: None,
+            emission_history: Vec::new(),
+        }
+    }
+
+    pub fn shared(model: LaserModel) -> SharedRig {
+        Arc::new(Mutex::new(SimulatedRig::new(model)))
+    }
+
+    /// Handle a command sent to the simulated CLD1015, returning the response for queries
+    pub fn cld_command(&mut self, command: &str) -> Option<String> {
+        let (header, argument) = split_command(command);
+        let response = match header.as_str() {
+            "*IDN?" => Some("Thorlabs,CLD1015,SIM00000,simulated".to_string()),
+            "*RST" => {
+                self.laser_on = false;
+                self.tec_on = false;
+                self.current_a = 0.0;
+                None
+            }
+            "*OPC?" => Some("1".to_string()),
+            "*TST?" => Some("0".to_string()),
+            "SYST:ERR?" => Some("0,\"No error\"".to_string()),
+            "OUTPUT2:STATE" => {
+                self.tec_on = parse_bool(argument);
+                None
+            }
+            "OUTPUT2:STATE?" => Some(bool_response(self.tec_on)),
+            "SOURCE2:TEMPERATURE:SPOINT" => {
+                self.tec_setpoint_c = argument.and_then(|a| a.parse().ok()).unwrap_or(self.tec_setpoint_c);
+                None
+            }
+            "SOURCE2:TEMPERATURE:SPOINT?" => Some(format!("{}", self.tec_setpoint_c)),
+            // The simulated TEC regulates instantly
+            "MEASURE:TEMPERATURE?" => {
+                let temperature_c = if self.tec_on { self.tec_setpoint_c } else { AMBIENT_TEMPERATURE_C };
+                Some(format!("{}", temperature_c + self.cld_link.faults.tec_offset_c))
+            }
+            "OUTPUT:STATE" => {
+                self.laser_on = parse_bool(argument) || self.cld_link.faults.laser_stuck_on;
+                None
+            }
+            "OUTPUT:STATE?" => Some(bool_response(self.laser_on)),
+            "SOURCE:FUNCTION:MODE" => {
+                self.constant_power = argument.map(|a| a.to_uppercase().starts_with("POW")).unwrap_or(false);
+                None
+            }
+            "SOURCE:FUNCTION:MODE?" => Some(if self.constant_power { "POW" } else { "CURR" }.to_string()),
+            "SOURCE:POWER:LEVEL:IMMEDIATE:AMPLITUDE" => {
+                self.power_setpoint_w = argument.and_then(|a| a.parse().ok()).unwrap_or(self.power_setpoint_w);
+                // The controller regulates the current to reach the setpoint, within the current limit
+                let current_ma = self.model.current_for_power_ma(self.power_setpoint_w * 1000.0);
+                self.current_a = (current_ma / 1000.0).min(self.current_limit_a);
+                None
+            }
+            "MEASURE:CURRENT?" => Some(format!("{}", self.current_a)),
+            "MEASURE:VOLTAGE?" => {
+                let voltage_v = if self.laser_on {
+                    self.model.turn_on_voltage_v + self.current_a * self.model.series_resistance_ohm
+                } else {
+                    0.0
+                };
+                Some(format!("{}", voltage_v))
+            }
+            "SOURCE:CURRENT:LEVEL:IMMEDIATE:AMPLITUDE" => {
+                self.current_a = argument.and_then(|a| a.parse().ok()).unwrap_or(self.current_a);
+                None
+            }
+            "SOURCE:CURRENT:LEVEL:IMMEDIATE:AMPLITUDE?" => Some(format!("{}", self.current_a)),
+            "SOURCE:CURRENT:LIMIT:AMPLITUDE?" => {
+                Some(format!("{}", self.cld_link.faults.current_limit_a.unwrap_or(self.current_limit_a)))
+            }
+            _ => {
+                warn!("Simulated CLD1015 ignoring unsupported command: {}", command);
+                if header.ends_with('?') { Some("0".to_string()) } else { None }
+            }
+        };
+
+        // Keep track of the emitted power over time so logged data follows the laser
+        if self.logging_started.is_some() {
+            let emitted_mw = self.emitted_mw();
+            self.emission_history.push((Instant::now(), emitted_mw));
+        }
+        response
+    }
+
+    /// Power currently reaching each port of the meter in mW
+    fn emitted_mw(&self) -> f64 {
+        if self.laser_on {
+            self.model.power_mw(self.current_a * 1000.0) * self.model.coupling_efficiency
+        } else {
+            0.0
+        }
+    }
+
+    /// Number of samples logged since MEAS, one per averaging time with the internal trigger
+    fn logged_samples(&self) -> usize {
+        match self.logging_started {
+            Some(started) => {
+                let samples = (started.elapsed().as_secs_f64() * 1000.0 / self.average_time_ms) as usize;
+                samples.min(self.logging_points)
+            }
+            None => 0,
+        }
+    }
+
+    /// Data logged since the last MEAS, in the configured unit
+    pub fn logged_data(&mut self, _module: u8, _port: u8) -> Vec<f64> {
+        let started = match self.logging_started {
+            Some(started) => started,
+            None => return Vec::new(),
+        };
+        let history = self.emission_history.clone();
+        (0..self.logged_samples())
+            .map(|i| {
+                // Each sample reports the power at the end of its averaging window
+                let sampled_at = started + Duration::from_secs_f64((i + 1) as f64 * self.average_time_ms / 1000.0);
+                let emitted_mw = history
+                    .iter()
+                    .take_while(|(changed_at, _)| *changed_at <= sampled_at)
+                    .last()
+                    .map_or(0.0, |&(_, mw)| mw);
+                let noisy = (emitted_mw * (1.0 + self.noise() * self.model.noise_fraction)).max(DARK_POWER_MW);
+                self.format_power(noisy).parse().unwrap_or(f64::NAN)
+            })
+            .collect()
+    }
+
+    /// Handle a command sent to the simulated MPM210H, returning the response for queries
+    pub fn mpm_command(&mut self, command: &str) -> Option<String> {
+        let (header, argument) = split_command(command);
+        match header.as_str() {
+            "*IDN?" => Some("santec,MPM-210H,SIM00000,simulated".to_string()),
+            "IDIS?" => Some("1,0,0,0,0".to_string()),
+            "ERR?" => Some("0,\"No error\"".to_string()),
+            "ZERO" => None,
+            "WAV" => {
+                self.wavelength_nm = argument.and_then(|a| a.parse().ok()).unwrap_or(self.wavelength_nm);
+                None
+            }
+            "WAV?" => Some(format!("{}", self.wavelength_nm)),
+            "WMOD" => {
+                self.measurement_mode = argument.unwrap_or("CONST1").to_string();
+                None
+            }
+            "WMOD?" => Some(self.measurement_mode.clone()),
+            "AVG" => {
+                self.average_time_ms = argument.and_then(|a| a.parse().ok()).unwrap_or(self.average_time_ms);
+                None
+            }
+            "AVG?" => Some(format!("{}", self.average_time_ms)),
+            "UNIT" => {
+                self.unit = argument.and_then(|a| a.parse().ok()).unwrap_or(self.unit);
+                None
+            }
+            "UNIT?" => Some(format!("{}", self.unit)),
+            "LOGN" => {
+                self.logging_points = argument.and_then(|a| a.parse().ok()).unwrap_or(self.logging_points);
+                None
+            }
+            "TRIG" => None,
+            "MEAS" => {
+                let emitted_mw = self.emitted_mw();
+                self.logging_started = Some(Instant::now());
+                self.emission_history = vec![(Instant::now(), emitted_mw)];
+                None
+            }
+            "STOP" => None,
+            "STAT?" => {
+                let samples = self.logged_samples();
+                let status = if samples >= self.logging_points { 1 } else { 0 };
+                Some(format!("{},{}", status, samples))
+            }
+            "READ?" if self.mpm_link.faults.invalid_readings => Some(vec!["OVER"; 4].join(",")),
+            "READ?" => {
+                // Light from the laser is coupled equally into every port of the module
+                let laser_mw = self.emitted_mw();
+                let values: Vec<String> = (0..4)
+                    .map(|_| {
+                        let noisy = (laser_mw * (1.0 + self.noise() * self.model.noise_fraction)).max(DARK_POWER_MW);
+                        self.format_power(noisy)
+                    })
+                    .collect();
+                Some(values.join(","))
+            }
+            _ => {
+                warn!("Simulated MPM210H ignoring unsupported command: {}", command);
+                if header.ends_with('?') { Some("0".to_string()) } else { None }
+            }
+        }
+    }
+
+    fn format_power(&self, power_mw: f64) -> String {
+        if self.unit == 0 {
+            format!("{:.3}", 10.0 * power_mw.log10())
+        } else {
+            format!("{:.6E}", power_mw)
+        }
+    }
+
+    /// Uniform pseudo-random value in [-1, 1] (xorshift, deterministic per rig)
+    fn noise(&mut self) -> f64 {
+        let mut x = self.noise_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.noise_state = x;
+        (x as f64 / u64::MAX as f64) * 2.0 - 1.0
+    }
+}
+
+/// Split a SCPI command into its normalized long-form header and its argument
+fn split_command(command: &str) -> (String, Option<&str>) {
+    let command = command.trim();
+    let (header, argument) = match command.split_once(char::is_whitespace) {
+        Some((header, argument)) => (header, Some(argument.trim())),
+        None => (command, None),
+    };
+    let header = header
+        .to_uppercase()
+        .replace("OUTP:", "OUTPUT:")
+        .replace("OUTP2:", "OUTPUT2:")
+        .replace("SYSTEM:ERROR", "SYST:ERR")
+        .replace("MEAS:CURR?", "MEASURE:CURRENT?")
+        .replace("MEAS:TEMP?", "MEASURE:TEMPERATURE?")
+        .replace("MEAS:VOLT?", "MEASURE:VOLTAGE?")
+        .replace("SOUR2:TEMP:SPO", "SOURCE2:TEMPERATURE:SPOINT");
+    (header, argument)
+}
+
+fn parse_bool(argument: Option<&str>) -> bool {
+    matches!(argument.map(|a| a.to_uppercase()).as_deref(), Some("ON") | Some("1"))
+}
+
+fn bool_response(state: bool) -> String {
+    if state { "1".to_string() } else { "0".to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::cld1015::{CLD1015Error, MAX_SAFE_CURRENT_AMPS};
+    use crate::devices::mpm210h::MPM210HError;
+
+    #[test]
+    fn simulated_laser_needs_the_tec() {
+        let (mut cld, _, rig) = simulated_bench(LaserModel::default());
+        assert!(matches!(cld.set_laser_output(true), Err(CLD1015Error::SafetyInterlock(_))));
+        assert!(!rig.lock().unwrap().laser_on);
+
+        cld.enable_tec().unwrap();
+        cld.set_laser_output(true).unwrap();
+        assert!(cld.get_laser_output().unwrap());
+    }
+
+    #[test]
+    fn simulated_current_is_capped_by_the_driver() {
+        let (mut cld, _, rig) = simulated_bench(LaserModel::default());
+        rig.lock().unwrap().current_limit_a = 2.0;
+        assert!(matches!(cld.set_current(MAX_SAFE_CURRENT_AMPS + 0.1), Err(CLD1015Error::SafetyInterlock(_))));
+        assert_eq!(rig.lock().unwrap().current_a, 0.0);
+    }
+
+    #[test]
+    fn link_faults_fail_commands_with_io_errors() {
+        let (_, mut mpm, rig) = simulated_bench(LaserModel::default());
+        rig.lock().unwrap().mpm_link.faults = Faults { timeout_every: Some(2), fail_after_commands: Some(3), ..Faults::default() };
+
+        mpm.query("*IDN?").unwrap();
+        let timeout = mpm.query("*IDN?").unwrap_err();
+        assert!(matches!(timeout, MPM210HError::IoError(ref e) if e.kind() == io::ErrorKind::TimedOut), "{}", timeout);
+        mpm.query("*IDN?").unwrap();
+        let lost = mpm.query("*IDN?").unwrap_err();
+        assert!(matches!(lost, MPM210HError::IoError(ref e) if e.kind() == io::ErrorKind::ConnectionAborted), "{}", lost);
+        assert_eq!(rig.lock().unwrap().mpm_link.commands(), 3);
+    }
+}
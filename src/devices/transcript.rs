@@ -0,0 +1,45 @@
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+/// Record of the traffic with the instruments, shared by both drivers.
+///
+/// Each command and response is written as one timestamped line and flushed at once,
+/// so the transcript is complete up to the moment of a crash.
+#[derive(Clone)]
+pub struct Transcript {
+    writer: Arc<Mutex<LineWriter<File>>>,
+}
+
+impl Transcript {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let writer = LineWriter::new(File::create(path)?);
+        info!("Recording instrument traffic to {}", path.display());
+        Ok(Transcript { writer: Arc::new(Mutex::new(writer)) })
+    }
+
+    /// Command sent to `device`
+    pub fn sent(&self, device: &str, command: &str) {
+        self.record(device, ">>", command);
+    }
+
+    /// Response received from `device`
+    pub fn received(&self, device: &str, response: &str) {
+        self.record(device, "<<", response);
+    }
+
+    /// Failed command or read
+    pub fn failed(&self, device: &str, error: &str) {
+        self.record(device, "!!", error);
+    }
+
+    fn record(&self, device: &str, direction: &str, text: &str) {
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        if let Ok(mut writer) = self.writer.lock() {
+            // A transcript that cannot be written must not stop the measurement
+            let _ = writeln!(writer, "{} {:<8} {} {}", timestamp, device, direction, text.trim_end());
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use serde::Serialize;
+use thiserror::Error;
+
+/// Underlying error of a failure, e.g. the driver error of a command that failed
+pub type Source = Box<dyn std::error::Error + Send + Sync>;
+
+/// Result of the experiments and everything they call
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// What stopped a run, so callers can react to the cause, e.g. with the process exit code
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    Config,     // Invalid settings, found before the hardware is touched
+    Connection, // An instrument could not be reached or brought into a safe state
+    Safety,     // Stopped by a safety check; partial data is saved when there is any
+    UserAbort,  // Stopped by the operator, e.g. by closing the live plot
+    Io,         // Output files could not be written
+    Instrument, // An instrument command failed during the measurement
+}
+
+/// Error of the experiments and the data files they read and write.
+///
+/// The message is what the operator sees and already names the underlying error; the
+/// driver or IO error itself is kept as the `source`, so callers can inspect it.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Invalid settings, found before the hardware is touched
+    #[error("{0}")]
+    Config(String),
+
+    /// An instrument could not be reached or brought into a safe state
+    #[error("{message}")]
+    ConnectionError { message: String, #[source] source: Option<Source> },
+
+    /// An instrument command failed or was answered with something unexpected
+    #[error("{message}")]
+    ScpiError { message: String, #[source] source: Option<Source> },
+
+    /// Stopped by a safety check; partial data is saved when there is any
+    #[error("{0}")]
+    SafetyAbort(String),
+
+    /// Stopped by the operator
+    #[error("{0}")]
+    UserAbort(String),
+
+    /// A data file could not be read or written
+    #[error("{message}")]
+    DataError { message: String, #[source] source: Option<Source> },
+}
+
+impl Error {
+    /// Error of the given kind without an underlying error
+    pub fn new(kind: FailureKind, message: impl Into<String>) -> Self {
+        let message = message.into();
+        match kind {
+            FailureKind::Config => Error::Config(message),
+            FailureKind::Connection => Error::ConnectionError { message, source: None },
+            FailureKind::Safety => Error::SafetyAbort(message),
+            FailureKind::UserAbort => Error::UserAbort(message),
+            FailureKind::Io => Error::DataError { message, source: None },
+            FailureKind::Instrument => Error::ScpiError { message, source: None },
+        }
+    }
+
+    /// "`context`: `source`", keeping `source`
+    pub fn connection(context: impl std::fmt::Display, source: impl Into<Source>) -> Self {
+        let source = source.into();
+        Error::ConnectionError { message: format!("{}: {}", context, source), source: Some(source) }
+    }
+
+    /// "`context`: `source`", keeping `source`
+    pub fn scpi(context: impl std::fmt::Display, source: impl Into<Source>) -> Self {
+        let source = source.into();
+        Error::ScpiError { message: format!("{}: {}", context, source), source: Some(source) }
+    }
+
+    /// "`context`: `source`", keeping `source`
+    pub fn data(context: impl std::fmt::Display, source: impl Into<Source>) -> Self {
+        let source = source.into();
+        Error::DataError { message: format!("{}: {}", context, source), source: Some(source) }
+    }
+
+    pub fn kind(&self) -> FailureKind {
+        match self {
+            Error::Config(_) => FailureKind::Config,
+            Error::ConnectionError { .. } => FailureKind::Connection,
+            Error::ScpiError { .. } => FailureKind::Instrument,
+            Error::SafetyAbort(_) => FailureKind::Safety,
+            Error::UserAbort(_) => FailureKind::UserAbort,
+            Error::DataError { .. } => FailureKind::Io,
+        }
+    }
+
+    /// Whether an instrument command failed on the link, e.g. with an IO error or a
+    /// timeout, so that reconnecting and repeating it may succeed. Commands refused by
+    /// the safety checks or answered with something unparseable are not transient.
+    pub fn is_transient(&self) -> bool {
+        let (Error::ConnectionError { source: Some(source), .. } | Error::ScpiError { source: Some(source), .. }) = self else {
+            return false;
+        };
+        let mut cause: Option<&(dyn std::error::Error + 'static)> = Some(source.as_ref());
+        while let Some(error) = cause {
+            if error.downcast_ref::<std::io::Error>().is_some_and(|e| is_link_failure(e.kind())) {
+                return true;
+            }
+            cause = error.source();
+        }
+        false
+    }
+
+    /// The same error with `context` in front of the message
+    pub fn context(self, context: impl std::fmt::Display) -> Self {
+        let prefix = |message: String| format!("{}: {}", context, message);
+        match self {
+            Error::Config(message) => Error::Config(prefix(message)),
+            Error::ConnectionError { message, source } => Error::ConnectionError { message: prefix(message), source },
+            Error::ScpiError { message, source } => Error::ScpiError { message: prefix(message), source },
+            Error::SafetyAbort(message) => Error::SafetyAbort(prefix(message)),
+            Error::UserAbort(message) => Error::UserAbort(prefix(message)),
+            Error::DataError { message, source } => Error::DataError { message: prefix(message), source },
+        }
+    }
+}
+
+/// IO errors of a link that timed out or dropped. Other kinds, e.g. `InvalidData` for a
+/// malformed response, fail the same way when repeated.
+fn is_link_failure(kind: std::io::ErrorKind) -> bool {
+    use std::io::ErrorKind;
+    matches!(kind, ErrorKind::TimedOut | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+        | ErrorKind::BrokenPipe | ErrorKind::UnexpectedEof | ErrorKind::WouldBlock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::mpm210h::MPM210HError;
+    use crate::devices::scpi::ScpiError;
+    use std::io;
+
+    fn scpi_io_error(kind: io::ErrorKind) -> Error {
+        Error::scpi("Failed to read logged data", ScpiError::Io(io::Error::new(kind, "link")))
+    }
+
+    #[test]
+    fn link_failures_are_transient() {
+        for kind in [io::ErrorKind::TimedOut, io::ErrorKind::ConnectionReset, io::ErrorKind::ConnectionAborted,
+                     io::ErrorKind::BrokenPipe, io::ErrorKind::UnexpectedEof, io::ErrorKind::WouldBlock] {
+            assert!(scpi_io_error(kind).is_transient(), "{:?}", kind);
+        }
+        let timeout = MPM210HError::IoError(io::Error::new(io::ErrorKind::TimedOut, "Simulated MPM210H timed out"));
+        assert!(Error::connection("Failed to connect", timeout).is_transient());
+    }
+
+    #[test]
+    fn invalid_data_is_not_retried() {
+        // ScpiSession::read_block fails a malformed block header with InvalidData
+        assert!(!scpi_io_error(io::ErrorKind::InvalidData).is_transient());
+        assert!(!scpi_io_error(io::ErrorKind::PermissionDenied).is_transient());
+    }
+
+    #[test]
+    fn errors_without_a_link_source_are_not_transient() {
+        assert!(!Error::SafetyAbort("Current above the limit".into()).is_transient());
+        assert!(!Error::new(FailureKind::Instrument, "No response").is_transient());
+        assert!(!Error::data("Failed to write", io::Error::new(io::ErrorKind::TimedOut, "disk")).is_transient());
+    }
+}
@@ -0,0 +1,197 @@
+use super::data::{KinkFlag, MeasurementRecord};
+use serde::{Deserialize, Serialize};
+
+/// Discontinuity in the slope dP/dI of an L-I curve
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Kink {
+    pub current_ma: f64,           // Current at which the slope changes
+    pub slope_before_mw_per_ma: f64,
+    pub slope_after_mw_per_ma: f64,
+    pub relative_change: f64,      // |after - before| / |before|
+}
+
+/// Fraction of the maximum power below which slope changes are ignored, so the
+/// lasing threshold itself is not reported as a kink
+const KINK_MIN_POWER_FRACTION: f64 = 0.1;
+
+/// Flag kinks in an L-I curve given as (current in mA, power in mW) points in sweep order.
+///
+/// A kink is reported where the slope of consecutive segments changes by more than
+/// `tolerance` relative to the slope of the preceding segment.
+pub fn detect_kinks(points: &[(f64, f64)], tolerance: f64) -> Vec<Kink> {
+    let max_power = points.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+    let min_power = max_power * KINK_MIN_POWER_FRACTION;
+
+    let slopes: Vec<f64> = points
+        .windows(2)
+        .map(|w| (w[1].1 - w[0].1) / (w[1].0 - w[0].0))
+        .collect();
+
+    let mut kinks = Vec::new();
+    for i in 1..slopes.len() {
+        let (before, after) = (slopes[i - 1], slopes[i]);
+        // Only consider the lasing region, where both segments start above the power floor
+        if points[i - 1].1 < min_power || !before.is_finite() || !after.is_finite() || before.abs() < f64::EPSILON {
+            continue;
+        }
+        let relative_change = (after - before).abs() / before.abs();
+        if relative_change > tolerance {
+            kinks.push(Kink {
+                current_ma: points[i].0,
+                slope_before_mw_per_ma: before,
+                slope_after_mw_per_ma: after,
+                relative_change,
+            });
+        }
+    }
+    kinks
+}
+
+/// Power range, as fractions of the maximum power, fitted to find the threshold and
+/// slope efficiency. The ends are left out to avoid the threshold knee and rollover.
+const FIT_POWER_FRACTIONS: (f64, f64) = (0.2, 0.8);
+
+/// Figures of merit of the L-I curve of one wavelength pass
+#[derive(Serialize, Debug, Clone)]
+pub struct CurveAnalysis {
+    pub wavelength_nm: u32,
+    pub points: usize,
+    pub max_power_mw: Option<f64>,
+    pub current_at_max_power_ma: Option<f64>,
+    pub threshold_ma: Option<f64>,              // Current at which the fitted line reaches zero power
+    pub slope_efficiency_w_per_a: Option<f64>,  // Slope of the fitted line (W/A = mW/mA)
+    pub fit_points: usize,                      // Points in the fitted power range
+    pub fit_r_squared: Option<f64>,             // Coefficient of determination of the fit, needs 3 points
+    pub kinks: Vec<Kink>,                       // Kinks flagged during the run
+}
+
+/// Least-squares line through (x, y) points: slope, intercept and R²
+fn linear_fit(points: &[(f64, f64)]) -> Option<(f64, f64, Option<f64>)> {
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    if sxx.abs() < f64::EPSILON {
+        return None;
+    }
+    let slope = sxy / sxx;
+    let intercept = mean_y - slope * mean_x;
+
+    let r_squared = if points.len() >= 3 {
+        let ss_tot: f64 = points.iter().map(|p| (p.1 - mean_y).powi(2)).sum();
+        let ss_res: f64 = points.iter().map(|p| (p.1 - (slope * p.0 + intercept)).powi(2)).sum();
+        (ss_tot > 0.0).then(|| 1.0 - ss_res / ss_tot)
+    } else {
+        None
+    };
+    Some((slope, intercept, r_squared))
+}
+
+/// Analyze an L-I curve given as (current in mA, power in mW) points sorted by current.
+///
+/// A line is fitted to the points between 20 % and 80 % of the maximum power, up to the
+/// maximum so that any rollover is excluded; its zero crossing is the threshold current
+/// and its slope the slope efficiency.
+pub fn analyze_curve(wavelength_nm: u32, points: &[(f64, f64)], kinks: Vec<Kink>) -> CurveAnalysis {
+    let max = points.iter().copied().filter(|p| p.1.is_finite()).max_by(|a, b| a.1.total_cmp(&b.1));
+
+    let (fit_points, fit) = match max {
+        Some((max_current_ma, max_power_mw)) if max_power_mw > 0.0 => {
+            let (low, high) = (FIT_POWER_FRACTIONS.0 * max_power_mw, FIT_POWER_FRACTIONS.1 * max_power_mw);
+            let region: Vec<(f64, f64)> = points
+                .iter()
+                .copied()
+                .filter(|p| p.0 <= max_current_ma && p.1 >= low && p.1 <= high)
+                .collect();
+            (region.len(), linear_fit(&region))
+        }
+        _ => (0, None),
+    };
+    let (slope, threshold, r_squared) = match fit {
+        Some((slope, intercept, r_squared)) if slope > 0.0 => (Some(slope), Some(-intercept / slope), r_squared),
+        _ => (None, None, None),
+    };
+
+    CurveAnalysis {
+        wavelength_nm,
+        points: points.len(),
+        max_power_mw: max.map(|p| p.1),
+        current_at_max_power_ma: max.map(|p| p.0),
+        threshold_ma: threshold,
+        slope_efficiency_w_per_a: slope,
+        fit_points,
+        fit_r_squared: r_squared,
+        kinks,
+    }
+}
+
+/// Analyze the L-I curve of every wavelength in the records, in wavelength order
+pub fn analyze_run(records: &[MeasurementRecord], kinks: &[KinkFlag]) -> Vec<CurveAnalysis> {
+    let mut wavelengths: Vec<u32> = records.iter().map(|r| r.wavelength_nm).collect();
+    wavelengths.sort_unstable();
+    wavelengths.dedup();
+
+    wavelengths
+        .into_iter()
+        .map(|wavelength_nm| {
+            let mut curve: Vec<(f64, f64)> = records
+                .iter()
+                .filter(|r| r.wavelength_nm == wavelength_nm)
+                .map(|r| (r.current_ma, r.power_mw))
+                .collect();
+            curve.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let curve_kinks = kinks
+                .iter()
+                .filter(|k| k.wavelength_nm == wavelength_nm)
+                .map(|k| k.kink.clone())
+                .collect();
+            analyze_curve(wavelength_nm, &curve, curve_kinks)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Slope efficiency halves at 30 mA
+    const KINKED: [(f64, f64); 5] = [(10.0, 0.0), (20.0, 10.0), (30.0, 20.0), (40.0, 25.0), (50.0, 30.0)];
+
+    #[test]
+    fn straight_curve_has_no_kinks() {
+        let points: Vec<(f64, f64)> = (1..=5).map(|i| (i as f64 * 10.0, i as f64 * 5.0)).collect();
+        assert!(detect_kinks(&points, 0.2).is_empty());
+    }
+
+    #[test]
+    fn slope_change_above_tolerance_is_a_kink() {
+        let kinks = detect_kinks(&KINKED, 0.2);
+        assert_eq!(kinks.len(), 1);
+        assert_eq!(kinks[0].current_ma, 30.0);
+        assert_eq!(kinks[0].slope_before_mw_per_ma, 1.0);
+        assert_eq!(kinks[0].slope_after_mw_per_ma, 0.5);
+        assert_eq!(kinks[0].relative_change, 0.5);
+    }
+
+    #[test]
+    fn slope_change_up_to_tolerance_is_not_a_kink() {
+        assert!(detect_kinks(&KINKED, 0.5).is_empty());
+        assert!(detect_kinks(&KINKED, 0.6).is_empty());
+    }
+
+    #[test]
+    fn lasing_threshold_is_not_a_kink() {
+        let points = [(0.0, 0.0), (10.0, 0.01), (20.0, 1.0), (30.0, 11.0), (40.0, 21.0)];
+        assert!(detect_kinks(&points, 0.2).is_empty());
+    }
+
+    #[test]
+    fn too_few_points_have_no_kinks() {
+        assert!(detect_kinks(&[], 0.2).is_empty());
+        assert!(detect_kinks(&[(10.0, 1.0), (20.0, 2.0)], 0.2).is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+use super::analysis::{self, CurveAnalysis};
+use super::output::with_suffix;
+use super::plot::{self, PlotFormat};
+use super::schema;
+use super::{Error, FailureKind};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// L-I data of a previous run, loaded from its CSV file
+#[derive(Debug, Clone)]
+pub struct LoadedRun {
+    pub name: String,                 // File name without directory
+    pub points: Vec<(u32, f64, f64)>, // (wavelength in nm, current in mA, power in mW)
+}
+
+impl LoadedRun {
+    /// Wavelengths of the run, in ascending order
+    pub fn wavelengths(&self) -> Vec<u32> {
+        let mut wavelengths: Vec<u32> = self.points.iter().map(|p| p.0).collect();
+        wavelengths.sort_unstable();
+        wavelengths.dedup();
+        wavelengths
+    }
+
+    /// L-I curve of one wavelength, sorted by current
+    pub fn curve(&self, wavelength_nm: u32) -> Vec<(f64, f64)> {
+        let mut curve: Vec<(f64, f64)> = self.points
+            .iter()
+            .filter(|p| p.0 == wavelength_nm)
+            .map(|p| (p.1, p.2))
+            .collect();
+        curve.sort_by(|a, b| a.0.total_cmp(&b.0));
+        curve
+    }
+}
+
+/// Load the current, power and wavelength columns of a data file written by any
+/// version of this application, see `schema::read_data_file`
+pub fn load_run(path: &Path) -> Result<LoadedRun, Error> {
+    let file = schema::read_data_file(path)?;
+    let points: Vec<(u32, f64, f64)> = file
+        .records
+        .iter()
+        // Files from before multi-wavelength sweeps hold a single pass without a wavelength
+        .filter_map(|r| Some((r.wavelength_nm.unwrap_or(0), r.current_ma, r.power_mw?)))
+        .collect();
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    info!("Loaded {} points from {}", points.len(), name);
+    Ok(LoadedRun { name, points })
+}
+
+/// Linear interpolation of the power at `current_ma` on a curve sorted by current,
+/// `None` outside the measured range
+pub fn power_at(curve: &[(f64, f64)], current_ma: f64) -> Option<f64> {
+    curve.windows(2).find(|w| w[0].0 <= current_ma && current_ma <= w[1].0).map(|w| {
+        let span = w[1].0 - w[0].0;
+        if span.abs() < f64::EPSILON {
+            w[0].1
+        } else {
+            w[0].1 + (w[1].1 - w[0].1) * (current_ma - w[0].0) / span
+        }
+    })
+}
+
+/// One run at one wavelength compared to the first (baseline) run
+#[derive(Debug, Clone)]
+pub struct RunComparison {
+    pub run: String,
+    pub wavelength_nm: u32,
+    pub analysis: CurveAnalysis,
+    pub power_at_reference_mw: Option<f64>,
+    pub delta_threshold_ma: Option<f64>,             // Difference to the baseline run
+    pub delta_slope_efficiency_w_per_a: Option<f64>, // Difference to the baseline run
+    pub delta_power_at_reference_mw: Option<f64>,    // Difference to the baseline run
+}
+
+/// Highest current measured in every run, used as the default reference current
+pub fn common_max_current(runs: &[LoadedRun]) -> Option<f64> {
+    runs.iter()
+        .map(|run| run.points.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max))
+        .reduce(f64::min)
+        .filter(|c| c.is_finite())
+}
+
+/// Compare the L-I curves of several runs, wavelength by wavelength, against the first run
+pub fn compare_runs(runs: &[LoadedRun], reference_current_ma: f64, kink_tolerance: f64) -> Vec<RunComparison> {
+    let Some(baseline) = runs.first() else {
+        return Vec::new();
+    };
+    let mut comparisons = Vec::new();
+    for wavelength_nm in baseline.wavelengths() {
+        let base_curve = baseline.curve(wavelength_nm);
+        let base = analysis::analyze_curve(wavelength_nm, &base_curve, Vec::new());
+        let base_power = power_at(&base_curve, reference_current_ma);
+
+        for run in runs {
+            let curve = run.curve(wavelength_nm);
+            if curve.is_empty() {
+                continue;
+            }
+            let analysis = analysis::analyze_curve(wavelength_nm, &curve, analysis::detect_kinks(&curve, kink_tolerance));
+            let power = power_at(&curve, reference_current_ma);
+            let delta = |a: Option<f64>, b: Option<f64>| Some(a? - b?);
+            comparisons.push(RunComparison {
+                run: run.name.clone(),
+                wavelength_nm,
+                delta_threshold_ma: delta(analysis.threshold_ma, base.threshold_ma),
+                delta_slope_efficiency_w_per_a: delta(analysis.slope_efficiency_w_per_a, base.slope_efficiency_w_per_a),
+                delta_power_at_reference_mw: delta(power, base_power),
+                power_at_reference_mw: power,
+                analysis,
+            });
+        }
+    }
+    comparisons
+}
+
+/// Comparison as a text table for the console
+pub fn format_table(comparisons: &[RunComparison], reference_current_ma: f64) -> String {
+    let value = |v: Option<f64>, precision: usize| v.map_or("-".to_string(), |v| format!("{:.*}", precision, v));
+    let mut table = format!(
+        "{:<48} {:>6} {:>10} {:>10} {:>10} {:>10} {:>12} {:>12}\n",
+        "run", "nm", "Ith (mA)", "dIth", "SE (W/A)", "dSE", format!("P@{}mA", reference_current_ma), "dP (mW)"
+    );
+    for c in comparisons {
+        table.push_str(&format!(
+            "{:<48} {:>6} {:>10} {:>10} {:>10} {:>10} {:>12} {:>12}\n",
+            c.run,
+            c.wavelength_nm,
+            value(c.analysis.threshold_ma, 2),
+            value(c.delta_threshold_ma, 2),
+            value(c.analysis.slope_efficiency_w_per_a, 4),
+            value(c.delta_slope_efficiency_w_per_a, 4),
+            value(c.power_at_reference_mw, 4),
+            value(c.delta_power_at_reference_mw, 4),
+        ));
+    }
+    table
+}
+
+/// Save the comparison table as `<base>.csv` and an overlay of the L-I curves as `<base>_li.png`
+pub fn save_comparison(
+    base: &Path,
+    runs: &[LoadedRun],
+    comparisons: &[RunComparison],
+    reference_current_ma: f64,
+) -> Result<Vec<PathBuf>, Error> {
+    let csv_path = with_suffix(base, ".csv");
+    let mut writer = csv::Writer::from_path(&csv_path).map_err(|e| Error::data(format!("Failed to create {}", csv_path.display()), e))?;
+    let value = |v: Option<f64>| v.map_or(String::new(), |v| v.to_string());
+    let write = |writer: &mut csv::Writer<File>, row: Vec<String>| writer.write_record(row)
+        .map_err(|e| Error::data(format!("Failed to write {}", csv_path.display()), e));
+    write(&mut writer, [
+        "run", "wavelength_nm", "threshold_mA", "delta_threshold_mA", "slope_efficiency_W_per_A",
+        "delta_slope_efficiency_W_per_A", "reference_current_mA", "power_at_reference_mW", "delta_power_at_reference_mW",
+        "max_power_mW", "kinks",
+    ].map(String::from).to_vec())?;
+    for c in comparisons {
+        write(&mut writer, vec![
+            c.run.clone(),
+            c.wavelength_nm.to_string(),
+            value(c.analysis.threshold_ma),
+            value(c.delta_threshold_ma),
+            value(c.analysis.slope_efficiency_w_per_a),
+            value(c.delta_slope_efficiency_w_per_a),
+            reference_current_ma.to_string(),
+            value(c.power_at_reference_mw),
+            value(c.delta_power_at_reference_mw),
+            value(c.analysis.max_power_mw),
+            c.analysis.kinks.len().to_string(),
+        ])?;
+    }
+    writer.flush().map_err(|e| Error::data(format!("Failed to write {}", csv_path.display()), e))?;
+    info!("Run comparison saved to {}", csv_path.display());
+
+    let curves: Vec<(String, Vec<(f64, f64)>)> = runs
+        .iter()
+        .flat_map(|run| {
+            let several = run.wavelengths().len() > 1;
+            run.wavelengths().into_iter().map(move |wavelength_nm| {
+                let label = if several { format!("{} ({} nm)", run.name, wavelength_nm) } else { run.name.clone() };
+                (label, run.curve(wavelength_nm))
+            })
+        })
+        .collect();
+    let plot_path = plot::save_overlay_plot(base, PlotFormat::Png, "L-I comparison", &curves)
+        .map_err(|e| Error::data("Failed to save comparison plot", e))?;
+
+    Ok(vec![csv_path, plot_path])
+}
+
+/// Load two or more run files, compare them against the first one and save the table and
+/// overlay plot as `comparison_<timestamp>` in `directory`. Returns the table for display
+/// and the files written.
+pub fn compare_files(
+    paths: &[PathBuf],
+    reference_current_ma: Option<f64>,
+    kink_tolerance: f64,
+    directory: &Path,
+) -> Result<(String, Vec<PathBuf>), Error> {
+    if paths.len() < 2 {
+        return Err(Error::Config("At least two run files are needed for a comparison".into()));
+    }
+    let runs = paths.iter().map(|path| load_run(path)).collect::<Result<Vec<_>, _>>()?;
+
+    let reference_current_ma = match reference_current_ma.or_else(|| common_max_current(&runs)) {
+        Some(current) => current,
+        None => return Err(Error::new(FailureKind::Io, "The run files contain no measurements")),
+    };
+    let comparisons = compare_runs(&runs, reference_current_ma, kink_tolerance);
+
+    std::fs::create_dir_all(directory).map_err(|e| Error::data(format!("Failed to create {}", directory.display()), e))?;
+    let base = directory.join(chrono::Local::now().format("comparison_%Y-%m-%d_%H-%M-%S").to_string());
+    let saved = save_comparison(&base, &runs, &comparisons, reference_current_ma)?;
+
+    Ok((format_table(&comparisons, reference_current_ma), saved))
+}
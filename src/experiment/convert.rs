@@ -0,0 +1,219 @@
+use super::data::{MeasurementRecord, RecordTags, RunMetadata};
+use super::output::{self, OutputConfig, OutputFormat};
+use super::schema::{self, StoredRecord};
+use super::{Error, PowerUnit};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Measurement record rebuilt from a stored one. Quantities missing from older files
+/// are left empty, or zero where the record requires a value.
+pub(super) fn to_measurement(r: &StoredRecord) -> MeasurementRecord {
+    let (power, power_unit) = match (r.power, r.power_unit.as_deref()) {
+        (Some(power), Some("mW")) => (power, PowerUnit::MilliWatt),
+        (Some(power), Some("dBm")) => (power, PowerUnit::DBm),
+        // Without a unit column the dBm value is the reading
+        _ => (r.power_dbm.unwrap_or(f64::NAN), PowerUnit::DBm),
+    };
+    let (power_dbm, power_mw) = match (r.power_dbm, r.power_mw) {
+        (Some(dbm), Some(mw)) => (dbm, mw),
+        _ => power_unit.dbm_and_mw(power),
+    };
+    let timestamp = r.timestamp.clone().unwrap_or_default();
+    MeasurementRecord {
+        run_id: r.run_id.clone().unwrap_or_default(),
+        sample_id: r.sample_id.clone().unwrap_or_default(),
+        repeat_index: r.repeat_index.unwrap_or_default(),
+        // Files from before the plan index was recorded hold the points in plan order
+        point_index: r.point_index.or(r.set_order).unwrap_or_default(),
+        timestamp: timestamp.clone(),
+        elapsed_s: r.elapsed_s.unwrap_or(f64::NAN),
+        current_ma: r.current_ma,
+        measured_current_ma: r.measured_current_ma,
+        current_mismatch: r.current_mismatch.unwrap_or(false),
+        voltage_v: r.voltage_v,
+        tec_temperature_c: r.tec_temperature_c,
+        power,
+        power_unit,
+        power_dbm,
+        power_mw,
+        module: r.module.unwrap_or_default(),
+        port: r.port.unwrap_or_default(),
+        wavelength_nm: r.wavelength_nm.unwrap_or_default(),
+        set_order: r.set_order.unwrap_or_default(),
+        power_setpoint_mw: r.power_setpoint_mw,
+        current_set_at: r.current_set_at.clone().unwrap_or_else(|| timestamp.clone()),
+        power_read_at: r.power_read_at.clone().unwrap_or(timestamp),
+        read_latency_ms: r.read_latency_ms.unwrap_or(f64::NAN),
+    }
+}
+
+/// Reduction of dense runs on export. Windows never span two passes or channels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Downsample {
+    Decimate(usize), // Keep the first point of every n
+    Average(usize),  // Mean of every n consecutive points
+}
+
+impl Downsample {
+    /// Suffix of the exported files, so they never replace the full-resolution ones
+    fn suffix(&self) -> String {
+        match self {
+            Downsample::Decimate(n) => format!("_every{}", n),
+            Downsample::Average(n) => format!("_avg{}", n),
+        }
+    }
+
+    /// Apply to records in acquisition order
+    pub fn apply(&self, records: &[MeasurementRecord]) -> Vec<MeasurementRecord> {
+        let (Downsample::Decimate(n) | Downsample::Average(n)) = *self;
+        let n = n.max(1);
+        records
+            .chunk_by(|a, b| (a.module, a.port, a.wavelength_nm, a.repeat_index) == (b.module, b.port, b.wavelength_nm, b.repeat_index))
+            .flat_map(|pass| pass.chunks(n))
+            .map(|window| match self {
+                Downsample::Decimate(_) => window[0].clone(),
+                Downsample::Average(_) => average(window),
+            })
+            .collect()
+    }
+}
+
+/// Mean of a window of points. Power is averaged in mW, as a mean of dBm values is not a
+/// mean power; the time and indices are those of the middle point.
+fn average(window: &[MeasurementRecord]) -> MeasurementRecord {
+    let mean = |f: &dyn Fn(&MeasurementRecord) -> Option<f64>| {
+        let values: Vec<f64> = window.iter().filter_map(f).filter(|v| v.is_finite()).collect();
+        (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+    };
+    let mut record = window[window.len() / 2].clone();
+    record.elapsed_s = mean(&|r| Some(r.elapsed_s)).unwrap_or(f64::NAN);
+    record.current_ma = mean(&|r| Some(r.current_ma)).unwrap_or(f64::NAN);
+    record.measured_current_ma = mean(&|r| r.measured_current_ma);
+    record.voltage_v = mean(&|r| r.voltage_v);
+    record.tec_temperature_c = mean(&|r| r.tec_temperature_c);
+    record.power_setpoint_mw = mean(&|r| r.power_setpoint_mw);
+    record.read_latency_ms = mean(&|r| Some(r.read_latency_ms)).unwrap_or(f64::NAN);
+    record.current_mismatch = window.iter().any(|r| r.current_mismatch);
+    let (power_dbm, power_mw) = PowerUnit::MilliWatt.dbm_and_mw(mean(&|r| Some(r.power_mw)).unwrap_or(f64::NAN));
+    record.power_dbm = power_dbm;
+    record.power_mw = power_mw;
+    record.power = match record.power_unit {
+        PowerUnit::MilliWatt => power_mw,
+        PowerUnit::DBm => power_dbm,
+    };
+    record
+}
+
+/// Base path of the run a data file belongs to, e.g. `data/run` for `data/run.csv.gz`
+fn run_base(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    path.with_file_name(stem)
+}
+
+/// Format a data file was written in, from its extension
+fn source_format(path: &Path) -> Option<OutputFormat> {
+    let name = path.to_string_lossy();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    if name.ends_with(".jsonl") {
+        Some(OutputFormat::Jsonl)
+    } else if name.ends_with(".json") {
+        Some(OutputFormat::Json)
+    } else if name.ends_with(".csv") {
+        Some(OutputFormat::Csv)
+    } else {
+        None
+    }
+}
+
+/// JSON sidecar of the run, if it exists and can be read
+fn read_sidecar(base: &Path, suffix: &str) -> Option<serde_json::Value> {
+    let path = output::with_suffix(base, suffix);
+    let text = std::fs::read_to_string(&path).ok()?;
+    match serde_json::from_str(&text) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!("Ignoring {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Re-export a saved run (CSV, JSON Lines or JSON, of any schema version) to `formats`,
+/// next to the original file. The configuration and experiment name are taken from the
+/// run's manifest and the metadata from its metadata file, when they exist. Downsampled
+/// files get a suffix, e.g. `run_avg10.csv`, and leave the original untouched.
+pub fn convert_file(
+    path: &Path,
+    formats: &[OutputFormat],
+    output: &OutputConfig,
+    downsample: Option<Downsample>,
+) -> Result<Vec<PathBuf>, Error> {
+    let file = schema::read_data_file(path)?;
+    let base = run_base(path);
+    let source = source_format(path).filter(|_| downsample.is_none());
+
+    let manifest = read_sidecar(&base, "_manifest.json");
+    let experiment = manifest.as_ref()
+        .and_then(|m| m.get("experiment")?.as_str().map(str::to_string))
+        .unwrap_or_else(|| "unknown".to_string());
+    let config = manifest.as_ref().and_then(|m| m.get("config").cloned()).unwrap_or_default();
+    let mut metadata: RunMetadata = read_sidecar(&base, "_metadata.json")
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or_default();
+
+    let records: Vec<MeasurementRecord> = file.records.iter().map(to_measurement).collect();
+    if metadata.sample_id.is_empty() {
+        metadata.sample_id = records.first().map(|r| r.sample_id.clone()).unwrap_or_default();
+    }
+    // Files from before the run was recorded in every row are named after it
+    let tags = RecordTags {
+        run_id: records.first().map(|r| r.run_id.clone()).filter(|id| !id.is_empty())
+            .unwrap_or_else(|| base.file_name().unwrap_or_default().to_string_lossy().to_string()),
+        sample_id: metadata.sample_id.clone(),
+        repeat_index: metadata.repeat_index,
+    };
+    let records: Vec<MeasurementRecord> = records.iter().map(|r| output::output_record(r, &tags, &output.timezone)).collect();
+    let (base, records) = match downsample {
+        Some(downsample) => {
+            let reduced = downsample.apply(&records);
+            info!("Downsampled {} points to {} ({:?})", records.len(), reduced.len(), downsample);
+            (output::with_suffix(&base, &downsample.suffix()), reduced)
+        }
+        None => (base, records),
+    };
+
+    let mut saved = Vec::new();
+    for &format in formats {
+        // Writing the source format again would overwrite the file being converted
+        if Some(format) == source {
+            return Err(Error::Config(format!("{} is already a {:?} file", path.display(), format)));
+        }
+        let result = match format {
+            OutputFormat::Csv | OutputFormat::Jsonl => {
+                let comments = manifest.as_ref().map(output::csv_header_comments).unwrap_or_default();
+                let mut writer = output::create_streamed(format, &base, &tags, output, &comments)
+                    .map_err(|e| Error::data(format!("Failed to create {:?} output", format), e))?;
+                records.iter().for_each(|r| writer.on_record(r));
+                writer.on_finish(true);
+                Ok(writer.paths())
+            }
+            OutputFormat::Json => output::save_json(&base, &experiment, &config, &metadata, &records, output.compression)
+                .map(|p| vec![p]),
+            OutputFormat::Parquet => output::save_parquet(&base, &records, output.compression).map(|p| vec![p]),
+            OutputFormat::Hdf5 => output::save_hdf5(&base, output.hdf5_file.as_deref(), &experiment, &config, &metadata, &records)
+                .map(|p| vec![p]),
+            OutputFormat::Xlsx => output::save_xlsx(&base, &experiment, &config, &metadata, &records).map(|p| vec![p]),
+            OutputFormat::Mat => output::save_mat(&base, &experiment, &config, &metadata, &records).map(|p| vec![p]),
+            OutputFormat::Origin => output::save_origin(&base, &records).map(|p| vec![p]),
+        };
+        match result {
+            Ok(paths) => saved.extend(paths),
+            Err(e) => return Err(Error::data(format!("Failed to save {:?} output", format), e)),
+        }
+    }
+
+    info!("Converted {} (schema version {}) to {:?}", path.display(), file.schema_version, formats);
+    Ok(saved)
+}
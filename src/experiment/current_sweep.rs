@@ -0,0 +1,1252 @@
+use super::analysis;
+use super::data::{DarkReading, KinkFlag, MeasurementRecord, Rollover, RunMetadata, WarmUpReading};
+use super::progress::{SweepObserver, SweepProgress};
+use super::validation::{format_violations, optional_limit, ConfigViolation};
+use super::{configure_power_meter, instruments, verify_tec_regulation, Error, Experiment, ZEROING_WAIT};
+use crate::devices::{CurrentSource, DeviceRegistry, OpticalPowerMeter};
+use crate::devices::cld1015::MAX_SAFE_CURRENT_AMPS;
+use crate::devices::mpm210h::{AVERAGE_TIME_RANGE_MS, PORTS_PER_MODULE, WAVELENGTH_RANGE_NM};
+use chrono::{DateTime, Utc};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tracing::{info, error, warn};
+
+/// Configuration for a current sweep experiment.
+///
+/// Every field is optional when loaded from a config file; missing fields take
+/// the values from `Default`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CurrentSweepConfig {
+    pub module: u8,                  // MPM210H module number to use
+    pub port: u8,                    // MPM210H port number to use (1-4)
+    pub start_ma: f64,               // Start current in mA
+    pub stop_ma: f64,                // End current in mA (below start_ma for a descending sweep)
+    pub step_ma: f64,                // Step size in mA, always positive
+    pub stabilization_delay_ms: u64, // Delay after setting current before measuring
+    pub wavelengths_nm: Vec<u32>,    // Calibration wavelengths in nm, one sweep pass per wavelength
+    pub averaging_time_ms: f64,      // Power meter averaging time in ms
+    pub power_unit: PowerUnit,       // Power measurement unit
+    pub point_retries: u32,          // Retries for a failed point before aborting the sweep
+    pub retry_backoff_ms: u64,       // Initial delay before retrying a point, doubled on each retry
+    #[serde(with = "optional_limit")]
+    pub max_power_dbm: Option<f64>,  // Abort the sweep if any reading exceeds this power, "off" for no limit
+    pub settling: Option<SettlingConfig>, // Wait for readings to settle instead of a fixed delay
+    pub kink_tolerance: f64,         // Relative change in dP/dI flagged as a kink
+    #[serde(with = "optional_limit")]
+    pub cod_drop_fraction: Option<f64>, // Emergency stop if power drops by this fraction while current increases, "off" to disable
+    pub disable_tec_after_run: bool, // Turn the TEC off as part of the shutdown sequence
+    pub randomize_order: bool,       // Visit the current points in random order to decouple drift from current
+    pub random_seed: Option<u64>,    // Seed for the random order, random if not set
+    pub warm_up: Option<WarmUpConfig>, // Hold the laser at a fixed current before the sweep
+    pub max_slew_rate_ma_per_s: Option<f64>, // Ramp through intermediate setpoints when a current change would be faster
+    pub tec_check: Option<TecCheckConfig>, // Verify the TEC reached its setpoint before the laser is turned on
+    pub current_tolerance_ma: Option<f64>, // Measure the delivered current at each point and flag deviations above this
+    pub dark_check_interval_s: Option<u64>, // Turn the laser off for a dark reading this often during the sweep
+    pub rezero_interval_min: Option<u64>, // Turn the laser off and re-zero the MPM210H this often during the sweep
+    pub rollover_points: Option<u32>, // End a pass once power falls with rising current for this many consecutive points
+    pub measure_voltage: bool,       // Record the LD forward voltage at each point
+    pub measure_temperature: bool,   // Record the laser temperature at each point
+}
+
+impl Default for CurrentSweepConfig {
+    fn default() -> Self {
+        CurrentSweepConfig {
+            module: 0,                    // Module 0
+            port: 2,                      // Port 2
+            start_ma: 10.0,               // Start at 10 mA
+            stop_ma: 100.0,               // End at 100 mA
+            step_ma: 5.0,                 // 5 mA steps
+            stabilization_delay_ms: 50,   // 50ms stabilization delay
+            wavelengths_nm: vec![980],    // 980nm wavelength
+            averaging_time_ms: 100.0,     // 100ms averaging time
+            power_unit: PowerUnit::DBm,   // Use dBm units
+            point_retries: 3,             // Retry a failed point up to 3 times
+            retry_backoff_ms: 200,        // 200ms, 400ms, 800ms between retries
+            max_power_dbm: Some(20.0),    // Stop if the reading exceeds 20 dBm (100 mW)
+            settling: None,               // Use the fixed stabilization delay
+            kink_tolerance: 0.2,          // Flag slope changes above 20%
+            cod_drop_fraction: Some(0.3), // Emergency stop on a 30% power drop
+            disable_tec_after_run: false, // Keep the TEC regulating after the run
+            randomize_order: false,       // Visit points in sweep order
+            random_seed: None,            // Fresh random order for each run
+            warm_up: None,                // Start the sweep straight away
+            max_slew_rate_ma_per_s: None, // Step straight to each point
+            tec_check: Some(TecCheckConfig::default()), // Require the TEC to be at its setpoint
+            current_tolerance_ma: None,   // Trust the controller to deliver the setpoint
+            dark_check_interval_s: None,  // Dark references only before and after the sweep
+            rezero_interval_min: None,    // Zero only before the sweep
+            rollover_points: None,        // Always sweep up to stop_ma
+            measure_voltage: true,        // Record the forward voltage
+            measure_temperature: true,    // Record the laser temperature
+        }
+    }
+}
+
+impl CurrentSweepConfig {
+    /// Check every field and report all problems found, not just the first one
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        let max_safe_ma = MAX_SAFE_CURRENT_AMPS * 1000.0;
+
+        if self.step_ma.is_nan() || self.step_ma <= 0.0 {
+            violations.push(ConfigViolation::new("step_ma", self.step_ma, "step size must be positive")
+                .allowed("> 0 mA"));
+        }
+        for (field, value) in [("start_ma", self.start_ma), ("stop_ma", self.stop_ma)] {
+            if !(0.0..=max_safe_ma).contains(&value) {
+                violations.push(ConfigViolation::new(field, value, "current outside the driver safety range")
+                    .allowed(format!("0-{} mA", max_safe_ma)));
+            }
+        }
+        violations.extend(port_violation(self.port));
+        if self.wavelengths_nm.is_empty() {
+            violations.push(ConfigViolation::new("wavelengths_nm", "[]", "at least one wavelength is required"));
+        }
+        for (i, &wavelength_nm) in self.wavelengths_nm.iter().enumerate() {
+            violations.extend(wavelength_violation(&format!("wavelengths_nm[{}]", i), wavelength_nm));
+        }
+        violations.extend(averaging_time_violation(self.averaging_time_ms));
+        violations.extend(power_limit_violations(self.max_power_dbm, self.cod_drop_fraction));
+        if let Some(settling) = &self.settling {
+            if settling.tolerance_db.is_nan() || settling.tolerance_db <= 0.0 {
+                violations.push(ConfigViolation::new("settling.tolerance_db", settling.tolerance_db,
+                    "tolerance must be positive").allowed("> 0 dB"));
+            }
+            if settling.interval_ms == 0 {
+                violations.push(ConfigViolation::new("settling.interval_ms", settling.interval_ms,
+                    "interval must be positive").allowed("> 0 ms"));
+            }
+            if settling.max_wait_ms < settling.interval_ms {
+                violations.push(ConfigViolation::new("settling.max_wait_ms", settling.max_wait_ms,
+                    "maximum wait is shorter than one settling interval")
+                    .allowed(format!(">= {} ms (settling.interval_ms)", settling.interval_ms)));
+            }
+        }
+        if let Some(warm_up) = &self.warm_up {
+            if !(0.0..=max_safe_ma).contains(&warm_up.current_ma) {
+                violations.push(ConfigViolation::new("warm_up.current_ma", warm_up.current_ma,
+                    "current outside the driver safety range").allowed(format!("0-{} mA", max_safe_ma)));
+            }
+            if warm_up.record_interval_ms == Some(0) {
+                violations.push(ConfigViolation::new("warm_up.record_interval_ms", 0,
+                    "interval must be positive").allowed("> 0 ms"));
+            }
+        }
+        if let Some(rate) = self.max_slew_rate_ma_per_s {
+            if !rate.is_finite() || rate <= 0.0 {
+                violations.push(ConfigViolation::new("max_slew_rate_ma_per_s", rate,
+                    "slew rate must be a positive number").allowed("> 0 mA/s"));
+            }
+        }
+        if let Some(tec_check) = &self.tec_check {
+            violations.extend(tec_check.violations().into_iter().map(|v| v.in_section("tec_check")));
+        }
+        if let Some(tolerance) = self.current_tolerance_ma {
+            if tolerance.is_nan() || tolerance <= 0.0 {
+                violations.push(ConfigViolation::new("current_tolerance_ma", tolerance,
+                    "tolerance must be positive").allowed("> 0 mA"));
+            }
+        }
+        if self.dark_check_interval_s == Some(0) {
+            violations.push(ConfigViolation::new("dark_check_interval_s", 0,
+                "interval must be positive").allowed("> 0 s"));
+        }
+        if self.rezero_interval_min == Some(0) {
+            violations.push(ConfigViolation::new("rezero_interval_min", 0,
+                "interval must be positive").allowed("> 0 min"));
+        }
+        if let Some(points) = self.rollover_points {
+            if points == 0 {
+                violations.push(ConfigViolation::new("rollover_points", points,
+                    "number of points must be positive").allowed(">= 1"));
+            }
+            if self.randomize_order {
+                violations.push(ConfigViolation::new("rollover_points", points,
+                    "rollover detection needs the points in sweep order")
+                    .allowed("leave unset when randomize_order = true"));
+            }
+        }
+        if self.kink_tolerance.is_nan() || self.kink_tolerance <= 0.0 {
+            violations.push(ConfigViolation::new("kink_tolerance", self.kink_tolerance,
+                "relative slope change must be positive").allowed("> 0"));
+        }
+
+        violations
+    }
+
+    /// Build a configuration in code, starting from the defaults. The result is checked
+    /// once by `build`, so a bad value is reported before any hardware is touched.
+    ///
+    /// ```no_run
+    /// use cld1015_mpm210h::experiment::CurrentSweepConfig;
+    ///
+    /// let config = CurrentSweepConfig::builder()
+    ///     .channel(0, 1)
+    ///     .range(0.0, 150.0, 0.5)
+    ///     .wavelengths_nm([1310, 1550])
+    ///     .averaging_time_ms(50.0)
+    ///     .build()?;
+    /// # Ok::<(), cld1015_mpm210h::Error>(())
+    /// ```
+    pub fn builder() -> CurrentSweepConfigBuilder {
+        CurrentSweepConfigBuilder { config: CurrentSweepConfig::default() }
+    }
+}
+
+/// Chained setters for a `CurrentSweepConfig`, see `CurrentSweepConfig::builder`.
+/// Optional settings accept either a value or `None` to turn them off.
+#[derive(Debug, Clone)]
+pub struct CurrentSweepConfigBuilder {
+    config: CurrentSweepConfig,
+}
+
+impl CurrentSweepConfigBuilder {
+    /// MPM210H module and port (1-4) to read
+    pub fn channel(mut self, module: u8, port: u8) -> Self {
+        self.config.module = module;
+        self.config.port = port;
+        self
+    }
+
+    /// Sweep from `start_ma` to `stop_ma` in steps of `step_ma`; a `stop_ma` below
+    /// `start_ma` sweeps downwards
+    pub fn range(mut self, start_ma: f64, stop_ma: f64, step_ma: f64) -> Self {
+        self.config.start_ma = start_ma;
+        self.config.stop_ma = stop_ma;
+        self.config.step_ma = step_ma;
+        self
+    }
+
+    /// Calibration wavelengths, one sweep pass per wavelength
+    pub fn wavelengths_nm(mut self, wavelengths_nm: impl IntoIterator<Item = u32>) -> Self {
+        self.config.wavelengths_nm = wavelengths_nm.into_iter().collect();
+        self
+    }
+
+    pub fn stabilization_delay_ms(mut self, delay_ms: u64) -> Self {
+        self.config.stabilization_delay_ms = delay_ms;
+        self
+    }
+
+    pub fn averaging_time_ms(mut self, averaging_time_ms: f64) -> Self {
+        self.config.averaging_time_ms = averaging_time_ms;
+        self
+    }
+
+    pub fn power_unit(mut self, power_unit: PowerUnit) -> Self {
+        self.config.power_unit = power_unit;
+        self
+    }
+
+    /// Retries of a failed point, the first after `backoff_ms` and each further one after twice as long
+    pub fn retries(mut self, point_retries: u32, backoff_ms: u64) -> Self {
+        self.config.point_retries = point_retries;
+        self.config.retry_backoff_ms = backoff_ms;
+        self
+    }
+
+    pub fn max_power_dbm(mut self, max_power_dbm: impl Into<Option<f64>>) -> Self {
+        self.config.max_power_dbm = max_power_dbm.into();
+        self
+    }
+
+    pub fn settling(mut self, settling: impl Into<Option<SettlingConfig>>) -> Self {
+        self.config.settling = settling.into();
+        self
+    }
+
+    pub fn kink_tolerance(mut self, kink_tolerance: f64) -> Self {
+        self.config.kink_tolerance = kink_tolerance;
+        self
+    }
+
+    pub fn cod_drop_fraction(mut self, fraction: impl Into<Option<f64>>) -> Self {
+        self.config.cod_drop_fraction = fraction.into();
+        self
+    }
+
+    pub fn disable_tec_after_run(mut self, disable: bool) -> Self {
+        self.config.disable_tec_after_run = disable;
+        self
+    }
+
+    /// Visit the points in random order, reproducibly when a seed is given
+    pub fn randomize_order(mut self, seed: impl Into<Option<u64>>) -> Self {
+        self.config.randomize_order = true;
+        self.config.random_seed = seed.into();
+        self
+    }
+
+    pub fn warm_up(mut self, warm_up: impl Into<Option<WarmUpConfig>>) -> Self {
+        self.config.warm_up = warm_up.into();
+        self
+    }
+
+    pub fn max_slew_rate_ma_per_s(mut self, rate: impl Into<Option<f64>>) -> Self {
+        self.config.max_slew_rate_ma_per_s = rate.into();
+        self
+    }
+
+    pub fn tec_check(mut self, tec_check: impl Into<Option<TecCheckConfig>>) -> Self {
+        self.config.tec_check = tec_check.into();
+        self
+    }
+
+    pub fn current_tolerance_ma(mut self, tolerance_ma: impl Into<Option<f64>>) -> Self {
+        self.config.current_tolerance_ma = tolerance_ma.into();
+        self
+    }
+
+    pub fn dark_check_interval_s(mut self, interval_s: impl Into<Option<u64>>) -> Self {
+        self.config.dark_check_interval_s = interval_s.into();
+        self
+    }
+
+    pub fn rezero_interval_min(mut self, interval_min: impl Into<Option<u64>>) -> Self {
+        self.config.rezero_interval_min = interval_min.into();
+        self
+    }
+
+    pub fn rollover_points(mut self, points: impl Into<Option<u32>>) -> Self {
+        self.config.rollover_points = points.into();
+        self
+    }
+
+    pub fn measure_voltage(mut self, measure: bool) -> Self {
+        self.config.measure_voltage = measure;
+        self
+    }
+
+    pub fn measure_temperature(mut self, measure: bool) -> Self {
+        self.config.measure_temperature = measure;
+        self
+    }
+
+    /// The configuration, or a `Config` error listing every value out of range
+    pub fn build(self) -> Result<CurrentSweepConfig, Error> {
+        let violations = self.config.violations();
+        if violations.is_empty() {
+            Ok(self.config)
+        } else {
+            Err(Error::Config(format_violations(&violations)))
+        }
+    }
+}
+
+/// MPM210H port outside the ports of a module
+pub(crate) fn port_violation(port: u8) -> Option<ConfigViolation> {
+    (port < 1 || port > PORTS_PER_MODULE).then(|| {
+        ConfigViolation::new("port", port, "MPM210H modules do not have this port")
+            .allowed(format!("1-{}", PORTS_PER_MODULE))
+    })
+}
+
+/// Calibration wavelength the MPM210H does not accept
+pub(crate) fn wavelength_violation(field: &str, wavelength_nm: u32) -> Option<ConfigViolation> {
+    (!WAVELENGTH_RANGE_NM.contains(&wavelength_nm)).then(|| {
+        ConfigViolation::new(field, wavelength_nm, "wavelength not accepted by the MPM210H")
+            .allowed(format!("{}-{} nm", WAVELENGTH_RANGE_NM.start(), WAVELENGTH_RANGE_NM.end()))
+    })
+}
+
+/// Averaging time the MPM210H does not accept
+pub(crate) fn averaging_time_violation(averaging_time_ms: f64) -> Option<ConfigViolation> {
+    (!AVERAGE_TIME_RANGE_MS.contains(&averaging_time_ms)).then(|| {
+        ConfigViolation::new("averaging_time_ms", averaging_time_ms, "averaging time not accepted by the MPM210H")
+            .allowed(format!("{}-{} ms", AVERAGE_TIME_RANGE_MS.start(), AVERAGE_TIME_RANGE_MS.end()))
+    })
+}
+
+/// Invalid `max_power_dbm` and `cod_drop_fraction` safety limits
+pub(crate) fn power_limit_violations(max_power_dbm: Option<f64>, cod_drop_fraction: Option<f64>) -> Vec<ConfigViolation> {
+    let mut violations = Vec::new();
+    if let Some(max_power_dbm) = max_power_dbm {
+        if !max_power_dbm.is_finite() {
+            violations.push(ConfigViolation::new("max_power_dbm", max_power_dbm, "power limit must be a finite number"));
+        }
+    }
+    if let Some(fraction) = cod_drop_fraction {
+        if fraction.is_nan() || fraction <= 0.0 || fraction >= 1.0 {
+            violations.push(ConfigViolation::new("cod_drop_fraction", fraction,
+                "power drop fraction must be between 0 and 1").allowed("0 < fraction < 1"));
+        }
+    }
+    violations
+}
+
+/// Settling detection: after setting the current, readings are taken every
+/// `interval_ms` until two consecutive ones agree within `tolerance_db`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettlingConfig {
+    pub tolerance_db: f64, // Maximum difference between consecutive readings in dB
+    pub interval_ms: u64,  // Delay between readings
+    pub max_wait_ms: u64,  // Give up waiting and use the last reading after this time
+}
+
+/// Warm-up phase: the laser is held at `current_ma` for `duration_s` before the
+/// sweep starts, optionally reading the power every `record_interval_ms`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WarmUpConfig {
+    pub current_ma: f64,                 // Warm-up current in mA
+    pub duration_s: u64,                 // How long to hold the warm-up current
+    pub record_interval_ms: Option<u64>, // Record power readings at this interval, none if not set
+}
+
+/// TEC regulation check: before the laser is enabled, the measured temperature must be
+/// within `tolerance_c` of the setpoint, waiting up to `timeout_s` for it to get there
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TecCheckConfig {
+    pub setpoint_c: Option<f64>, // Setpoint to program in °C, keep the controller setting if not set
+    pub tolerance_c: f64,        // Maximum difference between measured temperature and setpoint
+    pub timeout_s: u64,          // Fail if the temperature is not within tolerance after this time
+}
+
+impl Default for TecCheckConfig {
+    fn default() -> Self {
+        TecCheckConfig {
+            setpoint_c: None, // Use the setpoint configured on the controller
+            tolerance_c: 0.1, // Within 0.1 °C
+            timeout_s: 60,    // Give the TEC a minute to settle
+        }
+    }
+}
+
+impl TecCheckConfig {
+    /// Check every field and report all problems found
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        if let Some(setpoint_c) = self.setpoint_c {
+            if !setpoint_c.is_finite() {
+                violations.push(ConfigViolation::new("setpoint_c", setpoint_c, "setpoint must be a finite number"));
+            }
+        }
+        if self.tolerance_c.is_nan() || self.tolerance_c <= 0.0 {
+            violations.push(ConfigViolation::new("tolerance_c", self.tolerance_c, "tolerance must be positive")
+                .allowed("> 0 °C"));
+        }
+        violations
+    }
+}
+
+/// Power measurement unit
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PowerUnit {
+    #[serde(rename = "dBm")]
+    DBm,
+    #[serde(rename = "mW")]
+    MilliWatt,
+}
+
+impl PowerUnit {
+    /// Unit symbol, as written in the output files
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            PowerUnit::DBm => "dBm",
+            PowerUnit::MilliWatt => "mW",
+        }
+    }
+
+    /// Axis/column label for powers in this unit
+    pub fn label(&self) -> &'static str {
+        match self {
+            PowerUnit::DBm => "Power (dBm)",
+            PowerUnit::MilliWatt => "Power (mW)",
+        }
+    }
+
+    /// Convert a reading in this unit to mW
+    pub fn to_mw(&self, value: f64) -> f64 {
+        match self {
+            PowerUnit::DBm => 10_f64.powf(value / 10.0),
+            PowerUnit::MilliWatt => value,
+        }
+    }
+
+    /// Convert a reading in this unit to dBm
+    pub fn to_dbm(&self, value: f64) -> f64 {
+        match self {
+            PowerUnit::DBm => value,
+            PowerUnit::MilliWatt => 10.0 * value.log10(),
+        }
+    }
+
+    /// Express a reading in this unit in both dBm and mW
+    pub fn dbm_and_mw(&self, value: f64) -> (f64, f64) {
+        (self.to_dbm(value), self.to_mw(value))
+    }
+}
+
+/// Current sweep experiment: steps the CLD1015 current and records the optical power
+pub struct CurrentSweep {
+    config: CurrentSweepConfig,
+    records: Vec<MeasurementRecord>,
+    metadata: RunMetadata,
+    applied_current_ma: f64, // Last current sent to the CLD1015, start of the next slew-limited ramp
+    earlier: Vec<MeasurementRecord>, // Points of the interrupted run this one continues
+}
+
+impl CurrentSweep {
+    pub fn new(config: CurrentSweepConfig) -> Self {
+        CurrentSweep {
+            config,
+            records: Vec::new(),
+            metadata: RunMetadata::default(),
+            applied_current_ma: 0.0,
+            earlier: Vec::new(),
+        }
+    }
+
+    /// Continue an interrupted run: the points in `earlier` are not measured again but
+    /// passed on with the new ones, so the results hold the whole sweep. Passes before the
+    /// last one with a point are complete, or ended at rollover.
+    pub fn resuming(mut self, earlier: Vec<MeasurementRecord>) -> Self {
+        self.earlier = earlier;
+        self
+    }
+}
+
+/// Expand the sweep range into the list of current setpoints in mA.
+///
+/// The sweep runs downwards when `stop_ma` is below `start_ma`; `step_ma` is the
+/// magnitude of the step in either direction.
+pub fn sweep_points(start_ma: f64, stop_ma: f64, step_ma: f64) -> Vec<f64> {
+    if step_ma <= 0.0 {
+        return Vec::new();
+    }
+    let direction = if stop_ma < start_ma { -1.0 } else { 1.0 };
+    // Small tolerance so that floating point error does not drop the last point
+    let count = ((stop_ma - start_ma).abs() / step_ma + 1e-9).floor() as usize + 1;
+    (0..count).map(|i| start_ma + direction * i as f64 * step_ma).collect()
+}
+
+/// Interval between the intermediate setpoints of a slew-limited ramp
+pub(crate) const SLEW_STEP_INTERVAL_MS: u64 = 10;
+
+/// Intermediate setpoints needed to move from `from_ma` to `to_ma` without exceeding
+/// `max_rate_ma_per_s` when one setpoint is applied every `SLEW_STEP_INTERVAL_MS`.
+///
+/// Neither end point is included; the result is empty if the change is small enough
+/// to be applied in a single step.
+pub fn slew_ramp(from_ma: f64, to_ma: f64, max_rate_ma_per_s: f64) -> Vec<f64> {
+    let max_step_ma = max_rate_ma_per_s * SLEW_STEP_INTERVAL_MS as f64 / 1000.0;
+    let steps = ((to_ma - from_ma).abs() / max_step_ma).ceil() as usize;
+    (1..steps).map(|i| from_ma + (to_ma - from_ma) * i as f64 / steps as f64).collect()
+}
+
+/// Power reading for one point together with its timing
+struct PointReading {
+    power: f64, // In the configured power unit
+    measured_current_ma: Option<f64>, // Delivered current, when current checking is enabled
+    voltage_v: Option<f64>,
+    temperature_c: Option<f64>,
+    current_set_at: DateTime<Utc>,
+    power_read_at: DateTime<Utc>,
+    read_latency: Duration, // Round-trip time of the power query
+}
+
+/// Instrument whose command failed while measuring a point
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PointInstrument {
+    CurrentSource,
+    PowerMeter,
+}
+
+impl CurrentSweep {
+    /// Set the laser current, ramping through intermediate setpoints first if the
+    /// change would exceed the configured slew rate
+    fn apply_current<S: CurrentSource>(&mut self, cld: &mut S, current_ma: f64) -> Result<(), Error> {
+        if let Some(rate) = self.config.max_slew_rate_ma_per_s {
+            let ramp = slew_ramp(self.applied_current_ma, current_ma, rate);
+            if !ramp.is_empty() {
+                info!("Ramping current from {} mA to {} mA in {} steps", self.applied_current_ma, current_ma, ramp.len() + 1);
+            }
+            for setpoint_ma in ramp {
+                cld.set_current(setpoint_ma / 1000.0)
+                    .map_err(|e| Error::scpi(format!("Failed to set current to {} mA while ramping", setpoint_ma), e))?;
+                self.applied_current_ma = setpoint_ma;
+                std::thread::sleep(Duration::from_millis(SLEW_STEP_INTERVAL_MS));
+            }
+        }
+
+        cld.set_current(current_ma / 1000.0)  // convert to A
+            .map_err(|e| Error::scpi(format!("Failed to set current to {} mA", current_ma), e))?;
+        self.applied_current_ma = current_ma;
+        Ok(())
+    }
+
+    /// Read the configured port with the laser off at every configured wavelength
+    fn read_dark_references<M: OpticalPowerMeter>(&mut self, mpm: &mut M, phase: &str) -> Result<(), Error> {
+        for wavelength_nm in self.config.wavelengths_nm.clone() {
+            mpm.set_wavelength(wavelength_nm)
+                .map_err(|e| Error::scpi("Failed to set MPM210H wavelength", e))?;
+            self.read_dark(mpm, phase, wavelength_nm)?;
+        }
+        Ok(())
+    }
+
+    /// Take one dark reading at the wavelength the MPM210H is currently set to
+    fn read_dark<M: OpticalPowerMeter>(&mut self, mpm: &mut M, phase: &str, wavelength_nm: u32) -> Result<(), Error> {
+        let module = self.config.module;
+        let port = self.config.port;
+        let power = mpm.read_power_from_port(module, port)
+            .map_err(|e| Error::scpi(format!("Failed to read dark reference {} sweep from module {}, port {}", phase, module, port), e))?;
+        info!("Dark reference {} sweep at module {}, port {}, {} nm: {}", phase, module, port, wavelength_nm, power);
+        self.metadata.dark_readings.push(DarkReading {
+            timestamp: Utc::now().to_rfc3339(),
+            phase: phase.to_string(),
+            module,
+            port,
+            wavelength_nm,
+            power,
+        });
+        Ok(())
+    }
+
+    /// Turn the laser off and the current to zero for a pause in the sweep.
+    /// The next point ramps up from zero again.
+    fn pause_laser<S: CurrentSource>(&mut self, cld: &mut S, reason: &str) -> Result<(), Error> {
+        info!("Pausing sweep for {}", reason);
+        cld.set_laser_output(false)
+            .map_err(|e| Error::scpi(format!("Failed to disable laser output for {}", reason), e))?;
+        cld.set_current(0.0)
+            .map_err(|e| Error::scpi(format!("Failed to set current to 0 for {}", reason), e))?;
+        self.applied_current_ma = 0.0;
+        Ok(())
+    }
+
+    /// Turn the laser back on after `pause_laser`
+    fn resume_laser<S: CurrentSource>(&mut self, cld: &mut S, reason: &str) -> Result<(), Error> {
+        cld.set_laser_output(true)
+            .map_err(|e| Error::scpi(format!("Failed to re-enable laser output after {}", reason), e))?;
+        info!("Resuming sweep after {}", reason);
+        Ok(())
+    }
+
+    /// Pause the sweep for a dark reading at the current pass wavelength
+    fn periodic_dark_check<S: CurrentSource, M: OpticalPowerMeter>(&mut self, cld: &mut S, mpm: &mut M, wavelength_nm: u32) -> Result<(), Error> {
+        self.pause_laser(cld, "dark reading")?;
+        std::thread::sleep(Duration::from_millis(self.config.stabilization_delay_ms));
+        self.read_dark(mpm, "during", wavelength_nm)?;
+        self.resume_laser(cld, "dark reading")
+    }
+
+    /// Pause the sweep to zero the MPM210H again, compensating slow electrical offset drift
+    fn rezero<S: CurrentSource, M: OpticalPowerMeter>(&mut self, cld: &mut S, mpm: &mut M) -> Result<(), Error> {
+        self.pause_laser(cld, "re-zeroing")?;
+        std::thread::sleep(Duration::from_millis(self.config.stabilization_delay_ms));
+        mpm.perform_zeroing()
+            .map_err(|e| Error::scpi("Failed to re-zero MPM210H", e))?;
+        std::thread::sleep(ZEROING_WAIT);
+        self.metadata.rezeroed_at.push(Utc::now().to_rfc3339());
+        self.resume_laser(cld, "re-zeroing")
+    }
+
+    /// Hold the warm-up current for the configured duration, recording readings if requested
+    fn warm_up<S: CurrentSource, M: OpticalPowerMeter>(&mut self, cld: &mut S, mpm: &mut M, observer: &mut dyn SweepObserver) -> Result<(), Error> {
+        let warm_up = match self.config.warm_up.clone() {
+            Some(warm_up) => warm_up,
+            None => return Ok(()),
+        };
+        info!("Warming up at {} mA for {} s", warm_up.current_ma, warm_up.duration_s);
+
+        // Readings are taken at the first calibration wavelength
+        if let Some(&wavelength_nm) = self.config.wavelengths_nm.first() {
+            mpm.set_wavelength(wavelength_nm)
+                .map_err(|e| Error::scpi("Failed to set MPM210H wavelength", e))?;
+        }
+        self.apply_current(cld, warm_up.current_ma)
+            .map_err(|e| e.context("Failed to apply warm-up current"))?;
+
+        let started = Instant::now();
+        let duration = Duration::from_secs(warm_up.duration_s);
+        // Without recording, still wake up regularly to honour abort requests
+        let interval = Duration::from_millis(warm_up.record_interval_ms.unwrap_or(1000));
+        while started.elapsed() < duration {
+            if observer.should_abort() {
+                warn!("Warm-up aborted by user after {:?}", started.elapsed());
+                return Err(Error::UserAbort("Warm-up aborted by user".into()));
+            }
+            std::thread::sleep(interval.min(duration.saturating_sub(started.elapsed())));
+
+            if warm_up.record_interval_ms.is_some() {
+                let power = mpm.read_power_from_port(self.config.module, self.config.port)
+                    .map_err(|e| Error::scpi("Failed to read power during warm-up", e))?;
+                self.metadata.warm_up_readings.push(WarmUpReading {
+                    timestamp: Utc::now().to_rfc3339(),
+                    elapsed_s: started.elapsed().as_secs_f64(),
+                    current_ma: warm_up.current_ma,
+                    power,
+                });
+            }
+        }
+        info!("Warm-up completed after {:?}", started.elapsed());
+        Ok(())
+    }
+
+    /// Set the current for one point, wait for stabilization and read the power,
+    /// then measure the delivered current, voltage and temperature as configured
+    fn measure_point<S: CurrentSource, M: OpticalPowerMeter>(&mut self, cld: &mut S, mpm: &mut M, current_ma: f64)
+        -> Result<PointReading, (PointInstrument, Error)> {
+        use PointInstrument::{CurrentSource, PowerMeter};
+
+        // Set the current
+        self.apply_current(cld, current_ma).map_err(|e| (CurrentSource, e))?;
+        let current_set_at = Utc::now();
+
+        let mut reading = self.read_power(mpm, current_ma, current_set_at).map_err(|e| (PowerMeter, e))?;
+        if self.config.current_tolerance_ma.is_some() {
+            let measured_a = cld.measure_current()
+                .map_err(|e| (CurrentSource, Error::scpi(format!("Failed to measure delivered current at {} mA", current_ma), e)))?;
+            reading.measured_current_ma = Some(measured_a * 1000.0);
+        }
+        if self.config.measure_voltage {
+            let voltage_v = cld.measure_voltage()
+                .map_err(|e| (CurrentSource, Error::scpi(format!("Failed to measure LD voltage at {} mA", current_ma), e)))?;
+            reading.voltage_v = Some(voltage_v);
+        }
+        if self.config.measure_temperature {
+            let temperature_c = cld.measure_temperature()
+                .map_err(|e| (CurrentSource, Error::scpi(format!("Failed to measure laser temperature at {} mA", current_ma), e)))?;
+            reading.temperature_c = Some(temperature_c);
+        }
+        Ok(reading)
+    }
+
+    /// Read the power for a point after stabilization or settling
+    fn read_power<M: OpticalPowerMeter>(&self, mpm: &mut M, current_ma: f64, current_set_at: DateTime<Utc>) -> Result<PointReading, Error> {
+        let module = self.config.module;
+        let port = self.config.port;
+
+        let mut read_power = || {
+            let started = Instant::now();
+            let power = mpm.read_power_value(module, port)
+                .map_err(|e| Error::scpi(format!("Failed to read power at {} mA from module {}, port {}", current_ma, module, port), e))?;
+            Ok::<_, Error>(PointReading {
+                power,
+                measured_current_ma: None,
+                voltage_v: None,
+                temperature_c: None,
+                current_set_at,
+                power_read_at: Utc::now(),
+                read_latency: started.elapsed(),
+            })
+        };
+
+        let settling = match &self.config.settling {
+            Some(settling) => settling,
+            None => {
+                // Wait for stabilization
+                std::thread::sleep(Duration::from_millis(self.config.stabilization_delay_ms));
+
+                // Read power from the specific module and port
+                return read_power();
+            }
+        };
+
+        // Read repeatedly until two consecutive readings agree within the tolerance
+        let settle_start = Instant::now();
+        let mut previous = read_power()?;
+        loop {
+            std::thread::sleep(Duration::from_millis(settling.interval_ms));
+            let reading = read_power()?;
+
+            let unit = &self.config.power_unit;
+            let settled = (unit.to_dbm(reading.power) - unit.to_dbm(previous.power)).abs() <= settling.tolerance_db;
+            if settled {
+                info!("Power settled at {} mA after {:?}", current_ma, settle_start.elapsed());
+                return Ok(reading);
+            }
+            if settle_start.elapsed() >= Duration::from_millis(settling.max_wait_ms) {
+                warn!("Power did not settle within {} ms at {} mA, using last reading {}",
+                      settling.max_wait_ms, current_ma, reading.power);
+                return Ok(reading);
+            }
+            previous = reading;
+        }
+    }
+}
+
+impl<S: CurrentSource, M: OpticalPowerMeter> Experiment<S, M> for CurrentSweep {
+    type Config = CurrentSweepConfig;
+
+    fn name(&self) -> &'static str {
+        "current_sweep"
+    }
+
+    fn config(&self) -> &CurrentSweepConfig {
+        &self.config
+    }
+
+    fn configure(&mut self, config: CurrentSweepConfig) {
+        self.config = config;
+        self.records.clear();
+        self.metadata = RunMetadata::default();
+        self.earlier.clear();
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let violations = self.config.violations();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Config(format_violations(&violations)))
+        }
+    }
+
+    fn preflight(&self, devices: &mut DeviceRegistry<S, M>) -> Vec<String> {
+        let (cld, mpm) = match instruments(devices) {
+            Ok(instruments) => instruments,
+            Err(e) => return vec![e.to_string()],
+        };
+        let config = &self.config;
+        let mut violations = Vec::new();
+
+        // Static limits are covered by validate(); these checks need the connected devices.
+        // Current range vs the controller limit
+        let warm_up_ma = config.warm_up.as_ref().map_or(0.0, |w| w.current_ma);
+        let max_ma = config.start_ma.max(config.stop_ma).max(warm_up_ma);
+        match cld.get_current_limit() {
+            Ok(limit_a) if max_ma / 1000.0 > limit_a => violations.push(format!(
+                "The sweep goes up to {} mA but the CLD1015 current limit is {} mA; lower start_ma/stop_ma or raise the LD limit on the controller",
+                max_ma, limit_a * 1000.0)),
+            Ok(_) => {}
+            Err(e) => violations.push(format!("Could not query the CLD1015 current limit: {}", e)),
+        }
+
+        // Module must exist on the power meter
+        match mpm.recognized_module_slots() {
+            Ok(slots) if !slots.contains(&config.module) => violations.push(format!(
+                "module is {} but the MPM210H only recognizes modules in slots {:?}; check the module slot",
+                config.module, slots)),
+            Ok(_) => {}
+            Err(e) => violations.push(format!("Could not query MPM210H modules: {}", e)),
+        }
+
+        violations
+    }
+
+    fn run(
+        &mut self,
+        devices: &mut DeviceRegistry<S, M>,
+        observer: &mut dyn SweepObserver,
+    ) -> Result<(), Error> {
+        let (cld, mpm) = instruments(devices)?;
+        // Extract configuration parameters
+        let module = self.config.module;
+        let port = self.config.port;
+        let start_ma = self.config.start_ma;
+        let stop_ma = self.config.stop_ma;
+        let step_ma = self.config.step_ma;
+        let stabilization_delay_ms = self.config.stabilization_delay_ms;
+        let wavelengths_nm = self.config.wavelengths_nm.clone();
+        let averaging_time_ms = self.config.averaging_time_ms;
+        let power_unit = self.config.power_unit;
+        self.records.clear();
+        self.metadata = RunMetadata::default();
+
+        // The controller was reset before the run, so ramps start from zero
+        self.applied_current_ma = 0.0;
+
+        // Set current mode
+        if let Err(e) = cld.set_current_mode() {
+            return Err(Error::scpi("Failed to set current mode", e));
+        }
+
+        // Turn laser off at the beginning
+        if let Err(e) = cld.set_laser_output(false) {
+            warn!("Failed to disable laser output: {}", e);
+        }
+
+        // Configure the MPM210H
+        configure_power_meter(mpm, averaging_time_ms, power_unit)?;
+
+        // Dark references with the laser still off
+        self.read_dark_references(mpm, "before")?;
+
+        if let Some(tec_check) = &self.config.tec_check {
+            verify_tec_regulation(cld, tec_check)?;
+        }
+
+        // Turn laser on
+        if let Err(e) = cld.set_laser_output(true) {
+            return Err(Error::scpi("Failed to enable laser output", e));
+        }
+
+        if self.config.warm_up.is_some() {
+            self.warm_up(cld, mpm, observer)?;
+        }
+
+        info!("Starting current sweep: {} mA to {} mA, step {} mA, module {}, port {}, wavelengths {:?} nm", 
+              start_ma, stop_ma, step_ma, module, port, wavelengths_nm);
+
+        let mut points = sweep_points(start_ma, stop_ma, step_ma);
+        let planned = points.clone(); // Order of the plan, before any randomization
+
+        // Points already measured by the interrupted run this one continues
+        let earlier = self.earlier.clone();
+        let last_pass = earlier.iter().filter_map(|r| wavelengths_nm.iter().position(|&w| w == r.wavelength_nm)).max();
+        let measured = |pass: usize, current_ma: f64| {
+            last_pass.is_some_and(|last| pass < last)
+                || earlier.iter().any(|r| r.wavelength_nm == wavelengths_nm[pass] && (r.current_ma - current_ma).abs() < 1e-9)
+        };
+        let total = (0..wavelengths_nm.len())
+            .map(|pass| planned.iter().filter(|&&current_ma| !measured(pass, current_ma)).count())
+            .sum::<usize>();
+        let mut rng = match self.config.random_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let sweep_start = Instant::now();
+        let mut last_dark_check = Instant::now();
+        let mut last_zeroing = Instant::now();
+        let mut index = 0;
+        observer.on_start(total);
+        for record in &earlier {
+            observer.on_record(record);
+            self.records.push(record.clone());
+        }
+
+        'passes: for (pass, &wavelength_nm) in wavelengths_nm.iter().enumerate() {
+            if planned.iter().all(|&current_ma| measured(pass, current_ma)) {
+                continue;
+            }
+            // Ensure mpm210h is at the correct wavelength for this pass
+            if let Err(e) = mpm.set_wavelength(wavelength_nm) {
+                return Err(Error::scpi("Failed to set MPM210H wavelength", e));
+            }
+            info!("Starting sweep pass at {} nm", wavelength_nm);
+            if self.config.randomize_order {
+                points.shuffle(&mut rng);
+                info!("Randomized point order: {:?}", points);
+            }
+            let mut previous_point: Option<(f64, f64)> = None; // (current mA, power mW)
+            let mut falling_points = 0; // Consecutive points with dP/dI < 0
+
+            for &current_ma in &points {
+                if measured(pass, current_ma) {
+                    continue;
+                }
+                if observer.should_abort() {
+                    warn!("Sweep aborted by user at {} mA", current_ma);
+                    return Err(Error::UserAbort(format!("Sweep aborted by user at {} mA", current_ma)));
+                }
+
+                if let Some(interval_s) = self.config.dark_check_interval_s {
+                    if last_dark_check.elapsed() >= Duration::from_secs(interval_s) {
+                        self.periodic_dark_check(cld, mpm, wavelength_nm)?;
+                        last_dark_check = Instant::now();
+                    }
+                }
+                if let Some(interval_min) = self.config.rezero_interval_min {
+                    if last_zeroing.elapsed() >= Duration::from_secs(interval_min * 60) {
+                        self.rezero(cld, mpm)?;
+                        last_zeroing = Instant::now();
+                    }
+                }
+
+                // Measure the point, retrying failures on the link to an instrument with
+                // exponential backoff. Refusals by the safety checks and unparseable
+                // responses would fail the same way again and end the sweep at once.
+                let mut attempt = 0;
+                let reading = loop {
+                    match self.measure_point(cld, mpm, current_ma) {
+                        Ok(r) => break r,
+                        Err((instrument, e)) if attempt < self.config.point_retries && e.is_transient() => {
+                            attempt += 1;
+                            self.metadata.retries += 1;
+                            let backoff_ms = self.config.retry_backoff_ms.saturating_mul(1 << (attempt - 1).min(16));
+                            warn!("{} (attempt {}/{}), retrying in {} ms",
+                                  e, attempt, self.config.point_retries + 1, backoff_ms);
+                            std::thread::sleep(Duration::from_millis(backoff_ms));
+
+                            // A timed out query can leave a stale response on the link, so
+                            // reconnect the instrument that failed
+                            let reconnected = match instrument {
+                                PointInstrument::CurrentSource => cld.connect().map_err(|e| e.to_string()),
+                                PointInstrument::PowerMeter => mpm.connect().map_err(|e| e.to_string()),
+                            };
+                            if let Err(e) = reconnected {
+                                warn!("Failed to reconnect to the {:?} before retry: {}", instrument, e);
+                            }
+                        }
+                        Err((_, e)) => return Err(e),
+                    }
+                };
+                let power = reading.power;
+                let (power_dbm, power_mw) = power_unit.dbm_and_mw(power);
+
+                // Safety: stop immediately if the reading exceeds the configured power limit.
+                // The points measured so far are kept.
+                if let Some(max_power_dbm) = self.config.max_power_dbm {
+                    if power_dbm > max_power_dbm {
+                        let reason = format!("Power {} at {} mA exceeds the {} dBm limit", power, current_ma, max_power_dbm);
+                        error!("{}, stopping sweep", reason);
+                        self.metadata.abort_reason = Some(reason);
+                        break 'passes;
+                    }
+                }
+
+                // Flag points where the controller did not deliver the programmed current,
+                // e.g. because of compliance voltage or limit clamping
+                let current_mismatch = match (reading.measured_current_ma, self.config.current_tolerance_ma) {
+                    (Some(measured_ma), Some(tolerance)) if (measured_ma - current_ma).abs() > tolerance => {
+                        warn!("Delivered current {:.3} mA differs from the programmed {} mA by more than {} mA",
+                              measured_ma, current_ma, tolerance);
+                        true
+                    }
+                    _ => false,
+                };
+
+                let now = Utc::now().to_rfc3339();
+
+                // Create measurement record
+                let record = MeasurementRecord {
+                    run_id: String::new(),
+                    sample_id: String::new(),
+                    repeat_index: 0,
+                    point_index: pass * planned.len() + planned.iter().position(|&p| p == current_ma).unwrap_or_default(),
+                    timestamp: now.clone(),
+                    elapsed_s: sweep_start.elapsed().as_secs_f64(),
+                    current_ma,
+                    measured_current_ma: reading.measured_current_ma,
+                    current_mismatch,
+                    voltage_v: reading.voltage_v,
+                    tec_temperature_c: reading.temperature_c,
+                    power,
+                    power_unit,
+                    power_dbm,
+                    power_mw,
+                    module,
+                    port,
+                    wavelength_nm,
+                    set_order: earlier.len() + index,
+                    power_setpoint_mw: None,
+                    current_set_at: reading.current_set_at.to_rfc3339(),
+                    power_read_at: reading.power_read_at.to_rfc3339(),
+                    read_latency_ms: reading.read_latency.as_secs_f64() * 1000.0,
+                };
+
+                observer.on_record(&record);
+                self.records.push(record);
+
+                // Safety: catastrophic optical damage shows up as a power drop while the current rises.
+                // Stop at once so that the damaged state is preserved for failure analysis.
+                if let (Some(fraction), Some((previous_ma, previous_mw))) = (self.config.cod_drop_fraction, previous_point) {
+                    if current_ma > previous_ma && power_mw < previous_mw * (1.0 - fraction) {
+                        let reason = format!(
+                            "Possible catastrophic optical damage at {} mA: power dropped from {:.4} mW to {:.4} mW",
+                            current_ma, previous_mw, power_mw);
+                        error!("{}, stopping sweep", reason);
+                        self.metadata.abort_reason = Some(reason);
+                        break 'passes;
+                    }
+                }
+
+                // Thermal rollover: the interesting part of the curve has been captured
+                if let Some((previous_ma, previous_mw)) = previous_point {
+                    if current_ma > previous_ma && power_mw < previous_mw {
+                        falling_points += 1;
+                    } else {
+                        falling_points = 0;
+                    }
+                }
+                previous_point = Some((current_ma, power_mw));
+                let rolled_over = self.config.rollover_points.is_some_and(|points| falling_points >= points);
+
+                // Report progress with a simple linear time estimate
+                let elapsed = sweep_start.elapsed();
+                let remaining = (total - index - 1) as u32;
+                let eta = elapsed / (index as u32 + 1) * remaining;
+                observer.on_progress(&SweepProgress {
+                    index,
+                    total,
+                    current_ma,
+                    power,
+                    elapsed,
+                    eta,
+                });
+                index += 1;
+
+                if rolled_over {
+                    info!("Power rolled over at {} mA, {} nm, ending this pass", current_ma, wavelength_nm);
+                    self.metadata.rollovers.push(Rollover { wavelength_nm, current_ma });
+                    continue 'passes;
+                }
+            }
+        }
+
+        // Dark reference after the sweep to quantify background drift during the run.
+        // This also turns the laser off straight away when a safety check stopped the sweep.
+        if let Err(e) = cld.set_laser_output(false) {
+            return Err(Error::scpi("Failed to disable laser output for dark reference", e));
+        }
+        std::thread::sleep(Duration::from_millis(stabilization_delay_ms));
+        self.read_dark_references(mpm, "after")?;
+
+        // Screen the L-I curve of each wavelength pass for kinks, in current order
+        for &wavelength_nm in &wavelengths_nm {
+            let mut curve: Vec<(f64, f64)> = self.records
+                .iter()
+                .filter(|r| r.wavelength_nm == wavelength_nm)
+                .map(|r| (r.current_ma, r.power_mw))
+                .collect();
+            curve.sort_by(|a, b| a.0.total_cmp(&b.0));
+            for kink in analysis::detect_kinks(&curve, self.config.kink_tolerance) {
+                warn!("Kink detected at {:.2} mA, {} nm: slope {:.4} -> {:.4} mW/mA",
+                      kink.current_ma, wavelength_nm, kink.slope_before_mw_per_ma, kink.slope_after_mw_per_ma);
+                self.metadata.kinks.push(KinkFlag { wavelength_nm, kink });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn results(&self) -> &[MeasurementRecord] {
+        &self.records
+    }
+
+    fn disable_tec_after_run(&self) -> bool {
+        self.config.disable_tec_after_run
+    }
+
+    fn metadata(&self) -> RunMetadata {
+        self.metadata.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_points(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{:?} != {:?}", actual, expected);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn sweep_points_upwards() {
+        assert_points(&sweep_points(10.0, 30.0, 5.0), &[10.0, 15.0, 20.0, 25.0, 30.0]);
+    }
+
+    #[test]
+    fn sweep_points_downwards() {
+        assert_points(&sweep_points(30.0, 10.0, 5.0), &[30.0, 25.0, 20.0, 15.0, 10.0]);
+    }
+
+    #[test]
+    fn sweep_points_keep_the_end_point_despite_rounding() {
+        // 0.3 / 0.1 is just below 3 in floating point
+        assert_points(&sweep_points(0.0, 0.3, 0.1), &[0.0, 0.1, 0.2, 0.3]);
+        assert_points(&sweep_points(0.3, 0.0, 0.1), &[0.3, 0.2, 0.1, 0.0]);
+    }
+
+    #[test]
+    fn sweep_points_stop_before_overshooting() {
+        assert_points(&sweep_points(0.0, 12.0, 5.0), &[0.0, 5.0, 10.0]);
+        assert_points(&sweep_points(12.0, 0.0, 5.0), &[12.0, 7.0, 2.0]);
+    }
+
+    #[test]
+    fn sweep_points_of_degenerate_ranges() {
+        assert_points(&sweep_points(20.0, 20.0, 5.0), &[20.0]);
+        assert_points(&sweep_points(10.0, 12.0, 5.0), &[10.0]);
+        assert!(sweep_points(10.0, 30.0, 0.0).is_empty());
+        assert!(sweep_points(10.0, 30.0, -5.0).is_empty());
+    }
+
+    #[test]
+    fn slew_ramp_in_both_directions() {
+        // 100 mA/s allows 1 mA per SLEW_STEP_INTERVAL_MS
+        assert_points(&slew_ramp(0.0, 5.0, 100.0), &[1.0, 2.0, 3.0, 4.0]);
+        assert_points(&slew_ramp(5.0, 0.0, 100.0), &[4.0, 3.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn slew_ramp_never_exceeds_the_rate() {
+        let ramp = slew_ramp(0.0, 2.5, 100.0);
+        assert_eq!(ramp.len(), 2);
+        let setpoints: Vec<f64> = std::iter::once(0.0).chain(ramp).chain(std::iter::once(2.5)).collect();
+        assert!(setpoints.windows(2).all(|w| w[1] > w[0] && w[1] - w[0] <= 1.0 + 1e-9), "{:?}", setpoints);
+    }
+
+    #[test]
+    fn slew_ramp_is_empty_for_small_changes() {
+        assert!(slew_ramp(10.0, 10.0, 100.0).is_empty());
+        assert!(slew_ramp(10.0, 10.5, 100.0).is_empty());
+        assert!(slew_ramp(10.0, 11.0, 100.0).is_empty());
+    }
+
+    fn violated_fields(config: &CurrentSweepConfig) -> Vec<String> {
+        config.violations().into_iter().map(|v| v.field).collect()
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert!(CurrentSweepConfig::default().violations().is_empty());
+    }
+
+    #[test]
+    fn each_invalid_field_is_reported() {
+        let default = CurrentSweepConfig::default;
+        let settling = SettlingConfig { tolerance_db: 0.05, interval_ms: 50, max_wait_ms: 1000 };
+        let warm_up = WarmUpConfig { current_ma: 50.0, duration_s: 60, record_interval_ms: None };
+        let tec_check = TecCheckConfig::default;
+        let cases = [
+            ("step_ma", CurrentSweepConfig { step_ma: 0.0, ..default() }),
+            ("step_ma", CurrentSweepConfig { step_ma: f64::NAN, ..default() }),
+            ("start_ma", CurrentSweepConfig { start_ma: -1.0, ..default() }),
+            ("stop_ma", CurrentSweepConfig { stop_ma: MAX_SAFE_CURRENT_AMPS * 1000.0 + 1.0, ..default() }),
+            ("port", CurrentSweepConfig { port: 0, ..default() }),
+            ("port", CurrentSweepConfig { port: PORTS_PER_MODULE + 1, ..default() }),
+            ("wavelengths_nm", CurrentSweepConfig { wavelengths_nm: vec![], ..default() }),
+            ("wavelengths_nm[1]", CurrentSweepConfig { wavelengths_nm: vec![980, 2000], ..default() }),
+            ("averaging_time_ms", CurrentSweepConfig { averaging_time_ms: 0.0, ..default() }),
+            ("max_power_dbm", CurrentSweepConfig { max_power_dbm: Some(f64::INFINITY), ..default() }),
+            ("cod_drop_fraction", CurrentSweepConfig { cod_drop_fraction: Some(1.0), ..default() }),
+            ("settling.tolerance_db", CurrentSweepConfig {
+                settling: Some(SettlingConfig { tolerance_db: 0.0, ..settling.clone() }), ..default()
+            }),
+            ("settling.interval_ms", CurrentSweepConfig {
+                settling: Some(SettlingConfig { interval_ms: 0, ..settling.clone() }), ..default()
+            }),
+            ("settling.max_wait_ms", CurrentSweepConfig {
+                settling: Some(SettlingConfig { max_wait_ms: 10, ..settling.clone() }), ..default()
+            }),
+            ("warm_up.current_ma", CurrentSweepConfig {
+                warm_up: Some(WarmUpConfig { current_ma: -1.0, ..warm_up.clone() }), ..default()
+            }),
+            ("warm_up.record_interval_ms", CurrentSweepConfig {
+                warm_up: Some(WarmUpConfig { record_interval_ms: Some(0), ..warm_up.clone() }), ..default()
+            }),
+            ("max_slew_rate_ma_per_s", CurrentSweepConfig { max_slew_rate_ma_per_s: Some(0.0), ..default() }),
+            ("tec_check.setpoint_c", CurrentSweepConfig {
+                tec_check: Some(TecCheckConfig { setpoint_c: Some(f64::NAN), ..tec_check() }), ..default()
+            }),
+            ("tec_check.tolerance_c", CurrentSweepConfig {
+                tec_check: Some(TecCheckConfig { tolerance_c: 0.0, ..tec_check() }), ..default()
+            }),
+            ("current_tolerance_ma", CurrentSweepConfig { current_tolerance_ma: Some(-0.5), ..default() }),
+            ("dark_check_interval_s", CurrentSweepConfig { dark_check_interval_s: Some(0), ..default() }),
+            ("rezero_interval_min", CurrentSweepConfig { rezero_interval_min: Some(0), ..default() }),
+            ("rollover_points", CurrentSweepConfig { rollover_points: Some(0), ..default() }),
+            ("rollover_points", CurrentSweepConfig { rollover_points: Some(3), randomize_order: true, ..default() }),
+            ("kink_tolerance", CurrentSweepConfig { kink_tolerance: 0.0, ..default() }),
+        ];
+        for (field, config) in &cases {
+            assert_eq!(violated_fields(config), [*field], "expected only a violation of {}", field);
+        }
+    }
+
+    #[test]
+    fn every_violation_is_reported_at_once() {
+        let config = CurrentSweepConfig { step_ma: -1.0, port: 9, averaging_time_ms: 1e6, ..CurrentSweepConfig::default() };
+        assert_eq!(violated_fields(&config), ["step_ma", "port", "averaging_time_ms"]);
+    }
+}
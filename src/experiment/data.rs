@@ -1,11 +1,213 @@
-use serde::Serialize;
+use super::analysis::{CurveAnalysis, Kink};
+use super::PowerUnit;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Serialize)]
+#[derive(Serialize, Debug, Clone)]
 pub struct MeasurementRecord {
-    pub timestamp: String, // UTC ISO timestamp
+    pub run_id: String, // name of the run's files, filled in by the lifecycle before observers see the record
+    pub sample_id: String, // device under test, filled in by the lifecycle before observers see the record
+    pub repeat_index: usize, // repetition of the run on the same sample, filled in by the lifecycle before observers see the record
+    pub point_index: usize, // position of the point in the sweep plan, whatever the order it was visited in
+    pub timestamp: String, // ISO timestamp, UTC until converted to the output timezone
+    pub elapsed_s: f64, // monotonic time since the start of the sweep
     #[serde(rename = "current_mA")]
-    pub current_ma: f64, // laser input current
+    pub current_ma: f64, // laser current set on the CLD1015
+    pub measured_current_ma: Option<f64>, // current actually delivered by the CLD1015, if measured
+    pub current_mismatch: bool, // measured current differs from current_mA by more than the tolerance
+    pub voltage_v: Option<f64>, // LD forward voltage, if measured
+    pub tec_temperature_c: Option<f64>, // laser temperature reported by the TEC, if measured
+    pub power: f64, // MPM-210H reading in `power_unit`
+    pub power_unit: PowerUnit, // unit the MPM-210H was set to for `power`
     #[serde(rename = "power_dBm")]
-    pub power_dbm: String, // MPM-210H output
+    pub power_dbm: f64, // reading converted to dBm
+    #[serde(rename = "power_mW")]
+    pub power_mw: f64, // reading converted to mW
     pub module: u8, // port/module ID on MPM-210H
-}
\ No newline at end of file
+    pub port: u8, // optical port of the module
+    pub wavelength_nm: u32, // MPM-210H calibration wavelength for this pass
+    pub set_order: usize, // order in which the point was visited, differs from the row order only for randomized sweeps
+    pub power_setpoint_mw: Option<f64>, // CLD1015 constant-power setpoint, power sweeps only
+    pub current_set_at: String, // ISO timestamp at which the current was applied
+    pub power_read_at: String, // ISO timestamp at which the power reading returned
+    pub read_latency_ms: f64, // round-trip time of the power query
+}
+
+/// Laser-off power reading used to quantify background drift
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DarkReading {
+    pub timestamp: String, // UTC ISO timestamp
+    pub phase: String,     // "before", "during" or "after" the sweep
+    pub module: u8,
+    pub port: u8,
+    pub wavelength_nm: u32,
+    pub power: String,     // MPM-210H output with the laser off
+}
+
+/// Power reading taken while the laser warms up before the sweep
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WarmUpReading {
+    pub timestamp: String, // UTC ISO timestamp
+    pub elapsed_s: f64,    // Time since the warm-up started
+    pub current_ma: f64,
+    pub power: String,
+}
+
+/// Kink found in the L-I curve of one wavelength pass
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KinkFlag {
+    pub wavelength_nm: u32,
+    #[serde(flatten)]
+    pub kink: Kink,
+}
+
+/// Pass that ended early because the output power rolled over
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rollover {
+    pub wavelength_nm: u32,
+    pub current_ma: f64, // Last current measured in the pass
+}
+
+/// Conditions in the lab during the run
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct EnvironmentMetadata {
+    pub ambient_temperature_c: Option<f64>,
+    pub humidity_percent: Option<f64>,
+    pub operator: Option<String>,
+    pub bench_id: Option<String>,
+}
+
+/// What was measured and under which conditions, supplied by the caller for each run
+#[derive(Debug, Clone, Default)]
+pub struct RunInfo {
+    pub sample_id: String,     // Chip / device under test
+    pub notes: Option<String>, // Free-form notes about the sample or setup
+    pub environment: EnvironmentMetadata,
+    pub repeat_index: usize,   // Repetition of the run on the same sample, 0 for the first
+    pub resumed_from: Option<String>, // Run ID of the interrupted run this one continues
+}
+
+/// Run identification written into every record, so concatenated datasets stay unambiguous
+#[derive(Debug, Clone, Default)]
+pub struct RecordTags {
+    pub run_id: String,
+    pub sample_id: String,
+    pub repeat_index: usize,
+}
+
+impl RecordTags {
+    /// `record` tagged with the run
+    pub fn apply(&self, record: &MeasurementRecord) -> MeasurementRecord {
+        MeasurementRecord {
+            run_id: self.run_id.clone(),
+            sample_id: self.sample_id.clone(),
+            repeat_index: self.repeat_index,
+            ..record.clone()
+        }
+    }
+}
+
+/// Run metadata saved alongside the measurement records
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RunMetadata {
+    pub sample_id: String,
+    pub notes: Option<String>,
+    pub environment: EnvironmentMetadata,
+    pub repeat_index: usize,
+    pub dark_readings: Vec<DarkReading>,
+    pub warm_up_readings: Vec<WarmUpReading>,
+    pub rezeroed_at: Vec<String>, // UTC ISO timestamps of zeroing cycles during the run
+    pub retries: usize, // Point measurements repeated after a failed query
+    pub kinks: Vec<KinkFlag>, // Slope discontinuities found in the L-I curves
+    pub rollovers: Vec<Rollover>, // Passes stopped early by the rollover criterion
+    pub abort_reason: Option<String>, // Set when a safety check stopped the run early
+}
+
+/// Identification of a connected instrument, parsed from its `*IDN?` response
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct InstrumentInfo {
+    pub idn: String,          // Raw *IDN? response
+    pub manufacturer: String,
+    pub model: String,
+    pub serial_number: String,
+    pub firmware: String,
+}
+
+impl InstrumentInfo {
+    /// Split an IEEE 488.2 identification string `<manufacturer>,<model>,<serial>,<firmware>`.
+    /// Missing fields are left empty.
+    pub fn from_idn(idn: &str) -> Self {
+        let mut fields = idn.split(',').map(|f| f.trim().to_string());
+        InstrumentInfo {
+            idn: idn.trim().to_string(),
+            manufacturer: fields.next().unwrap_or_default(),
+            model: fields.next().unwrap_or_default(),
+            serial_number: fields.next().unwrap_or_default(),
+            firmware: fields.next().unwrap_or_default(),
+        }
+    }
+}
+
+/// Error queue entries read from both instruments by the shutdown sequence.
+/// `None` when the queue could not be read.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct InstrumentErrors {
+    pub cld1015: Option<Vec<String>>,
+    pub mpm210h: Option<Vec<String>>,
+}
+
+/// How a run ended
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Completed, // All points measured and saved
+    Aborted,   // Stopped early by a safety check, partial data saved
+    #[default]
+    Failed,    // Stopped by an error
+}
+
+impl RunStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::Completed => "completed",
+            RunStatus::Aborted => "aborted",
+            RunStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Outcome of a run for the caller, e.g. for a machine-readable result. Filled in for
+/// failed runs too, as far as they got.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RunReport {
+    pub run_id: String,
+    pub experiment: String,
+    pub sample_id: String,
+    pub status: RunStatus,
+    pub data_files: Vec<String>,
+    pub curves: Vec<CurveAnalysis>, // L-I analysis of each wavelength, empty if the run failed before it
+}
+
+/// Everything needed to reproduce a run, written next to the data files whether the
+/// run succeeded or not
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RunManifest {
+    pub experiment: String,
+    pub sample_id: String,
+    pub software_version: String,    // Crate version
+    pub git_hash: Option<String>,    // Commit the software was built from, if known
+    pub schema_version: u32,         // Layout of the data files, see `schema::SCHEMA_VERSION`
+    pub started_at: String,          // UTC ISO timestamp
+    pub finished_at: Option<String>, // UTC ISO timestamp
+    pub status: RunStatus,
+    pub error: Option<String>,       // Why the run failed or was aborted
+    pub cld1015: Option<InstrumentInfo>,
+    pub mpm210h: Option<InstrumentInfo>,
+    pub instrument_errors: InstrumentErrors, // Error queues at the end of the run
+    pub config: serde_json::Value,   // Full experiment configuration
+    pub data_files: Vec<String>,
+    pub checksums: BTreeMap<String, String>, // SHA-256 of each data file, hex encoded
+    pub resumed_from: Option<String>, // Run ID of the interrupted run this one continues
+}
@@ -0,0 +1,136 @@
+use super::data::{MeasurementRecord, RunMetadata};
+use super::Error;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::path::Path;
+use tracing::info;
+
+/// Tables of the run database. Runs hold the configuration and metadata as JSON,
+/// points hold one row per measurement record.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    experiment TEXT NOT NULL,
+    sample_id TEXT NOT NULL,
+    saved_at TEXT NOT NULL,
+    data_file TEXT,
+    config TEXT NOT NULL,
+    metadata TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS points (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    set_order INTEGER NOT NULL,
+    repeat_index INTEGER,
+    point_index INTEGER,
+    timestamp TEXT NOT NULL,
+    elapsed_s REAL,
+    current_ma REAL NOT NULL,
+    measured_current_ma REAL,
+    current_mismatch INTEGER NOT NULL,
+    voltage_v REAL,
+    tec_temperature_c REAL,
+    power REAL NOT NULL,
+    power_unit TEXT NOT NULL,
+    power_dbm REAL NOT NULL,
+    power_mw REAL NOT NULL,
+    module INTEGER NOT NULL,
+    port INTEGER NOT NULL,
+    wavelength_nm INTEGER NOT NULL,
+    power_setpoint_mw REAL,
+    current_set_at TEXT NOT NULL,
+    power_read_at TEXT NOT NULL,
+    read_latency_ms REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS points_run ON points(run_id);
+CREATE INDEX IF NOT EXISTS runs_sample ON runs(sample_id);
+";
+
+/// Append a run and all of its points to the SQLite database at `path`, creating
+/// the database if needed. Everything is written in one transaction.
+pub fn append_run<C: Serialize>(
+    path: &Path,
+    name: &str,
+    experiment: &str,
+    config: &C,
+    metadata: &RunMetadata,
+    data_file: Option<&Path>,
+    data: &[MeasurementRecord],
+) -> Result<i64, Error> {
+    let sqlite_error = |e: rusqlite::Error| Error::data(format!("SQLite error in {}", path.display()), e);
+
+    let mut connection = Connection::open(path).map_err(sqlite_error)?;
+    connection.execute_batch(SCHEMA).map_err(sqlite_error)?;
+    add_missing_columns(&connection).map_err(sqlite_error)?;
+
+    let config = serde_json::sqlite_error(config).map_err(|e| Error::data("Failed to serialize config", e))?;
+    let metadata_json = serde_json::sqlite_error(metadata).map_err(|e| Error::data("Failed to serialize metadata", e))?;
+
+    let transaction = connection.transaction().map_err(sqlite_error)?;
+    transaction.execute(
+        "INSERT INTO runs (name, experiment, sample_id, saved_at, data_file, config, metadata)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            name,
+            experiment,
+            metadata.sample_id,
+            chrono::Utc::now().to_rfc3339(),
+            data_file.map(|p| p.display().sqlite_error()),
+            config,
+            metadata_json,
+        ],
+    ).map_err(sqlite_error)?;
+    let run_id = transaction.last_insert_rowid();
+
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO points (run_id, set_order, repeat_index, point_index, timestamp, elapsed_s, current_ma,
+                                 measured_current_ma, current_mismatch, voltage_v, tec_temperature_c, power, power_unit, power_dbm, power_mw, module, port,
+                                 wavelength_nm, power_setpoint_mw, current_set_at, power_read_at, read_latency_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22)",
+        ).map_err(sqlite_error)?;
+        for r in data {
+            insert.execute(params![
+                run_id,
+                r.set_order as i64,
+                r.repeat_index as i64,
+                r.point_index as i64,
+                r.timestamp,
+                r.elapsed_s,
+                r.current_ma,
+                r.measured_current_ma,
+                r.current_mismatch,
+                r.voltage_v,
+                r.tec_temperature_c,
+                r.power,
+                r.power_unit.symbol(),
+                r.power_dbm,
+                r.power_mw,
+                r.module,
+                r.port,
+                r.wavelength_nm,
+                r.power_setpoint_mw,
+                r.current_set_at,
+                r.power_read_at,
+                r.read_latency_ms,
+            ]).map_err(sqlite_error)?;
+        }
+    }
+    transaction.commit().map_err(sqlite_error)?;
+
+    info!("Run {} appended to database {} as run {}", name, path.display(), run_id);
+    Ok(run_id)
+}
+
+/// Add the columns introduced after a database was created, so older databases keep
+/// accepting runs
+fn add_missing_columns(connection: &Connection) -> rusqlite::Result<()> {
+    let mut statement = connection.prepare("SELECT name FROM pragma_table_info('points')")?;
+    let columns = statement.query_map([], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    for (column, sql_type) in [("elapsed_s", "REAL"), ("repeat_index", "INTEGER"), ("point_index", "INTEGER")] {
+        if !columns.iter().any(|c| c == column) {
+            connection.execute(&format!("ALTER TABLE points ADD COLUMN {} {}", column, sql_type), [])?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,432 @@
+use super::data::{MeasurementRecord, RunMetadata};
+use super::progress::{SweepObserver, SweepProgress};
+use super::validation::{format_violations, optional_limit, ConfigViolation};
+use super::current_sweep::{power_limit_violations, slew_ramp, SLEW_STEP_INTERVAL_MS};
+use super::{configure_power_meter, instruments, sweep_points, verify_tec_regulation, Error, Experiment, FailureKind, PowerUnit, TecCheckConfig};
+use crate::devices::DeviceRegistry;
+use crate::devices::cld1015::MAX_SAFE_CURRENT_AMPS;
+use crate::devices::mpm210h::{AVERAGE_TIME_RANGE_MS, MAX_LOGGING_POINTS, PORTS_PER_MODULE, WAVELENGTH_RANGE_NM};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tracing::{info, error, warn};
+
+/// Configuration for a fast current sweep using the MPM210H logging memory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FastSweepConfig {
+    pub module: u8,                // MPM210H module number to use
+    pub port: u8,                  // MPM210H port number to use (1-4)
+    pub start_ma: f64,             // Start current in mA
+    pub stop_ma: f64,              // End current in mA (below start_ma for a descending sweep)
+    pub step_ma: f64,              // Step size in mA, always positive
+    pub dwell_ms: u64,             // Time spent at each current step
+    pub wavelength_nm: u32,        // Calibration wavelength in nm
+    pub averaging_time_ms: f64,    // Power meter averaging time in ms, also the logging sample period
+    pub power_unit: PowerUnit,     // Power measurement unit
+    #[serde(with = "optional_limit")]
+    pub max_power_dbm: Option<f64>, // Discard the log from the first sample above this power, "off" for no limit
+    #[serde(with = "optional_limit")]
+    pub cod_drop_fraction: Option<f64>, // Stop at a power drop by this fraction while current increases, "off" to disable
+    pub max_slew_rate_ma_per_s: Option<f64>, // Ramp up to the first step at this dI/dt; steps must not be faster
+    pub tec_check: Option<TecCheckConfig>, // Verify the TEC reached its setpoint before the laser is turned on
+}
+
+impl Default for FastSweepConfig {
+    fn default() -> Self {
+        FastSweepConfig {
+            module: 0,                  // Module 0
+            port: 2,                    // Port 2
+            start_ma: 10.0,             // Start at 10 mA
+            stop_ma: 100.0,             // End at 100 mA
+            step_ma: 0.5,               // 0.5 mA steps
+            dwell_ms: 20,               // 20ms per step
+            wavelength_nm: 980,         // 980nm wavelength
+            averaging_time_ms: 1.0,     // 1ms samples, 20 per step
+            power_unit: PowerUnit::DBm, // Use dBm units
+            max_power_dbm: Some(20.0),  // Stop at a reading above 20 dBm (100 mW)
+            cod_drop_fraction: Some(0.3), // Stop at a 30% power drop
+            max_slew_rate_ma_per_s: None, // Switch straight to the first step
+            tec_check: Some(TecCheckConfig::default()), // Require the TEC to be at its setpoint
+        }
+    }
+}
+
+impl FastSweepConfig {
+    /// Logged samples per current step
+    pub fn samples_per_step(&self) -> usize {
+        (self.dwell_ms as f64 / self.averaging_time_ms).floor() as usize
+    }
+
+    /// Check every field and report all problems found
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        let max_safe_ma = MAX_SAFE_CURRENT_AMPS * 1000.0;
+
+        if self.step_ma.is_nan() || self.step_ma <= 0.0 {
+            violations.push(ConfigViolation::new("step_ma", self.step_ma, "step size must be positive")
+                .allowed("> 0 mA"));
+        }
+        for (field, value) in [("start_ma", self.start_ma), ("stop_ma", self.stop_ma)] {
+            if !(0.0..=max_safe_ma).contains(&value) {
+                violations.push(ConfigViolation::new(field, value, "current outside the driver safety range")
+                    .allowed(format!("0-{} mA", max_safe_ma)));
+            }
+        }
+        if self.port < 1 || self.port > PORTS_PER_MODULE {
+            violations.push(ConfigViolation::new("port", self.port, "MPM210H modules do not have this port")
+                .allowed(format!("1-{}", PORTS_PER_MODULE)));
+        }
+        if !WAVELENGTH_RANGE_NM.contains(&self.wavelength_nm) {
+            violations.push(ConfigViolation::new("wavelength_nm", self.wavelength_nm,
+                "wavelength not accepted by the MPM210H")
+                .allowed(format!("{}-{} nm", WAVELENGTH_RANGE_NM.start(), WAVELENGTH_RANGE_NM.end())));
+        }
+        if !AVERAGE_TIME_RANGE_MS.contains(&self.averaging_time_ms) {
+            violations.push(ConfigViolation::new("averaging_time_ms", self.averaging_time_ms,
+                "averaging time not accepted by the MPM210H")
+                .allowed(format!("{}-{} ms", AVERAGE_TIME_RANGE_MS.start(), AVERAGE_TIME_RANGE_MS.end())));
+        } else if self.samples_per_step() < 2 {
+            // At least one sample is needed to cover the transition and one to keep
+            violations.push(ConfigViolation::new("dwell_ms", self.dwell_ms,
+                "dwell time must cover at least two averaging periods")
+                .allowed(format!(">= {} ms", 2.0 * self.averaging_time_ms)));
+        } else {
+            let steps = sweep_points(self.start_ma, self.stop_ma, self.step_ma).len();
+            let samples = steps * self.samples_per_step();
+            if samples > MAX_LOGGING_POINTS {
+                violations.push(ConfigViolation::new("dwell_ms", self.dwell_ms,
+                    &format!("{} steps need {} logged samples, more than the MPM210H can store", steps, samples))
+                    .allowed(format!("<= {} samples in total", MAX_LOGGING_POINTS)));
+            }
+        }
+        violations.extend(power_limit_violations(self.max_power_dbm, self.cod_drop_fraction));
+        if let Some(rate) = self.max_slew_rate_ma_per_s {
+            if !rate.is_finite() || rate <= 0.0 {
+                violations.push(ConfigViolation::new("max_slew_rate_ma_per_s", rate,
+                    "slew rate must be a positive number").allowed("> 0 mA/s"));
+            } else if !slew_ramp(0.0, self.step_ma, rate).is_empty() {
+                // The steps are clocked by the logging run and cannot be ramped
+                violations.push(ConfigViolation::new("step_ma", self.step_ma,
+                    "each step is applied at once and would exceed max_slew_rate_ma_per_s")
+                    .allowed(format!("<= {} mA", rate * SLEW_STEP_INTERVAL_MS as f64 / 1000.0)));
+            }
+        }
+        if let Some(tec_check) = &self.tec_check {
+            violations.extend(tec_check.violations().into_iter().map(|v| v.in_section("tec_check")));
+        }
+        violations
+    }
+}
+
+/// Fast current sweep: the MPM210H records continuously into its logging memory,
+/// one sample per averaging time, while the current is stepped every `dwell_ms`.
+/// The log is retrieved afterwards and the last sample of each step is kept.
+///
+/// The steps are not synchronized by hardware: the CLD1015 has no list or triggered
+/// stepping mode for its DC current and no trigger output, so the steps are clocked
+/// from the host against the start of the MPM210H logging run. The first samples of
+/// each step cover the transition and are discarded; only the sample at the end of
+/// the dwell, when the current has been stable for several averaging periods, is
+/// recorded.
+///
+/// The power meter cannot be read while it logs, so the power limit and the COD
+/// check are applied to the retrieved log: the run keeps the steps before the first
+/// violation and is reported as aborted.
+pub struct FastSweep {
+    config: FastSweepConfig,
+    records: Vec<MeasurementRecord>,
+    metadata: RunMetadata,
+}
+
+impl FastSweep {
+    pub fn new(config: FastSweepConfig) -> Self {
+        FastSweep {
+            config,
+            records: Vec::new(),
+            metadata: RunMetadata::default(),
+        }
+    }
+}
+
+impl Experiment for FastSweep {
+    type Config = FastSweepConfig;
+
+    fn name(&self) -> &'static str {
+        "fast_sweep"
+    }
+
+    fn config(&self) -> &FastSweepConfig {
+        &self.config
+    }
+
+    fn configure(&mut self, config: FastSweepConfig) {
+        self.config = config;
+        self.records.clear();
+        self.metadata = RunMetadata::default();
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let violations = self.config.violations();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Config(format_violations(&violations)))
+        }
+    }
+
+    fn preflight(&self, devices: &mut DeviceRegistry) -> Vec<String> {
+        let (cld, mpm) = match instruments(devices) {
+            Ok(instruments) => instruments,
+            Err(e) => return vec![e.to_string()],
+        };
+        let config = &self.config;
+        let mut violations = Vec::new();
+
+        let max_ma = config.start_ma.max(config.stop_ma);
+        match cld.get_current_limit() {
+            Ok(limit_a) if max_ma / 1000.0 > limit_a => violations.push(format!(
+                "The sweep goes up to {} mA but the CLD1015 current limit is {} mA; lower start_ma/stop_ma or raise the LD limit on the controller",
+                max_ma, limit_a * 1000.0)),
+            Ok(_) => {}
+            Err(e) => violations.push(format!("Could not query the CLD1015 current limit: {}", e)),
+        }
+
+        match mpm.recognized_module_slots() {
+            Ok(slots) if !slots.contains(&config.module) => violations.push(format!(
+                "module is {} but the MPM210H only recognizes modules in slots {:?}; check the module slot",
+                config.module, slots)),
+            Ok(_) => {}
+            Err(e) => violations.push(format!("Could not query MPM210H modules: {}", e)),
+        }
+
+        violations
+    }
+
+    fn run(
+        &mut self,
+        devices: &mut DeviceRegistry,
+        observer: &mut dyn SweepObserver,
+    ) -> Result<(), Error> {
+        let (cld, mpm) = instruments(devices)?;
+        let module = self.config.module;
+        let port = self.config.port;
+        let wavelength_nm = self.config.wavelength_nm;
+        let power_unit = self.config.power_unit;
+        let samples_per_step = self.config.samples_per_step();
+        let dwell = Duration::from_millis(self.config.dwell_ms);
+        self.records.clear();
+        self.metadata = RunMetadata::default();
+
+        // Set current mode with the laser off
+        if let Err(e) = cld.set_current_mode() {
+            return Err(Error::scpi("Failed to set current mode", e));
+        }
+        if let Err(e) = cld.set_laser_output(false) {
+            warn!("Failed to disable laser output: {}", e);
+        }
+
+        // Configure the MPM210H to log on its internal timer
+        let points = sweep_points(self.config.start_ma, self.config.stop_ma, self.config.step_ma);
+        let total_samples = points.len() * samples_per_step;
+        configure_power_meter(mpm, self.config.averaging_time_ms, power_unit)?;
+        if let Err(e) = mpm.set_wavelength(wavelength_nm) {
+            return Err(Error::scpi("Failed to set MPM210H wavelength", e));
+        }
+        if let Err(e) = mpm.set_logging_points(total_samples) {
+            return Err(Error::scpi("Failed to set MPM210H logging points", e));
+        }
+        if let Err(e) = mpm.set_trigger_input(false) {
+            return Err(Error::scpi("Failed to select the MPM210H internal trigger", e));
+        }
+
+        if let Some(tec_check) = &self.config.tec_check {
+            verify_tec_regulation(cld, tec_check)?;
+        }
+
+        // Start at the first step so the first dwell is not spent ramping. With a slew
+        // limit the laser is turned on at zero current and ramped up to it.
+        let first_ma = points.first().copied().unwrap_or_default();
+        let ramp = self.config.max_slew_rate_ma_per_s.map(|rate| slew_ramp(0.0, first_ma, rate)).unwrap_or_default();
+        let initial_ma = if ramp.is_empty() { first_ma } else { 0.0 };
+        if let Err(e) = cld.set_current(initial_ma / 1000.0) {
+            return Err(Error::scpi(format!("Failed to set current to {} mA", initial_ma), e));
+        }
+        if let Err(e) = cld.set_laser_output(true) {
+            return Err(Error::scpi("Failed to enable laser output", e));
+        }
+        if !ramp.is_empty() {
+            info!("Ramping current from 0 mA to {} mA in {} steps", first_ma, ramp.len() + 1);
+            for setpoint_ma in ramp.into_iter().chain([first_ma]) {
+                std::thread::sleep(Duration::from_millis(SLEW_STEP_INTERVAL_MS));
+                if let Err(e) = cld.set_current(setpoint_ma / 1000.0) {
+                    return Err(Error::scpi(format!("Failed to set current to {} mA while ramping", setpoint_ma), e));
+                }
+            }
+        }
+
+        info!("Starting fast sweep: {} mA to {} mA, step {} mA, {} ms dwell, {} samples per step, module {}, port {}",
+              self.config.start_ma, self.config.stop_ma, self.config.step_ma, self.config.dwell_ms,
+              samples_per_step, module, port);
+
+        let total = points.len();
+        observer.on_start(total);
+
+        // Clock the current steps against the start of logging
+        if let Err(e) = mpm.start_measurement() {
+            return Err(Error::scpi("Failed to start MPM210H logging", e));
+        }
+        let logging_start = Instant::now();
+        let logging_start_utc = Utc::now();
+        let mut set_times: Vec<DateTime<Utc>> = Vec::with_capacity(total);
+
+        for (index, &current_ma) in points.iter().enumerate() {
+            if observer.should_abort() {
+                warn!("Fast sweep aborted by user at {} mA", current_ma);
+                let _ = mpm.stop_measurement();
+                return Err(Error::UserAbort(format!("Fast sweep aborted by user at {} mA", current_ma)));
+            }
+
+            // The first step was applied before logging started
+            if index > 0 {
+                let step_start = dwell * index as u32;
+                std::thread::sleep(step_start.saturating_sub(logging_start.elapsed()));
+                if let Err(e) = cld.set_current(current_ma / 1000.0) {
+                    let _ = mpm.stop_measurement();
+                    return Err(Error::scpi(format!("Failed to set current to {} mA", current_ma), e));
+                }
+                let late_by = logging_start.elapsed().saturating_sub(step_start);
+                if late_by * 2 > dwell {
+                    warn!("Current step to {} mA was applied {:?} late, the sample for this step may not be settled",
+                          current_ma, late_by);
+                }
+            }
+            set_times.push(Utc::now());
+        }
+
+        // Wait for the logging memory to fill
+        let log_duration = dwell * total as u32;
+        std::thread::sleep(log_duration.saturating_sub(logging_start.elapsed()));
+        let deadline = Instant::now() + Duration::from_secs(10) + log_duration / 10;
+        loop {
+            match mpm.get_logging_status() {
+                Ok((true, _)) => break,
+                Ok((false, count)) if Instant::now() > deadline => {
+                    let _ = mpm.stop_measurement();
+                    return Err(Error::new(FailureKind::Instrument, format!(
+                        "MPM210H logging did not complete: {} of {} samples recorded", count, total_samples)));
+                }
+                Ok(_) => std::thread::sleep(Duration::from_millis(100)),
+                Err(e) => {
+                    let _ = mpm.stop_measurement();
+                    return Err(Error::scpi("Failed to query MPM210H logging status", e));
+                }
+            }
+        }
+
+        if let Err(e) = cld.set_laser_output(false) {
+            error!("Failed to disable laser output after fast sweep: {}", e);
+        }
+
+        let read_started = Instant::now();
+        let samples = match mpm.read_logged_data(module, port, total_samples) {
+            Ok(samples) => samples,
+            Err(e) => return Err(Error::scpi(format!("Failed to read MPM210H logged data from module {}, port {}", module, port), e)),
+        };
+        if samples.len() < total_samples {
+            return Err(Error::new(FailureKind::Instrument, format!("MPM210H returned {} logged samples, expected {}", samples.len(), total_samples)));
+        }
+        info!("Retrieved {} logged samples in {:?}", samples.len(), read_started.elapsed());
+
+        // Safety: the log could not be watched while it was recorded, so check every
+        // sample, including the transitions, and keep only the steps before the first
+        // one above the power limit
+        let mut last_step = points.len();
+        if let Some(max_power_dbm) = self.config.max_power_dbm {
+            let exceeded = samples[..total_samples].iter().position(|&power| power_unit.to_dbm(power) > max_power_dbm);
+            if let Some(sample_index) = exceeded {
+                last_step = sample_index / samples_per_step;
+                let reason = format!("Power {} at {} mA exceeds the {} dBm limit",
+                                     samples[sample_index], points[last_step], max_power_dbm);
+                error!("{}, discarding the log from this step on", reason);
+                self.metadata.abort_reason = Some(reason);
+            }
+        }
+
+        // Keep the last, settled sample of each step
+        let sweep_start = Instant::now();
+        let mut previous_point: Option<(f64, f64)> = None; // (current mA, power mW)
+        for (index, &current_ma) in points.iter().enumerate().take(last_step) {
+            let sample_index = (index + 1) * samples_per_step - 1;
+            // Samples are clocked by the instrument, so their time follows from the sample index
+            let elapsed_s = (sample_index + 1) as f64 * self.config.averaging_time_ms / 1000.0;
+            let sampled_at = logging_start_utc + chrono::Duration::microseconds((elapsed_s * 1e6) as i64);
+            let power = samples[sample_index];
+            if !power.is_finite() {
+                return Err(Error::new(FailureKind::Instrument, format!("MPM210H logged an invalid power reading at {} mA: {}", current_ma, power)));
+            }
+
+            let (power_dbm, power_mw) = power_unit.dbm_and_mw(power);
+            let record = MeasurementRecord {
+                run_id: String::new(),
+                sample_id: String::new(),
+                repeat_index: 0,
+                point_index: index,
+                timestamp: sampled_at.to_rfc3339(),
+                elapsed_s,
+                current_ma,
+                measured_current_ma: None,
+                current_mismatch: false,
+                voltage_v: None,
+                tec_temperature_c: None,
+                power,
+                power_unit,
+                power_dbm,
+                power_mw,
+                module,
+                port,
+                wavelength_nm,
+                set_order: index,
+                power_setpoint_mw: None,
+                current_set_at: set_times[index].to_rfc3339(),
+                power_read_at: sampled_at.to_rfc3339(),
+                read_latency_ms: 0.0, // Read from the logging memory, not queried per point
+            };
+            observer.on_record(&record);
+            self.records.push(record);
+
+            // Safety: catastrophic optical damage shows up as a power drop while the current
+            // rises. The laser is already off, so the later steps are only discarded.
+            if let (Some(fraction), Some((previous_ma, previous_mw))) = (self.config.cod_drop_fraction, previous_point) {
+                if current_ma > previous_ma && power_mw < previous_mw * (1.0 - fraction) {
+                    let reason = format!(
+                        "Possible catastrophic optical damage at {} mA: power dropped from {:.4} mW to {:.4} mW",
+                        current_ma, previous_mw, power_mw);
+                    error!("{}, discarding the later steps", reason);
+                    self.metadata.abort_reason = Some(reason);
+                    break;
+                }
+            }
+            previous_point = Some((current_ma, power_mw));
+
+            observer.on_progress(&SweepProgress {
+                index,
+                total,
+                current_ma,
+                power,
+                elapsed: sweep_start.elapsed(),
+                eta: Duration::ZERO,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn results(&self) -> &[MeasurementRecord] {
+        &self.records
+    }
+
+    fn metadata(&self) -> RunMetadata {
+        self.metadata.clone()
+    }
+}
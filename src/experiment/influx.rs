@@ -0,0 +1,105 @@
+use super::data::MeasurementRecord;
+use super::Error;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::info;
+
+/// InfluxDB 2.x server the measurement points are pushed to after each run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InfluxConfig {
+    pub url: String,         // e.g. http://grafana-host:8086
+    pub org: String,
+    pub bucket: String,
+    pub token_env: String,   // Environment variable holding the API token, kept out of the config file
+    pub measurement: String, // Measurement name of the points
+}
+
+impl Default for InfluxConfig {
+    fn default() -> Self {
+        InfluxConfig {
+            url: "http://localhost:8086".to_string(),
+            org: String::new(),
+            bucket: "lab".to_string(),
+            token_env: "INFLUXDB_TOKEN".to_string(),
+            measurement: "li_sweep".to_string(),
+        }
+    }
+}
+
+/// Points per write request
+const BATCH_SIZE: usize = 5000;
+
+/// Escape a tag value for the line protocol
+fn escape_tag(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+/// One record as a line protocol point, tagged with the sample, run and optical channel.
+/// Missing optional values are left out, as the line protocol has no null.
+pub fn line_protocol(measurement: &str, experiment: &str, run_id: &str, record: &MeasurementRecord) -> String {
+    let mut fields = vec![
+        format!("elapsed_s={}", record.elapsed_s),
+        format!("current_ma={}", record.current_ma),
+        format!("power={}", record.power),
+        format!("power_dbm={}", record.power_dbm),
+        format!("power_mw={}", record.power_mw),
+        format!("current_mismatch={}", record.current_mismatch),
+        format!("set_order={}i", record.set_order),
+        format!("repeat_index={}i", record.repeat_index),
+        format!("point_index={}i", record.point_index),
+        format!("read_latency_ms={}", record.read_latency_ms),
+    ];
+    let optional = [
+        ("measured_current_ma", record.measured_current_ma),
+        ("voltage_v", record.voltage_v),
+        ("tec_temperature_c", record.tec_temperature_c),
+        ("power_setpoint_mw", record.power_setpoint_mw),
+    ];
+    fields.extend(optional.iter().filter_map(|(name, value)| value.map(|v| format!("{}={}", name, v))));
+    // Non-finite values are not accepted by InfluxDB, e.g. power_dbm of a zero mW reading
+    fields.retain(|field| !field.ends_with("NaN") && !field.ends_with("inf"));
+
+    let timestamp_ns = chrono::DateTime::parse_from_rfc3339(&record.timestamp)
+        .ok()
+        .and_then(|t| t.timestamp_nanos_opt())
+        .map_or(String::new(), |ns| format!(" {}", ns));
+
+    format!(
+        "{},sample_id={},run_id={},experiment={},wavelength_nm={},module={},port={},power_unit={} {}{}",
+        escape_tag(measurement),
+        escape_tag(&record.sample_id),
+        escape_tag(run_id),
+        escape_tag(experiment),
+        record.wavelength_nm,
+        record.module,
+        record.port,
+        record.power_unit.symbol(),
+        fields.join(","),
+        timestamp_ns,
+    )
+}
+
+/// Push every record of a run to InfluxDB, in batches
+pub fn push_run(config: &InfluxConfig, experiment: &str, run_id: &str, data: &[MeasurementRecord]) -> Result<(), Error> {
+    let token = std::env::var(&config.token_env)
+        .map_err(|_| Error::Config(format!("InfluxDB token not found, set the {} environment variable", config.token_env)))?;
+    let url = format!("{}/api/v2/write", config.url.trim_end_matches('/'));
+    let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(10)).build();
+
+    for batch in data.chunks(BATCH_SIZE) {
+        let body: Vec<String> = batch.iter().map(|r| line_protocol(&config.measurement, experiment, run_id, r)).collect();
+        agent
+            .post(&url)
+            .query("org", &config.org)
+            .query("bucket", &config.bucket)
+            .query("precision", "ns")
+            .set("Authorization", &format!("Token {}", token))
+            .set("Content-Type", "text/plain; charset=utf-8")
+            .send_string(&body.join("\n"))
+            .map_err(|e| Error::data(format!("InfluxDB write to {} failed", url), e))?;
+    }
+
+    info!("Pushed {} points of run {} to InfluxDB bucket {}", data.len(), run_id, config.bucket);
+    Ok(())
+}
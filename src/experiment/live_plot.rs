@@ -0,0 +1,171 @@
+use super::progress::{SweepObserver, SweepProgress};
+use minifb::{Key, Window, WindowOptions};
+use plotters::prelude::*;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tracing::{info, error, warn};
+
+const WIDTH: usize = 800;
+const HEIGHT: usize = 600;
+
+/// Live L-I plot window that is updated as each point is measured.
+///
+/// Closing the window (or pressing Escape) requests the sweep to abort. The window runs
+/// on a thread of its own while the sweep holds the main thread, and closes when the run
+/// finishes. macOS only allows windows on the main thread, so there the plot is not shown.
+pub struct LivePlot {
+    sender: Option<Sender<(f64, f64)>>,
+    abort_requested: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    power_label: String,
+}
+
+impl LivePlot {
+    pub fn new(power_label: &str) -> Self {
+        LivePlot {
+            sender: None,
+            abort_requested: Arc::new(AtomicBool::new(false)),
+            handle: None,
+            power_label: power_label.to_string(),
+        }
+    }
+}
+
+impl SweepObserver for LivePlot {
+    fn on_start(&mut self, total_points: usize) {
+        if cfg!(target_os = "macos") {
+            warn!("The live plot is not available on macOS, where windows can only be opened on the main thread");
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let abort_requested = self.abort_requested.clone();
+        let power_label = self.power_label.clone();
+        info!("Opening live plot window for {} points", total_points);
+        self.sender = Some(sender);
+        self.handle = Some(std::thread::spawn(move || {
+            if let Err(e) = run_plot_window(receiver, abort_requested, &power_label) {
+                error!("Live plot window failed: {}", e);
+            }
+        }));
+    }
+
+    fn on_progress(&mut self, progress: &SweepProgress) {
+        if let Some(sender) = &self.sender {
+            // The window may already have been closed, which is not an error for the sweep
+            let _ = sender.send((progress.current_ma, progress.power));
+        }
+    }
+
+    fn on_finish(&mut self, _success: bool) {
+        // Dropping the sender tells the window that no more points will arrive, so it closes
+        self.sender = None;
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                error!("Live plot window thread panicked");
+            }
+        }
+    }
+
+    fn should_abort(&self) -> bool {
+        self.abort_requested.load(Ordering::SeqCst)
+    }
+}
+
+fn run_plot_window(
+    receiver: Receiver<(f64, f64)>,
+    abort_requested: Arc<AtomicBool>,
+    power_label: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut window = Window::new(
+        "L-I curve (close or press Esc to abort)",
+        WIDTH,
+        HEIGHT,
+        WindowOptions::default(),
+    )?;
+    window.limit_update_rate(Some(Duration::from_millis(100)));
+
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    let mut rgb = vec![0_u8; WIDTH * HEIGHT * 3];
+    let mut buffer = vec![0_u32; WIDTH * HEIGHT];
+    let mut sweep_running = true;
+    let mut dirty = true;
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        loop {
+            match receiver.try_recv() {
+                Ok(point) => {
+                    points.push(point);
+                    dirty = true;
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    sweep_running = false;
+                    break;
+                }
+            }
+        }
+
+        // The run is over and the final curve is saved with the data, so close
+        if !sweep_running {
+            break;
+        }
+        if dirty {
+            draw_li_curve(&mut rgb, &points, power_label)?;
+            for (pixel, chunk) in buffer.iter_mut().zip(rgb.chunks_exact(3)) {
+                *pixel = ((chunk[0] as u32) << 16) | ((chunk[1] as u32) << 8) | chunk[2] as u32;
+            }
+            dirty = false;
+        }
+        window.update_with_buffer(&buffer, WIDTH, HEIGHT)?;
+    }
+
+    if sweep_running {
+        warn!("Live plot window closed during sweep, requesting abort");
+        abort_requested.store(true, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+/// Render the L-I curve measured so far into an RGB buffer
+fn draw_li_curve(rgb: &mut [u8], points: &[(f64, f64)], power_label: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let root = BitMapBackend::with_buffer(rgb, (WIDTH as u32, HEIGHT as u32)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let (x_min, x_max) = axis_range(points.iter().map(|p| p.0));
+    let (y_min, y_max) = axis_range(points.iter().map(|p| p.1));
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Live L-I curve", ("sans-serif", 20))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(x_min..x_max, y_min..y_max)?;
+
+    chart
+        .configure_mesh()
+        .x_desc("Current (mA)")
+        .y_desc(power_label)
+        .draw()?;
+
+    // Draw the line in current order so randomized or multi-pass sweeps still read as a curve
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+    chart.draw_series(LineSeries::new(sorted, &BLUE))?;
+    chart.draw_series(points.iter().map(|&(x, y)| Circle::new((x, y), 3, BLUE.filled())))?;
+
+    root.present()?;
+    Ok(())
+}
+
+/// Axis range covering all values with a small margin, never empty
+pub(crate) fn axis_range(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v)));
+    if !min.is_finite() || !max.is_finite() {
+        return (0.0, 1.0);
+    }
+    let margin = ((max - min) * 0.05).max(1e-3);
+    (min - margin, max + margin)
+}
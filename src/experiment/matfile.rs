@@ -0,0 +1,138 @@
+use std::io::{self, Write};
+
+// Data types of the MAT-file data elements
+const MI_INT8: u32 = 1;
+const MI_UINT16: u32 = 4;
+const MI_INT32: u32 = 5;
+const MI_UINT32: u32 = 6;
+const MI_DOUBLE: u32 = 9;
+const MI_MATRIX: u32 = 14;
+
+// MATLAB array classes
+const MX_STRUCT_CLASS: u32 = 2;
+const MX_CHAR_CLASS: u32 = 4;
+const MX_DOUBLE_CLASS: u32 = 6;
+
+/// Longest variable or field name accepted by MATLAB (`namelengthmax`)
+const MAX_NAME_LENGTH: usize = 63;
+
+/// Value of a MAT-file variable or struct field
+#[derive(Debug, Clone)]
+pub enum MatValue {
+    Double(Vec<f64>),                // Column vector, scalar if one element
+    Char(String),                    // Row character array
+    Struct(Vec<(String, MatValue)>), // 1x1 struct, fields in order
+}
+
+/// Write a level 5 MAT-file holding the given variables, readable by MATLAB's `load`
+/// and by scipy.io.loadmat
+pub fn write_mat_file(mut writer: impl Write, variables: &[(&str, MatValue)]) -> io::Result<()> {
+    writer.write_all(&header())?;
+    for (name, value) in variables {
+        writer.write_all(&matrix(&matlab_name(name), value))?;
+    }
+    writer.flush()
+}
+
+/// Turn a name into a valid MATLAB identifier: letters, digits and underscores,
+/// starting with a letter
+pub fn matlab_name(name: &str) -> String {
+    let mut result: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if !result.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        result.insert(0, 'x');
+    }
+    result.truncate(MAX_NAME_LENGTH);
+    result
+}
+
+/// 128-byte file header: description text, no subsystem data, version 0x0100, little-endian marker
+fn header() -> [u8; 128] {
+    let mut header = [b' '; 128];
+    let text = format!(
+        "MATLAB 5.0 MAT-file, Platform: {}, Created on: {}",
+        env!("CARGO_PKG_NAME"),
+        chrono::Local::now().format("%a %b %e %H:%M:%S %Y")
+    );
+    let len = text.len().min(116);
+    header[..len].copy_from_slice(&text.as_bytes()[..len]);
+    header[116..124].fill(0);
+    header[124..126].copy_from_slice(&0x0100_u16.to_le_bytes());
+    header[126..128].copy_from_slice(b"IM");
+    header
+}
+
+/// Data element: 8-byte tag followed by the data, padded to a multiple of 8 bytes
+fn element(data_type: u32, data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(8 + data.len() + 7);
+    bytes.extend_from_slice(&data_type.to_le_bytes());
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(data);
+    bytes.resize(bytes.len().div_ceil(8) * 8, 0);
+    bytes
+}
+
+/// Data element of up to 4 bytes packed into its tag (small data element format)
+fn small_element(data_type: u32, data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(8);
+    bytes.extend_from_slice(&data_type.to_le_bytes()[..2]);
+    bytes.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(data);
+    bytes.resize(8, 0);
+    bytes
+}
+
+fn int32s(values: &[i32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// miMATRIX element holding one named array
+fn matrix(name: &str, value: &MatValue) -> Vec<u8> {
+    let class = match value {
+        MatValue::Double(_) => MX_DOUBLE_CLASS,
+        MatValue::Char(_) => MX_CHAR_CLASS,
+        MatValue::Struct(_) => MX_STRUCT_CLASS,
+    };
+    let mut body = element(MI_UINT32, &[class.to_le_bytes(), 0_u32.to_le_bytes()].concat());
+
+    match value {
+        MatValue::Double(values) => {
+            let dims = if values.is_empty() { [0, 0] } else { [values.len() as i32, 1] };
+            body.extend(element(MI_INT32, &int32s(&dims)));
+            body.extend(element(MI_INT8, name.as_bytes()));
+            let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+            body.extend(element(MI_DOUBLE, &data));
+        }
+        MatValue::Char(text) => {
+            let chars: Vec<u16> = text.encode_utf16().collect();
+            body.extend(element(MI_INT32, &int32s(&[1, chars.len() as i32])));
+            body.extend(element(MI_INT8, name.as_bytes()));
+            let data: Vec<u8> = chars.iter().flat_map(|c| c.to_le_bytes()).collect();
+            body.extend(element(MI_UINT16, &data));
+        }
+        MatValue::Struct(fields) => {
+            body.extend(element(MI_INT32, &int32s(&[1, 1])));
+            body.extend(element(MI_INT8, name.as_bytes()));
+
+            // Field names are stored null-terminated in fixed-width slots
+            let names: Vec<String> = fields.iter().map(|(name, _)| matlab_name(name)).collect();
+            let slot = names.iter().map(|n| n.len()).max().unwrap_or(0) + 1;
+            body.extend(small_element(MI_INT32, &(slot as i32).to_le_bytes()));
+            let mut name_bytes = vec![0_u8; slot * names.len()];
+            for (i, name) in names.iter().enumerate() {
+                name_bytes[i * slot..i * slot + name.len()].copy_from_slice(name.as_bytes());
+            }
+            body.extend(element(MI_INT8, &name_bytes));
+
+            // Field values are unnamed arrays
+            for (_, value) in fields {
+                body.extend(matrix("", value));
+            }
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(8 + body.len());
+    bytes.extend_from_slice(&MI_MATRIX.to_le_bytes());
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    bytes.extend(body);
+    bytes
+}
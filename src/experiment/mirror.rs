@@ -0,0 +1,144 @@
+use super::data::MeasurementRecord;
+use super::progress::SweepObserver;
+use super::validation::ConfigViolation;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Second location every run is copied to, e.g. the group NAS
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MirrorConfig {
+    pub directory: PathBuf, // e.g. \\nas\photonics\cld1015 or /mnt/nas/cld1015
+    pub live: bool,         // Also copy the files streamed during the run, every interval_s
+    pub interval_s: f64,    // Time between copies of the streamed files
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        MirrorConfig {
+            directory: PathBuf::new(),
+            live: false,     // Files copied once the run is saved
+            interval_s: 30.0, // Keeps the share load low on dense sweeps
+        }
+    }
+}
+
+impl MirrorConfig {
+    /// Every problem in the mirror settings
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        if self.directory.as_os_str().is_empty() {
+            violations.push(ConfigViolation::new("directory", "\"\"", "a mirror directory is required")
+                .allowed("a UNC path or a directory, e.g. \"\\\\nas\\photonics\""));
+        }
+        if !(self.interval_s.is_finite() && self.interval_s > 0.0) {
+            violations.push(ConfigViolation::new("interval_s", self.interval_s, "must be a positive number of seconds"));
+        }
+        violations
+    }
+}
+
+/// Files of the run named `base`: every file in its directory whose name starts with the run name
+fn run_files(base: &Path) -> io::Result<Vec<PathBuf>> {
+    let name = base.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let directory = base.parent().unwrap_or(Path::new("."));
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() && entry.file_name().to_string_lossy().starts_with(&name) {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Copy `file` into `directory`. The copy is written under a temporary name and renamed,
+/// so readers of the share never see a partial file.
+fn copy_file(file: &Path, directory: &Path) -> io::Result<()> {
+    let name = file.file_name().unwrap_or_default();
+    let mut partial = OsString::from(name);
+    partial.push(".partial");
+    let partial = directory.join(partial);
+    std::fs::copy(file, &partial)?;
+    std::fs::rename(&partial, directory.join(name))
+}
+
+/// Copy every file of the run named `base` to the mirror, keeping their path relative to
+/// `output_directory`. Returns the number of files copied; files that fail are logged and skipped.
+pub fn mirror_run(config: &MirrorConfig, output_directory: &Path, base: &Path) -> io::Result<usize> {
+    let relative = base
+        .parent()
+        .and_then(|parent| parent.strip_prefix(output_directory).ok())
+        .unwrap_or(Path::new(""));
+    let target = config.directory.join(relative);
+    std::fs::create_dir_all(&target)?;
+
+    let mut copied = 0;
+    for file in run_files(base)? {
+        match copy_file(&file, &target) {
+            Ok(()) => copied += 1,
+            Err(e) => warn!("Failed to mirror {} to {}: {}", file.display(), target.display(), e),
+        }
+    }
+    debug!("Mirrored {} file(s) of {} to {}", copied, base.display(), target.display());
+    Ok(copied)
+}
+
+/// Copies the files of a running sweep to the mirror every `interval_s`, on a separate
+/// thread so a slow share does not hold up the measurement
+pub struct LiveMirror {
+    config: MirrorConfig,
+    output_directory: PathBuf,
+    base: PathBuf,
+    last_copy: Instant,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl LiveMirror {
+    pub fn new(config: &MirrorConfig, output_directory: &Path, base: &Path) -> Self {
+        LiveMirror {
+            config: config.clone(),
+            output_directory: output_directory.to_path_buf(),
+            base: base.to_path_buf(),
+            last_copy: Instant::now(),
+            worker: None,
+        }
+    }
+
+    fn wait_for_copy(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl SweepObserver for LiveMirror {
+    fn on_record(&mut self, _record: &MeasurementRecord) {
+        if self.last_copy.elapsed() < Duration::from_secs_f64(self.config.interval_s.max(0.0)) {
+            return;
+        }
+        // A copy still in progress is left to finish rather than queueing another
+        if self.worker.as_ref().is_some_and(|w| !w.is_finished()) {
+            return;
+        }
+        self.wait_for_copy();
+        self.last_copy = Instant::now();
+        let (config, output_directory, base) = (self.config.clone(), self.output_directory.clone(), self.base.clone());
+        self.worker = Some(std::thread::spawn(move || {
+            if let Err(e) = mirror_run(&config, &output_directory, &base) {
+                warn!("Failed to mirror the running sweep: {}", e);
+            }
+        }));
+    }
+
+    fn on_finish(&mut self, _success: bool) {
+        // The completed run is mirrored once every file is saved
+        self.wait_for_copy();
+    }
+}
@@ -1,76 +1,631 @@
+pub mod analysis;
+pub mod compare;
+pub mod convert;
+pub mod current_sweep;
 pub mod data;
-
-use crate::devices::{CLD1015, MPM210H};
-use data::MeasurementRecord;
-use chrono::Utc;
-use std::fs::File;
-use std::io;
-use std::path::PathBuf;
-use csv::Writer;
+pub mod database;
+pub mod influx;
+pub mod fast_sweep;
+pub mod live_plot;
+pub mod matfile;
+pub mod mirror;
+pub mod output;
+pub mod plan;
+pub mod plot;
+pub mod power_sweep;
+pub mod progress;
+pub mod resume;
+pub mod schema;
+pub mod stability;
+pub mod validation;
+
+pub use current_sweep::{CurrentSweep, CurrentSweepConfig, CurrentSweepConfigBuilder, PowerUnit, SettlingConfig, TecCheckConfig, WarmUpConfig, sweep_points};
+pub use fast_sweep::{FastSweep, FastSweepConfig};
+pub use output::{Compression, OutputConfig, OutputFormat};
+pub use plan::SweepPlan;
+pub use plot::PlotFormat;
+pub use power_sweep::{PowerSweep, PowerSweepConfig};
+pub use stability::{StabilityConfig, StabilityRun};
+
+pub use crate::error::{Error, FailureKind};
+use crate::devices::{registry, CurrentSource, DeviceRegistry, OpticalPowerMeter, Transcript, CLD1015, MPM210H};
+use data::{InstrumentErrors, InstrumentInfo, MeasurementRecord, RecordTags, RunInfo, RunManifest, RunMetadata, RunReport, RunStatus};
+use progress::{SweepObserver, TaggedRecords};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::{info, error, warn};
 
-/// Configuration for a current sweep experiment
-#[derive(Debug)]
-pub struct CurrentSweepConfig {
-    pub module: u8,                  // MPM210H module number to use
-    pub port: u8,                    // MPM210H port number to use (1-4)
-    pub start_ma: f64,               // Start current in mA
-    pub stop_ma: f64,                // End current in mA
-    pub step_ma: f64,                // Step size in mA
-    pub stabilization_delay_ms: u64, // Delay after setting current before measuring
-    pub wavelength_nm: u32,          // Wavelength in nm
-    pub averaging_time_ms: f64,      // Power meter averaging time in ms
-    pub power_unit: PowerUnit,       // Power measurement unit
-}
+/// Time the MPM210H needs to complete a zeroing operation (3 seconds as per documentation)
+pub const ZEROING_WAIT: Duration = Duration::from_secs(3);
+
+/// Lifecycle shared by all experiment types.
+///
+/// Connection, reset, safety checks, zeroing, laser shutdown and data saving are
+/// handled by `run_experiment`; implementations only provide the measurement itself.
+/// Experiments that only need the common instrument capabilities can be implemented for
+/// any `CurrentSource` and `OpticalPowerMeter`; the rest use the CLD1015 and MPM210H.
+pub trait Experiment<S: CurrentSource = CLD1015, M: OpticalPowerMeter = MPM210H> {
+    type Config: std::fmt::Debug + Serialize;
+
+    /// Short name used in logs and output files
+    fn name(&self) -> &'static str;
+
+    /// Current configuration, saved with the results
+    fn config(&self) -> &Self::Config;
+
+    /// Replace the experiment configuration, discarding previous results
+    fn configure(&mut self, config: Self::Config);
 
-/// Power measurement unit
-#[derive(Debug)]
-pub enum PowerUnit {
-    DBm,
-    MilliWatt,
+    /// Check the configuration before any hardware is touched
+    fn validate(&self) -> Result<(), Error>;
+
+    /// Check the configuration against the limits of the connected devices before the
+    /// laser is turned on, returning every violation found
+    fn preflight(&self, _devices: &mut DeviceRegistry<S, M>) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Acquire data. Devices are connected, reset and zeroed, with the TEC on and the laser off.
+    fn run(
+        &mut self,
+        devices: &mut DeviceRegistry<S, M>,
+        observer: &mut dyn SweepObserver,
+    ) -> Result<(), Error>;
+
+    /// Records acquired by the last run
+    fn results(&self) -> &[MeasurementRecord];
+
+    /// Whether the shutdown sequence should also turn the TEC off
+    fn disable_tec_after_run(&self) -> bool {
+        false
+    }
+
+    /// Metadata gathered during the last run, saved next to the records
+    fn metadata(&self) -> RunMetadata {
+        RunMetadata::default()
+    }
 }
 
-/// Run a current sweep with custom configuration
-pub fn run_current_sweep(
-    cld: &mut CLD1015,
-    mpm: &mut MPM210H,
+/// Run a current sweep with custom configuration, on the CLD1015 and MPM210H or any
+/// other current source and power meter
+pub fn run_current_sweep<S: CurrentSource, M: OpticalPowerMeter>(
+    devices: &mut DeviceRegistry<S, M>,
     config: CurrentSweepConfig,
-) -> Result<PathBuf, String> {
+    run_info: &RunInfo,
+    output: &OutputConfig,
+    observer: &mut dyn SweepObserver,
+    report: &mut RunReport,
+) -> Result<PathBuf, Error> {
     info!("Starting current sweep with configuration: {:?}", config);
-    
-    // Connect to devices and run experiment
-    _run_current_sweep_internal(cld, mpm, config)
+    run_experiment(&mut CurrentSweep::new(config), devices, run_info, output, observer, report)
 }
 
-/// Internal implementation of current sweep
-fn _run_current_sweep_internal(
-    cld: &mut CLD1015,
-    mpm: &mut MPM210H,
-    config: CurrentSweepConfig,
-) -> Result<PathBuf, String> {
-    // Extract configuration parameters
-    let module = config.module;
-    let port = config.port;
-    let start_ma = config.start_ma;
-    let stop_ma = config.stop_ma;
-    let step_ma = config.step_ma;
-    let stabilization_delay_ms = config.stabilization_delay_ms;
-    let wavelength_nm = config.wavelength_nm;
-    let averaging_time_ms = config.averaging_time_ms;
-    let power_unit = config.power_unit;
+/// Run a fast current sweep using the MPM210H logging memory
+pub fn run_fast_sweep(
+    devices: &mut DeviceRegistry,
+    config: FastSweepConfig,
+    run_info: &RunInfo,
+    output: &OutputConfig,
+    observer: &mut dyn SweepObserver,
+    report: &mut RunReport,
+) -> Result<PathBuf, Error> {
+    info!("Starting fast sweep with configuration: {:?}", config);
+    run_experiment(&mut FastSweep::new(config), devices, run_info, output, observer, report)
+}
 
-    // Connect to devices
-    info!("Connecting to devices");
-    match cld.connect() {
-        Ok(id) => info!("CLD1015 connected: {}", id),
-        Err(e) => return Err(format!("Failed to connect to CLD1015: {}", e)),
+/// Run a power-setpoint sweep in the CLD1015 constant-power mode
+pub fn run_power_sweep(
+    devices: &mut DeviceRegistry,
+    config: PowerSweepConfig,
+    run_info: &RunInfo,
+    output: &OutputConfig,
+    observer: &mut dyn SweepObserver,
+    report: &mut RunReport,
+) -> Result<PathBuf, Error> {
+    info!("Starting power sweep with configuration: {:?}", config);
+    run_experiment(&mut PowerSweep::new(config), devices, run_info, output, observer, report)
+}
+
+/// Continue an interrupted current sweep from its checkpoint. The instruments are
+/// prepared and configured from the original settings as for a new run; only the points
+/// not measured yet are measured, and the new run holds the whole sweep.
+pub fn resume_current_sweep<S: CurrentSource, M: OpticalPowerMeter>(
+    devices: &mut DeviceRegistry<S, M>,
+    checkpoint: resume::Checkpoint,
+    run_info: &RunInfo,
+    output: &OutputConfig,
+    observer: &mut dyn SweepObserver,
+    report: &mut RunReport,
+) -> Result<PathBuf, Error> {
+    info!("Resuming current sweep {} with {} points measured", checkpoint.run_id, checkpoint.records.len());
+    let run_info = RunInfo { resumed_from: Some(checkpoint.run_id), ..run_info.clone() };
+    let mut sweep = CurrentSweep::new(checkpoint.config).resuming(checkpoint.records);
+    run_experiment(&mut sweep, devices, &run_info, output, observer, report)
+}
+
+/// Run a stability measurement at a fixed current
+pub fn run_stability(
+    devices: &mut DeviceRegistry,
+    config: StabilityConfig,
+    run_info: &RunInfo,
+    output: &OutputConfig,
+    observer: &mut dyn SweepObserver,
+    report: &mut RunReport,
+) -> Result<PathBuf, Error> {
+    info!("Starting stability run with configuration: {:?}", config);
+    run_experiment(&mut StabilityRun::new(config), devices, run_info, output, observer, report)
+}
+
+/// Run any experiment through the shared lifecycle and save its results.
+///
+/// `run_info` identifies the sample and lab conditions; it is stored in the data file
+/// name, in every record and in the metadata sidecar. `output` selects the data files written.
+/// The files, status and analysis of the run are reported in `report`, whatever the outcome.
+pub fn run_experiment<S: CurrentSource, M: OpticalPowerMeter, E: Experiment<S, M>>(
+    experiment: &mut E,
+    devices: &mut DeviceRegistry<S, M>,
+    run_info: &RunInfo,
+    output: &OutputConfig,
+    observer: &mut dyn SweepObserver,
+    report: &mut RunReport,
+) -> Result<PathBuf, Error> {
+    info!("Starting {} experiment on sample {}", experiment.name(), run_info.sample_id);
+    *report = RunReport {
+        experiment: experiment.name().to_string(),
+        sample_id: run_info.sample_id.clone(),
+        ..RunReport::default()
+    };
+    let result = _run_experiment_internal(experiment, devices, run_info, output, observer, report);
+    observer.on_finish(result.is_ok());
+    result
+}
+
+/// Internal implementation of the experiment lifecycle
+fn _run_experiment_internal<S: CurrentSource, M: OpticalPowerMeter, E: Experiment<S, M>>(
+    experiment: &mut E,
+    devices: &mut DeviceRegistry<S, M>,
+    run_info: &RunInfo,
+    output: &OutputConfig,
+    observer: &mut dyn SweepObserver,
+    report: &mut RunReport,
+) -> Result<PathBuf, Error> {
+    // Validate parameters before touching the hardware
+    if run_info.sample_id.trim().is_empty() {
+        return Err(Error::Config("A sample ID is required so the data can be traced back to the device under test".into()));
+    }
+    let output_violations = output.violations();
+    if !output_violations.is_empty() {
+        return Err(Error::Config(validation::format_violations(&output_violations)));
+    }
+    experiment.validate()?;
+    instruments(devices)?;
+
+    let base = match output.base_path(experiment.name(), &run_info.sample_id, run_info.repeat_index) {
+        Ok(base) => base,
+        Err(e) => return Err(Error::data("Failed to create output directory", e)),
+    };
+
+    // Every log line of the run carries its ID and sample
+    let run_id = base.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let span = tracing::info_span!("run", run_id = %run_id, sample_id = %run_info.sample_id, experiment = experiment.name());
+    let _entered = span.enter();
+
+    let mut manifest = RunManifest {
+        experiment: experiment.name().to_string(),
+        sample_id: run_info.sample_id.clone(),
+        software_version: env!("CARGO_PKG_VERSION").to_string(),
+        git_hash: option_env!("GIT_HASH").map(str::to_string),
+        schema_version: schema::SCHEMA_VERSION,
+        started_at: chrono::Utc::now().to_rfc3339(),
+        config: serde_json::to_value(experiment.config()).unwrap_or_default(),
+        resumed_from: run_info.resumed_from.clone(),
+        ..RunManifest::default()
+    };
+
+    report.run_id = run_id.clone();
+    let result = _run_and_save(experiment, devices, run_info, output, observer, &base, &mut manifest, report);
+
+    // The manifest and summary are written for failed runs too, so every attempt can be traced
+    manifest.finished_at = Some(chrono::Utc::now().to_rfc3339());
+    if let Err(e) = &result {
+        manifest.error = Some(e.to_string());
+    }
+    // A shared HDF5 file keeps growing with later runs, so its checksum would not last
+    let complete: Vec<PathBuf> = manifest.data_files
+        .iter()
+        .map(PathBuf::from)
+        .filter(|path| Some(path.as_path()) != output.hdf5_file.as_deref())
+        .collect();
+    match output::save_checksums(&base, &complete) {
+        Ok(checksums) => manifest.checksums = checksums,
+        Err(e) => error!("Failed to save data file checksums: {}", e),
+    }
+    if let Err(e) = output::save_manifest(&base, &manifest) {
+        error!("Failed to save run manifest: {}", e);
+    }
+    report.status = manifest.status;
+    report.data_files = manifest.data_files.clone();
+    if let Err(e) = output::save_summary(&base, &output.csv, &manifest, &experiment.metadata(), experiment.results()) {
+        error!("Failed to save run summary: {}", e);
+    }
+
+    // The local files are complete, so an unreachable share only costs the copy
+    if let Some(mirror) = &output.mirror {
+        let output_directory = std::env::current_dir().unwrap_or_default().join(&output.directory);
+        match mirror::mirror_run(mirror, &output_directory, &base) {
+            Ok(copied) => info!("Mirrored {} file(s) to {}", copied, mirror.directory.display()),
+            Err(e) => warn!("Failed to mirror the run to {}: {}", mirror.directory.display(), e),
+        }
+    }
+
+    result
+}
+
+/// Acquire the data and write every output file, recording the outcome in `manifest`
+#[allow(clippy::too_many_arguments)]
+fn _run_and_save<S: CurrentSource, M: OpticalPowerMeter, E: Experiment<S, M>>(
+    experiment: &mut E,
+    devices: &mut DeviceRegistry<S, M>,
+    run_info: &RunInfo,
+    output: &OutputConfig,
+    observer: &mut dyn SweepObserver,
+    base: &Path,
+    manifest: &mut RunManifest,
+    report: &mut RunReport,
+) -> Result<PathBuf, Error> {
+    // The transcript covers the whole run, including the shutdown sequence
+    if output.transcript {
+        match Transcript::create(&output::with_suffix(base, "_scpi.log")) {
+            Ok(transcript) => {
+                devices.set_transcript(Some(transcript));
+            }
+            Err(e) => return Err(Error::data("Failed to create instrument transcript", e)),
+        }
+    }
+
+    let tags = RecordTags {
+        run_id: base.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        sample_id: run_info.sample_id.clone(),
+        repeat_index: run_info.repeat_index,
+    };
+    let streamed_paths = |streamed: &[(OutputFormat, Box<dyn output::StreamedOutput>)]| -> Vec<String> {
+        streamed.iter().flat_map(|(_, w)| w.paths()).map(|p| p.display().to_string()).collect()
+    };
+    let mut streamed = Vec::new();
+
+    let run_result = _connect(devices, manifest).and_then(|()| {
+        // CSV and JSON Lines output are written while the data is acquired, so a crash or
+        // a failed run keeps every point measured so far. They are created once the
+        // instruments are identified, for the CSV header comments.
+        let comments = output::csv_header_comments(&serde_json::to_value(&*manifest).unwrap_or_default());
+        for &format in output.formats.iter().filter(|f| matches!(f, OutputFormat::Csv | OutputFormat::Jsonl)) {
+            match output::create_streamed(format, base, &tags, output, &comments) {
+                Ok(writer) => streamed.push((format, writer)),
+                Err(e) => return Err(Error::data(format!("Failed to create {:?} output", format), e)),
+            }
+        }
+        manifest.data_files = streamed_paths(&streamed);
+
+        // Checkpoint: a run that dies from here on can be found and resumed from its
+        // manifest and the points in its streamed files
+        if let Err(e) = output::save_manifest(base, manifest) {
+            warn!("Failed to save the run checkpoint: {}", e);
+        }
+
+        // The streamed files are copied to the mirror while they grow when asked to
+        let mut live_mirror = output.mirror.as_ref().filter(|m| m.live).and_then(|mirror| {
+            let output_directory = std::env::current_dir().ok()?.join(&output.directory);
+            Some(mirror::LiveMirror::new(mirror, &output_directory, base))
+        });
+        let mut observers: Vec<&mut dyn SweepObserver> = vec![&mut *observer];
+        for (_, writer) in &mut streamed {
+            observers.push(writer.as_mut());
+        }
+        if let Some(live_mirror) = &mut live_mirror {
+            observers.push(live_mirror);
+        }
+        let result = _acquire(experiment, devices, &mut TaggedRecords::new(tags.clone(), &mut observers));
+        // The caller's observer is finished by run_experiment
+        for writer in observers.iter_mut().skip(1) {
+            writer.on_finish(result.is_ok());
+        }
+        result
+    });
+
+    // The shutdown sequence runs after every run, whether it succeeded or not
+    let disable_tec = experiment.disable_tec_after_run();
+    let shutdown_result = instruments(devices)
+        .and_then(|(cld, mpm)| shutdown_devices(cld, mpm, disable_tec, &mut manifest.instrument_errors));
+    devices.set_transcript(None);
+    // Channel files are only created once their first record arrives
+    manifest.data_files = streamed_paths(&streamed);
+    run_result?;
+    // A laser that cannot be confirmed off is a safety problem whatever the run did
+    shutdown_result?;
+
+    // Save the results
+    let records: Vec<MeasurementRecord> = experiment
+        .results()
+        .iter()
+        .map(|record| output::output_record(record, &tags, &output.timezone))
+        .collect();
+    let mut metadata = experiment.metadata();
+    metadata.sample_id = run_info.sample_id.clone();
+    metadata.notes = run_info.notes.clone();
+    metadata.environment = run_info.environment.clone();
+    metadata.repeat_index = run_info.repeat_index;
+
+    // Data files of the whole run, or of each channel; the metadata, analysis and
+    // manifest are shared by every channel
+    let file_sets: Vec<(PathBuf, Vec<MeasurementRecord>)> = if output.split_channels {
+        output::split_by_channel(&records)
+            .into_iter()
+            .map(|((module, port), records)| (output::channel_base(base, module, port), records))
+            .collect()
+    } else {
+        vec![(base.to_path_buf(), records.clone())]
+    };
+
+    // The first format listed is the one reported back to the caller
+    let mut data_paths = Vec::new();
+    for format in &output.formats {
+        if matches!(format, OutputFormat::Csv | OutputFormat::Jsonl) {
+            data_paths.extend(streamed.iter().filter(|(f, _)| f == format).flat_map(|(_, w)| w.paths()));
+            continue;
+        }
+        for (base, records) in &file_sets {
+            let saved = match format {
+                OutputFormat::Json => output::save_json(base, experiment.name(), experiment.config(), &metadata, records,
+                                                        output.compression),
+                OutputFormat::Parquet => output::save_parquet(base, records, output.compression),
+                OutputFormat::Hdf5 => output::save_hdf5(base, output.hdf5_file.as_deref(), experiment.name(),
+                                                        experiment.config(), &metadata, records),
+                OutputFormat::Xlsx => output::save_xlsx(base, experiment.name(), experiment.config(), &metadata, records),
+                OutputFormat::Mat => output::save_mat(base, experiment.name(), experiment.config(), &metadata, records),
+                OutputFormat::Origin => output::save_origin(base, records),
+                OutputFormat::Csv | OutputFormat::Jsonl => unreachable!("written during the run"),
+            };
+            match saved {
+                Ok(p) => data_paths.push(p),
+                Err(e) => return Err(Error::data(format!("Failed to save {:?} output", format), e)),
+            }
+        }
+    }
+    manifest.data_files = data_paths.iter().map(|p| p.display().to_string()).collect();
+    // A split run without any points has no data file
+    let path = data_paths.first().cloned().unwrap_or_else(|| base.to_path_buf());
+
+    if let Err(e) = output::save_metadata(base, &metadata) {
+        return Err(Error::data("Failed to save run metadata", e));
+    }
+
+    let curves = analysis::analyze_run(&records, &metadata.kinks);
+    for curve in &curves {
+        match (curve.threshold_ma, curve.slope_efficiency_w_per_a) {
+            (Some(threshold), Some(slope)) => info!("{} nm: threshold {:.2} mA, slope efficiency {:.4} W/A, R² {:?}",
+                                                    curve.wavelength_nm, threshold, slope, curve.fit_r_squared),
+            _ => warn!("{} nm: not enough points above threshold to fit the L-I curve", curve.wavelength_nm),
+        }
+    }
+    report.curves = curves.clone();
+    if let Err(e) = output::save_analysis(base, experiment.name(), &run_info.sample_id, &curves) {
+        return Err(Error::data("Failed to save analysis report", e));
+    }
+
+    // A failed plot is not worth losing the run over, the data is already saved
+    let title = format!("{} {}", run_info.sample_id, experiment.name());
+    for &format in &output.plots {
+        if let Err(e) = plot::save_plot(base, format, &title, &records) {
+            warn!("Failed to save {:?} L-I plot: {}", format, e);
+        }
+    }
+
+    let name = base.file_name().unwrap_or_default().to_string_lossy();
+    if let Some(database) = &output.database {
+        database::append_run(database, &name, experiment.name(), experiment.config(), &metadata, Some(&path), &records)?;
+    }
+
+    // The data is safely on disk, so an unreachable server only costs the dashboard update
+    if let Some(influxdb) = &output.influxdb {
+        if let Err(e) = influx::push_run(influxdb, experiment.name(), &name, &records) {
+            warn!("{}", e);
+        }
+    }
+
+    // Runs stopped by a safety check keep their data but are still reported as failed
+    if let Some(reason) = metadata.abort_reason {
+        manifest.status = RunStatus::Aborted;
+        return Err(Error::SafetyAbort(format!("{} aborted: {}. Partial data saved to: {}",
+                                              experiment.name(), reason, path.display())));
+    }
+
+    manifest.status = RunStatus::Completed;
+    info!("{} completed. Data saved to: {:?}", experiment.name(), path);
+
+    Ok(path)
+}
+
+/// The laser driver and power meter of `devices`, which every experiment runs on
+pub fn instruments<S: CurrentSource, M: OpticalPowerMeter>(devices: &mut DeviceRegistry<S, M>) -> Result<(&mut S, &mut M), Error> {
+    let names = devices.names().join(", ");
+    devices.laser_and_meter().ok_or_else(|| Error::Config(format!(
+        "Experiments need a {:?} and a {:?} in the device registry, it holds: {}",
+        registry::LASER_DRIVER, registry::POWER_METER, names)))
+}
+
+/// Prepare the devices and record their identification in `manifest`
+fn _connect<S: CurrentSource, M: OpticalPowerMeter>(devices: &mut DeviceRegistry<S, M>, manifest: &mut RunManifest) -> Result<(), Error> {
+    let (cld, mpm) = instruments(devices)?;
+    let (cld_id, mpm_id) = prepare_devices(cld, mpm)?;
+    manifest.cld1015 = Some(InstrumentInfo::from_idn(&cld_id));
+    manifest.mpm210h = Some(InstrumentInfo::from_idn(&mpm_id));
+    Ok(())
+}
+
+/// Run the pre-flight checks and acquire the experiment data. A run that fails once an
+/// observer asked to abort was stopped by the operator.
+fn _acquire<S: CurrentSource, M: OpticalPowerMeter, E: Experiment<S, M>>(
+    experiment: &mut E,
+    devices: &mut DeviceRegistry<S, M>,
+    observer: &mut dyn SweepObserver,
+) -> Result<(), Error> {
+    let violations = experiment.preflight(devices);
+    if !violations.is_empty() {
+        for violation in &violations {
+            error!("Pre-flight check failed: {}", violation);
+        }
+        return Err(Error::SafetyAbort(format!(
+            "Pre-flight validation failed with {} problem(s):\n  - {}",
+            violations.len(),
+            violations.join("\n  - ")
+        )));
     }
 
-    match mpm.connect() {
-        Ok(id) => info!("MPM210H connected: {}", id),
-        Err(e) => return Err(format!("Failed to connect to MPM210H: {}", e)),
+    experiment.run(devices, observer).map_err(|e| {
+        if observer.should_abort() { Error::UserAbort(e.to_string()) } else { e }
+    })
+}
+
+/// Structured teardown: laser off, verify it is off, current to zero, optionally TEC off,
+/// then drain the error queues of both instruments into `errors`.
+///
+/// Every step is attempted even if an earlier one fails. Only a laser that cannot be
+/// confirmed off is reported as an error; other problems are logged.
+pub fn shutdown_devices<S: CurrentSource, M: OpticalPowerMeter>(
+    cld: &mut S,
+    mpm: &mut M,
+    disable_tec: bool,
+    errors: &mut InstrumentErrors,
+) -> Result<(), Error> {
+    info!("Running shutdown sequence");
+    let mut laser_confirmed_off = true;
+
+    if cld.is_connected() {
+        if let Err(e) = cld.set_laser_output(false) {
+            error!("Failed to disable laser output during shutdown: {}", e);
+        }
+
+        laser_confirmed_off = match cld.get_laser_output() {
+            Ok(false) => {
+                info!("Confirmed laser is OFF");
+                true
+            },
+            Ok(true) => {
+                // One more attempt before giving up
+                error!("Laser output still ON after shutdown command, retrying");
+                let _ = cld.set_laser_output(false);
+                matches!(cld.get_laser_output(), Ok(false))
+            },
+            Err(e) => {
+                error!("Could not verify laser state during shutdown: {}", e);
+                false
+            }
+        };
+
+        if let Err(e) = cld.set_current(0.0) {
+            warn!("Failed to set current to 0 during shutdown: {}", e);
+        }
+
+        if disable_tec {
+            if let Err(e) = cld.disable_tec() {
+                warn!("Failed to disable TEC during shutdown: {}", e);
+            }
+        }
+
+        match cld.clear_error_queue() {
+            Ok(queue) => {
+                queue.iter().for_each(|e| warn!("CLD1015 reported error: {}", e));
+                errors.cld1015 = Some(queue);
+            }
+            Err(e) => warn!("Failed to read CLD1015 error queue during shutdown: {}", e),
+        }
     }
 
+    if mpm.is_connected() {
+        match mpm.clear_error_queue() {
+            Ok(queue) => {
+                queue.iter().for_each(|e| warn!("MPM210H reported error: {}", e));
+                errors.mpm210h = Some(queue);
+            }
+            Err(e) => warn!("Failed to read MPM210H error queue during shutdown: {}", e),
+        }
+    }
+
+    if !laser_confirmed_off {
+        return Err(Error::SafetyAbort("Shutdown could not confirm that the laser output is OFF, check the CLD1015".into()));
+    }
+    info!("Shutdown sequence completed");
+    Ok(())
+}
+
+/// Configure the power meter for fixed-wavelength power readings: CONST1 mode on the
+/// MPM210H, the averaging time and the power unit. The experiments call this themselves.
+pub fn configure_power_meter<M: OpticalPowerMeter>(mpm: &mut M, averaging_time_ms: f64, power_unit: PowerUnit) -> Result<(), Error> {
+    // Set measurement mode to fixed wavelength, manual range
+    mpm.set_fixed_wavelength_mode().map_err(|e| Error::scpi("Failed to set MPM210H measurement mode", e))?;
+
+    // Set average time
+    mpm.set_average_time(averaging_time_ms).map_err(|e| Error::scpi("Failed to set MPM210H averaging time", e))?;
+
+    // Set power unit
+    mpm.set_unit_dbm(power_unit == PowerUnit::DBm).map_err(|e| Error::scpi("Failed to set MPM210H measurement unit", e))?;
+
+    Ok(())
+}
+
+/// Make sure the TEC is actually regulating at its setpoint, not just switched on.
+///
+/// Programs the configured setpoint if there is one, then polls the measured
+/// temperature until it is within tolerance or the timeout expires.
+fn verify_tec_regulation<S: CurrentSource>(cld: &mut S, check: &TecCheckConfig) -> Result<(), Error> {
+    if let Some(setpoint_c) = check.setpoint_c {
+        cld.set_tec_setpoint(setpoint_c).map_err(|e| Error::scpi(format!("Failed to set TEC setpoint to {} °C", setpoint_c), e))?;
+    }
+    let setpoint_c = cld.get_tec_setpoint().map_err(|e| Error::scpi("Failed to query TEC setpoint", e))?;
+
+    info!("Waiting for TEC to reach {} °C ± {} °C", setpoint_c, check.tolerance_c);
+    let started = std::time::Instant::now();
+    loop {
+        let temperature_c = cld.measure_temperature().map_err(|e| Error::scpi("Failed to measure laser temperature", e))?;
+        if (temperature_c - setpoint_c).abs() <= check.tolerance_c {
+            info!("TEC regulating at {} °C (setpoint {} °C)", temperature_c, setpoint_c);
+            return Ok(());
+        }
+        if started.elapsed() >= std::time::Duration::from_secs(check.timeout_s) {
+            error!("TEC not regulating: {} °C measured, setpoint {} °C", temperature_c, setpoint_c);
+            return Err(Error::new(FailureKind::Instrument, format!(
+                "TEC is not regulating: laser temperature is {} °C but the setpoint is {} °C (tolerance {} °C) after {} s",
+                temperature_c, setpoint_c, check.tolerance_c, check.timeout_s)));
+        }
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+/// Connect to both instruments and bring them into a safe, zeroed state, returning
+/// their identification strings. Instruments still connected from an earlier run, as in
+/// the service, are only identified again.
+fn prepare_devices<S: CurrentSource, M: OpticalPowerMeter>(cld: &mut S, mpm: &mut M) -> Result<(String, String), Error> {
+    // Connect to devices
+    info!("Connecting to devices");
+    let cld_connection = if cld.is_connected() { cld.identify() } else { cld.connect() };
+    let cld_id = match cld_connection {
+        Ok(id) => {
+            info!("CLD1015 connected: {}", id);
+            id
+        },
+        Err(e) => return Err(Error::connection("Failed to connect to CLD1015", e)),
+    };
+
+    let mpm_connection = if mpm.is_connected() { mpm.identify() } else { mpm.connect() };
+    let mpm_id = match mpm_connection {
+        Ok(id) => {
+            info!("MPM210H connected: {}", id);
+            id
+        },
+        Err(e) => return Err(Error::connection("Failed to connect to MPM210H", e)),
+    };
+
     // Reset CLD1015 to ensure clean state before starting experiment
     info!("Resetting CLD1015 before starting experiment");
     match cld.reset() {
@@ -86,7 +641,7 @@ fn _run_current_sweep_internal(
         Ok(true) => {
             warn!("Laser output is still ON after reset, turning it OFF for safety");
             if let Err(e) = cld.set_laser_output(false) {
-                return Err(format!("Failed to turn laser off after reset: {}", e));
+                return Err(Error::connection("Failed to turn laser off after reset", e));
             }
         },
         Ok(false) => info!("Confirmed laser is OFF after reset"),
@@ -97,16 +652,8 @@ fn _run_current_sweep_internal(
         }
     }
 
-    // Validate parameters
-    if step_ma <= 0.0 || start_ma > stop_ma {
-        return Err("Invalid sweep parameters".into());
-    }
-
     // Safety: Ensure TEC is active
-    let tec_on = match cld.get_tec_state() {
-        Ok(state) => state,
-        Err(e) => return Err(format!("Failed to get TEC state: {}", e)),
-    };
+    let tec_on = cld.get_tec_state().map_err(|e| Error::connection("Failed to get TEC state", e))?;
 
     if !tec_on {
         info!("TEC is off, enabling it");
@@ -116,7 +663,7 @@ fn _run_current_sweep_internal(
                 // Wait for TEC to stabilize
                 std::thread::sleep(std::time::Duration::from_secs(5));
             },
-            Err(e) => return Err(format!("Failed to enable TEC: {}", e)),
+            Err(e) => return Err(Error::connection("Failed to enable TEC", e)),
         }
     }
 
@@ -126,136 +673,139 @@ fn _run_current_sweep_internal(
         Ok(_) => info!("Zeroing command sent successfully"),
         Err(e) => {
             error!("Failed to perform zeroing: {}", e);
-            return Err(format!("Failed to perform zeroing: {}", e));
+            return Err(Error::connection("Failed to perform zeroing", e));
         }
     }
 
-    // Give time for the zeroing operation to complete (3 seconds as per documentation)
-    std::thread::sleep(std::time::Duration::from_secs(3));
-    info!("Zeroing completed, proceeding with sweep");
+    // Give time for the zeroing operation to complete
+    std::thread::sleep(ZEROING_WAIT);
+    info!("Zeroing completed, proceeding with experiment");
 
-    // Set current mode
-    if let Err(e) = cld.set_current_mode() {
-        return Err(format!("Failed to set current mode: {}", e));
-    }
-
-    // Turn laser off at the beginning
-    if let Err(e) = cld.set_laser_output(false) {
-        warn!("Failed to disable laser output: {}", e);
-    }
+    Ok((cld_id, mpm_id))
+}
 
-    // Configure the MPM210H
-    // Set measurement mode to CONST1 (fixed wavelength, manual range)
-    if let Err(e) = mpm.set_measurement_mode("CONST1") {
-        return Err(format!("Failed to set MPM210H measurement mode: {}", e));
-    }
-    
-    // Set average time
-    if let Err(e) = mpm.set_average_time(averaging_time_ms) {
-        return Err(format!("Failed to set MPM210H averaging time: {}", e));
-    }
-    
-    // Set power unit
-    let unit_value = match power_unit {
-        PowerUnit::DBm => 0,
-        PowerUnit::MilliWatt => 1,
-    };
-    if let Err(e) = mpm.set_unit(unit_value) {
-        return Err(format!("Failed to set MPM210H measurement unit: {}", e));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{simulated_bench, Faults, LaserModel, SharedRig, CLD1015, MPM210H};
+    use progress::NoopObserver;
+
+    /// Short sweep on the simulated bench: 10 to 50 mA in 10 mA steps, quick retries
+    fn sweep_config() -> CurrentSweepConfig {
+        CurrentSweepConfig {
+            start_ma: 10.0,
+            stop_ma: 50.0,
+            step_ma: 10.0,
+            stabilization_delay_ms: 0,
+            retry_backoff_ms: 1,
+            ..CurrentSweepConfig::default()
+        }
     }
 
-    // Ensure mpm210h is at the correct wavelength for the laser
-    if let Err(e) = mpm.set_wavelength(wavelength_nm) {
-        return Err(format!("Failed to set MPM210H wavelength: {}", e));
+    /// Run a current sweep on a simulated bench with the given faults, writing into a
+    /// fresh directory under the system temp directory
+    fn run_sweep(name: &str, config: CurrentSweepConfig, cld_faults: Faults, mpm_faults: Faults)
+        -> (Result<PathBuf, Error>, RunReport, DeviceRegistry<CLD1015, MPM210H>, SharedRig) {
+        let directory = std::env::temp_dir().join(format!("cld1015_mpm210h_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let output = OutputConfig { directory, plots: vec![], ..OutputConfig::default() };
+        let run_info = RunInfo { sample_id: "test".into(), ..RunInfo::default() };
+
+        let (cld, mpm, rig) = simulated_bench(LaserModel::default());
+        rig.lock().unwrap().cld_link.faults = cld_faults;
+        rig.lock().unwrap().mpm_link.faults = mpm_faults;
+        let mut devices = DeviceRegistry::bench(cld, mpm);
+        let mut report = RunReport::default();
+        let result = run_current_sweep(&mut devices, config, &run_info, &output, &mut NoopObserver, &mut report);
+        (result, report, devices, rig)
     }
 
-    // Turn laser on
-    if let Err(e) = cld.set_laser_output(true) {
-        return Err(format!("Failed to enable laser output: {}", e));
+    fn laser_is_on(devices: &mut DeviceRegistry<CLD1015, MPM210H>) -> bool {
+        let (cld, _) = devices.laser_and_meter().unwrap();
+        cld.get_laser_output().unwrap()
     }
 
-    info!("Starting current sweep: {} mA to {} mA, step {} mA, module {}, port {}", 
-          start_ma, stop_ma, step_ma, module, port);
-
-    let mut records = Vec::new();
-    let mut current_ma = start_ma;
-
-    while current_ma <= stop_ma {
-        // Set the current
-        match cld.set_current(current_ma / 1000.0) {  // convert to A
-            Ok(_) => {},
-            Err(e) => {
-                // Turn off the laser before returning error
-                let _ = cld.set_laser_output(false);
-                return Err(format!("Failed to set current to {} mA: {}", current_ma, e));
-            }
-        }
+    #[test]
+    fn laser_stuck_on_fails_the_shutdown() {
+        let faults = Faults { laser_stuck_on: true, ..Faults::default() };
+        let (result, _, mut devices, _) = run_sweep("laser_stuck_on", sweep_config(), faults, Faults::default());
 
-        // Wait for stabilization
-        std::thread::sleep(std::time::Duration::from_millis(stabilization_delay_ms));
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), FailureKind::Safety);
+        assert!(error.to_string().contains("could not confirm that the laser output is OFF"), "{}", error);
+        assert!(laser_is_on(&mut devices));
+    }
 
-        // Read power from the specific module and port
-        let power = match mpm.read_power_from_port(module, port) {
-            Ok(p) => p,
-            Err(e) => {
-                // Turn off the laser before returning error
-                let _ = cld.set_laser_output(false);
-                return Err(format!("Failed to read power at {} mA from module {}, port {}: {}", 
-                                 current_ma, module, port, e));
-            }
-        };
+    #[test]
+    fn timed_out_reading_is_retried() {
+        // The MPM210H answers *IDN?, ZERO, IDIS?, the configuration, the dark reference
+        // and the pass wavelength before the first point, so its 11th command is the
+        // reading of the second point. The retry reconnects and the sweep completes.
+        let faults = Faults { timeout_every: Some(11), ..Faults::default() };
+        let (result, report, mut devices, _) = run_sweep("timeout_every", sweep_config(), Faults::default(), faults);
+
+        let path = result.unwrap();
+        assert_eq!(report.status, RunStatus::Completed);
+        let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+        let metadata: serde_json::Value = serde_json::from_reader(
+            std::fs::File::open(path.with_file_name(format!("{}_metadata.json", stem))).unwrap()).unwrap();
+        assert_eq!(metadata["retries"], 1);
+        assert!(!laser_is_on(&mut devices));
+    }
 
-        let now = Utc::now().to_rfc3339();
+    #[test]
+    fn lost_connection_still_turns_the_laser_off() {
+        // The power meter drops off after the first point and never comes back
+        let faults = Faults { fail_after_commands: Some(10), ..Faults::default() };
+        let (result, report, mut devices, _) = run_sweep("fail_after_commands", sweep_config(), Faults::default(), faults);
+
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), FailureKind::Instrument);
+        assert!(error.to_string().contains("Failed to read power at 20 mA"), "{}", error);
+        assert_eq!(report.status, RunStatus::Failed);
+        assert!(!laser_is_on(&mut devices));
+    }
 
-        // Create measurement record
-        let record = MeasurementRecord {
-            timestamp: now.clone(),
-            current_ma,
-            power_dbm: power.clone(),
-            module,
+    #[test]
+    fn tec_off_setpoint_aborts_before_the_laser_is_on() {
+        let config = CurrentSweepConfig {
+            tec_check: Some(TecCheckConfig { timeout_s: 0, ..TecCheckConfig::default() }),
+            ..sweep_config()
         };
+        let faults = Faults { tec_offset_c: 5.0, ..Faults::default() };
+        let (result, _, mut devices, _) = run_sweep("tec_offset_c", config, faults, Faults::default());
 
-        // Print the current measurement to console
-        println!("Current: {:.2} mA, Power: {} dBm", current_ma, power);
-
-        records.push(record);
-        current_ma += step_ma;
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), FailureKind::Instrument);
+        assert!(error.to_string().contains("TEC is not regulating"), "{}", error);
+        assert!(!laser_is_on(&mut devices));
     }
 
-    // Turn laser off after sweep
-    if let Err(e) = cld.set_laser_output(false) {
-        warn!("Failed to disable laser output after sweep: {}", e);
+    #[test]
+    fn invalid_reading_is_not_retried() {
+        let faults = Faults { invalid_readings: true, ..Faults::default() };
+        let (result, _, mut devices, rig) = run_sweep("invalid_readings", sweep_config(), Faults::default(), faults);
+
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), FailureKind::Instrument);
+        assert!(!error.is_transient());
+        assert!(error.to_string().contains("Failed to read power at 10 mA"), "{}", error);
+        // No reconnect: *IDN?, ZERO, IDIS?, configuration, dark reference, pass
+        // wavelength, the one reading of the first point and ERR? at shutdown
+        assert_eq!(rig.lock().unwrap().mpm_link.commands(), 11);
+        assert!(!laser_is_on(&mut devices));
     }
 
-    // Save the results
-    let path = match save_measurements_to_csv(&records) {
-        Ok(p) => p,
-        Err(e) => return Err(format!("Failed to save CSV: {}", e)),
-    };
-
-    info!("Sweep completed. Data saved to: {:?}", path);
-
-    Ok(path)
-}
-
-/// Save the measurement records to a timestamped CSV file
-fn save_measurements_to_csv(data: &[MeasurementRecord]) -> io::Result<PathBuf> {
-    let timestamp = chrono::Local::now()
-        .format("experiment_data_%Y-%m-%d_%H-%M-%S.csv")
-        .to_string();
-
-    let mut path = std::env::current_dir()?;
-    path.push("logs");
-    std::fs::create_dir_all(&path)?;
-    path.push(timestamp);
-
-    let file = File::create(&path)?;
-    let mut writer = Writer::from_writer(file);
-    for record in data {
-        writer.serialize(record)?;
+    #[test]
+    fn preflight_refuses_a_sweep_above_the_current_limit() {
+        let faults = Faults { current_limit_a: Some(0.02), ..Faults::default() };
+        let (result, report, mut devices, _) = run_sweep("current_limit_a", sweep_config(), faults, Faults::default());
+
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), FailureKind::Safety);
+        assert!(error.to_string().contains("Pre-flight validation failed"), "{}", error);
+        assert!(error.to_string().contains("current limit is 20 mA"), "{}", error);
+        assert!(report.curves.is_empty());
+        assert!(!laser_is_on(&mut devices));
     }
-    writer.flush()?;
-
-    info!("Measurements saved to {}", path.display());
-    Ok(path)
-}
\ No newline at end of file
+}
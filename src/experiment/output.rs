@@ -0,0 +1,1193 @@
+use super::analysis::CurveAnalysis;
+use super::data::{MeasurementRecord, RecordTags, RunManifest, RunMetadata};
+use super::influx::InfluxConfig;
+use super::matfile::{self, MatValue};
+use super::mirror::MirrorConfig;
+use super::plot::PlotFormat;
+use super::progress::SweepObserver;
+use super::schema::{self, SCHEMA_VERSION, SCHEMA_VERSION_KEY};
+use super::validation::ConfigViolation;
+use arrow::array::{ArrayRef, BooleanArray, Float64Array, StringArray, UInt32Array, UInt64Array, UInt8Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use csv::{Writer, WriterBuilder};
+use flate2::write::GzEncoder;
+use hdf5::types::VarLenUnicode;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::GzipLevel;
+use parquet::file::properties::WriterProperties;
+use rust_xlsxwriter::{Chart, ChartType, Format, Workbook, XlsxError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// File format for the measurement records
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Csv,  // One row per record
+    Json, // Single document with the configuration, metadata and records
+    Jsonl, // One JSON object per record, written as each point is measured
+    Parquet, // Typed columnar file for pandas/polars
+    Hdf5, // One group per run with a dataset per quantity, as written by the LabVIEW software
+    Xlsx, // Excel workbook with the data, the configuration and metadata, and an L-I chart
+    Mat, // MATLAB level 5 MAT-file with one vector per quantity and the configuration as a struct
+    Origin, // Tab-separated wide table with name and unit header rows, for Origin and Igor Pro
+}
+
+/// Compression applied to the data files
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip, // CSV, JSON and JSON Lines files get a .gz suffix, Parquet uses its internal gzip codec
+}
+
+/// Field separator of the CSV files
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CsvDelimiter {
+    Comma,
+    Semicolon,
+    Tab,
+}
+
+/// Decimal separator of the numbers in the CSV files
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DecimalSeparator {
+    Point,
+    Comma,
+}
+
+/// Line ending of the CSV files
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+/// CSV dialect, e.g. semicolons and decimal commas for spreadsheets in European locales
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvDialect {
+    pub delimiter: CsvDelimiter,
+    pub decimal_separator: DecimalSeparator,
+    pub line_ending: LineEnding,
+    pub header_comments: bool, // Describe the run in `#` comment lines above the column header
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: CsvDelimiter::Comma,            // 1.5,2.0
+            decimal_separator: DecimalSeparator::Point,
+            line_ending: LineEnding::Lf,
+            header_comments: false,                     // Only the schema version line
+        }
+    }
+}
+
+impl CsvDialect {
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        if self.delimiter == CsvDelimiter::Comma && self.decimal_separator == DecimalSeparator::Comma {
+            violations.push(ConfigViolation::new("decimal_separator", "\"comma\"",
+                "decimal commas cannot be used with comma-separated fields")
+                .allowed("delimiter = \"semicolon\" or \"tab\""));
+        }
+        violations
+    }
+
+    fn delimiter_byte(&self) -> u8 {
+        match self.delimiter {
+            CsvDelimiter::Comma => b',',
+            CsvDelimiter::Semicolon => b';',
+            CsvDelimiter::Tab => b'\t',
+        }
+    }
+
+    fn terminator_str(&self) -> &'static str {
+        match self.line_ending {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    fn terminator(&self) -> csv::Terminator {
+        match self.line_ending {
+            LineEnding::Lf => csv::Terminator::Any(b'\n'),
+            LineEnding::Crlf => csv::Terminator::CRLF,
+        }
+    }
+}
+
+/// Where and how the results of a run are saved
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub directory: PathBuf,         // Directory the data files are written to, created if missing
+    pub filename_template: String,  // Name of the data files without extension, see FILENAME_PLACEHOLDERS
+    pub formats: Vec<OutputFormat>, // Data files to write, the metadata sidecar is always written
+    pub hdf5_file: Option<PathBuf>, // Collect the run groups of every run in this HDF5 file instead of one file per run
+    pub database: Option<PathBuf>,  // Also append every run to this SQLite database
+    pub plots: Vec<PlotFormat>,     // Images of the L-I(-V) curve saved after each run
+    pub compression: Compression,   // Compression of the CSV, JSON, JSON Lines and Parquet files
+    pub csv: CsvDialect,
+    pub influxdb: Option<InfluxConfig>, // Also push every point to this InfluxDB server
+    pub fields: Option<Vec<String>>,    // Columns written to the CSV and JSON Lines files, in this order
+    pub transcript: bool,               // Record the instrument traffic in <name>_scpi.log
+    pub split_channels: bool,           // One data file per (module, port) channel instead of one per run
+    pub timezone: String,               // Timezone of the data file timestamps: "UTC", "local" or an IANA name
+    pub mirror: Option<MirrorConfig>,   // Also copy every run to this directory, e.g. a network share
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            directory: PathBuf::from("data"),                                  // Kept apart from the application logs
+            filename_template: "{experiment}_{sample_id}_{date}_{time}".into(), // e.g. current_sweep_wafer7-die12_2024-05-02_14-03-11
+            formats: vec![OutputFormat::Csv], // CSV only
+            hdf5_file: None,                  // One HDF5 file per run
+            database: None,                   // No run database
+            plots: vec![PlotFormat::Png],     // PNG plot next to the data
+            compression: Compression::None,   // Plain files
+            csv: CsvDialect::default(),       // Comma-separated, decimal point, LF
+            influxdb: None,                   // No time-series export
+            fields: None,                     // Every column
+            transcript: false,                // Instrument traffic only in the application log
+            split_channels: false,            // Every channel in the same file
+            timezone: "UTC".into(),           // Unaffected by daylight saving time
+            mirror: None,                     // Local files only
+        }
+    }
+}
+
+/// Placeholders accepted in `filename_template`
+pub const FILENAME_PLACEHOLDERS: [&str; 5] = ["{sample_id}", "{experiment}", "{date}", "{time}", "{repeat}"];
+
+impl OutputConfig {
+    /// Every problem in the output settings
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        if self.formats.is_empty() {
+            violations.push(ConfigViolation::new("formats", "[]", "at least one output format is required")
+                .allowed("\"csv\", \"json\", \"jsonl\", \"parquet\", \"hdf5\", \"xlsx\", \"mat\", \"origin\""));
+        }
+
+        let mut unknown = self.filename_template.clone();
+        for placeholder in FILENAME_PLACEHOLDERS {
+            unknown = unknown.replace(placeholder, "");
+        }
+        if self.filename_template.trim().is_empty() {
+            violations.push(ConfigViolation::new("filename_template", "\"\"", "a file name is required"));
+        } else if unknown.contains('{') || unknown.contains('}') {
+            violations.push(ConfigViolation::new("filename_template", &self.filename_template, "unknown placeholder")
+                .allowed(FILENAME_PLACEHOLDERS.join(", ")));
+        } else if !self.filename_template.contains("{time}") {
+            // Without the time every run of the day would overwrite the previous one
+            violations.push(ConfigViolation::new("filename_template", &self.filename_template,
+                "runs must get distinct names").allowed("a template containing {time}"));
+        }
+        if !valid_timezone(&self.timezone) {
+            violations.push(ConfigViolation::new("timezone", &self.timezone, "unknown timezone")
+                .allowed("\"UTC\", \"local\" or an IANA name such as \"Europe/Berlin\""));
+        }
+        violations.extend(self.csv.violations().into_iter().map(|v| v.in_section("csv")));
+        if let Some(mirror) = &self.mirror {
+            violations.extend(mirror.violations().into_iter().map(|v| v.in_section("mirror")));
+        }
+        if let Some(fields) = &self.fields {
+            if fields.is_empty() {
+                violations.push(ConfigViolation::new("fields", "[]", "at least one field is required")
+                    .allowed(RECORD_COLUMNS.join(", ")));
+            }
+            for field in fields.iter().filter(|f| !RECORD_COLUMNS.contains(&f.as_str())) {
+                violations.push(ConfigViolation::new("fields", field, "unknown field").allowed(RECORD_COLUMNS.join(", ")));
+            }
+        }
+        violations
+    }
+
+    /// Indices into `RECORD_COLUMNS` of the fields to write, in output order
+    pub fn field_indices(&self) -> Vec<usize> {
+        match &self.fields {
+            Some(fields) => fields
+                .iter()
+                .filter_map(|f| RECORD_COLUMNS.iter().position(|c| c == f))
+                .collect(),
+            None => (0..RECORD_COLUMNS.len()).collect(),
+        }
+    }
+
+    /// Base path shared by every file of a run: `filename_template` expanded with the
+    /// sample, experiment, current time and repetition, inside `directory`. Missing
+    /// directories, including any given in the template, are created.
+    pub fn base_path(&self, experiment: &str, sample_id: &str, repeat_index: usize) -> io::Result<PathBuf> {
+        let now = chrono::Local::now();
+        let name = self.filename_template
+            .replace("{sample_id}", &filename_safe(sample_id))
+            .replace("{experiment}", &filename_safe(experiment))
+            .replace("{date}", &now.format("%Y-%m-%d").to_string())
+            .replace("{time}", &now.format("%H-%M-%S").to_string())
+            .replace("{repeat}", &repeat_index.to_string());
+
+        let path = std::env::current_dir()?.join(&self.directory).join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(path)
+    }
+}
+
+/// Complete run as saved in the JSON output
+#[derive(Serialize)]
+struct RunDocument<'a, C: Serialize> {
+    schema_version: u32,
+    experiment: &'a str,
+    config: &'a C,
+    metadata: &'a RunMetadata,
+    records: &'a [MeasurementRecord],
+}
+
+/// Append a suffix to the base path. `Path::with_extension` is not used because
+/// sample IDs may contain dots.
+pub fn with_suffix(base: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(base.as_os_str());
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Create a data file, wrapped in a gzip stream (and named `<path>.gz`) when compressed.
+/// The gzip trailer is written when the returned writer is dropped.
+fn create_data_file(path: PathBuf, compression: Compression) -> io::Result<(PathBuf, Box<dyn Write + Send>)> {
+    match compression {
+        Compression::None => {
+            let file = File::create(&path)?;
+            Ok((path, Box::new(file)))
+        }
+        Compression::Gzip => {
+            let path = with_suffix(&path, ".gz");
+            let file = File::create(&path)?;
+            Ok((path, Box::new(GzEncoder::new(file, flate2::Compression::default()))))
+        }
+    }
+}
+
+fn valid_timezone(name: &str) -> bool {
+    name.eq_ignore_ascii_case("utc") || name.eq_ignore_ascii_case("local") || name.parse::<chrono_tz::Tz>().is_ok()
+}
+
+/// Re-express an RFC 3339 timestamp in `timezone` ("UTC", "local" or an IANA name).
+/// The offset is kept in the text, so timestamps stay unambiguous across DST changes.
+/// Timestamps or timezones that cannot be parsed are returned unchanged.
+pub fn in_timezone(timestamp: &str, timezone: &str) -> String {
+    let Ok(time) = chrono::DateTime::parse_from_rfc3339(timestamp) else {
+        return timestamp.to_string();
+    };
+    if timezone.eq_ignore_ascii_case("utc") {
+        time.with_timezone(&chrono::Utc).to_rfc3339()
+    } else if timezone.eq_ignore_ascii_case("local") {
+        time.with_timezone(&chrono::Local).to_rfc3339()
+    } else {
+        match timezone.parse::<chrono_tz::Tz>() {
+            Ok(tz) => time.with_timezone(&tz).to_rfc3339(),
+            Err(_) => timestamp.to_string(),
+        }
+    }
+}
+
+/// Record as written to the data files: tagged with the run and the timestamps in the
+/// output timezone
+pub fn output_record(record: &MeasurementRecord, tags: &RecordTags, timezone: &str) -> MeasurementRecord {
+    let record = tags.apply(record);
+    MeasurementRecord {
+        timestamp: in_timezone(&record.timestamp, timezone),
+        current_set_at: in_timezone(&record.current_set_at, timezone),
+        power_read_at: in_timezone(&record.power_read_at, timezone),
+        ..record
+    }
+}
+
+/// Lines describing the run for the CSV header comments: experiment, sample, software,
+/// instrument identification and every configuration setting, from the run manifest
+pub fn csv_header_comments(manifest: &serde_json::Value) -> Vec<String> {
+    let text = |value: Option<&serde_json::Value>| value.and_then(|v| v.as_str()).unwrap_or("").to_string();
+    let mut rows = vec![
+        ("experiment".to_string(), text(manifest.get("experiment"))),
+        ("sample_id".to_string(), text(manifest.get("sample_id"))),
+        ("started_at".to_string(), text(manifest.get("started_at"))),
+        ("software_version".to_string(), text(manifest.get("software_version"))),
+        ("git_hash".to_string(), text(manifest.get("git_hash"))),
+        ("cld1015".to_string(), text(manifest.pointer("/cld1015/idn"))),
+        ("mpm210h".to_string(), text(manifest.pointer("/mpm210h/idn"))),
+    ];
+    if let Some(config) = manifest.get("config") {
+        flatten_json("config", config, &mut rows);
+    }
+    // A line break in a value would end the comment
+    rows.into_iter().map(|(key, value)| format!("{}: {}", key, value.replace(['\r', '\n'], " "))).collect()
+}
+
+/// Replace characters that are not safe in file names on every platform
+fn filename_safe(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .collect()
+}
+
+/// Save the whole run as one JSON document
+pub fn save_json<C: Serialize>(
+    base: &Path,
+    experiment: &str,
+    config: &C,
+    metadata: &RunMetadata,
+    data: &[MeasurementRecord],
+    compression: Compression,
+) -> io::Result<PathBuf> {
+    let (path, file) = create_data_file(with_suffix(base, ".json"), compression)?;
+    let document = RunDocument { schema_version: SCHEMA_VERSION, experiment, config, metadata, records: data };
+    serde_json::to_writer_pretty(file, &document)?;
+
+    info!("Run saved as JSON to {}", path.display());
+    Ok(path)
+}
+
+/// Unit of the `power` column. Every record of a run is read in the same unit.
+fn power_unit_symbol(data: &[MeasurementRecord]) -> &'static str {
+    data.first().map_or("", |r| r.power_unit.symbol())
+}
+
+/// Save the measurement records as an Apache Parquet file, one column per record field.
+/// Physical quantities carry their unit in the `unit` field metadata.
+pub fn save_parquet(base: &Path, data: &[MeasurementRecord], compression: Compression) -> io::Result<PathBuf> {
+    let with_unit = |field: Field, unit: &str| field.with_metadata(HashMap::from([("unit".to_string(), unit.to_string())]));
+    let schema_metadata = HashMap::from([(SCHEMA_VERSION_KEY.to_string(), SCHEMA_VERSION.to_string())]);
+    let schema = Arc::new(Schema::new_with_metadata(vec![
+        Field::new("run_id", DataType::Utf8, false),
+        Field::new("sample_id", DataType::Utf8, false),
+        Field::new("repeat_index", DataType::UInt64, false),
+        Field::new("point_index", DataType::UInt64, false),
+        Field::new("timestamp", DataType::Utf8, false),
+        with_unit(Field::new("elapsed_s", DataType::Float64, false), "s"),
+        with_unit(Field::new("current_mA", DataType::Float64, false), "mA"),
+        with_unit(Field::new("measured_current_ma", DataType::Float64, true), "mA"),
+        Field::new("current_mismatch", DataType::Boolean, false),
+        with_unit(Field::new("voltage_v", DataType::Float64, true), "V"),
+        with_unit(Field::new("tec_temperature_c", DataType::Float64, true), "degC"),
+        with_unit(Field::new("power", DataType::Float64, false), power_unit_symbol(data)),
+        Field::new("power_unit", DataType::Utf8, false),
+        with_unit(Field::new("power_dBm", DataType::Float64, false), "dBm"),
+        with_unit(Field::new("power_mW", DataType::Float64, false), "mW"),
+        Field::new("module", DataType::UInt8, false),
+        Field::new("port", DataType::UInt8, false),
+        with_unit(Field::new("wavelength_nm", DataType::UInt32, false), "nm"),
+        Field::new("set_order", DataType::UInt64, false),
+        with_unit(Field::new("power_setpoint_mw", DataType::Float64, true), "mW"),
+        Field::new("current_set_at", DataType::Utf8, false),
+        Field::new("power_read_at", DataType::Utf8, false),
+        with_unit(Field::new("read_latency_ms", DataType::Float64, false), "ms"),
+    ], schema_metadata));
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(data.iter().map(|r| r.run_id.as_str()))),
+        Arc::new(StringArray::from_iter_values(data.iter().map(|r| r.sample_id.as_str()))),
+        Arc::new(UInt64Array::from_iter_values(data.iter().map(|r| r.repeat_index as u64))),
+        Arc::new(UInt64Array::from_iter_values(data.iter().map(|r| r.point_index as u64))),
+        Arc::new(StringArray::from_iter_values(data.iter().map(|r| r.timestamp.as_str()))),
+        Arc::new(Float64Array::from_iter_values(data.iter().map(|r| r.elapsed_s))),
+        Arc::new(Float64Array::from_iter_values(data.iter().map(|r| r.current_ma))),
+        Arc::new(Float64Array::from_iter(data.iter().map(|r| r.measured_current_ma))),
+        Arc::new(BooleanArray::from_iter(data.iter().map(|r| Some(r.current_mismatch)))),
+        Arc::new(Float64Array::from_iter(data.iter().map(|r| r.voltage_v))),
+        Arc::new(Float64Array::from_iter(data.iter().map(|r| r.tec_temperature_c))),
+        Arc::new(Float64Array::from_iter_values(data.iter().map(|r| r.power))),
+        Arc::new(StringArray::from_iter_values(data.iter().map(|r| r.power_unit.symbol()))),
+        Arc::new(Float64Array::from_iter_values(data.iter().map(|r| r.power_dbm))),
+        Arc::new(Float64Array::from_iter_values(data.iter().map(|r| r.power_mw))),
+        Arc::new(UInt8Array::from_iter_values(data.iter().map(|r| r.module))),
+        Arc::new(UInt8Array::from_iter_values(data.iter().map(|r| r.port))),
+        Arc::new(UInt32Array::from_iter_values(data.iter().map(|r| r.wavelength_nm))),
+        Arc::new(UInt64Array::from_iter_values(data.iter().map(|r| r.set_order as u64))),
+        Arc::new(Float64Array::from_iter(data.iter().map(|r| r.power_setpoint_mw))),
+        Arc::new(StringArray::from_iter_values(data.iter().map(|r| r.current_set_at.as_str()))),
+        Arc::new(StringArray::from_iter_values(data.iter().map(|r| r.power_read_at.as_str()))),
+        Arc::new(Float64Array::from_iter_values(data.iter().map(|r| r.read_latency_ms))),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns).map_err(to_io_error)?;
+
+    let path = with_suffix(base, ".parquet");
+    let file = File::create(&path)?;
+    let properties = match compression {
+        Compression::None => None,
+        Compression::Gzip => Some(WriterProperties::builder()
+            .set_compression(parquet::basic::Compression::GZIP(GzipLevel::default()))
+            .build()),
+    };
+    let mut writer = ArrowWriter::try_new(file, schema, properties).map_err(to_io_error)?;
+    writer.write(&batch).map_err(to_io_error)?;
+    writer.close().map_err(to_io_error)?;
+
+    info!("Measurements saved to {}", path.display());
+    Ok(path)
+}
+
+/// Save the run as a group of an HDF5 file, with one dataset per measured quantity
+/// and the configuration and metadata as JSON attributes.
+///
+/// The group is named after the run; with `shared_file` set, runs are appended to
+/// that file, otherwise a new `<base>.h5` file is created. Missing values are NaN.
+pub fn save_hdf5<C: Serialize>(
+    base: &Path,
+    shared_file: Option<&Path>,
+    experiment: &str,
+    config: &C,
+    metadata: &RunMetadata,
+    data: &[MeasurementRecord],
+) -> io::Result<PathBuf> {
+    let path = match shared_file {
+        Some(path) => path.to_path_buf(),
+        None => with_suffix(base, ".h5"),
+    };
+    let run_name = base.file_name().unwrap_or_default().to_string_lossy().to_string();
+    write_hdf5_group(&path, &run_name, experiment, config, metadata, data)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+    info!("Run saved as HDF5 group {} in {}", run_name, path.display());
+    Ok(path)
+}
+
+fn write_hdf5_group<C: Serialize>(
+    path: &Path,
+    run_name: &str,
+    experiment: &str,
+    config: &C,
+    metadata: &RunMetadata,
+    data: &[MeasurementRecord],
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Opens an existing file read/write or creates a new one
+    let file = hdf5::File::append(path)?;
+    let group = file.create_group(run_name)?;
+
+    let floats = |values: Vec<Option<f64>>| -> Vec<f64> { values.into_iter().map(|v| v.unwrap_or(f64::NAN)).collect() };
+    let float_datasets = [
+        ("elapsed_s", "s", floats(data.iter().map(|r| Some(r.elapsed_s)).collect())),
+        ("current_mA", "mA", floats(data.iter().map(|r| Some(r.current_ma)).collect())),
+        ("measured_current_mA", "mA", floats(data.iter().map(|r| r.measured_current_ma).collect())),
+        ("voltage_V", "V", floats(data.iter().map(|r| r.voltage_v).collect())),
+        ("tec_temperature_C", "degC", floats(data.iter().map(|r| r.tec_temperature_c).collect())),
+        ("power", power_unit_symbol(data), floats(data.iter().map(|r| Some(r.power)).collect())),
+        ("power_dBm", "dBm", floats(data.iter().map(|r| Some(r.power_dbm)).collect())),
+        ("power_mW", "mW", floats(data.iter().map(|r| Some(r.power_mw)).collect())),
+        ("power_setpoint_mW", "mW", floats(data.iter().map(|r| r.power_setpoint_mw).collect())),
+        ("read_latency_ms", "ms", floats(data.iter().map(|r| Some(r.read_latency_ms)).collect())),
+    ];
+    for (name, unit, values) in &float_datasets {
+        let dataset = group.new_dataset_builder().with_data(values.as_slice()).create(*name)?;
+        dataset.new_attr::<VarLenUnicode>().create("units")?.write_scalar(&unit.parse::<VarLenUnicode>()?)?;
+    }
+
+    let wavelengths: Vec<u32> = data.iter().map(|r| r.wavelength_nm).collect();
+    group.new_dataset_builder().with_data(wavelengths.as_slice()).create("wavelength_nm")?;
+    let set_order: Vec<u64> = data.iter().map(|r| r.set_order as u64).collect();
+    group.new_dataset_builder().with_data(set_order.as_slice()).create("set_order")?;
+    let point_index: Vec<u64> = data.iter().map(|r| r.point_index as u64).collect();
+    group.new_dataset_builder().with_data(point_index.as_slice()).create("point_index")?;
+    let mismatch: Vec<u8> = data.iter().map(|r| r.current_mismatch as u8).collect();
+    group.new_dataset_builder().with_data(mismatch.as_slice()).create("current_mismatch")?;
+    let timestamps = data
+        .iter()
+        .map(|r| r.timestamp.parse::<VarLenUnicode>())
+        .collect::<Result<Vec<_>, _>>()?;
+    group.new_dataset_builder().with_data(timestamps.as_slice()).create("timestamp")?;
+
+    let attributes = [
+        ("experiment", experiment.to_string()),
+        ("sample_id", metadata.sample_id.clone()),
+        ("config", serde_json::to_string(config)?),
+        ("metadata", serde_json::to_string(metadata)?),
+    ];
+    for (name, value) in &attributes {
+        group.new_attr::<VarLenUnicode>().create(*name)?.write_scalar(&value.parse::<VarLenUnicode>()?)?;
+    }
+    group.new_attr::<u32>().create(SCHEMA_VERSION_KEY)?.write_scalar(&SCHEMA_VERSION)?;
+    group.new_attr::<u64>().create("repeat_index")?.write_scalar(&(metadata.repeat_index as u64))?;
+    if let Some(first) = data.first() {
+        let module_port = [first.module, first.port];
+        group.new_attr_builder().with_data(module_port.as_slice()).create("module_port")?;
+    }
+    Ok(())
+}
+
+/// Flatten a JSON value into (dotted path, value) rows for the settings sheet
+fn flatten_json(prefix: &str, value: &serde_json::Value, rows: &mut Vec<(String, String)>) {
+    let join = |key: &str| if prefix.is_empty() { key.to_string() } else { format!("{}.{}", prefix, key) };
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                flatten_json(&join(key), value, rows);
+            }
+        }
+        serde_json::Value::Array(values) if !values.is_empty() => {
+            for (i, value) in values.iter().enumerate() {
+                flatten_json(&join(&i.to_string()), value, rows);
+            }
+        }
+        serde_json::Value::String(s) => rows.push((prefix.to_string(), s.clone())),
+        serde_json::Value::Null => rows.push((prefix.to_string(), String::new())),
+        other => rows.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+/// Save the run as an Excel workbook: a "Data" sheet with one row per record and an
+/// L-I chart, and a "Settings" sheet listing the configuration and metadata
+pub fn save_xlsx<C: Serialize>(
+    base: &Path,
+    experiment: &str,
+    config: &C,
+    metadata: &RunMetadata,
+    data: &[MeasurementRecord],
+) -> io::Result<PathBuf> {
+    let path = with_suffix(base, ".xlsx");
+    write_xlsx(&path, experiment, config, metadata, data).map_err(to_io_error)?;
+
+    info!("Run saved as Excel workbook to {}", path.display());
+    Ok(path)
+}
+
+fn write_xlsx<C: Serialize>(
+    path: &Path,
+    experiment: &str,
+    config: &C,
+    metadata: &RunMetadata,
+    data: &[MeasurementRecord],
+) -> Result<(), XlsxError> {
+    let mut workbook = Workbook::new();
+    let bold = Format::new().set_bold();
+
+    let sheet = workbook.add_worksheet();
+    sheet.set_name("Data")?;
+    for (col, header) in RECORD_COLUMNS.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, *header, &bold)?;
+    }
+    for (i, record) in data.iter().enumerate() {
+        let row = i as u32 + 1;
+        for (col, cell) in record_cells(record).into_iter().enumerate() {
+            let col = col as u16;
+            match cell {
+                Cell::Number(value) => sheet.write_number(row, col, value)?,
+                Cell::Integer(value) => sheet.write_number(row, col, value as f64)?,
+                Cell::Text(value) => sheet.write_string(row, col, value)?,
+                Cell::Bool(value) => sheet.write_boolean(row, col, value)?,
+                Cell::Empty => continue,
+            };
+        }
+    }
+    sheet.set_freeze_panes(1, 0)?;
+
+    // One series per block of consecutive rows at the same wavelength (one per pass)
+    let column = |name: &str| RECORD_COLUMNS.iter().position(|c| *c == name).unwrap_or_default() as u16;
+    let current_col = column("current_mA");
+    let power_col = column("power_mW");
+    let mut chart = Chart::new(ChartType::Scatter);
+    chart.title().set_name("L-I curve");
+    chart.x_axis().set_name("Current (mA)");
+    chart.y_axis().set_name("Power (mW)");
+    let mut start = 0;
+    while start < data.len() {
+        let wavelength_nm = data[start].wavelength_nm;
+        let end = data[start..].iter().position(|r| r.wavelength_nm != wavelength_nm).map_or(data.len(), |n| start + n);
+        let (first_row, last_row) = (start as u32 + 1, end as u32);
+        chart
+            .add_series()
+            .set_name(format!("{} nm", wavelength_nm).as_str())
+            .set_categories(("Data", first_row, current_col, last_row, current_col))
+            .set_values(("Data", first_row, power_col, last_row, power_col));
+        start = end;
+    }
+    if !data.is_empty() {
+        sheet.insert_chart(1, RECORD_COLUMNS.len() as u16 + 1, &chart)?;
+    }
+
+    let mut rows = vec![
+        (SCHEMA_VERSION_KEY.to_string(), SCHEMA_VERSION.to_string()),
+        ("experiment".to_string(), experiment.to_string()),
+    ];
+    let config = serde_json::to_value(config).map_err(|e| XlsxError::ParameterError(e.to_string()))?;
+    let metadata = serde_json::to_value(metadata).map_err(|e| XlsxError::ParameterError(e.to_string()))?;
+    flatten_json("config", &config, &mut rows);
+    flatten_json("metadata", &metadata, &mut rows);
+
+    let settings = workbook.add_worksheet();
+    settings.set_name("Settings")?;
+    settings.write_string_with_format(0, 0, "setting", &bold)?;
+    settings.write_string_with_format(0, 1, "value", &bold)?;
+    for (i, (key, value)) in rows.iter().enumerate() {
+        settings.write_string(i as u32 + 1, 0, key)?;
+        settings.write_string(i as u32 + 1, 1, value)?;
+    }
+    settings.set_column_width(0, 40)?;
+
+    workbook.save(path)
+}
+
+/// Convert a JSON value to a MAT-file value: objects become structs, numbers and booleans
+/// doubles, numeric arrays column vectors. Other arrays are kept as JSON text.
+fn json_to_mat(value: &serde_json::Value) -> MatValue {
+    match value {
+        serde_json::Value::Object(map) => MatValue::Struct(map.iter().map(|(k, v)| (k.clone(), json_to_mat(v))).collect()),
+        serde_json::Value::Number(n) => MatValue::Double(vec![n.as_f64().unwrap_or(f64::NAN)]),
+        serde_json::Value::Bool(b) => MatValue::Double(vec![if *b { 1.0 } else { 0.0 }]),
+        serde_json::Value::String(s) => MatValue::Char(s.clone()),
+        serde_json::Value::Null => MatValue::Double(Vec::new()),
+        serde_json::Value::Array(values) => match values.iter().map(|v| v.as_f64()).collect::<Option<Vec<f64>>>() {
+            Some(numbers) => MatValue::Double(numbers),
+            None => MatValue::Char(value.to_string()),
+        },
+    }
+}
+
+/// Save the run as a MATLAB MAT-file: one column vector per quantity (missing values are
+/// NaN), the sample and experiment as strings, and the configuration and metadata as structs
+pub fn save_mat<C: Serialize>(
+    base: &Path,
+    experiment: &str,
+    config: &C,
+    metadata: &RunMetadata,
+    data: &[MeasurementRecord],
+) -> io::Result<PathBuf> {
+    let path = with_suffix(base, ".mat");
+    let column = |f: &dyn Fn(&MeasurementRecord) -> Option<f64>| {
+        MatValue::Double(data.iter().map(|r| f(r).unwrap_or(f64::NAN)).collect())
+    };
+    let variables = [
+        (SCHEMA_VERSION_KEY, MatValue::Double(vec![SCHEMA_VERSION as f64])),
+        ("experiment", MatValue::Char(experiment.to_string())),
+        ("sample_id", MatValue::Char(metadata.sample_id.clone())),
+        ("run_id", MatValue::Char(data.first().map(|r| r.run_id.clone()).unwrap_or_default())),
+        ("repeat_index", MatValue::Double(vec![metadata.repeat_index as f64])),
+        ("point_index", column(&|r| Some(r.point_index as f64))),
+        ("elapsed_s", column(&|r| Some(r.elapsed_s))),
+        ("current_mA", column(&|r| Some(r.current_ma))),
+        ("measured_current_mA", column(&|r| r.measured_current_ma)),
+        ("voltage_V", column(&|r| r.voltage_v)),
+        ("tec_temperature_C", column(&|r| r.tec_temperature_c)),
+        ("power_dBm", column(&|r| Some(r.power_dbm))),
+        ("power_mW", column(&|r| Some(r.power_mw))),
+        ("wavelength_nm", column(&|r| Some(r.wavelength_nm as f64))),
+        ("set_order", column(&|r| Some(r.set_order as f64))),
+        ("power_setpoint_mW", column(&|r| r.power_setpoint_mw)),
+        ("config", json_to_mat(&serde_json::to_value(config)?)),
+        ("metadata", json_to_mat(&serde_json::to_value(metadata)?)),
+    ];
+    matfile::write_mat_file(io::BufWriter::new(File::create(&path)?), &variables)?;
+
+    info!("Run saved as MAT-file to {}", path.display());
+    Ok(path)
+}
+
+/// Save the run as a wide tab-separated table for Origin and Igor Pro: one block of
+/// current, power (mW) and voltage columns per wavelength, and per channel when several
+/// were measured, with the points of each block in plan order. The first header row
+/// holds the column names and the second the units; values not measured are left empty.
+pub fn save_origin(base: &Path, data: &[MeasurementRecord]) -> io::Result<PathBuf> {
+    let path = with_suffix(base, "_origin.txt");
+
+    // Blocks in the order they were measured
+    let mut blocks: Vec<((u8, u8, u32), Vec<&MeasurementRecord>)> = Vec::new();
+    for record in data {
+        let key = (record.module, record.port, record.wavelength_nm);
+        match blocks.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, records)) => records.push(record),
+            None => blocks.push((key, vec![record])),
+        }
+    }
+    blocks.iter_mut().for_each(|(_, records)| records.sort_by_key(|r| r.point_index));
+    let channels = blocks.iter().map(|((module, port, _), _)| (module, port)).collect::<BTreeSet<_>>().len();
+    let with_voltage = data.iter().any(|r| r.voltage_v.is_some());
+
+    let mut names = Vec::new();
+    let mut units = Vec::new();
+    for ((module, port, wavelength), _) in &blocks {
+        // Names are valid Igor wave names
+        let suffix = if channels > 1 {
+            format!("{}nm_m{}_p{}", wavelength, module, port)
+        } else {
+            format!("{}nm", wavelength)
+        };
+        names.push(format!("current_{}", suffix));
+        units.push("mA");
+        names.push(format!("power_{}", suffix));
+        units.push("mW");
+        if with_voltage {
+            names.push(format!("voltage_{}", suffix));
+            units.push("V");
+        }
+    }
+
+    let mut file = io::BufWriter::new(File::create(&path)?);
+    writeln!(file, "{}", names.join("\t"))?;
+    writeln!(file, "{}", units.join("\t"))?;
+    let cell = |value: Option<f64>| value.filter(|v| v.is_finite()).map(|v| v.to_string()).unwrap_or_default();
+    let rows = blocks.iter().map(|(_, records)| records.len()).max().unwrap_or(0);
+    for i in 0..rows {
+        let mut cells = Vec::new();
+        for (_, records) in &blocks {
+            let record = records.get(i);
+            cells.push(cell(record.map(|r| r.current_ma)));
+            cells.push(cell(record.map(|r| r.power_mw)));
+            if with_voltage {
+                cells.push(cell(record.and_then(|r| r.voltage_v)));
+            }
+        }
+        writeln!(file, "{}", cells.join("\t"))?;
+    }
+    file.flush()?;
+
+    info!("Run saved as wide table to {}", path.display());
+    Ok(path)
+}
+
+fn to_io_error(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Value of one field of a record, as written to the CSV and XLSX files
+enum Cell<'a> {
+    Number(f64),
+    Integer(u64),
+    Text(&'a str),
+    Bool(bool),
+    Empty,
+}
+
+impl From<Option<f64>> for Cell<'_> {
+    fn from(value: Option<f64>) -> Self {
+        value.map_or(Cell::Empty, Cell::Number)
+    }
+}
+
+/// Column names of the CSV file and the XLSX data sheet, matching `record_cells`
+const RECORD_COLUMNS: [&str; 23] = [
+    "run_id", "sample_id", "repeat_index", "point_index", "timestamp", "elapsed_s", "current_mA", "measured_current_ma", "current_mismatch", "voltage_v",
+    "tec_temperature_c", "power", "power_unit", "power_dBm", "power_mW", "module", "port", "wavelength_nm",
+    "set_order", "power_setpoint_mw", "current_set_at", "power_read_at", "read_latency_ms",
+];
+
+fn record_cells(r: &MeasurementRecord) -> [Cell<'_>; 23] {
+    [
+        Cell::Text(&r.run_id),
+        Cell::Text(&r.sample_id),
+        Cell::Integer(r.repeat_index as u64),
+        Cell::Integer(r.point_index as u64),
+        Cell::Text(&r.timestamp),
+        Cell::Number(r.elapsed_s),
+        Cell::Number(r.current_ma),
+        r.measured_current_ma.into(),
+        Cell::Bool(r.current_mismatch),
+        r.voltage_v.into(),
+        r.tec_temperature_c.into(),
+        Cell::Number(r.power),
+        Cell::Text(r.power_unit.symbol()),
+        Cell::Number(r.power_dbm),
+        Cell::Number(r.power_mw),
+        Cell::Integer(r.module as u64),
+        Cell::Integer(r.port as u64),
+        Cell::Integer(r.wavelength_nm as u64),
+        Cell::Integer(r.set_order as u64),
+        r.power_setpoint_mw.into(),
+        Cell::Text(&r.current_set_at),
+        Cell::Text(&r.power_read_at),
+        Cell::Number(r.read_latency_ms),
+    ]
+}
+
+impl Cell<'_> {
+    /// Text of the cell in a CSV file, with `decimal` as the decimal separator
+    fn to_csv_field(&self, decimal: DecimalSeparator) -> String {
+        match self {
+            // Debug formatting keeps the shortest round-trip representation, e.g. "10.0" or "1e-7"
+            Cell::Number(value) => match decimal {
+                DecimalSeparator::Point => format!("{:?}", value),
+                DecimalSeparator::Comma => format!("{:?}", value).replace('.', ","),
+            },
+            Cell::Integer(value) => value.to_string(),
+            Cell::Text(value) => value.to_string(),
+            Cell::Bool(value) => value.to_string(),
+            Cell::Empty => String::new(),
+        }
+    }
+}
+
+/// Data file written while the data is acquired
+pub trait StreamedOutput: SweepObserver {
+    /// Files created so far
+    fn paths(&self) -> Vec<PathBuf>;
+}
+
+/// Create the writer of a format saved while the data is acquired (CSV or JSON Lines).
+/// With `split_channels` set, each (module, port) channel gets its own file. `comments`
+/// are written above the CSV header when `header_comments` is set.
+pub fn create_streamed(
+    format: OutputFormat,
+    base: &Path,
+    tags: &RecordTags,
+    output: &OutputConfig,
+    comments: &[String],
+) -> io::Result<Box<dyn StreamedOutput>> {
+    let split_channels = output.split_channels;
+    let (tags, output, comments) = (tags.clone(), output.clone(), comments.to_vec());
+    let open = move |base: &Path| -> io::Result<Box<dyn StreamedOutput>> {
+        match format {
+            OutputFormat::Csv => Ok(Box::new(CsvRowWriter::create(base, &tags, &output, &comments)?)),
+            OutputFormat::Jsonl => Ok(Box::new(JsonLinesWriter::create(base, &tags, &output)?)),
+            other => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} is not written during the run", other))),
+        }
+    };
+    if split_channels {
+        Ok(Box::new(ChannelSplit { base: base.to_path_buf(), open: Box::new(open), channels: Vec::new(), failed: false }))
+    } else {
+        open(base)
+    }
+}
+
+/// Base path of the files of one (module, port) channel: `<base>_m<module>_p<port>`
+pub fn channel_base(base: &Path, module: u8, port: u8) -> PathBuf {
+    with_suffix(base, &format!("_m{}_p{}", module, port))
+}
+
+/// Records grouped by (module, port) channel, in the order the channels were first measured
+pub fn split_by_channel(data: &[MeasurementRecord]) -> Vec<((u8, u8), Vec<MeasurementRecord>)> {
+    let mut channels: Vec<((u8, u8), Vec<MeasurementRecord>)> = Vec::new();
+    for record in data {
+        let channel = (record.module, record.port);
+        match channels.iter_mut().find(|(c, _)| *c == channel) {
+            Some((_, records)) => records.push(record.clone()),
+            None => channels.push((channel, vec![record.clone()])),
+        }
+    }
+    channels
+}
+
+type OpenStreamed = Box<dyn FnMut(&Path) -> io::Result<Box<dyn StreamedOutput>>>;
+
+/// Observer passing each record to the writer of its (module, port) channel. A channel's
+/// file is created when its first record arrives.
+struct ChannelSplit {
+    base: PathBuf,
+    open: OpenStreamed,
+    channels: Vec<((u8, u8), Box<dyn StreamedOutput>)>,
+    failed: bool, // Set after the first error so it is only reported once
+}
+
+impl SweepObserver for ChannelSplit {
+    fn on_record(&mut self, record: &MeasurementRecord) {
+        let channel = (record.module, record.port);
+        if !self.channels.iter().any(|(c, _)| *c == channel) {
+            let base = channel_base(&self.base, record.module, record.port);
+            match (self.open)(&base) {
+                Ok(writer) => self.channels.push((channel, writer)),
+                Err(e) => {
+                    if !self.failed {
+                        warn!("Failed to create output for module {}, port {}: {}", record.module, record.port, e);
+                        self.failed = true;
+                    }
+                    return;
+                }
+            }
+        }
+        if let Some((_, writer)) = self.channels.iter_mut().find(|(c, _)| *c == channel) {
+            writer.on_record(record);
+        }
+    }
+
+    fn on_finish(&mut self, success: bool) {
+        for (_, writer) in &mut self.channels {
+            writer.on_finish(success);
+        }
+    }
+}
+
+impl StreamedOutput for ChannelSplit {
+    fn paths(&self) -> Vec<PathBuf> {
+        self.channels.iter().flat_map(|(_, writer)| writer.paths()).collect()
+    }
+}
+
+/// Observer writing each record as one CSV row as soon as it is measured.
+///
+/// Every row is flushed, so a crash or a failed run keeps all points measured so far.
+pub struct CsvRowWriter {
+    path: PathBuf,
+    writer: Writer<Box<dyn Write + Send>>,
+    decimal: DecimalSeparator,
+    columns: Vec<usize>, // Indices into RECORD_COLUMNS
+    tags: RecordTags,
+    timezone: String,
+    failed: bool, // Set after the first write error so it is only reported once
+}
+
+impl CsvRowWriter {
+    pub fn create(base: &Path, tags: &RecordTags, output: &OutputConfig, comments: &[String]) -> io::Result<Self> {
+        let (path, file) = create_data_file(with_suffix(base, ".csv"), output.compression)?;
+        let mut writer = WriterBuilder::new()
+            .delimiter(output.csv.delimiter_byte())
+            .terminator(output.csv.terminator())
+            .from_writer(file);
+        let columns = output.field_indices();
+        let line_ending = output.csv.terminator_str();
+        writer.get_mut().write_all(format!("{}{}", schema::csv_version_comment(), line_ending).as_bytes())?;
+        if output.csv.header_comments {
+            for comment in comments {
+                writer.get_mut().write_all(format!("# {}{}", comment, line_ending).as_bytes())?;
+            }
+        }
+        writer.write_record(columns.iter().map(|&i| RECORD_COLUMNS[i]))?;
+        writer.flush()?;
+        info!("Writing records to {} as they are measured", path.display());
+        Ok(CsvRowWriter {
+            path,
+            writer,
+            decimal: output.csv.decimal_separator,
+            columns,
+            tags: tags.clone(),
+            timezone: output.timezone.clone(),
+            failed: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_record(&mut self, record: &MeasurementRecord) -> io::Result<()> {
+        let record = output_record(record, &self.tags, &self.timezone);
+        let cells = record_cells(&record);
+        let fields = self.columns.iter().map(|&i| cells[i].to_csv_field(self.decimal));
+        self.writer.write_record(fields)?;
+        self.writer.flush()
+    }
+}
+
+impl StreamedOutput for CsvRowWriter {
+    fn paths(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+}
+
+impl SweepObserver for CsvRowWriter {
+    fn on_record(&mut self, record: &MeasurementRecord) {
+        if let Err(e) = self.write_record(record) {
+            if !self.failed {
+                warn!("Failed to write record to {}: {}", self.path.display(), e);
+                self.failed = true;
+            }
+        }
+    }
+
+    fn on_finish(&mut self, _success: bool) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Observer writing each record as one JSON line as soon as it is measured.
+///
+/// Every line is flushed, so the data acquired so far survives a crash and the
+/// file can be followed live by other tools.
+pub struct JsonLinesWriter {
+    path: PathBuf,
+    writer: LineWriter<Box<dyn Write + Send>>,
+    fields: Option<Vec<String>>, // Keys to keep, all if None
+    tags: RecordTags,
+    timezone: String,
+    failed: bool, // Set after the first write error so it is only reported once
+}
+
+impl JsonLinesWriter {
+    pub fn create(base: &Path, tags: &RecordTags, output: &OutputConfig) -> io::Result<Self> {
+        let (path, file) = create_data_file(with_suffix(base, ".jsonl"), output.compression)?;
+        let writer = LineWriter::new(file);
+        info!("Writing records to {} as they are measured", path.display());
+        Ok(JsonLinesWriter {
+            path,
+            writer,
+            fields: output.fields.clone(),
+            tags: tags.clone(),
+            timezone: output.timezone.clone(),
+            failed: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_record(&mut self, record: &MeasurementRecord) -> io::Result<()> {
+        let record = output_record(record, &self.tags, &self.timezone);
+        let mut value = serde_json::to_value(&record)?;
+        if let (Some(fields), serde_json::Value::Object(map)) = (&self.fields, &mut value) {
+            map.retain(|key, _| fields.contains(key));
+        }
+        if let serde_json::Value::Object(map) = &mut value {
+            map.insert(SCHEMA_VERSION_KEY.to_string(), SCHEMA_VERSION.into());
+        }
+        let line = serde_json::to_string(&value)?;
+        writeln!(self.writer, "{}", line)
+    }
+}
+
+impl StreamedOutput for JsonLinesWriter {
+    fn paths(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+}
+
+impl SweepObserver for JsonLinesWriter {
+    fn on_record(&mut self, record: &MeasurementRecord) {
+        if let Err(e) = self.write_record(record) {
+            if !self.failed {
+                warn!("Failed to write record to {}: {}", self.path.display(), e);
+                self.failed = true;
+            }
+        }
+    }
+
+    fn on_finish(&mut self, _success: bool) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Save the run metadata as a JSON sidecar next to the data files
+pub fn save_metadata(base: &Path, metadata: &RunMetadata) -> io::Result<PathBuf> {
+    let path = with_suffix(base, "_metadata.json");
+    let mut value = serde_json::to_value(metadata)?;
+    if let serde_json::Value::Object(map) = &mut value {
+        map.insert(SCHEMA_VERSION_KEY.to_string(), SCHEMA_VERSION.into());
+    }
+    let file = File::create(&path)?;
+    serde_json::to_writer_pretty(file, &value)?;
+
+    info!("Run metadata saved to {}", path.display());
+    Ok(path)
+}
+
+/// Save a one-row CSV summary of the run for run-tracking spreadsheets: outcome, duration,
+/// point count, power statistics and problem counts. Written for failed runs too.
+pub fn save_summary(
+    base: &Path,
+    dialect: &CsvDialect,
+    manifest: &RunManifest,
+    metadata: &RunMetadata,
+    data: &[MeasurementRecord],
+) -> io::Result<PathBuf> {
+    let path = with_suffix(base, "_summary.csv");
+
+    let duration_s = manifest.finished_at.as_deref().and_then(|finished| {
+        let started = chrono::DateTime::parse_from_rfc3339(&manifest.started_at).ok()?;
+        let finished = chrono::DateTime::parse_from_rfc3339(finished).ok()?;
+        Some((finished - started).num_milliseconds() as f64 / 1000.0)
+    });
+    let powers: Vec<f64> = data.iter().map(|r| r.power_mw).filter(|p| p.is_finite()).collect();
+    let min_power = powers.iter().copied().reduce(f64::min);
+    let max_power = powers.iter().copied().reduce(f64::max);
+    let mean_power = (!powers.is_empty()).then(|| powers.iter().sum::<f64>() / powers.len() as f64);
+    let name = base.file_name().unwrap_or_default().to_string_lossy();
+    // Entries joined into one cell, "unread" when the queue could not be read
+    let queue = |entries: &Option<Vec<String>>| entries.as_ref().map_or("unread".to_string(), |e| e.join(" | "));
+    let (cld_errors, mpm_errors) = (queue(&manifest.instrument_errors.cld1015), queue(&manifest.instrument_errors.mpm210h));
+
+    let fields = [
+        ("run", Cell::Text(&name)),
+        ("experiment", Cell::Text(&manifest.experiment)),
+        ("sample_id", Cell::Text(&manifest.sample_id)),
+        ("status", Cell::Text(manifest.status.as_str())),
+        ("started_at", Cell::Text(&manifest.started_at)),
+        ("duration_s", duration_s.into()),
+        ("points", Cell::Integer(data.len() as u64)),
+        ("min_power_mW", min_power.into()),
+        ("max_power_mW", max_power.into()),
+        ("mean_power_mW", mean_power.into()),
+        ("retries", Cell::Integer(metadata.retries as u64)),
+        ("current_mismatches", Cell::Integer(data.iter().filter(|r| r.current_mismatch).count() as u64)),
+        ("kinks", Cell::Integer(metadata.kinks.len() as u64)),
+        ("rollovers", Cell::Integer(metadata.rollovers.len() as u64)),
+        ("error", Cell::Text(manifest.error.as_deref().unwrap_or(""))),
+        ("cld1015_errors", Cell::Text(&cld_errors)),
+        ("mpm210h_errors", Cell::Text(&mpm_errors)),
+        ("schema_version", Cell::Integer(manifest.schema_version as u64)),
+    ];
+
+    let mut writer = WriterBuilder::new()
+        .delimiter(dialect.delimiter_byte())
+        .terminator(dialect.terminator())
+        .from_path(&path)?;
+    writer.write_record(fields.iter().map(|(name, _)| *name))?;
+    writer.write_record(fields.iter().map(|(_, cell)| cell.to_csv_field(dialect.decimal_separator)))?;
+    writer.flush()?;
+
+    info!("Run summary saved to {}", path.display());
+    Ok(path)
+}
+
+/// SHA-256 of a file's contents, hex encoded
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Save the SHA-256 checksums of `files` as `<base>.sha256`, in the format read by
+/// `sha256sum -c` with paths relative to the run directory. Returns the checksums by path.
+pub fn save_checksums(base: &Path, files: &[PathBuf]) -> io::Result<BTreeMap<String, String>> {
+    let directory = base.parent().unwrap_or(Path::new(""));
+    let mut checksums = BTreeMap::new();
+    let mut lines = String::new();
+    for path in files {
+        let checksum = sha256_file(path)?;
+        let name = path.strip_prefix(directory).unwrap_or(path);
+        lines.push_str(&format!("{}  {}\n", checksum, name.display()));
+        checksums.insert(path.display().to_string(), checksum);
+    }
+
+    let path = with_suffix(base, ".sha256");
+    std::fs::write(&path, lines)?;
+    info!("Data file checksums saved to {}", path.display());
+    Ok(checksums)
+}
+
+/// Save the run manifest as a JSON sidecar next to the data files
+pub fn save_manifest(base: &Path, manifest: &RunManifest) -> io::Result<PathBuf> {
+    let path = with_suffix(base, "_manifest.json");
+    let file = File::create(&path)?;
+    serde_json::to_writer_pretty(file, manifest)?;
+
+    info!("Run manifest saved to {}", path.display());
+    Ok(path)
+}
+
+/// Analysis of a run as saved in the report file
+#[derive(Serialize)]
+struct AnalysisReport<'a> {
+    schema_version: u32,
+    experiment: &'a str,
+    sample_id: &'a str,
+    curves: &'a [CurveAnalysis],
+}
+
+/// Save the analysis report as a JSON file next to the data files
+pub fn save_analysis(base: &Path, experiment: &str, sample_id: &str, curves: &[CurveAnalysis]) -> io::Result<PathBuf> {
+    let path = with_suffix(base, "_analysis.json");
+    let file = File::create(&path)?;
+    serde_json::to_writer_pretty(file, &AnalysisReport { schema_version: SCHEMA_VERSION, experiment, sample_id, curves })?;
+
+    info!("Analysis report saved to {}", path.display());
+    Ok(path)
+}
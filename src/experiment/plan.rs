@@ -0,0 +1,264 @@
+use super::current_sweep::{slew_ramp, SLEW_STEP_INTERVAL_MS};
+use super::{sweep_points, CurrentSweepConfig, FastSweepConfig, PowerSweepConfig, StabilityConfig, ZEROING_WAIT};
+use std::fmt;
+use std::time::Duration;
+
+/// Typical round-trip time of one SCPI query, added to every power reading
+const QUERY_OVERHEAD_MS: f64 = 20.0;
+
+/// One point of the sweep, in the order it will be measured
+#[derive(Debug, Clone, Copy)]
+pub struct PlannedPoint {
+    pub wavelength_nm: u32,
+    pub current_ma: f64,                // 0 for a power sweep, where the controller chooses the current
+    pub power_setpoint_mw: Option<f64>, // Optical power setpoint of a power sweep
+}
+
+/// Every point a current sweep will visit, with an estimate of how long it will take
+#[derive(Debug, Clone)]
+pub struct SweepPlan {
+    pub points: Vec<PlannedPoint>,
+    pub estimated_duration: Duration,
+    pub randomized: bool, // Points are shuffled at run time, so the listed order is nominal
+    pub module: u8,       // MPM210H module read at every point
+    pub port: u8,         // MPM210H port read at every point
+}
+
+impl SweepPlan {
+    /// Expand the configuration into the full point list without touching any hardware.
+    ///
+    /// The estimate covers zeroing, dark references, warm-up, slew ramps, stabilization
+    /// or settling and the averaging time of every reading. Waiting for the TEC and
+    /// retries are not included.
+    pub fn for_current_sweep(config: &CurrentSweepConfig) -> SweepPlan {
+        let currents = sweep_points(config.start_ma, config.stop_ma, config.step_ma);
+        let reading_ms = config.averaging_time_ms + QUERY_OVERHEAD_MS;
+
+        let points: Vec<PlannedPoint> = config.wavelengths_nm
+            .iter()
+            .flat_map(|&wavelength_nm| currents.iter().map(move |&current_ma| PlannedPoint { wavelength_nm, current_ma, power_setpoint_mw: None }))
+            .collect();
+
+        // Fixed overhead: zeroing plus one dark reference per wavelength before and after
+        let mut total_ms = ZEROING_WAIT.as_secs_f64() * 1000.0
+            + 2.0 * config.wavelengths_nm.len() as f64 * reading_ms
+            + config.stabilization_delay_ms as f64;
+
+        let mut previous_ma = 0.0;
+        if let Some(warm_up) = &config.warm_up {
+            total_ms += warm_up.duration_s as f64 * 1000.0;
+            total_ms += ramp_ms(config, previous_ma, warm_up.current_ma);
+            previous_ma = warm_up.current_ma;
+        }
+
+        // Per point: slew ramp, then either the fixed delay and one reading or at least
+        // two readings one settling interval apart
+        let mut point_ms = match &config.settling {
+            Some(settling) => settling.interval_ms as f64 + 2.0 * reading_ms,
+            None => config.stabilization_delay_ms as f64 + reading_ms,
+        };
+        let extra_queries = [config.current_tolerance_ma.is_some(), config.measure_voltage, config.measure_temperature];
+        point_ms += extra_queries.iter().filter(|&&enabled| enabled).count() as f64 * QUERY_OVERHEAD_MS;
+        for point in &points {
+            total_ms += ramp_ms(config, previous_ma, point.current_ma) + point_ms;
+            previous_ma = point.current_ma;
+        }
+
+        SweepPlan {
+            points,
+            estimated_duration: Duration::from_secs_f64(total_ms / 1000.0),
+            randomized: config.randomize_order,
+            module: config.module,
+            port: config.port,
+        }
+    }
+
+    /// Expand a fast sweep configuration. The estimate covers zeroing and the logging
+    /// run itself; retrieving the log takes a few seconds more.
+    pub fn for_fast_sweep(config: &FastSweepConfig) -> SweepPlan {
+        let points: Vec<PlannedPoint> = sweep_points(config.start_ma, config.stop_ma, config.step_ma)
+            .into_iter()
+            .map(|current_ma| PlannedPoint { wavelength_nm: config.wavelength_nm, current_ma, power_setpoint_mw: None })
+            .collect();
+        let total_ms = ZEROING_WAIT.as_secs_f64() * 1000.0 + points.len() as f64 * config.dwell_ms as f64;
+
+        SweepPlan {
+            points,
+            estimated_duration: Duration::from_secs_f64(total_ms / 1000.0),
+            randomized: false,
+            module: config.module,
+            port: config.port,
+        }
+    }
+
+    /// Expand a stability run: one point per reading, all at the held current. The
+    /// estimate covers zeroing and the run itself.
+    pub fn for_stability(config: &StabilityConfig) -> SweepPlan {
+        let point = PlannedPoint { wavelength_nm: config.wavelength_nm, current_ma: config.current_ma, power_setpoint_mw: None };
+        let points = vec![point; config.points()];
+        let total_ms = ZEROING_WAIT.as_secs_f64() * 1000.0 + config.duration_s * 1000.0;
+
+        SweepPlan {
+            points,
+            estimated_duration: Duration::from_secs_f64(total_ms / 1000.0),
+            randomized: false,
+            module: config.module,
+            port: config.port,
+        }
+    }
+
+    /// Expand a power sweep: one point per power setpoint. The estimate covers zeroing,
+    /// the stabilization delay and the reading at every setpoint.
+    pub fn for_power_sweep(config: &PowerSweepConfig) -> SweepPlan {
+        let points: Vec<PlannedPoint> = sweep_points(config.start_mw, config.stop_mw, config.step_mw)
+            .into_iter()
+            .map(|setpoint_mw| PlannedPoint { wavelength_nm: config.wavelength_nm, current_ma: 0.0, power_setpoint_mw: Some(setpoint_mw) })
+            .collect();
+        let point_ms = config.stabilization_delay_ms as f64 + 2.0 * QUERY_OVERHEAD_MS + config.averaging_time_ms;
+        let total_ms = ZEROING_WAIT.as_secs_f64() * 1000.0 + points.len() as f64 * point_ms;
+
+        SweepPlan {
+            points,
+            estimated_duration: Duration::from_secs_f64(total_ms / 1000.0),
+            randomized: false,
+            module: config.module,
+            port: config.port,
+        }
+    }
+
+    /// Calibration wavelengths in the order they are measured
+    pub fn wavelengths_nm(&self) -> Vec<u32> {
+        let mut wavelengths: Vec<u32> = Vec::new();
+        for point in &self.points {
+            if !wavelengths.contains(&point.wavelength_nm) {
+                wavelengths.push(point.wavelength_nm);
+            }
+        }
+        wavelengths
+    }
+}
+
+/// Time spent on intermediate setpoints when moving between two currents
+fn ramp_ms(config: &CurrentSweepConfig, from_ma: f64, to_ma: f64) -> f64 {
+    match config.max_slew_rate_ma_per_s {
+        Some(rate) => (slew_ramp(from_ma, to_ma, rate).len() as u64 * SLEW_STEP_INTERVAL_MS) as f64,
+        None => 0.0,
+    }
+}
+
+impl fmt::Display for SweepPlan {
+    /// Summary line: point count and estimated duration
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.estimated_duration.as_secs();
+        write!(f, "{} points, estimated duration {}h {:02}m {:02}s",
+               self.points.len(), seconds / 3600, seconds / 60 % 60, seconds % 60)?;
+        if self.randomized {
+            write!(f, " (points are visited in random order)")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experiment::WarmUpConfig;
+
+    /// Three points at one wavelength: 10, 20 and 30 mA
+    fn short_sweep() -> CurrentSweepConfig {
+        CurrentSweepConfig {
+            start_ma: 10.0,
+            stop_ma: 30.0,
+            step_ma: 10.0,
+            stabilization_delay_ms: 50,
+            averaging_time_ms: 100.0,
+            ..CurrentSweepConfig::default()
+        }
+    }
+
+    fn assert_duration_ms(plan: &SweepPlan, expected_ms: f64) {
+        let actual_ms = plan.estimated_duration.as_secs_f64() * 1000.0;
+        assert!((actual_ms - expected_ms).abs() < 1e-3, "{} ms != {} ms", actual_ms, expected_ms);
+    }
+
+    #[test]
+    fn current_sweep_lists_every_wavelength_pass_in_order() {
+        let config = CurrentSweepConfig { stop_ma: 10.0, start_ma: 30.0, wavelengths_nm: vec![1550, 1310], ..short_sweep() };
+        let plan = SweepPlan::for_current_sweep(&config);
+
+        let points: Vec<(u32, f64)> = plan.points.iter().map(|p| (p.wavelength_nm, p.current_ma)).collect();
+        assert_eq!(points, [(1550, 30.0), (1550, 20.0), (1550, 10.0), (1310, 30.0), (1310, 20.0), (1310, 10.0)]);
+        assert!(plan.points.iter().all(|p| p.power_setpoint_mw.is_none()));
+        assert_eq!(plan.wavelengths_nm(), [1550, 1310]);
+        assert!(!plan.randomized);
+    }
+
+    #[test]
+    fn current_sweep_estimate() {
+        // Zeroing, a dark reference before and after and the stabilization delay before
+        // the final dark reference, then per point the delay, one reading and the
+        // voltage and temperature queries
+        let plan = SweepPlan::for_current_sweep(&short_sweep());
+        assert_duration_ms(&plan, 3000.0 + 2.0 * 120.0 + 50.0 + 3.0 * (50.0 + 120.0 + 2.0 * 20.0));
+    }
+
+    #[test]
+    fn current_sweep_estimate_includes_slew_ramps_and_warm_up() {
+        let base = SweepPlan::for_current_sweep(&short_sweep()).estimated_duration;
+
+        // 100 mA/s allows 1 mA per step, so every 10 mA change takes 9 intermediate steps
+        let slewed = CurrentSweepConfig { max_slew_rate_ma_per_s: Some(100.0), ..short_sweep() };
+        let plan = SweepPlan::for_current_sweep(&slewed);
+        assert_duration_ms(&plan, base.as_secs_f64() * 1000.0 + 3.0 * 9.0 * SLEW_STEP_INTERVAL_MS as f64);
+
+        let warm_up = WarmUpConfig { current_ma: 10.0, duration_s: 60, record_interval_ms: None };
+        let warmed = CurrentSweepConfig { warm_up: Some(warm_up), ..short_sweep() };
+        let plan = SweepPlan::for_current_sweep(&warmed);
+        assert_duration_ms(&plan, base.as_secs_f64() * 1000.0 + 60_000.0);
+    }
+
+    #[test]
+    fn randomized_sweep_is_flagged() {
+        let plan = SweepPlan::for_current_sweep(&CurrentSweepConfig { randomize_order: true, ..short_sweep() });
+        assert!(plan.randomized);
+        assert!(plan.to_string().ends_with("(points are visited in random order)"));
+    }
+
+    #[test]
+    fn fast_sweep_plan() {
+        let config = FastSweepConfig { start_ma: 10.0, stop_ma: 12.0, step_ma: 0.5, dwell_ms: 20, ..FastSweepConfig::default() };
+        let plan = SweepPlan::for_fast_sweep(&config);
+        assert_eq!(plan.points.len(), 5);
+        assert_eq!(plan.wavelengths_nm(), [config.wavelength_nm]);
+        assert_duration_ms(&plan, 3000.0 + 5.0 * 20.0);
+    }
+
+    #[test]
+    fn stability_plan_holds_one_current() {
+        let config = StabilityConfig { duration_s: 10.0, interval_ms: 1000, ..StabilityConfig::default() };
+        let plan = SweepPlan::for_stability(&config);
+        assert_eq!(plan.points.len(), 11);
+        assert!(plan.points.iter().all(|p| p.current_ma == config.current_ma));
+        assert_duration_ms(&plan, 3000.0 + 10_000.0);
+    }
+
+    #[test]
+    fn power_sweep_plan_lists_the_setpoints() {
+        let config = PowerSweepConfig { start_mw: 1.0, stop_mw: 3.0, step_mw: 1.0, ..PowerSweepConfig::default() };
+        let plan = SweepPlan::for_power_sweep(&config);
+        let setpoints: Vec<Option<f64>> = plan.points.iter().map(|p| p.power_setpoint_mw).collect();
+        assert_eq!(setpoints, [Some(1.0), Some(2.0), Some(3.0)]);
+    }
+
+    #[test]
+    fn summary_line() {
+        let plan = SweepPlan {
+            points: vec![],
+            estimated_duration: Duration::from_secs(3723),
+            randomized: false,
+            module: 0,
+            port: 2,
+        };
+        assert_eq!(plan.to_string(), "0 points, estimated duration 1h 02m 03s");
+    }
+}
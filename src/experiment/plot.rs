@@ -0,0 +1,170 @@
+use super::data::MeasurementRecord;
+use super::live_plot::axis_range;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Image format of the plot saved after each run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlotFormat {
+    Png,
+    Svg,
+}
+
+const WIDTH: u32 = 1024;
+const HEIGHT: u32 = 768;
+
+/// Save the L-I curve of every wavelength as `<base>_li.png` or `.svg`. When the forward
+/// voltage was measured it is drawn against a second axis (L-I-V plot).
+pub fn save_plot(base: &Path, format: PlotFormat, title: &str, data: &[MeasurementRecord]) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = match format {
+        PlotFormat::Png => super::output::with_suffix(base, "_li.png"),
+        PlotFormat::Svg => super::output::with_suffix(base, "_li.svg"),
+    };
+    match format {
+        PlotFormat::Png => draw_li_plot(&BitMapBackend::new(&path, (WIDTH, HEIGHT)).into_drawing_area(), title, data)?,
+        PlotFormat::Svg => draw_li_plot(&SVGBackend::new(&path, (WIDTH, HEIGHT)).into_drawing_area(), title, data)?,
+    }
+
+    info!("L-I plot saved to {}", path.display());
+    Ok(path)
+}
+
+fn draw_li_plot<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    title: &str,
+    data: &[MeasurementRecord],
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+
+    let mut wavelengths: Vec<u32> = data.iter().map(|r| r.wavelength_nm).collect();
+    wavelengths.sort_unstable();
+    wavelengths.dedup();
+    let has_voltage = data.iter().any(|r| r.voltage_v.is_some());
+
+    let (x_min, x_max) = axis_range(data.iter().map(|r| r.current_ma));
+    let (y_min, y_max) = axis_range(data.iter().map(|r| r.power_mw).filter(|p| p.is_finite()));
+    let (v_min, v_max) = axis_range(data.iter().filter_map(|r| r.voltage_v));
+
+    let mut chart = ChartBuilder::on(root)
+        .caption(title, ("sans-serif", 24))
+        .margin(15)
+        .x_label_area_size(45)
+        .y_label_area_size(70)
+        .right_y_label_area_size(if has_voltage { 70 } else { 0 })
+        .build_cartesian_2d(x_min..x_max, y_min..y_max)?
+        .set_secondary_coord(x_min..x_max, v_min..v_max);
+
+    chart
+        .configure_mesh()
+        .x_desc("Current (mA)")
+        .y_desc("Power (mW)")
+        .draw()?;
+    if has_voltage {
+        chart.configure_secondary_axes().y_desc("Voltage (V)").draw()?;
+    }
+
+    for (i, &wavelength_nm) in wavelengths.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        let mut pass: Vec<&MeasurementRecord> = data.iter().filter(|r| r.wavelength_nm == wavelength_nm).collect();
+        pass.sort_by(|a, b| a.current_ma.total_cmp(&b.current_ma));
+
+        let power: Vec<(f64, f64)> = pass.iter().map(|r| (r.current_ma, r.power_mw)).filter(|p| p.1.is_finite()).collect();
+        chart
+            .draw_series(LineSeries::new(power.iter().copied(), color.stroke_width(2)))?
+            .label(format!("{} nm power", wavelength_nm))
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2)));
+        chart.draw_series(power.iter().map(|&(x, y)| Circle::new((x, y), 3, color.filled())))?;
+
+        if has_voltage {
+            let voltage: Vec<(f64, f64)> = pass.iter().filter_map(|r| r.voltage_v.map(|v| (r.current_ma, v))).collect();
+            let faded = color.mix(0.5);
+            chart
+                .draw_secondary_series(LineSeries::new(voltage, faded.stroke_width(1)))?
+                .label(format!("{} nm voltage", wavelength_nm))
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], faded.stroke_width(1)));
+        }
+    }
+
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+
+    root.present()?;
+    Ok(())
+}
+
+/// Save several labelled L-I curves (current in mA, power in mW) in one plot as
+/// `<base>_li.png` or `.svg`, e.g. to compare runs before and after burn-in
+pub fn save_overlay_plot(
+    base: &Path,
+    format: PlotFormat,
+    title: &str,
+    curves: &[(String, Vec<(f64, f64)>)],
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = match format {
+        PlotFormat::Png => super::output::with_suffix(base, "_li.png"),
+        PlotFormat::Svg => super::output::with_suffix(base, "_li.svg"),
+    };
+    match format {
+        PlotFormat::Png => draw_overlay(&BitMapBackend::new(&path, (WIDTH, HEIGHT)).into_drawing_area(), title, curves)?,
+        PlotFormat::Svg => draw_overlay(&SVGBackend::new(&path, (WIDTH, HEIGHT)).into_drawing_area(), title, curves)?,
+    }
+
+    info!("Overlay plot saved to {}", path.display());
+    Ok(path)
+}
+
+fn draw_overlay<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    title: &str,
+    curves: &[(String, Vec<(f64, f64)>)],
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+
+    let all_points = || curves.iter().flat_map(|(_, points)| points.iter());
+    let (x_min, x_max) = axis_range(all_points().map(|p| p.0));
+    let (y_min, y_max) = axis_range(all_points().map(|p| p.1).filter(|p| p.is_finite()));
+
+    let mut chart = ChartBuilder::on(root)
+        .caption(title, ("sans-serif", 24))
+        .margin(15)
+        .x_label_area_size(45)
+        .y_label_area_size(70)
+        .build_cartesian_2d(x_min..x_max, y_min..y_max)?;
+    chart
+        .configure_mesh()
+        .x_desc("Current (mA)")
+        .y_desc("Power (mW)")
+        .draw()?;
+
+    for (i, (label, points)) in curves.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        chart
+            .draw_series(LineSeries::new(points.iter().copied(), color.stroke_width(2)))?
+            .label(label.as_str())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2)));
+    }
+
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+
+    root.present()?;
+    Ok(())
+}
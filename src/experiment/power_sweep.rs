@@ -0,0 +1,324 @@
+use super::data::{MeasurementRecord, RunMetadata};
+use super::progress::{SweepObserver, SweepProgress};
+use super::validation::{format_violations, optional_limit, ConfigViolation};
+use super::current_sweep::{averaging_time_violation, port_violation, power_limit_violations, wavelength_violation};
+use super::{configure_power_meter, instruments, sweep_points, verify_tec_regulation, Error, Experiment, PowerUnit, TecCheckConfig};
+use crate::devices::DeviceRegistry;
+use crate::devices::cld1015::MAX_SAFE_CURRENT_AMPS;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tracing::{info, error, warn};
+
+/// Configuration for a power-setpoint sweep in the CLD1015 constant-power mode
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PowerSweepConfig {
+    pub module: u8,                  // MPM210H module number to use
+    pub port: u8,                    // MPM210H port number to use (1-4)
+    pub start_mw: f64,               // First optical power setpoint in mW
+    pub stop_mw: f64,                // Last optical power setpoint in mW
+    pub step_mw: f64,                // Setpoint step size in mW
+    pub stabilization_delay_ms: u64, // Delay after changing the setpoint before measuring
+    pub wavelength_nm: u32,          // Wavelength in nm
+    pub averaging_time_ms: f64,      // Power meter averaging time in ms
+    pub power_unit: PowerUnit,       // Power measurement unit
+    #[serde(with = "optional_limit")]
+    pub max_power_dbm: Option<f64>,  // Stop if the reading exceeds this power, "off" for no limit
+    #[serde(with = "optional_limit")]
+    pub cod_drop_fraction: Option<f64>, // Stop at a power drop by this fraction while current increases, "off" to disable
+    pub tec_check: Option<TecCheckConfig>, // Verify the TEC reached its setpoint before the laser is turned on
+}
+
+impl Default for PowerSweepConfig {
+    fn default() -> Self {
+        PowerSweepConfig {
+            module: 0,                    // Module 0
+            port: 2,                      // Port 2
+            start_mw: 1.0,                // Start at 1 mW
+            stop_mw: 20.0,                // End at 20 mW
+            step_mw: 1.0,                 // 1 mW steps
+            stabilization_delay_ms: 200,  // Let the power control loop settle for 200ms
+            wavelength_nm: 980,           // 980nm wavelength
+            averaging_time_ms: 100.0,     // 100ms averaging time
+            power_unit: PowerUnit::DBm,   // Use dBm units
+            max_power_dbm: Some(20.0),    // Stop if the reading exceeds 20 dBm (100 mW)
+            cod_drop_fraction: Some(0.3), // Emergency stop on a 30% power drop
+            tec_check: Some(TecCheckConfig::default()), // Require the TEC to be at its setpoint
+        }
+    }
+}
+
+impl PowerSweepConfig {
+    /// Check every field and report all problems found
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+
+        if self.step_mw.is_nan() || self.step_mw <= 0.0 {
+            violations.push(ConfigViolation::new("step_mw", self.step_mw, "step size must be positive")
+                .allowed("> 0 mW"));
+        }
+        for (field, value) in [("start_mw", self.start_mw), ("stop_mw", self.stop_mw)] {
+            if value.is_nan() || value <= 0.0 {
+                violations.push(ConfigViolation::new(field, value, "power setpoint must be positive")
+                    .allowed("> 0 mW"));
+            } else if let Some(max_power_dbm) = self.max_power_dbm {
+                // A setpoint above the limit would abort the sweep at that point anyway
+                let max_power_mw = 10f64.powf(max_power_dbm / 10.0);
+                if value > max_power_mw {
+                    violations.push(ConfigViolation::new(field, value, "power setpoint above max_power_dbm")
+                        .allowed(format!("<= {:.3} mW", max_power_mw)));
+                }
+            }
+        }
+        violations.extend(port_violation(self.port));
+        violations.extend(wavelength_violation("wavelength_nm", self.wavelength_nm));
+        violations.extend(averaging_time_violation(self.averaging_time_ms));
+        violations.extend(power_limit_violations(self.max_power_dbm, self.cod_drop_fraction));
+        if let Some(tec_check) = &self.tec_check {
+            violations.extend(tec_check.violations().into_iter().map(|v| v.in_section("tec_check")));
+        }
+        violations
+    }
+}
+
+/// Power-setpoint sweep: steps the CLD1015 optical power setpoint and records the
+/// drive current the controller needs to reach it, together with the external power reading.
+///
+/// In constant-power mode the controller raises the drive current until its monitor
+/// reaches the setpoint, up to the LD current limit. The preflight therefore refuses
+/// a controller limit above [`MAX_SAFE_CURRENT_AMPS`], and the sweep stops as soon as
+/// the measured current exceeds it.
+pub struct PowerSweep {
+    config: PowerSweepConfig,
+    records: Vec<MeasurementRecord>,
+    metadata: RunMetadata,
+}
+
+impl PowerSweep {
+    pub fn new(config: PowerSweepConfig) -> Self {
+        PowerSweep {
+            config,
+            records: Vec::new(),
+            metadata: RunMetadata::default(),
+        }
+    }
+}
+
+impl Experiment for PowerSweep {
+    type Config = PowerSweepConfig;
+
+    fn name(&self) -> &'static str {
+        "power_sweep"
+    }
+
+    fn config(&self) -> &PowerSweepConfig {
+        &self.config
+    }
+
+    fn configure(&mut self, config: PowerSweepConfig) {
+        self.config = config;
+        self.records.clear();
+        self.metadata = RunMetadata::default();
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let violations = self.config.violations();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Config(format_violations(&violations)))
+        }
+    }
+
+    fn preflight(&self, devices: &mut DeviceRegistry) -> Vec<String> {
+        let (cld, mpm) = match instruments(devices) {
+            Ok(instruments) => instruments,
+            Err(e) => return vec![e.to_string()],
+        };
+        let config = &self.config;
+        let mut violations = Vec::new();
+
+        // The controller may drive up to its LD limit to reach a setpoint
+        match cld.get_current_limit() {
+            Ok(limit_a) if limit_a > MAX_SAFE_CURRENT_AMPS => violations.push(format!(
+                "The CLD1015 current limit is {} mA, above the {} mA safety limit; constant-power mode can drive up to it, lower the LD limit on the controller",
+                limit_a * 1000.0, MAX_SAFE_CURRENT_AMPS * 1000.0)),
+            Ok(_) => {}
+            Err(e) => violations.push(format!("Could not query the CLD1015 current limit: {}", e)),
+        }
+
+        match mpm.recognized_module_slots() {
+            Ok(slots) if !slots.contains(&config.module) => violations.push(format!(
+                "module is {} but the MPM210H only recognizes modules in slots {:?}; check the module slot",
+                config.module, slots)),
+            Ok(_) => {}
+            Err(e) => violations.push(format!("Could not query MPM210H modules: {}", e)),
+        }
+
+        violations
+    }
+
+    fn run(
+        &mut self,
+        devices: &mut DeviceRegistry,
+        observer: &mut dyn SweepObserver,
+    ) -> Result<(), Error> {
+        let (cld, mpm) = instruments(devices)?;
+        let module = self.config.module;
+        let port = self.config.port;
+        let wavelength_nm = self.config.wavelength_nm;
+        self.records.clear();
+        self.metadata = RunMetadata::default();
+
+        // Set constant-power mode with the laser off
+        if let Err(e) = cld.set_laser_output(false) {
+            warn!("Failed to disable laser output: {}", e);
+        }
+        if let Err(e) = cld.set_power_mode() {
+            return Err(Error::scpi("Failed to set constant-power mode", e));
+        }
+
+        // Configure the MPM210H
+        configure_power_meter(mpm, self.config.averaging_time_ms, self.config.power_unit)?;
+        if let Err(e) = mpm.set_wavelength(wavelength_nm) {
+            return Err(Error::scpi("Failed to set MPM210H wavelength", e));
+        }
+
+        // Start from the first setpoint so the laser does not come up at a stale one
+        let points = sweep_points(self.config.start_mw, self.config.stop_mw, self.config.step_mw);
+        if let Some(&first_mw) = points.first() {
+            if let Err(e) = cld.set_power(first_mw / 1000.0) {
+                return Err(Error::scpi(format!("Failed to set power setpoint to {} mW", first_mw), e));
+            }
+        }
+
+        if let Some(tec_check) = &self.config.tec_check {
+            verify_tec_regulation(cld, tec_check)?;
+        }
+
+        // Turn laser on
+        if let Err(e) = cld.set_laser_output(true) {
+            return Err(Error::scpi("Failed to enable laser output", e));
+        }
+
+        info!("Starting power sweep: {} mW to {} mW, step {} mW, module {}, port {}",
+              self.config.start_mw, self.config.stop_mw, self.config.step_mw, module, port);
+
+        let total = points.len();
+        let sweep_start = Instant::now();
+        let mut previous_point: Option<(f64, f64)> = None; // (current mA, power mW)
+        observer.on_start(total);
+
+        for (index, &setpoint_mw) in points.iter().enumerate() {
+            if observer.should_abort() {
+                warn!("Power sweep aborted by user at {} mW", setpoint_mw);
+                return Err(Error::UserAbort(format!("Power sweep aborted by user at {} mW", setpoint_mw)));
+            }
+
+            // Set the power setpoint (in W) and let the controller regulate
+            if let Err(e) = cld.set_power(setpoint_mw / 1000.0) {
+                return Err(Error::scpi(format!("Failed to set power setpoint to {} mW", setpoint_mw), e));
+            }
+            let current_set_at = Utc::now();
+            std::thread::sleep(Duration::from_millis(self.config.stabilization_delay_ms));
+
+            // Record the drive current needed to reach the setpoint
+            let current_ma = match cld.measure_current() {
+                Ok(amps) => amps * 1000.0,
+                Err(e) => return Err(Error::scpi(format!("Failed to measure drive current at {} mW", setpoint_mw), e)),
+            };
+
+            // Safety: the power loop may drive the current past the safe limit, e.g. when
+            // the monitor photodiode is not coupled. The points measured so far are kept.
+            if current_ma / 1000.0 > MAX_SAFE_CURRENT_AMPS {
+                let reason = format!("Drive current {} mA at {} mW exceeds the {} mA safety limit",
+                    current_ma, setpoint_mw, MAX_SAFE_CURRENT_AMPS * 1000.0);
+                error!("{}, stopping sweep", reason);
+                self.metadata.abort_reason = Some(reason);
+                break;
+            }
+
+            let read_started = Instant::now();
+            let power = match mpm.read_power_value(module, port) {
+                Ok(p) => p,
+                Err(e) => return Err(Error::scpi(format!("Failed to read power at {} mW setpoint from module {}, port {}", setpoint_mw, module, port), e)),
+            };
+            let read_latency = read_started.elapsed();
+            let now = Utc::now().to_rfc3339();
+
+            let (power_dbm, power_mw) = self.config.power_unit.dbm_and_mw(power);
+
+            if let Some(max_power_dbm) = self.config.max_power_dbm {
+                if power_dbm > max_power_dbm {
+                    let reason = format!("Power {} at {} mW setpoint exceeds the {} dBm limit", power, setpoint_mw, max_power_dbm);
+                    error!("{}, stopping sweep", reason);
+                    self.metadata.abort_reason = Some(reason);
+                    break;
+                }
+            }
+
+            // Safety: a falling output while the drive current rises indicates facet damage
+            if let (Some(fraction), Some((previous_ma, previous_mw))) = (self.config.cod_drop_fraction, previous_point) {
+                if current_ma > previous_ma && power_mw < previous_mw * (1.0 - fraction) {
+                    let reason = format!(
+                        "Possible catastrophic optical damage at {} mA: power dropped from {:.4} mW to {:.4} mW",
+                        current_ma, previous_mw, power_mw);
+                    error!("{}, stopping sweep", reason);
+                    self.metadata.abort_reason = Some(reason);
+                    break;
+                }
+            }
+            previous_point = Some((current_ma, power_mw));
+            let record = MeasurementRecord {
+                run_id: String::new(),
+                sample_id: String::new(),
+                repeat_index: 0,
+                point_index: index,
+                timestamp: now.clone(),
+                elapsed_s: sweep_start.elapsed().as_secs_f64(),
+                current_ma,
+                measured_current_ma: Some(current_ma),
+                current_mismatch: false,
+                voltage_v: None,
+                tec_temperature_c: None,
+                power,
+                power_unit: self.config.power_unit,
+                power_dbm,
+                power_mw,
+                module,
+                port,
+                wavelength_nm,
+                set_order: index,
+                power_setpoint_mw: Some(setpoint_mw),
+                current_set_at: current_set_at.to_rfc3339(),
+                power_read_at: now,
+                read_latency_ms: read_latency.as_secs_f64() * 1000.0,
+            };
+            observer.on_record(&record);
+            self.records.push(record);
+
+            let elapsed = sweep_start.elapsed();
+            let remaining = (total - index - 1) as u32;
+            let eta = elapsed / (index as u32 + 1) * remaining;
+            observer.on_progress(&SweepProgress {
+                index,
+                total,
+                current_ma,
+                power,
+                elapsed,
+                eta,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn results(&self) -> &[MeasurementRecord] {
+        &self.records
+    }
+
+    fn metadata(&self) -> RunMetadata {
+        self.metadata.clone()
+    }
+}
@@ -0,0 +1,299 @@
+use super::data::{MeasurementRecord, RecordTags};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::VecDeque;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+/// Progress of a running sweep, reported after each measured point
+#[derive(Debug, Clone)]
+pub struct SweepProgress {
+    pub index: usize,      // Zero-based index of the point just measured
+    pub total: usize,      // Total number of points in the sweep
+    pub current_ma: f64,   // Current applied for this point in mA
+    pub power: f64,        // Power reading for this point, in the configured unit
+    pub elapsed: Duration, // Time since the first point was started
+    pub eta: Duration,     // Estimated time until the sweep completes
+}
+
+impl SweepProgress {
+    pub fn completed(&self) -> usize {
+        self.index + 1
+    }
+}
+
+/// Receives notifications from a running sweep
+pub trait SweepObserver {
+    /// Called once before the first point with the number of planned points
+    fn on_start(&mut self, _total_points: usize) {}
+
+    /// Called with each measurement record as soon as it is acquired. Runs started through
+    /// the lifecycle, e.g. `run_experiment`, pass records tagged with the run and sample.
+    fn on_record(&mut self, _record: &MeasurementRecord) {}
+
+    /// Called after each point has been measured
+    fn on_progress(&mut self, _progress: &SweepProgress) {}
+
+    /// Called once when the sweep stops, successfully or not
+    fn on_finish(&mut self, _success: bool) {}
+
+    /// Polled before each point; returning true stops the sweep with the laser off
+    fn should_abort(&self) -> bool {
+        false
+    }
+}
+
+/// Forward notifications to several observers at once
+impl SweepObserver for Vec<Box<dyn SweepObserver>> {
+    fn on_start(&mut self, total_points: usize) {
+        self.iter_mut().for_each(|o| o.on_start(total_points));
+    }
+
+    fn on_record(&mut self, record: &MeasurementRecord) {
+        self.iter_mut().for_each(|o| o.on_record(record));
+    }
+
+    fn on_progress(&mut self, progress: &SweepProgress) {
+        self.iter_mut().for_each(|o| o.on_progress(progress));
+    }
+
+    fn on_finish(&mut self, success: bool) {
+        self.iter_mut().for_each(|o| o.on_finish(success));
+    }
+
+    fn should_abort(&self) -> bool {
+        self.iter().any(|o| o.should_abort())
+    }
+}
+
+/// Borrowed observers, used to add observers of its own around the caller's one
+impl<'a> SweepObserver for Vec<&'a mut dyn SweepObserver> {
+    fn on_start(&mut self, total_points: usize) {
+        self.iter_mut().for_each(|o| o.on_start(total_points));
+    }
+
+    fn on_record(&mut self, record: &MeasurementRecord) {
+        self.iter_mut().for_each(|o| o.on_record(record));
+    }
+
+    fn on_progress(&mut self, progress: &SweepProgress) {
+        self.iter_mut().for_each(|o| o.on_progress(progress));
+    }
+
+    fn on_finish(&mut self, success: bool) {
+        self.iter_mut().for_each(|o| o.on_finish(success));
+    }
+
+    fn should_abort(&self) -> bool {
+        self.iter().any(|o| o.should_abort())
+    }
+}
+
+/// Tags each record with the run, sample and repeat index before passing it on, so every
+/// observer sees complete records. The lifecycle wraps the observers of a run in it.
+pub struct TaggedRecords<'a> {
+    tags: RecordTags,
+    observer: &'a mut dyn SweepObserver,
+}
+
+impl<'a> TaggedRecords<'a> {
+    pub fn new(tags: RecordTags, observer: &'a mut dyn SweepObserver) -> Self {
+        TaggedRecords { tags, observer }
+    }
+}
+
+impl SweepObserver for TaggedRecords<'_> {
+    fn on_start(&mut self, total_points: usize) {
+        self.observer.on_start(total_points);
+    }
+
+    fn on_record(&mut self, record: &MeasurementRecord) {
+        self.observer.on_record(&self.tags.apply(record));
+    }
+
+    fn on_progress(&mut self, progress: &SweepProgress) {
+        self.observer.on_progress(progress);
+    }
+
+    fn on_finish(&mut self, success: bool) {
+        self.observer.on_finish(success);
+    }
+
+    fn should_abort(&self) -> bool {
+        self.observer.should_abort()
+    }
+}
+
+/// Streams each measurement record over a channel so that GUIs, plots or
+/// network streamers can consume data live. A disconnected receiver is ignored.
+impl SweepObserver for Sender<MeasurementRecord> {
+    fn on_record(&mut self, record: &MeasurementRecord) {
+        let _ = self.send(record.clone());
+    }
+}
+
+/// Observer that ignores all notifications
+pub struct NoopObserver;
+
+impl SweepObserver for NoopObserver {}
+
+/// Adapts a closure taking a progress snapshot into an observer
+pub struct ProgressCallback<F: FnMut(&SweepProgress)>(pub F);
+
+impl<F: FnMut(&SweepProgress)> SweepObserver for ProgressCallback<F> {
+    fn on_progress(&mut self, progress: &SweepProgress) {
+        (self.0)(progress)
+    }
+}
+
+/// Renders sweep progress as a console progress bar
+#[derive(Default)]
+pub struct ConsoleProgressBar {
+    bar: Option<ProgressBar>,
+}
+
+impl ConsoleProgressBar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SweepObserver for ConsoleProgressBar {
+    fn on_start(&mut self, total_points: usize) {
+        let bar = ProgressBar::new(total_points as u64);
+        bar.set_style(
+            ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} (ETA {eta}) {msg}")
+                .unwrap()
+                .progress_chars("=>-"),
+        );
+        self.bar = Some(bar);
+    }
+
+    fn on_progress(&mut self, progress: &SweepProgress) {
+        if let Some(bar) = &self.bar {
+            bar.set_position(progress.completed() as u64);
+            bar.set_message(format!("Current: {:.2} mA, Power: {:.4}", progress.current_ma, progress.power));
+        }
+    }
+
+    fn on_finish(&mut self, success: bool) {
+        if let Some(bar) = self.bar.take() {
+            if success {
+                bar.finish_with_message("Sweep complete");
+            } else {
+                bar.abandon_with_message("Sweep aborted");
+            }
+        }
+    }
+}
+
+/// Number of recent readings drawn in the sparkline of the console table
+const SPARKLINE_POINTS: usize = 40;
+
+const SPARKLINE_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Readings scaled between their minimum and maximum, one block character each
+fn sparkline(values: &VecDeque<f64>) -> String {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let top = (SPARKLINE_LEVELS.len() - 1) as f64;
+    values
+        .iter()
+        .map(|&v| {
+            let level = if max > min { ((v - min) / (max - min) * top).round() as usize } else { 0 };
+            SPARKLINE_LEVELS[level.min(SPARKLINE_LEVELS.len() - 1)]
+        })
+        .collect()
+}
+
+/// Format a duration as h:mm:ss
+fn clock(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Change from the previous reading, blank for the first point
+fn delta(previous: Option<f64>, power: f64) -> String {
+    previous.map_or(String::new(), |previous| format!("{:+.4}", power - previous))
+}
+
+/// Renders each point as a table row on the terminal, under a status line with a
+/// sparkline of the recent readings, the elapsed time and the ETA
+pub struct ConsoleTable {
+    bar: Option<ProgressBar>,
+    previous: Option<f64>,
+    recent: VecDeque<f64>,
+}
+
+impl ConsoleTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for ConsoleTable {
+    fn default() -> Self {
+        ConsoleTable { bar: None, previous: None, recent: VecDeque::with_capacity(SPARKLINE_POINTS) }
+    }
+}
+
+impl SweepObserver for ConsoleTable {
+    fn on_start(&mut self, total_points: usize) {
+        let bar = ProgressBar::new(total_points as u64);
+        bar.set_style(ProgressStyle::with_template("{pos:>5}/{len}  elapsed {elapsed_precise}  ETA {eta_precise}  {msg}").unwrap());
+        bar.println(format!("{:>5}  {:>10}  {:>12}  {:>10}  {:>9}  {:>9}", "point", "current mA", "power", "delta", "elapsed", "ETA"));
+        self.bar = Some(bar);
+        self.previous = None;
+        self.recent.clear();
+    }
+
+    fn on_progress(&mut self, progress: &SweepProgress) {
+        if let Some(bar) = &self.bar {
+            bar.println(format!("{:>5}  {:>10.3}  {:>12.4}  {:>10}  {:>9}  {:>9}",
+                                progress.completed(), progress.current_ma, progress.power,
+                                delta(self.previous, progress.power), clock(progress.elapsed), clock(progress.eta)));
+            if self.recent.len() == SPARKLINE_POINTS {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(progress.power);
+            bar.set_position(progress.completed() as u64);
+            bar.set_message(sparkline(&self.recent));
+        }
+        self.previous = Some(progress.power);
+    }
+
+    fn on_finish(&mut self, success: bool) {
+        if let Some(bar) = self.bar.take() {
+            if success {
+                bar.finish_with_message("Sweep complete");
+            } else {
+                bar.abandon_with_message("Sweep aborted");
+            }
+        }
+    }
+}
+
+/// Writes one tab-separated line per point to stdout, without escape codes, for
+/// output piped to a file or another program
+#[derive(Default)]
+pub struct PlainProgress {
+    previous: Option<f64>,
+}
+
+impl PlainProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SweepObserver for PlainProgress {
+    fn on_start(&mut self, _total_points: usize) {
+        self.previous = None;
+        println!("point\tcurrent_ma\tpower\tdelta\telapsed_s\teta_s");
+    }
+
+    fn on_progress(&mut self, progress: &SweepProgress) {
+        println!("{}\t{:.3}\t{:.4}\t{}\t{:.1}\t{:.1}", progress.completed(), progress.current_ma, progress.power,
+                 delta(self.previous, progress.power), progress.elapsed.as_secs_f64(), progress.eta.as_secs_f64());
+        self.previous = Some(progress.power);
+    }
+}
@@ -0,0 +1,81 @@
+use super::convert::to_measurement;
+use super::data::MeasurementRecord;
+use super::schema;
+use super::{CurrentSweepConfig, Error};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// What is left of an interrupted current sweep: its configuration and the points
+/// measured before it stopped
+#[derive(Debug)]
+pub struct Checkpoint {
+    pub run_id: String,
+    pub sample_id: String,
+    pub config: CurrentSweepConfig,
+    pub records: Vec<MeasurementRecord>,
+}
+
+/// Find the checkpoint of `run_id` under the output `directory`, including the
+/// subdirectories of the filename template. The manifest is written as soon as the run
+/// has connected, so runs that crashed or lost power are found as well as failed ones.
+/// Completed runs and runs stopped by a safety check are not resumed.
+pub fn find_checkpoint(directory: &Path, run_id: &str) -> Result<Checkpoint, Error> {
+    let file_name = format!("{}_manifest.json", run_id);
+    let path = find_file(directory, &file_name)
+        .ok_or_else(|| Error::Config(format!("No run {:?} in {}: {} not found", run_id, directory.display(), file_name)))?;
+    info!("Reading checkpoint {}", path.display());
+    let contents = std::fs::read_to_string(&path).map_err(|e| Error::data(format!("Failed to read {}", path.display()), e))?;
+    let manifest: serde_json::Value = serde_json::from_str(&contents)
+        .map_err(|e| Error::data(format!("Failed to parse {}", path.display()), e))?;
+
+    let text = |key: &str| manifest.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+    match text("status").as_str() {
+        "completed" => return Err(Error::Config(format!("Run {} completed, there is nothing to resume", run_id))),
+        "aborted" => return Err(Error::Config(format!(
+            "Run {} was stopped by a safety check ({}); check the device and start a new run", run_id, text("error")))),
+        _ => {}
+    }
+    let experiment = text("experiment");
+    if experiment != "current_sweep" {
+        return Err(Error::Config(format!("Run {} is a {} run; only current sweeps can be resumed", run_id, experiment)));
+    }
+    let config: CurrentSweepConfig = serde_json::from_value(manifest.get("config").cloned().unwrap_or_default())
+        .map_err(|e| Error::data(format!("Failed to read the configuration of run {}", run_id), e))?;
+
+    // The points are in the streamed files, CSV preferred as JSON Lines holds the same
+    let data_files: Vec<PathBuf> = manifest
+        .get("data_files")
+        .and_then(|v| v.as_array())
+        .map(|files| files.iter().filter_map(|f| f.as_str()).map(PathBuf::from).collect())
+        .unwrap_or_default();
+    let streamed = |extension: &str| -> Vec<&PathBuf> {
+        data_files
+            .iter()
+            .filter(|f| f.to_string_lossy().trim_end_matches(".gz").ends_with(extension))
+            .collect()
+    };
+    let csv = streamed(".csv");
+    let files = if csv.is_empty() { streamed(".jsonl") } else { csv };
+    let mut records = Vec::new();
+    for file in files {
+        let data = schema::read_data_file(file)?;
+        records.extend(data.records.iter().map(to_measurement));
+    }
+    info!("Run {} stopped after {} points", run_id, records.len());
+
+    Ok(Checkpoint { run_id: run_id.to_string(), sample_id: text("sample_id"), config, records })
+}
+
+/// Path of the first file named `file_name` in `directory` or below
+fn find_file(directory: &Path, file_name: &str) -> Option<PathBuf> {
+    let candidate = directory.join(file_name);
+    if candidate.is_file() {
+        return Some(candidate);
+    }
+    std::fs::read_dir(directory)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .find_map(|path| find_file(&path, file_name))
+}
@@ -0,0 +1,331 @@
+use super::{Error, FailureKind};
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use tracing::info;
+
+/// Version of the layout of the data files written by this application.
+///
+/// - 1: files written before the version was recorded. Power columns may hold the
+///   raw instrument text, the earliest files only have `timestamp`, `current_mA`,
+///   `power_dBm` and `module`.
+/// - 2: numeric `power`, `power_dBm` and `power_mW` columns, version recorded in every file.
+/// - 3: `elapsed_s` column; timestamps carry the offset of the configured timezone.
+/// - 4: `run_id`, `repeat_index` and `point_index` columns.
+pub const SCHEMA_VERSION: u32 = 4;
+
+/// Key under which the version is recorded in JSON documents, JSON lines and metadata
+pub const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// First line of CSV files, e.g. `# schema_version=2`
+pub fn csv_version_comment() -> String {
+    format!("# {}={}", SCHEMA_VERSION_KEY, SCHEMA_VERSION)
+}
+
+/// One measurement point read back from a data file of any schema version.
+/// Quantities the file does not contain are `None`.
+#[derive(Debug, Clone, Default)]
+pub struct StoredRecord {
+    pub run_id: Option<String>,
+    pub sample_id: Option<String>,
+    pub repeat_index: Option<usize>,
+    pub point_index: Option<usize>,
+    pub timestamp: Option<String>,
+    pub elapsed_s: Option<f64>, // Seconds since the start of the sweep, from version 3
+    pub current_ma: f64,
+    pub measured_current_ma: Option<f64>,
+    pub voltage_v: Option<f64>,
+    pub tec_temperature_c: Option<f64>,
+    pub current_mismatch: Option<bool>,
+    pub power: Option<f64>,
+    pub power_unit: Option<String>,
+    pub power_dbm: Option<f64>,
+    pub power_mw: Option<f64>,
+    pub module: Option<u8>,
+    pub port: Option<u8>,
+    pub wavelength_nm: Option<u32>,
+    pub set_order: Option<usize>,
+    pub power_setpoint_mw: Option<f64>,
+    pub current_set_at: Option<String>,
+    pub power_read_at: Option<String>,
+    pub read_latency_ms: Option<f64>,
+}
+
+/// Records of a data file, migrated to the current schema
+#[derive(Debug, Clone)]
+pub struct DataFile {
+    pub schema_version: u32, // Version the file was written with
+    pub records: Vec<StoredRecord>,
+}
+
+/// Read the records of a CSV, JSON Lines or JSON data file written by any version of
+/// this application. Gzip-compressed files and every CSV dialect are accepted.
+/// Files written by a newer version are rejected.
+pub fn read_data_file(path: &Path) -> Result<DataFile, Error> {
+    let mut contents = String::new();
+    let mut file = File::open(path).map_err(|e| Error::data(format!("Failed to open {}", path.display()), e))?;
+    let compressed = path.extension().is_some_and(|e| e == "gz");
+    let read = if compressed {
+        GzDecoder::new(file).read_to_string(&mut contents)
+    } else {
+        file.read_to_string(&mut contents)
+    };
+    read.map_err(|e| Error::data(format!("Failed to read {}", path.display()), e))?;
+
+    let name = path.to_string_lossy();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    let (version, rows) = if name.ends_with(".jsonl") {
+        json_lines_rows(&contents, path)?
+    } else if name.ends_with(".json") {
+        json_document_rows(&contents, path)?
+    } else {
+        csv_rows(&contents, path)?
+    };
+    if version > SCHEMA_VERSION {
+        return Err(Error::new(FailureKind::Io, format!(
+            "{} was written with schema version {}, this version reads up to {}",
+            path.display(), version, SCHEMA_VERSION
+        )));
+    }
+
+    let migrated: Vec<Option<StoredRecord>> = rows.iter().map(|row| migrate(version, row)).collect();
+    let missing = migrated.iter().filter(|record| record.is_none()).count();
+    if missing > 0 {
+        // Without the current the points cannot be placed on the curve, e.g. files written
+        // with a `fields` selection that left out `current_mA`
+        let first = migrated.iter().position(|record| record.is_none()).unwrap_or_default();
+        return Err(Error::new(FailureKind::Io, format!(
+            "{} has {} of {} rows without a current_mA value, the first at row {}",
+            path.display(), missing, rows.len(), first + 1
+        )));
+    }
+    let mut records: Vec<StoredRecord> = migrated.into_iter().flatten().collect();
+    if version < 3 {
+        fill_elapsed(&mut records);
+    }
+    info!("Read {} records from {} (schema version {})", records.len(), path.display(), version);
+    Ok(DataFile { schema_version: version, records })
+}
+
+/// Field values of one row by column name, numbers with a decimal point
+type Row = HashMap<String, String>;
+
+fn csv_rows(contents: &str, path: &Path) -> Result<(u32, Vec<Row>), Error> {
+    let version = contents
+        .lines()
+        .take_while(|line| line.starts_with('#'))
+        .find_map(|line| {
+            let value = line.trim_start_matches('#').trim().strip_prefix(SCHEMA_VERSION_KEY)?;
+            value.trim_start_matches('=').trim().parse().ok()
+        })
+        .unwrap_or(1);
+
+    let header = contents.lines().find(|line| !line.starts_with('#')).unwrap_or("");
+    let delimiter = [b';', b'\t', b','].into_iter().find(|&d| header.contains(d as char)).unwrap_or(b',');
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .comment(Some(b'#'))
+        .from_reader(contents.as_bytes());
+    let headers = reader.headers().map_err(|e| Error::data(format!("Failed to read header of {}", path.display()), e))?.clone();
+
+    let mut rows = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| Error::data(format!("Failed to read row {} of {}", i + 1, path.display()), e))?;
+        // Decimal commas are only possible when the fields are not comma-separated
+        let row = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(h, v)| (h.to_string(), if delimiter == b',' { v.to_string() } else { v.replace(',', ".") }))
+            .collect();
+        rows.push(row);
+    }
+    Ok((version, rows))
+}
+
+fn json_row(value: &serde_json::Value) -> Row {
+    let Some(map) = value.as_object() else {
+        return Row::new();
+    };
+    map.iter()
+        .map(|(key, value)| {
+            let text = match value {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Null => String::new(),
+                other => other.to_string(),
+            };
+            (key.clone(), text)
+        })
+        .collect()
+}
+
+fn json_version(value: &serde_json::Value) -> u32 {
+    value.get(SCHEMA_VERSION_KEY).and_then(|v| v.as_u64()).map_or(1, |v| v as u32)
+}
+
+fn json_lines_rows(contents: &str, path: &Path) -> Result<(u32, Vec<Row>), Error> {
+    let mut version = 1;
+    let mut rows = Vec::new();
+    for (i, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let value: serde_json::Value = serde_json::from_str(line)
+            .map_err(|e| Error::data(format!("Failed to parse line {} of {}", i + 1, path.display()), e))?;
+        version = json_version(&value);
+        rows.push(json_row(&value));
+    }
+    Ok((version, rows))
+}
+
+fn json_document_rows(contents: &str, path: &Path) -> Result<(u32, Vec<Row>), Error> {
+    let document: serde_json::Value = serde_json::from_str(contents)
+        .map_err(|e| Error::data(format!("Failed to parse {}", path.display()), e))?;
+    let records = document.get("records").and_then(|r| r.as_array())
+        .ok_or_else(|| Error::new(FailureKind::Io, format!("{} has no records array", path.display())))?;
+    Ok((json_version(&document), records.iter().map(json_row).collect()))
+}
+
+/// Build a current-schema record from a row written with schema `version`, or `None`
+/// when the row has no usable current.
+fn migrate(version: u32, row: &Row) -> Option<StoredRecord> {
+    let text = |key: &str| row.get(key).filter(|v| !v.is_empty()).cloned();
+    let number = |key: &str| row.get(key).and_then(|v| v.trim().parse::<f64>().ok()).filter(|v| v.is_finite());
+    let integer = |key: &str| row.get(key).and_then(|v| v.trim().parse::<u64>().ok());
+
+    let mut record = StoredRecord {
+        run_id: text("run_id"),
+        sample_id: text("sample_id"),
+        repeat_index: integer("repeat_index").map(|v| v as usize),
+        point_index: integer("point_index").map(|v| v as usize),
+        timestamp: text("timestamp"),
+        elapsed_s: number("elapsed_s"),
+        current_ma: number("current_mA")?,
+        measured_current_ma: number("measured_current_ma"),
+        voltage_v: number("voltage_v"),
+        tec_temperature_c: number("tec_temperature_c"),
+        current_mismatch: row.get("current_mismatch").and_then(|v| v.trim().parse().ok()),
+        power: number("power"),
+        power_unit: text("power_unit"),
+        power_dbm: number("power_dBm"),
+        power_mw: number("power_mW"),
+        module: integer("module").map(|v| v as u8),
+        port: integer("port").map(|v| v as u8),
+        wavelength_nm: integer("wavelength_nm").map(|v| v as u32),
+        set_order: integer("set_order").map(|v| v as usize),
+        power_setpoint_mw: number("power_setpoint_mw"),
+        current_set_at: text("current_set_at"),
+        power_read_at: text("power_read_at"),
+        read_latency_ms: number("read_latency_ms"),
+    };
+
+    if version < 2 {
+        // Raw readings that could not be parsed were written as text; the earliest
+        // files only have the dBm column
+        if record.power_mw.is_none() {
+            record.power_mw = record.power_dbm.map(|dbm| 10f64.powf(dbm / 10.0));
+        }
+        if record.power_dbm.is_none() {
+            record.power_dbm = record.power_mw.filter(|&mw| mw > 0.0).map(|mw| 10.0 * mw.log10());
+        }
+    }
+    Some(record)
+}
+
+/// Elapsed time of files written before it was recorded, from the timestamps. Unlike the
+/// recorded value this follows the wall clock, so it is off by clock adjustments.
+fn fill_elapsed(records: &mut [StoredRecord]) {
+    let time = |r: &StoredRecord| chrono::DateTime::parse_from_rfc3339(r.timestamp.as_deref()?).ok();
+    let Some(start) = records.iter().find_map(time) else {
+        return;
+    };
+    for record in records.iter_mut() {
+        if record.elapsed_s.is_none() {
+            record.elapsed_s = time(record).map(|t| (t - start).num_microseconds().unwrap_or_default() as f64 / 1e6);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(fields: &[(&str, &str)]) -> Row {
+        fields.iter().map(|&(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        assert!(actual.is_some_and(|v| (v - expected).abs() < 1e-9), "{:?} != {}", actual, expected);
+    }
+
+    #[test]
+    fn version_1_row_with_only_dbm_gets_mw() {
+        let v1 = row(&[("timestamp", "2024-05-02T14:03:11Z"), ("current_mA", "10"), ("power_dBm", "3"), ("module", "0")]);
+        let record = migrate(1, &v1).unwrap();
+        assert_eq!(record.current_ma, 10.0);
+        assert_close(record.power_dbm, 3.0);
+        assert_close(record.power_mw, 10f64.powf(0.3));
+        assert_eq!(record.module, Some(0));
+        assert_eq!(record.port, None);
+    }
+
+    #[test]
+    fn version_1_row_with_only_mw_gets_dbm() {
+        let record = migrate(1, &row(&[("current_mA", "10"), ("power_mW", "10")])).unwrap();
+        assert_close(record.power_dbm, 10.0);
+        let dark = migrate(1, &row(&[("current_mA", "0"), ("power_mW", "0")])).unwrap();
+        assert_eq!(dark.power_dbm, None);
+    }
+
+    #[test]
+    fn version_1_raw_text_reading_is_no_power() {
+        let record = migrate(1, &row(&[("current_mA", "10"), ("power_dBm", "OVER")])).unwrap();
+        assert_eq!(record.power_dbm, None);
+        assert_eq!(record.power_mw, None);
+    }
+
+    #[test]
+    fn version_2_row_is_taken_as_written() {
+        let v2 = row(&[("current_mA", "20.5"), ("power", "3"), ("power_unit", "dBm"), ("power_dBm", "3"), ("power_mW", ""),
+                       ("port", "2"), ("wavelength_nm", "980")]);
+        let record = migrate(2, &v2).unwrap();
+        assert_eq!(record.current_ma, 20.5);
+        assert_close(record.power, 3.0);
+        assert_eq!(record.power_unit.as_deref(), Some("dBm"));
+        assert_close(record.power_dbm, 3.0);
+        assert_eq!(record.power_mw, None);
+        assert_eq!(record.port, Some(2));
+        assert_eq!(record.wavelength_nm, Some(980));
+        assert_eq!(record.elapsed_s, None);
+    }
+
+    #[test]
+    fn row_without_current_is_rejected() {
+        assert!(migrate(2, &row(&[("power_dBm", "3")])).is_none());
+        assert!(migrate(1, &row(&[("current_mA", "n/a"), ("power_dBm", "3")])).is_none());
+    }
+
+    fn write_temp(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("cld1015_mpm210h_{}_{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn version_1_csv_gets_elapsed_time_from_timestamps() {
+        let path = write_temp("v1.csv", "timestamp,current_mA,power_dBm,module\n\
+                                         2024-05-02T14:03:11Z,10,-3,0\n\
+                                         2024-05-02T14:03:12.5Z,20,0,0\n");
+        let file = read_data_file(&path).unwrap();
+        assert_eq!(file.schema_version, 1);
+        assert_eq!(file.records.len(), 2);
+        assert_close(file.records[0].elapsed_s, 0.0);
+        assert_close(file.records[1].elapsed_s, 1.5);
+        assert_close(file.records[1].power_mw, 1.0);
+    }
+
+    #[test]
+    fn newer_schema_is_rejected() {
+        let path = write_temp("newer.csv", &format!("# {}={}\ncurrent_mA,power_dBm\n10,0\n", SCHEMA_VERSION_KEY, SCHEMA_VERSION + 1));
+        let error = read_data_file(&path).unwrap_err();
+        assert_eq!(error.kind(), FailureKind::Io);
+    }
+}
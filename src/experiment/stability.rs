@@ -0,0 +1,290 @@
+use super::data::{MeasurementRecord, RunMetadata};
+use super::progress::{SweepObserver, SweepProgress};
+use super::validation::{format_violations, optional_limit, ConfigViolation};
+use super::{configure_power_meter, instruments, verify_tec_regulation, Error, Experiment, PowerUnit, TecCheckConfig};
+use crate::devices::DeviceRegistry;
+use crate::devices::cld1015::MAX_SAFE_CURRENT_AMPS;
+use crate::devices::mpm210h::{AVERAGE_TIME_RANGE_MS, PORTS_PER_MODULE, WAVELENGTH_RANGE_NM};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tracing::{info, error, warn};
+
+/// Configuration for a stability run: the laser is held at one current and the power
+/// is read at a fixed interval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StabilityConfig {
+    pub module: u8,                 // MPM210H module number to use
+    pub port: u8,                   // MPM210H port number to use (1-4)
+    pub current_ma: f64,            // Drive current held during the run in mA
+    pub wavelength_nm: u32,         // Wavelength in nm
+    pub duration_s: f64,            // Length of the run in seconds
+    pub interval_ms: u64,           // Time between readings in ms
+    pub averaging_time_ms: f64,     // Power meter averaging time in ms
+    pub power_unit: PowerUnit,      // Power measurement unit
+    #[serde(with = "optional_limit")]
+    pub max_power_dbm: Option<f64>, // Abort the run if any reading exceeds this power, "off" for no limit
+    pub tec_check: Option<TecCheckConfig>, // Verify the TEC reached its setpoint before the laser is turned on
+    pub measure_temperature: bool,  // Record the laser temperature with each reading
+    pub disable_tec_after_run: bool, // Turn the TEC off as part of the shutdown sequence
+}
+
+impl Default for StabilityConfig {
+    fn default() -> Self {
+        StabilityConfig {
+            module: 0,                    // Module 0
+            port: 2,                      // Port 2
+            current_ma: 50.0,             // 50 mA
+            wavelength_nm: 980,           // 980nm wavelength
+            duration_s: 600.0,            // 10 minutes
+            interval_ms: 1000,            // One reading per second
+            averaging_time_ms: 100.0,     // 100ms averaging time
+            power_unit: PowerUnit::DBm,   // Use dBm units
+            max_power_dbm: Some(20.0),    // Stop if the reading exceeds 20 dBm (100 mW)
+            tec_check: Some(TecCheckConfig::default()), // Require the TEC to be at its setpoint
+            measure_temperature: true,    // Record the laser temperature
+            disable_tec_after_run: false, // Keep the TEC regulating after the run
+        }
+    }
+}
+
+impl StabilityConfig {
+    /// Check every field and report all problems found
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        let max_safe_ma = MAX_SAFE_CURRENT_AMPS * 1000.0;
+
+        if !(0.0..=max_safe_ma).contains(&self.current_ma) {
+            violations.push(ConfigViolation::new("current_ma", self.current_ma, "current outside the driver safety range")
+                .allowed(format!("0-{} mA", max_safe_ma)));
+        }
+        if self.port < 1 || self.port > PORTS_PER_MODULE {
+            violations.push(ConfigViolation::new("port", self.port, "MPM210H modules do not have this port")
+                .allowed(format!("1-{}", PORTS_PER_MODULE)));
+        }
+        if !WAVELENGTH_RANGE_NM.contains(&self.wavelength_nm) {
+            violations.push(ConfigViolation::new("wavelength_nm", self.wavelength_nm, "wavelength not accepted by the MPM210H")
+                .allowed(format!("{}-{} nm", WAVELENGTH_RANGE_NM.start(), WAVELENGTH_RANGE_NM.end())));
+        }
+        if !self.duration_s.is_finite() || self.duration_s <= 0.0 {
+            violations.push(ConfigViolation::new("duration_s", self.duration_s, "duration must be a positive number")
+                .allowed("> 0 s"));
+        }
+        if self.interval_ms == 0 {
+            violations.push(ConfigViolation::new("interval_ms", 0, "interval must be positive").allowed("> 0 ms"));
+        }
+        if !AVERAGE_TIME_RANGE_MS.contains(&self.averaging_time_ms) {
+            violations.push(ConfigViolation::new("averaging_time_ms", self.averaging_time_ms,
+                "averaging time not accepted by the MPM210H")
+                .allowed(format!("{}-{} ms", AVERAGE_TIME_RANGE_MS.start(), AVERAGE_TIME_RANGE_MS.end())));
+        } else if self.averaging_time_ms > self.interval_ms as f64 {
+            violations.push(ConfigViolation::new("averaging_time_ms", self.averaging_time_ms,
+                "averaging takes longer than the interval between readings")
+                .allowed(format!("<= {} ms (interval_ms)", self.interval_ms)));
+        }
+        if let Some(max_power_dbm) = self.max_power_dbm {
+            if !max_power_dbm.is_finite() {
+                violations.push(ConfigViolation::new("max_power_dbm", max_power_dbm, "power limit must be a finite number"));
+            }
+        }
+        if let Some(tec_check) = &self.tec_check {
+            violations.extend(tec_check.violations().into_iter().map(|v| v.in_section("tec_check")));
+        }
+        violations
+    }
+
+    /// Number of readings: one at the start and one after every full interval
+    pub fn points(&self) -> usize {
+        (self.duration_s * 1000.0 / self.interval_ms.max(1) as f64).floor() as usize + 1
+    }
+}
+
+/// Stability run: holds the laser at a fixed current and records the optical power
+/// over time, e.g. to check the coupling or the drift of a packaged device
+pub struct StabilityRun {
+    config: StabilityConfig,
+    records: Vec<MeasurementRecord>,
+    metadata: RunMetadata,
+}
+
+impl StabilityRun {
+    pub fn new(config: StabilityConfig) -> Self {
+        StabilityRun {
+            config,
+            records: Vec::new(),
+            metadata: RunMetadata::default(),
+        }
+    }
+}
+
+impl Experiment for StabilityRun {
+    type Config = StabilityConfig;
+
+    fn name(&self) -> &'static str {
+        "stability"
+    }
+
+    fn config(&self) -> &StabilityConfig {
+        &self.config
+    }
+
+    fn configure(&mut self, config: StabilityConfig) {
+        self.config = config;
+        self.records.clear();
+        self.metadata = RunMetadata::default();
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let violations = self.config.violations();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Config(format_violations(&violations)))
+        }
+    }
+
+    fn run(
+        &mut self,
+        devices: &mut DeviceRegistry,
+        observer: &mut dyn SweepObserver,
+    ) -> Result<(), Error> {
+        let (cld, mpm) = instruments(devices)?;
+        let module = self.config.module;
+        let port = self.config.port;
+        let wavelength_nm = self.config.wavelength_nm;
+        let current_ma = self.config.current_ma;
+        self.records.clear();
+        self.metadata = RunMetadata::default();
+
+        // Set constant-current mode with the laser off
+        if let Err(e) = cld.set_laser_output(false) {
+            warn!("Failed to disable laser output: {}", e);
+        }
+        if let Err(e) = cld.set_current_mode() {
+            return Err(Error::scpi("Failed to set constant-current mode", e));
+        }
+
+        configure_power_meter(mpm, self.config.averaging_time_ms, self.config.power_unit)?;
+        if let Err(e) = mpm.set_wavelength(wavelength_nm) {
+            return Err(Error::scpi("Failed to set MPM210H wavelength", e));
+        }
+
+        // The current is set before the laser comes up, so it never runs at a stale setpoint
+        if let Err(e) = cld.set_current(current_ma / 1000.0) {
+            return Err(Error::scpi(format!("Failed to set current to {} mA", current_ma), e));
+        }
+        if let Some(tec_check) = &self.config.tec_check {
+            verify_tec_regulation(cld, tec_check)?;
+        }
+        if let Err(e) = cld.set_laser_output(true) {
+            return Err(Error::scpi("Failed to enable laser output", e));
+        }
+        let current_set_at = Utc::now().to_rfc3339();
+
+        let total = self.config.points();
+        let interval = Duration::from_millis(self.config.interval_ms);
+        info!("Starting stability run: {} mA for {} s, {} readings every {} ms, module {}, port {}",
+              current_ma, self.config.duration_s, total, self.config.interval_ms, module, port);
+
+        let run_start = Instant::now();
+        observer.on_start(total);
+
+        for index in 0..total {
+            // Readings follow a fixed schedule, so a slow reading does not shift the later ones
+            if let Some(wait) = (interval * index as u32).checked_sub(run_start.elapsed()) {
+                std::thread::sleep(wait);
+            }
+            if observer.should_abort() {
+                warn!("Stability run aborted by user after {} readings", index);
+                return Err(Error::UserAbort(format!("Stability run aborted by user after {} readings", index)));
+            }
+
+            let read_started = Instant::now();
+            let power = match mpm.read_power_value(module, port) {
+                Ok(p) => p,
+                Err(e) => return Err(Error::scpi(format!("Failed to read power from module {}, port {}", module, port), e)),
+            };
+            let read_latency = read_started.elapsed();
+            let now = Utc::now().to_rfc3339();
+            let elapsed_s = run_start.elapsed().as_secs_f64();
+
+            let tec_temperature_c = if self.config.measure_temperature {
+                match cld.measure_temperature() {
+                    Ok(t) => Some(t),
+                    Err(e) => {
+                        warn!("Failed to measure laser temperature: {}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            let (power_dbm, power_mw) = self.config.power_unit.dbm_and_mw(power);
+
+            // Safety: stop immediately if the reading exceeds the configured power limit.
+            // The readings taken so far are kept.
+            if let Some(max_power_dbm) = self.config.max_power_dbm {
+                if power_dbm > max_power_dbm {
+                    let reason = format!("Power {} exceeds the {} dBm limit", power, max_power_dbm);
+                    error!("{}, stopping stability run", reason);
+                    self.metadata.abort_reason = Some(reason);
+                    break;
+                }
+            }
+
+            let record = MeasurementRecord {
+                run_id: String::new(),
+                sample_id: String::new(),
+                repeat_index: 0,
+                point_index: index,
+                timestamp: now.clone(),
+                elapsed_s,
+                current_ma,
+                measured_current_ma: None,
+                current_mismatch: false,
+                voltage_v: None,
+                tec_temperature_c,
+                power,
+                power_unit: self.config.power_unit,
+                power_dbm,
+                power_mw,
+                module,
+                port,
+                wavelength_nm,
+                set_order: index,
+                power_setpoint_mw: None,
+                current_set_at: current_set_at.clone(),
+                power_read_at: now,
+                read_latency_ms: read_latency.as_secs_f64() * 1000.0,
+            };
+            observer.on_record(&record);
+            self.records.push(record);
+
+            let elapsed = run_start.elapsed();
+            observer.on_progress(&SweepProgress {
+                index,
+                total,
+                current_ma,
+                power,
+                elapsed,
+                eta: interval * (total - index - 1) as u32,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn results(&self) -> &[MeasurementRecord] {
+        &self.records
+    }
+
+    fn disable_tec_after_run(&self) -> bool {
+        self.config.disable_tec_after_run
+    }
+
+    fn metadata(&self) -> RunMetadata {
+        self.metadata.clone()
+    }
+}
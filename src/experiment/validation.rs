@@ -0,0 +1,85 @@
+use std::fmt;
+
+/// A single problem found in a configuration
+#[derive(Debug, Clone)]
+pub struct ConfigViolation {
+    pub field: String,           // Path of the offending field, e.g. "sweep.port"
+    pub value: String,           // Value found in the configuration
+    pub reason: String,          // What is wrong with it
+    pub allowed: Option<String>, // Allowed range or values, when there is one
+}
+
+impl ConfigViolation {
+    pub fn new(field: &str, value: impl fmt::Display, reason: &str) -> Self {
+        ConfigViolation {
+            field: field.to_string(),
+            value: value.to_string(),
+            reason: reason.to_string(),
+            allowed: None,
+        }
+    }
+
+    pub fn allowed(mut self, allowed: impl fmt::Display) -> Self {
+        self.allowed = Some(allowed.to_string());
+        self
+    }
+
+    /// Prefix the field path, e.g. with the config file section
+    pub fn in_section(mut self, section: &str) -> Self {
+        self.field = format!("{}.{}", section, self.field);
+        self
+    }
+}
+
+impl fmt::Display for ConfigViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}: {}", self.field, self.value, self.reason)?;
+        if let Some(allowed) = &self.allowed {
+            write!(f, " (allowed: {})", allowed)?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a list of violations as one message listing every problem
+pub fn format_violations(violations: &[ConfigViolation]) -> String {
+    let lines: Vec<String> = violations.iter().map(|v| format!("  - {}", v)).collect();
+    format!("Invalid configuration, {} problem(s):\n{}", violations.len(), lines.join("\n"))
+}
+
+/// Serde format of a limit that is on by default: a number, or `"off"` to turn it off.
+/// A missing field keeps the default, so `None` is written as `"off"` for the file to
+/// read back the same.
+pub mod optional_limit {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Limit {
+        Value(f64),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(limit: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match limit {
+            Some(value) => serializer.serialize_f64(*value),
+            None => serializer.serialize_str("off"),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+        match Option::<Limit>::deserialize(deserializer)? {
+            Some(Limit::Value(value)) => Ok(Some(value)),
+            Some(Limit::Text(text)) => super::parse_limit(&text).map_err(serde::de::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A limit given as text, e.g. on the command line: a number, or `off` for none
+pub fn parse_limit(text: &str) -> Result<Option<f64>, String> {
+    if text.trim().eq_ignore_ascii_case("off") {
+        return Ok(None);
+    }
+    text.trim().parse().map(Some).map_err(|_| format!("expected a number or \"off\", found \"{}\"", text))
+}
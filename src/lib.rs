@@ -0,0 +1,35 @@
+//! Drivers and experiments for a Thorlabs CLD1015 laser diode controller and a Santec
+//! MPM-210H optical power meter.
+//!
+//! The `cld1015-mpm210h` binary is built on this library; other tools can embed the same
+//! drivers and measurement lifecycle without it. [`devices`] talks to the instruments
+//! (or a simulated rig), [`experiment`] runs sweeps and stability measurements on them
+//! and writes the data files.
+//!
+//! ```no_run
+//! use cld1015_mpm210h::devices::{DeviceRegistry, CLD1015, MPM210H};
+//! use cld1015_mpm210h::experiment::{self, CurrentSweepConfig, OutputConfig};
+//! use cld1015_mpm210h::experiment::data::{RunInfo, RunReport};
+//! use cld1015_mpm210h::experiment::progress::NoopObserver;
+//!
+//! let mut bench = DeviceRegistry::bench(CLD1015::new("USB0::0x1313::0x804F::M00000000::INSTR"),
+//!                                       MPM210H::new("192.168.1.161", 5000));
+//! let run_info = RunInfo { sample_id: "wafer7-die12".to_string(), ..RunInfo::default() };
+//! let mut report = RunReport::default();
+//! let path = experiment::run_current_sweep(&mut bench, CurrentSweepConfig::default(), &run_info,
+//!                                          &OutputConfig::default(), &mut NoopObserver, &mut report)?;
+//! println!("Saved to {}", path.display());
+//! # Ok::<(), cld1015_mpm210h::Error>(())
+//! ```
+//!
+//! The experiments connect the instruments, check and configure them, and always turn the
+//! laser off at the end, whether the run completed or failed.
+
+/// Instrument drivers, the simulated rig and VISA discovery
+pub mod devices;
+/// Error type of the experiments, matchable by the cause of the failure
+pub mod error;
+/// Experiment lifecycle, the built-in experiments, analysis and data files
+pub mod experiment;
+
+pub use error::{Error, FailureKind};
@@ -1,78 +1,1046 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
-#![allow(unused)]
 
-mod devices;
-mod experiment;
+mod cli;
+mod config;
+mod monitor;
+mod prompt;
+mod repl;
+mod selftest;
+mod service;
 
-use std::sync::Mutex;
+use cld1015_mpm210h::{devices, experiment};
 use tracing_subscriber::fmt;
-use tracing_appender::rolling;
-use tracing::{info, error, warn, Level};
-use devices::{CLD1015, MPM210H};
-use visa_rs::DefaultRM;
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Set up logging
-    setup_logging();
-    info!("Starting application");
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use std::process::ExitCode;
+use std::time::Duration;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing::{info, error, warn};
+use clap::{CommandFactory, Parser};
+use cli::{Cli, Command};
+use devices::{CLD1015, DeviceRegistry, MPM210H, LaserModel, SimulatedRig};
+use visa_rs::prelude::*;
 
-    // Initialize VISA Resource Manager
-    let rm = match DefaultRM::new() {
-        Ok(rm) => {
-            info!("Successfully initialized VISA resource manager");
-            rm
-        },
+/// Exit code of the process for each kind of failure, so wrapper scripts can react to the
+/// cause. 1 is any other failure and 2 a command line error, reported by clap.
+fn exit_code(error: &(dyn std::error::Error + 'static)) -> u8 {
+    use experiment::FailureKind::*;
+    match error.downcast_ref::<experiment::Error>().map(|e| e.kind()) {
+        Some(Config) => 3,
+        Some(Connection) => 4,
+        Some(Safety) => 5,
+        Some(UserAbort) => 6,
+        Some(Io) => 7,
+        Some(Instrument) => 8,
+        None if error.is::<std::io::Error>() => 7,
+        None => 1,
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
-            error!("Failed to initialize VISA resource manager: {}", e);
-            return Err(Box::new(e));
+            // Failures with a kind have been reported where they happened
+            if !e.is::<experiment::Error>() {
+                eprintln!("Error: {}", e);
+            }
+            ExitCode::from(exit_code(e.as_ref()))
         }
+    }
+}
+
+/// Log and print a failure, returning it with its kind for the exit code
+fn fail(kind: experiment::FailureKind, message: impl Into<String>) -> Box<dyn std::error::Error> {
+    failed(experiment::Error::new(kind, message))
+}
+
+/// Log and print an error of the library, keeping its source
+fn failed(error: experiment::Error) -> Box<dyn std::error::Error> {
+    error!("{}", error);
+    eprintln!("{}", error);
+    Box::new(error)
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    // Completion scripts need neither the configuration nor the log
+    if let Command::Completions(args) = &cli.command {
+        let mut command = Cli::command();
+        let name = command.get_name().to_string();
+        clap_complete::generate(args.shell, &mut command, name, &mut std::io::stdout());
+        return Ok(());
+    }
+
+    // Instrument addresses of this bench from the device file, when there is one
+    let device_file = cli.devices.file.clone()
+        .or_else(|| Some(std::path::PathBuf::from(config::DEFAULT_DEVICE_FILE)).filter(|path| path.exists()));
+    let devices = match &device_file {
+        Some(path) => config::load_device_file(path).map_err(|e| fail(experiment::FailureKind::Config, e))?,
+        None => config::DeviceConfig::default(),
     };
 
-    // Initialize devices
-    let mut cld = CLD1015::new("USB0::4883::32847::M01053290::0::INSTR");
-    let mut mpm = MPM210H::new("192.168.1.161", 5000);
-
-    // Run the experiment - specifically using module 0, port 2
-    // Create a custom configuration
-    let config = experiment::CurrentSweepConfig {
-        module: 0,               // Module 0
-        port: 2,                 // Port 2 (specifically requested)
-        start_ma: 10.0,          // Start at 10 mA
-        stop_ma: 100.0,          // End at 100 mA
-        step_ma: 5.0,            // 5 mA steps
-        stabilization_delay_ms: 50, // 50ms stabilization delay
-        wavelength_nm: 980,      // 980nm wavelength
-        averaging_time_ms: 100.0, // 100ms averaging time
-        power_unit: experiment::PowerUnit::DBm, // Use dBm units
+    // Load the experiment configuration from a file if one was given, otherwise use the defaults.
+    // This comes first because the file also configures the logging.
+    let profile_dir = cli.profile_dir.clone().unwrap_or_else(|| std::path::PathBuf::from(config::DEFAULT_PROFILE_DIR));
+    let config_file = match &cli.profile {
+        Some(name) => Some(config::profile_path(&profile_dir, name).map_err(|e| fail(experiment::FailureKind::Config, e))?),
+        None => cli.config.clone(),
     };
-    
-    // Run the experiment with our custom config that specifies module 0, port 2
-    match experiment::run_current_sweep(&mut cld, &mut mpm, config) {
+    let mut config = match &config_file {
+        Some(path) => config::load_config(path, &devices).map_err(|e| fail(experiment::FailureKind::Config, e))?,
+        None => config::ExperimentConfig { devices, ..Default::default() },
+    };
+    if cli.log_format.as_deref() == Some("json") {
+        config.logging.format = config::LogFormat::Json;
+    }
+    if let Some(level) = cli.log_level {
+        config.logging.level = level;
+    }
+    config.logging.console_level = config.logging.console_level.shifted(cli.verbose as i32 - cli.quiet as i32);
+    if let Some(directory) = &cli.output_dir {
+        config.output.directory = directory.clone();
+    }
+
+    // Set up logging. The guard flushes the log file when main returns.
+    let _log_guard = setup_logging(&config.logging)?;
+    info!("Starting application");
+
+    // Instrument addresses given on the command line override the config and device files
+    cli.devices.apply(&mut config.devices);
+    let simulate = cli.devices.simulate;
+
+    let result = match &cli.command {
+        Command::Sweep(args) => sweep(config, args, simulate, false),
+        Command::Liv(args) => sweep(config, args, simulate, true),
+        Command::Stability(args) => stability(config, args, simulate),
+        Command::PowerSweep(args) => power_sweep(config, args, simulate),
+        Command::Resume(args) => resume(config, simulate, args),
+        Command::Schedule(args) => schedule(config, simulate, args),
+        Command::Monitor(args) => monitor(&config, simulate, args),
+        Command::Zero(args) => zero(&config.devices, simulate, args),
+        Command::Status => status(&config.devices, simulate),
+        Command::SetCurrent(args) => set_current(&config.devices, simulate, args),
+        Command::Stop(args) => stop(&config.devices, simulate, args),
+        Command::Scan => scan(&config.devices, simulate),
+        Command::Devices(cli::DevicesCommand::Test) => devices_test(&config.devices, simulate),
+        Command::Selftest(args) => selftest(&config.devices, simulate, args),
+        Command::Repl(args) => repl(&config.devices, simulate, args),
+        Command::Convert(args) => convert(&config.output, args),
+        Command::Compare(args) => compare(&config, args),
+        Command::Config(command) => config_command(&config.devices, command),
+        Command::Service(args) => service(config, profile_dir.clone(), simulate, args),
+        Command::Profiles => {
+            for name in config::list_profiles(&profile_dir) {
+                println!("{}", name);
+            }
+            Ok(())
+        }
+        Command::Completions(_) => unreachable!("completions are printed before the configuration is loaded"),
+    };
+
+    info!("Application shutting down");
+    result
+}
+
+/// Check the configuration after the command line overrides, reporting every problem at once.
+/// A missing sample ID, port or wavelength is asked for first when someone is at the terminal.
+fn validate(config: &mut config::ExperimentConfig, session: &cli::SessionArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if !session.machine_output && !session.no_input && prompt::can_prompt() {
+        prompt::fill_missing(config)?;
+    }
+    info!("Using configuration: {:?}", config);
+    config.validate().map_err(|e| fail(experiment::FailureKind::Config, e))
+}
+
+/// Show what the run will do before anything is sent to the hardware. Returns true
+/// when only the plan was asked for.
+fn show_plan(
+    plan: &experiment::SweepPlan,
+    devices: &config::DeviceConfig,
+    simulate: bool,
+    session: &cli::SessionArgs,
+) -> bool {
+    info!("Sweep plan: {}", plan);
+    if !session.machine_output {
+        println!("Sweep plan: {}", plan);
+    }
+    if session.plan {
+        let rig = if simulate { " (simulated)" } else { "" };
+        let wavelengths: Vec<String> = plan.wavelengths_nm().iter().map(|nm| format!("{} nm", nm)).collect();
+        println!("CLD1015  {}{}", devices.cld1015_resource, rig);
+        println!("MPM210H  {}:{}{}, module {}, port {}, {}", devices.mpm210h_address, devices.mpm210h_port, rig,
+                 plan.module, plan.port, wavelengths.join(", "));
+        for (i, point) in plan.points.iter().enumerate() {
+            match point.power_setpoint_mw {
+                Some(setpoint_mw) => println!("{:>5}  {:>6} nm  {:>10.3} mW", i + 1, point.wavelength_nm, setpoint_mw),
+                None => println!("{:>5}  {:>6} nm  {:>10.3} mA", i + 1, point.wavelength_nm, point.current_ma),
+            }
+        }
+    }
+    session.plan
+}
+
+/// Instruments of the rig: the simulated rig, or the hardware at the configured addresses.
+/// Nothing is sent to the instruments until they are connected.
+fn open_rig(devices: &config::DeviceConfig, simulate: bool) -> Result<(CLD1015, MPM210H), Box<dyn std::error::Error>> {
+    if simulate {
+        // Dry-run mode: run against a simulated rig instead of hardware
+        info!("Simulation mode enabled, no hardware will be accessed");
+        let rig = SimulatedRig::shared(LaserModel::default());
+        return Ok((CLD1015::simulated(rig.clone()), MPM210H::simulated(rig)));
+    }
+
+    // Initialize VISA Resource Manager
+    match DefaultRM::new() {
+        Ok(_) => info!("Successfully initialized VISA resource manager"),
+        Err(e) => return Err(fail(experiment::FailureKind::Connection, format!("Failed to initialize VISA resource manager: {}", e))),
+    }
+
+    // Initialize devices
+    let mut cld = CLD1015::new(&devices.cld1015_resource);
+    cld.set_timeout(Duration::from_millis(devices.cld1015_timeout_ms));
+    let mut mpm = MPM210H::new(&devices.mpm210h_address, devices.mpm210h_port);
+    mpm.set_timeout(Duration::from_millis(devices.mpm210h_timeout_ms));
+    Ok((cld, mpm))
+}
+
+/// Live table on a terminal, plain lines when piped or asked for, nothing in machine
+/// output mode, plus the live plot window when asked for
+fn observers(power_unit: experiment::PowerUnit, session: &cli::SessionArgs) -> Vec<Box<dyn experiment::progress::SweepObserver>> {
+    let mut observers: Vec<Box<dyn experiment::progress::SweepObserver>> = Vec::new();
+    if !session.machine_output {
+        if session.plain || !std::io::IsTerminal::is_terminal(&std::io::stdout()) {
+            observers.push(Box::new(experiment::progress::PlainProgress::new()));
+        } else {
+            observers.push(Box::new(experiment::progress::ConsoleTable::new()));
+        }
+    }
+    if session.live_plot {
+        observers.push(Box::new(experiment::live_plot::LivePlot::new(power_unit.label())));
+    }
+    observers
+}
+
+/// Report the outcome of a measurement
+fn report(result: Result<std::path::PathBuf, experiment::Error>, session: &cli::SessionArgs) -> Result<(), Box<dyn std::error::Error>> {
+    match result {
         Ok(path) => {
             info!("Experiment completed successfully. Results saved to: {}", path.display());
-            println!("Experiment completed successfully. Results saved to: {}", path.display());
+            if !session.machine_output {
+                println!("Experiment completed successfully. Results saved to: {}", path.display());
+            }
+            Ok(())
         },
+        Err(e) => Err(failed(e.context("Experiment failed"))),
+    }
+}
+
+/// With `--machine-output`, print the result of a measuring subcommand as one JSON object
+/// on stdout, including failures before the run started
+fn machine_result(
+    session: &cli::SessionArgs,
+    run_report: &experiment::data::RunReport,
+    result: Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if session.machine_output {
+        let (exit_code, failure, error) = match &result {
+            Ok(()) => (0, None, None),
+            Err(e) => (exit_code(e.as_ref()), e.downcast_ref::<experiment::Error>().map(|e| e.kind()), Some(e.to_string())),
+        };
+        let value = serde_json::json!({
+            // Failures before the run started leave the report at its default, failed
+            "status": run_report.status.as_str(),
+            "exit_code": exit_code,
+            "failure": failure,
+            "error": error,
+            "experiment": run_report.experiment,
+            "run_id": run_report.run_id,
+            "sample_id": run_report.sample_id,
+            "data_files": run_report.data_files,
+            "analysis": run_report.curves,
+        });
+        println!("{}", value);
+    }
+    result
+}
+
+/// Number of runs of a measuring subcommand. With more than one, the file names get the
+/// repetition when the template does not place it.
+fn repeat_count(output: &mut experiment::OutputConfig, session: &cli::SessionArgs) -> usize {
+    // The plan is the same for every run, so it is shown once
+    let runs = if session.plan { 1 } else { session.repeat as usize };
+    if runs > 1 && !output.filename_template.contains("{repeat}") {
+        output.filename_template.push_str("_r{repeat}");
+    }
+    runs
+}
+
+/// Run a measuring subcommand `runs` times, back to back or one start every `--interval`
+/// minutes, stopping at the first failure. With `--machine-output` every run prints its result.
+fn repeat_runs(
+    runs: usize,
+    session: &cli::SessionArgs,
+    mut run: impl FnMut(usize, &mut experiment::data::RunReport) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(minutes) = session.repeat_interval_min.filter(|minutes| !(*minutes > 0.0 && minutes.is_finite())) {
+        return Err(fail(experiment::FailureKind::Config, format!("--interval must be a positive number of minutes, got {}", minutes)));
+    }
+    let interval = session.repeat_interval_min.map(|minutes| Duration::from_secs_f64(minutes * 60.0));
+    let started = std::time::Instant::now();
+
+    for repeat_index in 0..runs {
+        if repeat_index > 0 {
+            match interval.and_then(|interval| (interval * repeat_index as u32).checked_sub(started.elapsed())) {
+                Some(wait) => {
+                    info!("Waiting {:.0} s for run {} of {}", wait.as_secs_f64(), repeat_index + 1, runs);
+                    std::thread::sleep(wait);
+                }
+                None if interval.is_some() => warn!("Run {} of {} starts late, the previous runs took longer than the interval",
+                                                    repeat_index + 1, runs),
+                None => {}
+            }
+            info!("Starting run {} of {}", repeat_index + 1, runs);
+        }
+        let mut run_report = experiment::data::RunReport::default();
+        let result = run(repeat_index, &mut run_report);
+        machine_result(session, &run_report, result)?;
+    }
+    Ok(())
+}
+
+/// `sweep` and `liv`: the current sweep, or the fast sweep when one is configured. The
+/// L-I-V sweep always steps the current itself, as the fast sweep cannot read the voltage.
+fn sweep(
+    mut config: config::ExperimentConfig,
+    args: &cli::SweepCommand,
+    simulate: bool,
+    liv: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let runs = repeat_count(&mut config.output, &args.session);
+    repeat_runs(runs, &args.session, |repeat_index, run_report| {
+        run_sweep(&mut config, args, simulate, liv, repeat_index, run_report)
+    })
+}
+
+fn run_sweep(
+    config: &mut config::ExperimentConfig,
+    args: &cli::SweepCommand,
+    simulate: bool,
+    liv: bool,
+    repeat_index: usize,
+    run_report: &mut experiment::data::RunReport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    args.run.apply(config);
+    args.sweep.apply(&mut config.sweep);
+    if liv {
+        config.sweep.measure_voltage = true;
+        if config.fast_sweep.take().is_some() {
+            warn!("Ignoring [fast_sweep] for the L-I-V sweep, which needs the voltage at every point");
+        }
+    }
+    validate(config, &args.session)?;
+
+    let plan = match &config.fast_sweep {
+        Some(fast_sweep) => experiment::SweepPlan::for_fast_sweep(fast_sweep),
+        None => experiment::SweepPlan::for_current_sweep(&config.sweep),
+    };
+    if show_plan(&plan, &config.devices, simulate, &args.session) {
+        return Ok(());
+    }
+
+    let (cld, mpm) = open_rig(&config.devices, simulate)?;
+    let mut bench = DeviceRegistry::bench(cld, mpm);
+    let run_info = experiment::data::RunInfo { repeat_index, ..config.run_info() };
+    let power_unit = config.fast_sweep.as_ref().map_or(config.sweep.power_unit, |fast| fast.power_unit);
+    let mut observers = observers(power_unit, &args.session);
+
+    let result = match &config.fast_sweep {
+        Some(fast_sweep) => experiment::run_fast_sweep(&mut bench, fast_sweep.clone(), &run_info, &config.output,
+                                                       &mut observers, run_report),
+        None => experiment::run_current_sweep(&mut bench, config.sweep.clone(), &run_info, &config.output,
+                                              &mut observers, run_report),
+    };
+    report(result, &args.session)
+}
+
+/// `stability`: hold one current and record the power over time
+fn stability(
+    mut config: config::ExperimentConfig,
+    args: &cli::StabilityCommand,
+    simulate: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let runs = repeat_count(&mut config.output, &args.session);
+    repeat_runs(runs, &args.session, |repeat_index, run_report| {
+        run_stability(&mut config, args, simulate, repeat_index, run_report)
+    })
+}
+
+fn run_stability(
+    config: &mut config::ExperimentConfig,
+    args: &cli::StabilityCommand,
+    simulate: bool,
+    repeat_index: usize,
+    run_report: &mut experiment::data::RunReport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    args.run.apply(config);
+    args.stability.apply(&mut config.stability);
+    validate(config, &args.session)?;
+    let plan = experiment::SweepPlan::for_stability(&config.stability);
+    if show_plan(&plan, &config.devices, simulate, &args.session) {
+        return Ok(());
+    }
+
+    let (cld, mpm) = open_rig(&config.devices, simulate)?;
+    let mut bench = DeviceRegistry::bench(cld, mpm);
+    let run_info = experiment::data::RunInfo { repeat_index, ..config.run_info() };
+    let mut observers = observers(config.stability.power_unit, &args.session);
+    report(experiment::run_stability(&mut bench, config.stability.clone(), &run_info, &config.output,
+                                     &mut observers, run_report), &args.session)
+}
+
+/// `power-sweep`: step the optical power setpoint in constant-power mode
+fn power_sweep(
+    mut config: config::ExperimentConfig,
+    args: &cli::PowerSweepCommand,
+    simulate: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let runs = repeat_count(&mut config.output, &args.session);
+    repeat_runs(runs, &args.session, |repeat_index, run_report| {
+        run_power_sweep(&mut config, args, simulate, repeat_index, run_report)
+    })
+}
+
+fn run_power_sweep(
+    config: &mut config::ExperimentConfig,
+    args: &cli::PowerSweepCommand,
+    simulate: bool,
+    repeat_index: usize,
+    run_report: &mut experiment::data::RunReport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    args.run.apply(config);
+    args.power_sweep.apply(&mut config.power_sweep);
+    validate(config, &args.session)?;
+    let plan = experiment::SweepPlan::for_power_sweep(&config.power_sweep);
+    if show_plan(&plan, &config.devices, simulate, &args.session) {
+        return Ok(());
+    }
+
+    let (cld, mpm) = open_rig(&config.devices, simulate)?;
+    let mut bench = DeviceRegistry::bench(cld, mpm);
+    let run_info = experiment::data::RunInfo { repeat_index, ..config.run_info() };
+    let mut observers = observers(config.power_sweep.power_unit, &args.session);
+    report(experiment::run_power_sweep(&mut bench, config.power_sweep.clone(), &run_info, &config.output,
+                                       &mut observers, run_report), &args.session)
+}
+
+/// `schedule`: run one measuring subcommand at every time of a cron schedule, each run
+/// into its own files numbered like repeated runs. A run still going at the next time
+/// makes the scheduler skip that time rather than start a second run on the rig. A run
+/// stopped by a safety check or a configuration problem ends the schedule; other failures
+/// are reported and the schedule goes on.
+fn schedule(
+    mut config: config::ExperimentConfig,
+    simulate: bool,
+    args: &cli::ScheduleArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use cli::ScheduledCommand::*;
+    use experiment::FailureKind;
+    let schedule: cron::Schedule = args.cron.parse()
+        .map_err(|e| fail(FailureKind::Config, format!("Invalid cron expression {:?}: {}", args.cron, e)))?;
+    let session = match &args.experiment {
+        Sweep(command) | Liv(command) => &command.session,
+        Stability(command) => &command.session,
+    };
+    if !config.output.filename_template.contains("{repeat}") {
+        config.output.filename_template.push_str("_r{repeat}");
+    }
+
+    let mut run_index = 0;
+    while args.count.is_none_or(|count| run_index < count as usize) {
+        // Counted from now, so times that passed during the previous run are skipped
+        let Some(next) = schedule.upcoming(chrono::Local).next() else {
+            info!("No scheduled times left");
+            break;
+        };
+        info!("Next scheduled run at {}", next);
+        if !session.machine_output {
+            println!("Next run at {}", next.format("%Y-%m-%d %H:%M:%S"));
+        }
+        if let Ok(wait) = (next - chrono::Local::now()).to_std() {
+            std::thread::sleep(wait);
+        }
+
+        let mut run_report = experiment::data::RunReport::default();
+        let result = match &args.experiment {
+            Sweep(command) => run_sweep(&mut config, command, simulate, false, run_index, &mut run_report),
+            Liv(command) => run_sweep(&mut config, command, simulate, true, run_index, &mut run_report),
+            Stability(command) => run_stability(&mut config, command, simulate, run_index, &mut run_report),
+        };
+        run_index += 1;
+        if let Err(e) = machine_result(session, &run_report, result) {
+            let kind = e.downcast_ref::<experiment::Error>().map(|e| e.kind());
+            if matches!(kind, Some(FailureKind::Safety | FailureKind::Config)) {
+                return Err(e);
+            }
+            warn!("Scheduled run {} failed, keeping to the schedule: {}", run_index, e);
+        }
+    }
+    Ok(())
+}
+
+/// `resume`: continue an interrupted current sweep with its original settings. The
+/// instruments and output settings are those of this invocation.
+fn resume(mut config: config::ExperimentConfig, simulate: bool, args: &cli::ResumeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let checkpoint = experiment::resume::find_checkpoint(&config.output.directory, &args.run_id).map_err(failed)?;
+    println!("Resuming {} on {}: {} points measured before it stopped", checkpoint.run_id, checkpoint.sample_id,
+             checkpoint.records.len());
+    config.sample_id = checkpoint.sample_id.clone();
+
+    let (cld, mpm) = open_rig(&config.devices, simulate)?;
+    let mut bench = DeviceRegistry::bench(cld, mpm);
+    let run_info = config.run_info();
+    let mut observers: Vec<Box<dyn experiment::progress::SweepObserver>> = Vec::new();
+    if args.plain || !std::io::IsTerminal::is_terminal(&std::io::stdout()) {
+        observers.push(Box::new(experiment::progress::PlainProgress::new()));
+    } else {
+        observers.push(Box::new(experiment::progress::ConsoleTable::new()));
+    }
+    let mut run_report = experiment::data::RunReport::default();
+    match experiment::resume_current_sweep(&mut bench, checkpoint, &run_info, &config.output, &mut observers,
+                                           &mut run_report) {
+        Ok(path) => {
+            info!("Resumed run completed. Results saved to: {}", path.display());
+            println!("Resumed run completed. Results saved to: {}", path.display());
+            Ok(())
+        }
+        Err(e) => Err(failed(e.context("Resumed run failed"))),
+    }
+}
+
+/// `monitor`: read one port until Ctrl-C or the duration is over. The laser is left as it
+/// is, so it can be driven from the front panel or another session while aligning.
+fn monitor(config: &config::ExperimentConfig, simulate: bool, args: &cli::MonitorArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let sweep = &config.sweep;
+    let settings = monitor::MonitorSettings {
+        module: args.module.unwrap_or(sweep.module),
+        port: args.port.unwrap_or(sweep.port),
+        interval: Duration::from_millis(args.interval_ms),
+        power_unit: args.power_unit.unwrap_or(sweep.power_unit),
+        duration: args.duration_s.map(Duration::from_secs_f64),
+    };
+    let wavelength_nm = args.wavelength_nm.or(sweep.wavelengths_nm.first().copied()).unwrap_or(1550);
+    let averaging_time_ms = args.averaging_time_ms.unwrap_or(sweep.averaging_time_ms);
+    if averaging_time_ms > args.interval_ms as f64 {
+        warn!("Averaging time {} ms is longer than the {} ms interval, readings will lag", averaging_time_ms, args.interval_ms);
+    }
+
+    let (_, mut mpm) = open_rig(&config.devices, simulate)?;
+    let configured = mpm.connect().map_err(|e| e.to_string())
+        .and_then(|_| experiment::configure_power_meter(&mut mpm, averaging_time_ms, settings.power_unit).map_err(|e| e.to_string()))
+        .and_then(|_| mpm.set_wavelength(wavelength_nm).map_err(|e| e.to_string()));
+    if let Err(e) = configured {
+        return Err(fail(experiment::FailureKind::Connection, format!("Failed to set up the MPM210H: {}", e)));
+    }
+
+    let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    ctrlc::set_handler(move || handler_stop.store(true, std::sync::atomic::Ordering::Relaxed))?;
+
+    let mut file = match &args.output {
+        Some(path) => Some(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => None,
+    };
+    println!("Module {}, port {} at {} nm, Ctrl-C to stop", settings.module, settings.port, wavelength_nm);
+    monitor::run(&mut mpm, &settings, file.as_mut().map(|f| f as &mut dyn std::io::Write), stop)?;
+    Ok(())
+}
+
+/// `zero`: zero the MPM210H on its own. Every port of every recognized module is read
+/// first; light on an input would be subtracted from every later reading, so zeroing
+/// is refused unless `--force` is given. The error queue is checked once zeroing is done.
+fn zero(devices: &config::DeviceConfig, simulate: bool, args: &cli::ZeroArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use experiment::FailureKind::*;
+    let (_, mut mpm) = open_rig(devices, simulate)?;
+    let id = mpm.connect().map_err(|e| fail(Connection, format!("Failed to connect to the MPM210H: {}", e)))?;
+    println!("MPM210H: {}", id);
+
+    // Dark check, in dBm whatever unit the meter was left in
+    mpm.set_unit(0).map_err(|e| fail(Instrument, format!("Failed to set the MPM210H unit: {}", e)))?;
+    let slots = mpm.recognized_module_slots()
+        .map_err(|e| fail(Instrument, format!("Failed to list the MPM210H modules: {}", e)))?;
+    let mut lit = Vec::new();
+    for &slot in &slots {
+        let response = mpm.read_power(slot).map_err(|e| fail(Instrument, format!("Failed to read the inputs: {}", e)))?;
+        for (index, reading) in response.split(',').enumerate() {
+            let port = index + 1;
+            match devices::mpm210h::parse_power(reading) {
+                Ok(dbm) if dbm > args.dark_threshold_dbm => {
+                    println!("  module {} port {}: {:.2} dBm, not dark", slot, port, dbm);
+                    lit.push(format!("module {} port {} ({:.2} dBm)", slot, port, dbm));
+                }
+                Ok(dbm) => println!("  module {} port {}: {:.2} dBm", slot, port, dbm),
+                Err(e) => println!("  module {} port {}: {}", slot, port, e),
+            }
+        }
+    }
+    if !lit.is_empty() {
+        let message = format!("Inputs above {} dBm: {}", args.dark_threshold_dbm, lit.join(", "));
+        if !args.force {
+            return Err(fail(Safety, format!("Zeroing refused, cover the inputs or use --force: {}", message)));
+        }
+        warn!("{}; zeroing anyway (--force)", message);
+        eprintln!("Warning: {}; zeroing anyway", message);
+    }
+
+    mpm.perform_zeroing().map_err(|e| fail(Instrument, format!("Zeroing failed: {}", e)))?;
+    println!("Zeroing, waiting {} s", experiment::ZEROING_WAIT.as_secs_f64());
+    std::thread::sleep(experiment::ZEROING_WAIT);
+
+    match mpm.clear_error_queue() {
+        Ok(errors) if errors.is_empty() => {
+            info!("Zeroing completed");
+            println!("Zeroing completed");
+            Ok(())
+        }
+        Ok(errors) => Err(fail(Instrument, format!("Zeroing reported errors: {}", errors.join("; ")))),
+        Err(e) => Err(fail(Instrument, format!("Failed to read the MPM210H error queue after zeroing: {}", e))),
+    }
+}
+
+/// `status`: snapshot of both instruments, to check the rig before a run. Nothing is
+/// changed on either instrument; values that cannot be read are reported as such.
+fn status(devices: &config::DeviceConfig, simulate: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut cld, mut mpm) = open_rig(devices, simulate)?;
+    fn show<T, E: std::fmt::Display>(label: &str, value: Result<T, E>, format: impl Fn(T) -> String) {
+        match value {
+            Ok(value) => println!("  {:<22}{}", label, format(value)),
+            Err(e) => println!("  {:<22}unavailable: {}", label, e),
+        }
+    }
+    let state = |on: bool| if on { "on" } else { "off" }.to_string();
+
+    match cld.connect() {
+        Ok(id) => {
+            println!("CLD1015: {}", id);
+            show("laser output", cld.get_laser_output(), state);
+            show("TEC", cld.get_tec_state(), state);
+            show("mode", cld.get_operating_mode(), |mode| mode);
+            show("current setpoint", cld.get_current(), |a| format!("{:.3} mA", a * 1000.0));
+            show("current limit", cld.get_current_limit(), |a| format!("{:.3} mA", a * 1000.0));
+            show("measured current", cld.measure_current(), |a| format!("{:.3} mA", a * 1000.0));
+            show("TEC setpoint", cld.get_tec_setpoint(), |c| format!("{:.2} °C", c));
+            show("temperature", cld.measure_temperature(), |c| format!("{:.2} °C", c));
+        }
+        Err(e) => println!("CLD1015: not reachable at {}: {}", devices.cld1015_resource, e),
+    }
+    match mpm.connect() {
+        Ok(id) => {
+            println!("MPM210H: {}", id);
+            show("modules", mpm.recognized_module_slots(), |slots| format!("{:?}", slots));
+            show("wavelength", mpm.get_wavelength(), |nm| format!("{} nm", nm));
+            show("unit", mpm.get_unit(), |unit| if unit == 0 { "dBm" } else { "mW" }.to_string());
+            show("averaging time", mpm.get_average_time(), |ms| format!("{} ms", ms));
+            show("measurement mode", mpm.get_measurement_mode(), |mode| mode);
+        }
+        Err(e) => println!("MPM210H: not reachable at {}:{}: {}", devices.mpm210h_address, devices.mpm210h_port, e),
+    }
+    Ok(())
+}
+
+/// `config init` and `config check`
+fn config_command(devices: &config::DeviceConfig, command: &cli::ConfigCommand) -> Result<(), Box<dyn std::error::Error>> {
+    use experiment::FailureKind::*;
+    match command {
+        cli::ConfigCommand::Init(args) => {
+            if args.path.exists() && !args.force {
+                return Err(fail(Config, format!("{} exists; add --force to replace it", args.path.display())));
+            }
+            std::fs::write(&args.path, config::config_template(devices))
+                .map_err(|e| fail(Io, format!("Failed to write {}: {}", args.path.display(), e)))?;
+            info!("Template configuration written to {}", args.path.display());
+            println!("Template configuration written to {}", args.path.display());
+            Ok(())
+        }
+        cli::ConfigCommand::Check(args) => {
+            let config = config::load_config(&args.path, devices).map_err(|e| fail(Config, e))?;
+            // Profiles and templates leave the sample to the command line
+            let (sample, violations): (Vec<_>, Vec<_>) = config.violations().into_iter().partition(|v| v.field == "sample_id");
+            if !violations.is_empty() {
+                return Err(fail(Config, format!("{}: {}", args.path.display(), experiment::validation::format_violations(&violations))));
+            }
+            println!("{}: OK", args.path.display());
+            if !sample.is_empty() {
+                println!("sample_id is not set; give it with --sample-id");
+            }
+            Ok(())
+        }
+    }
+}
+
+/// `service`: connect to both instruments once and run the jobs sent to the local port
+/// until a client asks for a shutdown or Ctrl-C. The laser is turned off at the end.
+fn service(
+    config: config::ExperimentConfig,
+    profile_dir: std::path::PathBuf,
+    simulate: bool,
+    args: &cli::ServiceArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use experiment::Error;
+    let listener = std::net::TcpListener::bind(args.listen)
+        .map_err(|e| failed(Error::data(format!("Failed to listen on {}", args.listen), e)))?;
+    let (cld, mpm) = open_rig(&config.devices, simulate)?;
+    let mut bench = DeviceRegistry::bench(cld, mpm);
+    bench.connect_all().map_err(failed)?;
+
+    let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    ctrlc::set_handler(move || handler_stop.store(true, std::sync::atomic::Ordering::Relaxed))?;
+
+    println!("Accepting jobs on {}, Ctrl-C to stop", args.listen);
+    let served = service::Service { devices: &mut bench, base: config, profile_dir, stop, jobs_run: 0 }
+        .serve(listener);
+
+    // Leave the rig safe, however the service ended
+    let mut errors = experiment::data::InstrumentErrors::default();
+    let shutdown = experiment::instruments(&mut bench)
+        .and_then(|(cld, mpm)| experiment::shutdown_devices(cld, mpm, false, &mut errors));
+    bench.disconnect_all();
+    served?;
+    shutdown.map_err(failed)
+}
+
+/// `set-current`: apply one current setpoint and turn the laser on. The setpoint must be
+/// within the driver limit and the controller's current limit, the TEC must be on, and
+/// setpoints above `--confirm-above-ma` are confirmed at the prompt.
+fn set_current(devices: &config::DeviceConfig, simulate: bool, args: &cli::SetCurrentArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use experiment::FailureKind::*;
+    let current_ma = args.current_ma;
+    let max_safe_ma = devices::cld1015::MAX_SAFE_CURRENT_AMPS * 1000.0;
+    if !(0.0..=max_safe_ma).contains(&current_ma) {
+        return Err(fail(Config, format!("{} mA is outside the driver safety range 0-{} mA", current_ma, max_safe_ma)));
+    }
+
+    let (mut cld, _) = open_rig(devices, simulate)?;
+    cld.connect().map_err(|e| fail(Connection, format!("Failed to connect to the CLD1015: {}", e)))?;
+
+    if current_ma == 0.0 {
+        cld.set_laser_output(false).map_err(|e| fail(Instrument, format!("Failed to turn the laser off: {}", e)))?;
+        cld.set_current(0.0).map_err(|e| fail(Instrument, format!("Failed to set the current to 0: {}", e)))?;
+        println!("Laser off, current 0 mA");
+        return Ok(());
+    }
+
+    let limit_ma = cld.get_current_limit()
+        .map_err(|e| fail(Instrument, format!("Failed to read the current limit: {}", e)))? * 1000.0;
+    if current_ma > limit_ma {
+        return Err(fail(Safety, format!("{} mA is above the {:.1} mA current limit of the controller", current_ma, limit_ma)));
+    }
+    match cld.get_tec_state() {
+        Ok(true) => {}
+        Ok(false) => return Err(fail(Safety, "The TEC is off; turn it on before driving the laser")),
+        Err(e) => return Err(fail(Instrument, format!("Failed to read the TEC state: {}", e))),
+    }
+    if current_ma > args.confirm_above_ma && !args.yes {
+        print!("Drive the laser at {} mA? [y/N] ", current_ma);
+        std::io::Write::flush(&mut std::io::stdout())?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            return Err(fail(UserAbort, "Not confirmed, nothing changed"));
+        }
+    }
+
+    let applied = cld.set_current_mode()
+        .and_then(|_| cld.set_current(current_ma / 1000.0))
+        .and_then(|_| cld.set_laser_output(true));
+    if let Err(e) = applied {
+        let _ = cld.set_laser_output(false);
+        return Err(fail(Instrument, format!("Failed to apply {} mA: {}", current_ma, e)));
+    }
+    info!("Laser on at {} mA (set-current)", current_ma);
+    match cld.measure_current() {
+        Ok(amps) => println!("Laser on at {} mA, {:.3} mA measured", current_ma, amps * 1000.0),
+        Err(e) => println!("Laser on at {} mA, measured current unavailable: {}", current_ma, e),
+    }
+    println!("Turn it off with `stop` or `set-current 0`");
+    Ok(())
+}
+
+/// `stop`: connect to the CLD1015 only and run the shutdown sequence of a run, whatever
+/// state another process left it in
+fn stop(devices: &config::DeviceConfig, simulate: bool, args: &cli::StopArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut cld, mut mpm) = open_rig(devices, simulate)?;
+    if let Err(e) = cld.connect() {
+        return Err(fail(experiment::FailureKind::Connection,
+                        format!("Failed to connect to the CLD1015 at {}: {}", devices.cld1015_resource, e)));
+    }
+    // The MPM210H is left unconnected, so the sequence only touches the CLD1015
+    let mut errors = experiment::data::InstrumentErrors::default();
+    if let Err(e) = experiment::shutdown_devices(&mut cld, &mut mpm, args.tec, &mut errors) {
+        return Err(failed(e));
+    }
+    for entry in errors.cld1015.unwrap_or_default() {
+        println!("CLD1015 reported: {}", entry);
+    }
+    println!("Laser output off, current 0 mA{}", if args.tec { ", TEC off" } else { "" });
+    Ok(())
+}
+
+/// Queries timed by `devices test` for the round-trip latency
+const LATENCY_QUERIES: u32 = 5;
+
+/// `devices test`: connect to each configured instrument and report its `*IDN?`, the
+/// round-trip latency of a query and its error queue, which is cleared. Fails when an
+/// instrument is unreachable; errors in the queues are only reported.
+fn devices_test(devices: &config::DeviceConfig, simulate: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut cld, mut mpm) = open_rig(devices, simulate)?;
+
+    /// Mean and longest time of LATENCY_QUERIES identification queries
+    fn latency<E: std::fmt::Display>(mut query: impl FnMut() -> Result<String, E>) -> Result<(Duration, Duration), String> {
+        let mut longest = Duration::ZERO;
+        let started = std::time::Instant::now();
+        for _ in 0..LATENCY_QUERIES {
+            let sent = std::time::Instant::now();
+            query().map_err(|e| e.to_string())?;
+            longest = longest.max(sent.elapsed());
+        }
+        Ok((started.elapsed() / LATENCY_QUERIES, longest))
+    }
+    fn show<T, E: std::fmt::Display>(label: &str, value: Result<T, E>, format: impl Fn(T) -> String) {
+        match value {
+            Ok(value) => println!("  {:<14}{}", label, format(value)),
+            Err(e) => println!("  {:<14}unavailable: {}", label, e),
+        }
+    }
+    let round_trip = |(mean, longest): (Duration, Duration)| {
+        format!("{:.1} ms mean, {:.1} ms max over {} queries", mean.as_secs_f64() * 1000.0,
+                longest.as_secs_f64() * 1000.0, LATENCY_QUERIES)
+    };
+    let queue = |errors: Vec<String>| if errors.is_empty() { "empty".to_string() } else { errors.join("; ") };
+    let mut unreachable = Vec::new();
+
+    println!("CLD1015 at {}", devices.cld1015_resource);
+    match cld.connect() {
+        Ok(id) => {
+            println!("  {:<14}{}", "identification", id);
+            show("round trip", latency(|| cld.query("*IDN?")), round_trip);
+            show("error queue", cld.clear_error_queue(), queue);
+        }
         Err(e) => {
-            error!("Experiment failed: {}", e);
-            eprintln!("Experiment failed: {}", e);
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)));
+            println!("  unreachable: {}", e);
+            unreachable.push("CLD1015");
+        }
+    }
+    println!("MPM210H at {}:{}", devices.mpm210h_address, devices.mpm210h_port);
+    match mpm.connect() {
+        Ok(id) => {
+            println!("  {:<14}{}", "identification", id);
+            show("round trip", latency(|| mpm.query("*IDN?")), round_trip);
+            show("error queue", mpm.clear_error_queue(), queue);
+        }
+        Err(e) => {
+            println!("  unreachable: {}", e);
+            unreachable.push("MPM210H");
         }
     }
 
-    info!("Application shutting down");
+    if unreachable.is_empty() {
+        info!("Device test passed");
+        Ok(())
+    } else {
+        Err(fail(experiment::FailureKind::Connection, format!("Unreachable: {}", unreachable.join(", "))))
+    }
+}
+
+/// `scan`: every instrument VISA can see with its identification, and the modules and
+/// ports of the MPM210H at the configured address
+fn scan(devices: &config::DeviceConfig, simulate: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut cld, mut mpm) = open_rig(devices, simulate)?;
+
+    println!("VISA resources:");
+    if simulate {
+        match cld.connect() {
+            Ok(id) => println!("  SIMULATED  {}", id),
+            Err(e) => println!("  SIMULATED  no answer: {}", e),
+        }
+    } else {
+        let resources = match devices::discovery::visa_resources() {
+            Ok(resources) => resources,
+            Err(e) => return Err(fail(experiment::FailureKind::Connection, format!("VISA resource scan failed: {}", e))),
+        };
+        if resources.is_empty() {
+            println!("  none found");
+        }
+        for resource in &resources {
+            match devices::discovery::visa_identify(resource, Duration::from_millis(devices.cld1015_timeout_ms)) {
+                Ok(id) => println!("  {}  {}", resource, id),
+                Err(e) => println!("  {}  no answer to *IDN?: {}", resource, e),
+            }
+        }
+    }
+
+    // The MPM210H is on the network, where VISA does not look for it
+    println!("MPM210H at {}:{}:", devices.mpm210h_address, devices.mpm210h_port);
+    match mpm.connect() {
+        Ok(id) => {
+            println!("  {}", id);
+            match mpm.recognized_module_slots() {
+                Ok(slots) if slots.is_empty() => println!("  no modules recognized"),
+                Ok(slots) => {
+                    for slot in slots {
+                        println!("  module {}: ports 1-{}", slot, devices::mpm210h::PORTS_PER_MODULE);
+                    }
+                }
+                Err(e) => println!("  modules unavailable: {}", e),
+            }
+        }
+        Err(e) => println!("  not reachable: {}", e),
+    }
+    Ok(())
+}
+
+/// `selftest`: run the checks of both instruments and print one line per check. Fails
+/// when any check fails.
+fn selftest(devices: &config::DeviceConfig, simulate: bool, args: &cli::SelftestArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut cld, mut mpm) = open_rig(devices, simulate)?;
+    if args.pulse_ma.is_some() {
+        println!("The laser will be pulsed: make sure the beam is blocked.");
+    }
+    let checks = selftest::run(&mut cld, &mut mpm, args.pulse_ma);
+    checks.iter().for_each(|check| println!("{}", check));
+
+    let failed = checks.iter().filter(|check| matches!(check.outcome, selftest::Outcome::Fail(_))).count();
+    if failed > 0 {
+        return Err(fail(experiment::FailureKind::Instrument, format!("Self-test failed: {} of {} checks", failed, checks.len())));
+    }
+    info!("Self-test passed");
+    println!("Self-test passed");
+    Ok(())
+}
+
+/// `repl`: send raw commands to the instruments by hand. An instrument that cannot be
+/// reached is reported, the other one can still be used.
+fn repl(devices: &config::DeviceConfig, simulate: bool, args: &cli::ReplArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut cld, mut mpm) = open_rig(devices, simulate)?;
+    if let Some(path) = &args.transcript {
+        let transcript = devices::Transcript::create(path)?;
+        cld.set_transcript(Some(transcript.clone()));
+        mpm.set_transcript(Some(transcript));
+    }
+    match cld.connect() {
+        Ok(id) => println!("CLD1015: {}", id),
+        Err(e) => println!("CLD1015: not reachable at {}: {}", devices.cld1015_resource, e),
+    }
+    match mpm.connect() {
+        Ok(id) => println!("MPM210H: {}", id),
+        Err(e) => println!("MPM210H: not reachable at {}:{}: {}", devices.mpm210h_address, devices.mpm210h_port, e),
+    }
+    repl::run(&mut cld, &mut mpm)?;
     Ok(())
 }
 
-fn setup_logging() {
+/// `convert`: write each saved run in the given formats, optionally keeping every n-th
+/// point or the mean of every n points
+fn convert(output: &experiment::OutputConfig, args: &cli::ConvertArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let downsample = match (args.every, args.average) {
+        (Some(n), _) => Some(experiment::convert::Downsample::Decimate(n as usize)),
+        (None, Some(n)) => Some(experiment::convert::Downsample::Average(n as usize)),
+        (None, None) => None,
+    };
+
+    for file in &args.files {
+        match experiment::convert::convert_file(file, &args.to, output, downsample) {
+            Ok(saved) => saved.iter().for_each(|path| println!("Saved {}", path.display())),
+            Err(e) => return Err(failed(e.context(format!("Conversion of {} failed", file.display())))),
+        }
+    }
+    Ok(())
+}
+
+/// `compare`: analyze previous runs instead of measuring
+fn compare(config: &config::ExperimentConfig, args: &cli::CompareArgs) -> Result<(), Box<dyn std::error::Error>> {
+    match experiment::compare::compare_files(&args.files, args.reference_current, config.sweep.kink_tolerance,
+                                             &config.output.directory) {
+        Ok((table, saved)) => {
+            print!("{}", table);
+            for path in saved {
+                println!("Saved {}", path.display());
+            }
+            Ok(())
+        }
+        Err(e) => Err(failed(e.context("Run comparison failed"))),
+    }
+}
+
+/// Log to a rotated file, as plain text or as one JSON object per line for log collectors,
+/// and to stderr, each at its own level. JSON lines carry the fields of the current run
+/// span (run ID, sample ID).
+fn setup_logging(logging: &config::LoggingConfig) -> Result<WorkerGuard, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(&logging.directory)?;
+    if let Some(days) = logging.retention_days.filter(|&days| days > 0) {
+        prune_logs(&logging.directory, Duration::from_secs(days * 24 * 3600));
+    }
+
     // Set up file-based logging with rotation
-    let file_appender = rolling::daily("logs", "app.log");
-    let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
-    
-    // Create a subscriber that logs to both the file and the console
-    fmt()
+    let rotation = match logging.rotation {
+        config::LogRotation::Hourly => Rotation::HOURLY,
+        config::LogRotation::Daily => Rotation::DAILY,
+        config::LogRotation::Never => Rotation::NEVER,
+    };
+    let file_appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(LOG_FILE_PREFIX)
+        .build(&logging.directory)?;
+    let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
+
+    let file_layer = fmt::layer()
         .with_writer(non_blocking)
         .with_ansi(false) // Disable ANSI colors in log files
-        .with_level(true)
-        .init();
+        .with_level(true);
+    let file_layer = match logging.format {
+        config::LogFormat::Json => file_layer.json().with_current_span(true).with_span_list(false)
+            .with_filter(level_filter(logging.level)).boxed(),
+        config::LogFormat::Text => file_layer.with_filter(level_filter(logging.level)).boxed(),
+    };
+    let console_layer = fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .with_filter(level_filter(logging.console_level));
+    tracing_subscriber::registry().with(file_layer).with(console_layer).init();
+    Ok(guard)
+}
+
+fn level_filter(level: config::LogLevel) -> LevelFilter {
+    match level {
+        config::LogLevel::Off => LevelFilter::OFF,
+        config::LogLevel::Error => LevelFilter::ERROR,
+        config::LogLevel::Warn => LevelFilter::WARN,
+        config::LogLevel::Info => LevelFilter::INFO,
+        config::LogLevel::Debug => LevelFilter::DEBUG,
+        config::LogLevel::Trace => LevelFilter::TRACE,
+    }
+}
+
+/// Name of the application log files, followed by the date and hour of rotation
+const LOG_FILE_PREFIX: &str = "app.log";
+
+/// Delete application log files last modified more than `max_age` ago. Runs before the
+/// logger is set up, so problems are reported on stderr.
+fn prune_logs(directory: &std::path::Path, max_age: Duration) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let is_log = entry.file_name().to_string_lossy().starts_with(LOG_FILE_PREFIX);
+        let age = entry.metadata().and_then(|m| m.modified()).ok().and_then(|t| t.elapsed().ok());
+        if is_log && age.is_some_and(|age| age > max_age) {
+            if let Err(e) = std::fs::remove_file(entry.path()) {
+                eprintln!("Failed to delete old log file {}: {}", entry.path().display(), e);
+            }
+        }
+    }
 }
\ No newline at end of file
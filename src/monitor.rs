@@ -0,0 +1,67 @@
+use crate::devices::MPM210H;
+use crate::experiment::PowerUnit;
+use chrono::Utc;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Channel read by the monitor and how often
+#[derive(Debug, Clone)]
+pub struct MonitorSettings {
+    pub module: u8,
+    pub port: u8,
+    pub interval: Duration,
+    pub power_unit: PowerUnit,
+    pub duration: Option<Duration>, // Stop after this long, run until interrupted if None
+}
+
+/// Read one channel at a fixed interval and print every reading with the peak so far,
+/// e.g. while aligning a fiber. Each reading is also written to `stream` as a CSV line
+/// when given. Runs until `stop` is set (Ctrl-C) or the duration is over; the meter must
+/// already be configured. Returns the number of readings.
+pub fn run(
+    mpm: &mut MPM210H,
+    settings: &MonitorSettings,
+    mut stream: Option<&mut dyn Write>,
+    stop: Arc<AtomicBool>,
+) -> io::Result<usize> {
+    let unit = settings.power_unit.label();
+    if let Some(stream) = stream.as_mut() {
+        writeln!(stream, "timestamp,elapsed_s,power_{}", unit.to_lowercase())?;
+    }
+    info!("Monitoring module {}, port {} every {:?}", settings.module, settings.port, settings.interval);
+
+    let start = Instant::now();
+    let mut peak = f64::NEG_INFINITY;
+    let mut readings = 0;
+    while !stop.load(Ordering::Relaxed) && settings.duration.is_none_or(|d| start.elapsed() < d) {
+        // Readings follow a fixed schedule, like those of a stability run
+        if let Some(wait) = (settings.interval * readings as u32).checked_sub(start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+        let elapsed_s = start.elapsed().as_secs_f64();
+        match mpm.read_power_value(settings.module, settings.port) {
+            Ok(power) => {
+                peak = peak.max(power);
+                println!("{:>9.1} s  {:>10.3} {:<3}  peak {:>10.3} {}", elapsed_s, power, unit, peak, unit);
+                if let Some(stream) = stream.as_mut() {
+                    writeln!(stream, "{},{:.3},{}", Utc::now().to_rfc3339(), elapsed_s, power)?;
+                    stream.flush()?;
+                }
+            }
+            Err(e) => {
+                warn!("Failed to read module {}, port {}: {}", settings.module, settings.port, e);
+                println!("{:>9.1} s  reading failed: {}", elapsed_s, e);
+            }
+        }
+        readings += 1;
+    }
+
+    if peak.is_finite() {
+        println!("{} readings, peak {:.3} {}", readings, peak, unit);
+    }
+    info!("Monitor stopped after {} readings", readings);
+    Ok(readings)
+}
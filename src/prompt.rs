@@ -0,0 +1,88 @@
+use crate::config::ExperimentConfig;
+use std::io::{self, BufRead, IsTerminal, Write};
+use tracing::info;
+
+/// Fields asked for when missing or invalid, by path prefix as in the violations
+const PROMPTED_FIELDS: [&str; 7] = [
+    "sample_id",
+    "sweep.port",
+    "sweep.wavelengths_nm",
+    "fast_sweep.port",
+    "fast_sweep.wavelength_nm",
+    "stability.port",
+    "stability.wavelength_nm",
+];
+
+/// Whether a person can answer: stdin is a terminal rather than a pipe or file
+pub fn can_prompt() -> bool {
+    io::stdin().is_terminal()
+}
+
+/// Ask for the sample ID, ports and wavelengths the configuration lacks or has wrong,
+/// instead of failing on them. Every answer goes through the same checks as the
+/// configuration file and is asked again until it passes. Other problems are left for
+/// the validation to report. Stops asking at the end of input.
+pub fn fill_missing(config: &mut ExperimentConfig) -> io::Result<()> {
+    for field in PROMPTED_FIELDS {
+        while let Some(violation) = config.violations().into_iter().find(|v| v.field.starts_with(field)) {
+            println!("{}", violation);
+            let Some(answer) = ask(label(field))? else {
+                return Ok(());
+            };
+            match set(config, field, &answer) {
+                Ok(()) => info!("{} entered at the prompt: {}", field, answer),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn label(field: &str) -> &'static str {
+    match field {
+        "sample_id" => "Sample ID",
+        "sweep.port" => "Sweep MPM210H port (1-4)",
+        "sweep.wavelengths_nm" => "Sweep wavelengths in nm, comma separated",
+        "fast_sweep.port" => "Fast sweep MPM210H port (1-4)",
+        "fast_sweep.wavelength_nm" => "Fast sweep wavelength in nm",
+        "stability.port" => "Stability MPM210H port (1-4)",
+        _ => "Stability wavelength in nm",
+    }
+}
+
+/// Print the question and read one line, None at the end of input
+fn ask(label: &str) -> io::Result<Option<String>> {
+    print!("{}: ", label);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        println!();
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_string()))
+}
+
+fn set(config: &mut ExperimentConfig, field: &str, answer: &str) -> Result<(), String> {
+    let number = |answer: &str| answer.trim().parse::<u32>().map_err(|_| format!("{:?} is not a whole number", answer));
+    let port = |answer: &str| answer.trim().parse::<u8>().map_err(|_| format!("{:?} is not a port number", answer));
+    match field {
+        "sample_id" => config.sample_id = answer.to_string(),
+        "sweep.port" => config.sweep.port = port(answer)?,
+        "sweep.wavelengths_nm" => {
+            config.sweep.wavelengths_nm = answer.split(',').map(number).collect::<Result<_, _>>()?;
+        }
+        "stability.port" => config.stability.port = port(answer)?,
+        "stability.wavelength_nm" => config.stability.wavelength_nm = number(answer)?,
+        "fast_sweep.port" | "fast_sweep.wavelength_nm" => {
+            if let Some(fast_sweep) = config.fast_sweep.as_mut() {
+                if field == "fast_sweep.port" {
+                    fast_sweep.port = port(answer)?;
+                } else {
+                    fast_sweep.wavelength_nm = number(answer)?;
+                }
+            }
+        }
+        _ => unreachable!("no prompt for {}", field),
+    }
+    Ok(())
+}
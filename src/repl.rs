@@ -0,0 +1,99 @@
+use crate::devices::{CLD1015, MPM210H};
+use std::io::{self, BufRead, Write};
+use tracing::{info, warn};
+
+/// Instrument the typed commands are sent to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Cld,
+    Mpm,
+}
+
+impl Target {
+    fn prompt(&self) -> &'static str {
+        match self {
+            Target::Cld => "cld> ",
+            Target::Mpm => "mpm> ",
+        }
+    }
+}
+
+const HELP: &str = "\
+Commands ending in '?' are queries and print the response; others are sent as they are.
+  <command>          send to the current instrument
+  cld <command>      send to the CLD1015
+  mpm <command>      send to the MPM210H
+  use cld | use mpm  change the current instrument
+  errors             read and clear the error queues of both instruments
+  help               show this text
+  exit | quit        turn the laser off and leave (also Ctrl-D)
+Current setpoints above the driver limit are refused and the laser output is only turned
+on with the TEC on. The CLD1015 takes one command per line, without ';'.";
+
+/// Interactive prompt sending raw commands to the connected instruments. Every command
+/// goes through the drivers, so it is logged and recorded in any transcript like the
+/// commands of a run. The laser is turned off when the session ends.
+pub fn run(cld: &mut CLD1015, mpm: &mut MPM210H) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut target = Target::Cld;
+    println!("Type 'help' for the commands, 'exit' to leave.");
+
+    loop {
+        print!("{}", target.prompt());
+        stdout.flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+            break;
+        }
+        let line = line.trim();
+        let (word, rest) = line.split_once(char::is_whitespace).map_or((line, ""), |(w, r)| (w, r.trim()));
+
+        match (word.to_lowercase().as_str(), rest) {
+            ("", _) => continue,
+            ("exit" | "quit", _) => break,
+            ("help", _) => println!("{}", HELP),
+            ("use", "cld") => target = Target::Cld,
+            ("use", "mpm") => target = Target::Mpm,
+            ("use", other) => println!("Unknown instrument {:?}, use cld or mpm", other),
+            ("errors", _) => {
+                match cld.clear_error_queue() {
+                    Ok(errors) if errors.is_empty() => println!("CLD1015: no errors"),
+                    Ok(errors) => errors.iter().for_each(|e| println!("CLD1015: {}", e)),
+                    Err(e) => println!("CLD1015: error queue unavailable: {}", e),
+                }
+                match mpm.clear_error_queue() {
+                    Ok(errors) if errors.is_empty() => println!("MPM210H: no errors"),
+                    Ok(errors) => errors.iter().for_each(|e| println!("MPM210H: {}", e)),
+                    Err(e) => println!("MPM210H: error queue unavailable: {}", e),
+                }
+            }
+            ("cld", command) if !command.is_empty() => send(Target::Cld, command, cld, mpm),
+            ("mpm", command) if !command.is_empty() => send(Target::Mpm, command, cld, mpm),
+            _ => send(target, line, cld, mpm),
+        }
+    }
+
+    // Leave the rig safe, whatever was typed
+    match cld.set_laser_output(false) {
+        Ok(()) => info!("Laser output turned off at the end of the REPL session"),
+        Err(e) => warn!("Failed to turn the laser off at the end of the REPL session: {}", e),
+    }
+    Ok(())
+}
+
+/// Send one command and print the response of a query
+fn send(target: Target, command: &str, cld: &mut CLD1015, mpm: &mut MPM210H) {
+    let is_query = command.split_whitespace().next().is_some_and(|header| header.ends_with('?'));
+    let result = match target {
+        Target::Cld => cld.send_raw(command).map_err(|e| e.to_string()),
+        Target::Mpm if is_query => mpm.query(command).map(Some).map_err(|e| e.to_string()),
+        Target::Mpm => mpm.send_command(command).map(|_| None).map_err(|e| e.to_string()),
+    };
+    match result {
+        Ok(Some(response)) => println!("{}", response),
+        Ok(None) => {}
+        Err(e) => println!("Error: {}", e),
+    }
+}
@@ -0,0 +1,167 @@
+use crate::devices::{CLD1015, MPM210H};
+use crate::devices::mpm210h::parse_power;
+use std::fmt;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Highest current the pulse check may use, well below the threshold of the usual devices
+pub const MAX_PULSE_MA: f64 = 20.0;
+
+/// Time the laser is on during the pulse check
+const PULSE_DURATION: Duration = Duration::from_millis(200);
+
+/// Largest difference between the set and measured pulse current
+const PULSE_TOLERANCE_MA: f64 = 0.5;
+
+/// Result of one check
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Pass(String),
+    Fail(String),
+    Skip(String), // Not run, because an earlier check failed or it was not asked for
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (label, detail) = match &self.outcome {
+            Outcome::Pass(detail) => ("PASS", detail),
+            Outcome::Fail(detail) => ("FAIL", detail),
+            Outcome::Skip(detail) => ("SKIP", detail),
+        };
+        write!(f, "{}  {:<24}{}", label, self.name, detail)
+    }
+}
+
+/// Checks of both instruments, each reported on its own. With `pulse_ma`, the laser is
+/// driven at that current for a moment to check the controller delivers it; the beam
+/// must be blocked. The laser is off with the current at 0 when this returns.
+pub fn run(cld: &mut CLD1015, mpm: &mut MPM210H, pulse_ma: Option<f64>) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let mut check = |name: &'static str, outcome: Outcome| {
+        match &outcome {
+            Outcome::Fail(detail) => warn!("Self-test {} failed: {}", name, detail),
+            _ => info!("Self-test {}: {:?}", name, outcome),
+        }
+        checks.push(Check { name, outcome });
+    };
+
+    // CLD1015
+    let cld_connected = match cld.connect() {
+        Ok(id) => {
+            check("CLD1015 connection", Outcome::Pass(id));
+            true
+        }
+        Err(e) => {
+            check("CLD1015 connection", Outcome::Fail(e.to_string()));
+            false
+        }
+    };
+    if cld_connected {
+        check("CLD1015 *TST?", match cld.query("*TST?") {
+            Ok(response) if response.trim() == "0" => Outcome::Pass("no faults".to_string()),
+            Ok(response) => Outcome::Fail(format!("self-test result {}", response)),
+            Err(e) => Outcome::Fail(e.to_string()),
+        });
+        check("CLD1015 error queue", error_queue(cld.clear_error_queue()));
+    } else {
+        check("CLD1015 *TST?", Outcome::Skip("not connected".to_string()));
+        check("CLD1015 error queue", Outcome::Skip("not connected".to_string()));
+    }
+
+    // MPM210H
+    let mpm_connected = match mpm.connect() {
+        Ok(id) => {
+            check("MPM210H connection", Outcome::Pass(id));
+            true
+        }
+        Err(e) => {
+            check("MPM210H connection", Outcome::Fail(e.to_string()));
+            false
+        }
+    };
+    if mpm_connected {
+        match mpm.recognized_module_slots() {
+            Ok(slots) if slots.is_empty() => check("MPM210H modules", Outcome::Fail("no module recognized".to_string())),
+            Ok(slots) => {
+                check("MPM210H modules", Outcome::Pass(format!("{:?}", slots)));
+                for slot in slots {
+                    // Every port of a healthy module returns a number
+                    let outcome = match mpm.read_power(slot) {
+                        Ok(response) => match response.split(',').map(parse_power).collect::<Result<Vec<_>, _>>() {
+                            Ok(values) if values.len() >= 4 => Outcome::Pass(response),
+                            Ok(values) => Outcome::Fail(format!("{} values read, expected 4", values.len())),
+                            Err(e) => Outcome::Fail(e.to_string()),
+                        },
+                        Err(e) => Outcome::Fail(e.to_string()),
+                    };
+                    check("MPM210H module reading", outcome);
+                }
+            }
+            Err(e) => check("MPM210H modules", Outcome::Fail(e.to_string())),
+        }
+        check("MPM210H error queue", error_queue(mpm.clear_error_queue()));
+    } else {
+        check("MPM210H modules", Outcome::Skip("not connected".to_string()));
+        check("MPM210H error queue", Outcome::Skip("not connected".to_string()));
+    }
+
+    // Current pulse
+    let outcome = match pulse_ma {
+        None => Outcome::Skip("not asked for (--pulse-ma)".to_string()),
+        Some(_) if !cld_connected => Outcome::Skip("CLD1015 not connected".to_string()),
+        Some(pulse_ma) => current_pulse(cld, pulse_ma),
+    };
+    check("current pulse", outcome);
+
+    checks
+}
+
+fn error_queue<E: fmt::Display>(result: Result<Vec<String>, E>) -> Outcome {
+    match result {
+        Ok(errors) if errors.is_empty() => Outcome::Pass("empty".to_string()),
+        Ok(errors) => Outcome::Fail(errors.join("; ")),
+        Err(e) => Outcome::Fail(e.to_string()),
+    }
+}
+
+/// Drive the laser at `pulse_ma` for PULSE_DURATION and compare the measured current
+fn current_pulse(cld: &mut CLD1015, pulse_ma: f64) -> Outcome {
+    if !(pulse_ma > 0.0 && pulse_ma <= MAX_PULSE_MA) {
+        return Outcome::Fail(format!("pulse current must be within 0-{} mA, got {}", MAX_PULSE_MA, pulse_ma));
+    }
+    match cld.get_tec_state() {
+        Ok(true) => {}
+        Ok(false) => return Outcome::Skip("TEC off, the laser is only turned on with the TEC on".to_string()),
+        Err(e) => return Outcome::Fail(e.to_string()),
+    }
+
+    let measured = (|| {
+        cld.set_laser_output(false)?;
+        cld.set_current_mode()?;
+        cld.set_current(pulse_ma / 1000.0)?;
+        cld.set_laser_output(true)?;
+        std::thread::sleep(PULSE_DURATION);
+        cld.measure_current()
+    })();
+
+    // Back to a safe state whatever happened above
+    let _ = cld.set_laser_output(false);
+    let _ = cld.set_current(0.0);
+    if !matches!(cld.get_laser_output(), Ok(false)) {
+        return Outcome::Fail("laser output could not be confirmed off after the pulse".to_string());
+    }
+
+    match measured {
+        Ok(amps) if (amps * 1000.0 - pulse_ma).abs() <= PULSE_TOLERANCE_MA => {
+            Outcome::Pass(format!("{:.3} mA measured for {} mA", amps * 1000.0, pulse_ma))
+        }
+        Ok(amps) => Outcome::Fail(format!("{:.3} mA measured for {} mA", amps * 1000.0, pulse_ma)),
+        Err(e) => Outcome::Fail(e.to_string()),
+    }
+}
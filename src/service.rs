@@ -0,0 +1,204 @@
+use crate::config::{self, ExperimentConfig};
+use crate::devices::DeviceRegistry;
+use crate::experiment::{self, Error};
+use crate::experiment::data::RunReport;
+use crate::experiment::progress::SweepObserver;
+use serde::Deserialize;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Address the service listens on when no other is given: local connections only
+pub const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:5100";
+
+/// How often the listener checks for Ctrl-C while no client is connected
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// One request of a client, a JSON object on one line, e.g.
+/// `{"command": "run", "experiment": "sweep", "profile": "980nm-chip-screen", "sample_id": "wafer7-die12"}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Status,   // Identification of the instruments and number of jobs run
+    Run(Job), // Run one experiment and answer when it is done
+    Shutdown, // Turn the laser off and stop the service
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Sweep,
+    Liv,
+    Stability,
+    PowerSweep,
+}
+
+/// An experiment job. The configuration is the experiment file or profile given, or the
+/// one the service was started with; its `[devices]` section is ignored, as the
+/// instruments are already connected.
+#[derive(Debug, Deserialize)]
+pub struct Job {
+    pub experiment: JobKind,
+    pub config: Option<PathBuf>,   // Experiment file
+    pub profile: Option<String>,   // Profile name, used when no file is given
+    pub sample_id: Option<String>, // Overrides the sample ID of the configuration
+    pub notes: Option<String>,     // Overrides the notes of the configuration
+}
+
+/// Instruments and settings shared by every job of the service
+pub struct Service<'a> {
+    pub devices: &'a mut DeviceRegistry,
+    pub base: ExperimentConfig, // Configuration the service was started with
+    pub profile_dir: PathBuf,
+    pub stop: Arc<AtomicBool>,  // Set by Ctrl-C: aborts the running job and stops the service
+    pub jobs_run: usize,
+}
+
+/// Aborts the running job when the service is stopped
+struct StopFlag(Arc<AtomicBool>);
+
+impl SweepObserver for StopFlag {
+    fn should_abort(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Service<'_> {
+    /// Answer clients one after the other until a shutdown request or Ctrl-C. Jobs run one
+    /// at a time; other clients wait for the running one to finish. The instruments must
+    /// already be connected and stay connected between jobs.
+    pub fn serve(&mut self, listener: TcpListener) -> io::Result<()> {
+        listener.set_nonblocking(true)?;
+        info!("Service listening on {}", listener.local_addr()?);
+        while !self.stop.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    info!("Client connected from {}", peer);
+                    match self.handle_client(stream) {
+                        Ok(true) => break,
+                        Ok(false) => info!("Client {} disconnected", peer),
+                        Err(e) => warn!("Connection to {} failed: {}", peer, e),
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::sleep(POLL_INTERVAL),
+                Err(e) => return Err(e),
+            }
+        }
+        info!("Service stopped after {} jobs", self.jobs_run);
+        Ok(())
+    }
+
+    /// Answer every request of one client, one JSON line each. Returns true on a
+    /// shutdown request.
+    fn handle_client(&mut self, stream: TcpStream) -> io::Result<bool> {
+        stream.set_nonblocking(false)?;
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (response, shutdown) = match serde_json::from_str::<Request>(&line) {
+                Ok(Request::Status) => (self.status(), false),
+                Ok(Request::Run(job)) => (self.run_job(job), false),
+                Ok(Request::Shutdown) => (serde_json::json!({ "ok": true }), true),
+                Err(e) => (serde_json::json!({ "ok": false, "error": format!("Invalid request: {}", e) }), false),
+            };
+            writeln!(writer, "{}", response)?;
+            writer.flush()?;
+            if shutdown {
+                info!("Shutdown requested by a client");
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn status(&mut self) -> serde_json::Value {
+        let (cld, mpm) = match experiment::instruments(self.devices) {
+            Ok((cld, mpm)) => (cld.query("*IDN?").map_err(|e| e.to_string()), mpm.query("*IDN?").map_err(|e| e.to_string())),
+            Err(e) => (Err(e.to_string()), Err(e.to_string())),
+        };
+        serde_json::json!({
+            "ok": cld.is_ok() && mpm.is_ok(),
+            "cld1015": cld.unwrap_or_else(|e| format!("unavailable: {}", e)),
+            "mpm210h": mpm.unwrap_or_else(|e| format!("unavailable: {}", e)),
+            "jobs_run": self.jobs_run,
+        })
+    }
+
+    /// Run one job, answering with the same fields as `--machine-output`
+    fn run_job(&mut self, job: Job) -> serde_json::Value {
+        info!("Job received: {:?}", job);
+        let mut report = RunReport::default();
+        let result = self.configure(&job).and_then(|config| self.run(job.experiment, config, &mut report));
+        self.jobs_run += 1;
+        let (failure, error) = match &result {
+            Ok(path) => {
+                info!("Job completed, results saved to {}", path.display());
+                (None, None)
+            }
+            Err(e) => {
+                error!("Job failed: {}", e);
+                (Some(e.kind()), Some(e.to_string()))
+            }
+        };
+        serde_json::json!({
+            "ok": result.is_ok(),
+            "status": report.status.as_str(),
+            "failure": failure,
+            "error": error,
+            "experiment": report.experiment,
+            "run_id": report.run_id,
+            "sample_id": report.sample_id,
+            "data_files": report.data_files,
+            "analysis": report.curves,
+        })
+    }
+
+    /// Configuration of a job, checked before anything is sent to the instruments
+    fn configure(&self, job: &Job) -> Result<ExperimentConfig, Error> {
+        let config_error = Error::Config;
+        let file = match (&job.config, &job.profile) {
+            (Some(path), _) => Some(path.clone()),
+            (None, Some(name)) => Some(config::profile_path(&self.profile_dir, name).map_err(config_error)?),
+            (None, None) => None,
+        };
+        let mut config = match file {
+            Some(path) => config::load_config(&path, &self.base.devices).map_err(config_error)?,
+            None => self.base.clone(),
+        };
+        config.devices = self.base.devices.clone();
+        if let Some(sample_id) = &job.sample_id {
+            config.sample_id = sample_id.clone();
+        }
+        if let Some(notes) = &job.notes {
+            config.notes = Some(notes.clone());
+        }
+        if job.experiment == JobKind::Liv {
+            config.sweep.measure_voltage = true;
+            config.fast_sweep = None;
+        }
+        config.validate().map_err(config_error)?;
+        Ok(config)
+    }
+
+    fn run(&mut self, kind: JobKind, config: ExperimentConfig, report: &mut RunReport) -> Result<PathBuf, Error> {
+        let run_info = config.run_info();
+        let mut observer = StopFlag(self.stop.clone());
+        match (kind, config.fast_sweep) {
+            (JobKind::Stability, _) => experiment::run_stability(self.devices, config.stability, &run_info,
+                                                                 &config.output, &mut observer, report),
+            (JobKind::PowerSweep, _) => experiment::run_power_sweep(self.devices, config.power_sweep, &run_info,
+                                                                    &config.output, &mut observer, report),
+            (_, Some(fast_sweep)) => experiment::run_fast_sweep(self.devices, fast_sweep, &run_info,
+                                                                &config.output, &mut observer, report),
+            (_, None) => experiment::run_current_sweep(self.devices, config.sweep, &run_info,
+                                                       &config.output, &mut observer, report),
+        }
+    }
+}